@@ -3,6 +3,24 @@ alias face = array<vec3<f32>, 6>;
 const PASTE_MATERIAL        = 1u;  // Copy material of occupied voxels
 const PASTE_MATERIAL_ARG    = 2u;  // Set material of occupied voxels to args.material
 const PASTE_VERTEXES        = 4u;  // Copy vertexes on the corners of occupied voxels
+const PASTE_ERASE           = 8u;  // Empty occupied voxels and clear their vertexes
+const PASTE_MASK            = 16u; // Only overwrite empty voxels and voxels with args.mask_material
+
+const GENERATE_MESH_AO      = 1u;  // Compute ambient occlusion
+const GENERATE_MESH_IGNORE_OFFSETS = 2u; // Treat every corner offset as 0
+const GENERATE_MESH_CLOSE_BOUNDARY = 4u; // Treat padding as empty when culling faces
+const GENERATE_MESH_SPLIT_LAYOUT   = 8u; // voxel_grid_a holds 1 byte materials; see mesh_raw_voxel_a
+const GENERATE_MESH_SPLIT_OFFSETS  = 16u; // With GENERATE_MESH_SPLIT_LAYOUT, voxel_grid_b holds the offsets
+
+// Values of args.shape for paste_batch
+const SHAPE_CUBE            = 0u;
+const SHAPE_SPHERE          = 1u;
+const SHAPE_ELLIPSOID       = 2u;
+const SHAPE_CYLINDER        = 3u;
+const SHAPE_TORUS           = 4u;
+const SHAPE_WEDGE           = 5u;
+const SHAPE_LINE            = 6u;
+const SHAPE_CONE            = 7u;
 
 // Arguments for shaders. See each entry point for details.
 struct args_t {
@@ -14,16 +32,33 @@ struct args_t {
     flags: u32,
     material: u32,
     diameter: u32,
+    radius: u32,
+    height: u32,
+    axis: u32,
+    mask_material: u32,
+    shape: u32,
+    seed: u32,
+    rotation: vec4<f32>,
+    shape_size: vec3<f32>,
+    frequency: f32,
+    shape_center: vec3<f32>,
+    threshold: f32,
+    src_offset: vec3<i32>,
+    transparent: array<vec4<u32>, 2>,
 }
 
 @group(0) @binding(0)
-var<uniform> args: args_t;
+var<uniform> uniform_args: args_t;
+
+// Each entry point copies its arguments here, from uniform_args or from batch_args
+var<private> args: args_t;
 
 // See VoxelGridVec for format
 @group(0) @binding(1)
 var<storage,read> voxel_grid_a: array<u32>;
 
-// See VoxelGridVec for format
+// See VoxelGridVec for format. The generate_mesh entry points read a split
+// grid's offsets from here instead; see mesh_raw_voxel_a.
 @group(0) @binding(2)
 var<storage,read> voxel_grid_b: array<u32>;
 
@@ -52,6 +87,39 @@ var<storage,read_write> mesh: array<vec3<f32>>;
 @group(0) @binding(6)
 var<storage,read_write> mesh_normals: array<vec3<f32>>;
 
+// Material of the voxel which produced each face
+@group(0) @binding(7)
+var<storage,read_write> mesh_materials: array<u32>;
+
+// Ambient occlusion of each vertex. This is a darkening factor in the range [0.4, 1.0];
+// 1.0 means unoccluded.
+@group(0) @binding(8)
+var<storage,read_write> mesh_ao: array<f32>;
+
+// Height of each column for paste_heightmap. This is u32 since WGSL doesn't have u16.
+@group(0) @binding(9)
+var<storage,read> heights: array<u32>;
+
+// Arguments for each paste in paste_batch
+@group(0) @binding(10)
+var<storage,read> batch_args: array<args_t>;
+
+// Offset of each instance for paste_sphere_instanced
+@group(0) @binding(11)
+var<storage,read> instance_offsets: array<vec3<i32>>;
+
+// Number of filled faces before each entry of face_filled, for compact_mesh
+@group(0) @binding(12)
+var<storage,read> face_offsets: array<u32>;
+
+// compact_mesh's output. This holds mesh, mesh_normals, mesh_materials, and
+// mesh_ao for only the filled faces, in face order. With room for n faces,
+// it's n * 24 u32s of vertexes (vec3s padded to 4), n * 24 of normals, n of
+// materials, then n * 6 of ao. It's 1 binding instead of 4 to stay within
+// the limit of 8 storage buffers per shader stage.
+@group(0) @binding(13)
+var<storage,read_write> packed_faces: array<u32>;
+
 struct voxel {
     corner: vec3<f32>,
     material: u32,
@@ -91,26 +159,119 @@ fn filled(raw: u32) -> bool {
     return (raw & 0xff000000u) != 0u;
 }
 
+// Raw voxel for the generate_mesh entry points, which also read split grids.
+// With GENERATE_MESH_SPLIT_LAYOUT, voxel_grid_a holds 1 byte of material per
+// voxel, 4 per u32 with the first voxel in the LSBs. The offsets are the low
+// 24 bits of voxel_grid_b's entries if GENERATE_MESH_SPLIT_OFFSETS is set,
+// otherwise 0.
+fn mesh_raw_voxel_a(pos: vec3<i32>) -> u32 {
+    if (args.flags & GENERATE_MESH_SPLIT_LAYOUT) == 0u {
+        return raw_voxel_a(pos);
+    }
+    let i = u32(index(args.a_size, pos));
+    let material = (voxel_grid_a[i / 4u] >> ((i % 4u) * 8u)) & 0xffu;
+    var offsets = 0u;
+    if (args.flags & GENERATE_MESH_SPLIT_OFFSETS) != 0u {
+        offsets = voxel_grid_b[i] & 0x00ffffffu;
+    }
+    return offsets | (material << 24u);
+}
+
 fn unpack_voxel_a(pos: vec3<i32>) -> voxel {
-    return unpack(raw_voxel_a(pos));
+    return unpack(mesh_raw_voxel_a(pos));
 }
 
 fn write_voxel_out(pos: vec3<i32>, v: voxel) {
     voxel_grid_out[index(args.out_size, pos)] = pack(v);
 }
 
-fn write_face(pos: vec3<f32>, index: i32, filled: bool, face: face) {
+// Material of a neighbor when generating a mesh. GENERATE_MESH_CLOSE_BOUNDARY
+// treats the padding as empty, so the grid's outer faces are generated. The
+// padding's corner offsets are still used.
+fn mesh_material_a(pos: vec3<i32>) -> u32 {
+    if (args.flags & GENERATE_MESH_CLOSE_BOUNDARY) != 0u &&
+        (any(pos < vec3(0)) || any(pos >= vec3<i32>(args.a_size))) {
+        return 0u;
+    }
+    return unpack_voxel_a(pos).material;
+}
+
+fn is_solid_a(pos: vec3<i32>) -> u32 {
+    return u32(mesh_material_a(pos) != 0u);
+}
+
+// Ambient occlusion of a face's corner, using the classic count of the 3 voxels
+// which touch the corner on the face's outside.
+//
+// pos:     voxel
+// normal:  points out of the face
+// corner:  corner of the voxel, each component 0 or 1
+fn corner_ao(pos: vec3<i32>, normal: vec3<i32>, corner: vec3<i32>) -> f32 {
+    let u = abs(normal).yzx;
+    let v = abs(normal).zxy;
+    let t1 = u * (dot(corner, u) * 2 - 1);
+    let t2 = v * (dot(corner, v) * 2 - 1);
+    let side1 = is_solid_a(pos + normal + t1);
+    let side2 = is_solid_a(pos + normal + t2);
+    var occlusion = side1 + side2 + is_solid_a(pos + normal + t1 + t2);
+    if side1 == 1u && side2 == 1u {
+        occlusion = 3u;
+    }
+    return 1.0 - 0.2 * f32(occlusion);
+}
+
+// Ambient occlusion of a face's 4 corners, in the same order as the face's quad.
+// Returns 1.0 for all corners if GENERATE_MESH_AO isn't set.
+fn face_ao(pos: vec3<i32>, normal: vec3<i32>, c0: vec3<i32>, c1: vec3<i32>, c2: vec3<i32>, c3: vec3<i32>) -> vec4<f32> {
+    if (args.flags & GENERATE_MESH_AO) == 0u {
+        return vec4(1.0, 1.0, 1.0, 1.0);
+    }
+    return vec4(
+        corner_ao(pos, normal, c0),
+        corner_ao(pos, normal, c1),
+        corner_ao(pos, normal, c2),
+        corner_ao(pos, normal, c3)
+    );
+}
+
+// Is the material in args.transparent? Bit m of the 256-bit mask.
+fn is_transparent(material: u32) -> bool {
+    return ((args.transparent[material / 128u][(material / 32u) % 4u] >> (material % 32u)) & 1u) != 0u;
+}
+
+// A face is visible if the neighbor is empty or has a different opacity class
+fn face_visible(material: u32, neighbor: u32) -> bool {
+    return neighbor == 0u || is_transparent(material) != is_transparent(neighbor);
+}
+
+// quad is (q0, q1, q2, q2, q3, q0); ao holds the occlusion of q0..q3.
+fn write_face(pos: vec3<f32>, index: i32, filled: bool, material: u32, quad: face, ao: vec4<f32>) {
     if filled {
+        var f = quad;
+        var a = array<f32, 6>(ao.x, ao.y, ao.z, ao.z, ao.w, ao.x);
+        if ao.x + ao.z < ao.y + ao.w {
+            // Split along the other diagonal so the occlusion interpolates evenly
+            f = face(quad[1], quad[2], quad[4], quad[4], quad[0], quad[1]);
+            a = array<f32, 6>(ao.y, ao.z, ao.w, ao.w, ao.x, ao.y);
+        }
+
         face_filled[index / 30] |= 1u << (u32(index) % 30u);
-        mesh[index * 6 + 0] = pos + face[0];
-        mesh[index * 6 + 1] = pos + face[1];
-        mesh[index * 6 + 2] = pos + face[2];
-        mesh[index * 6 + 3] = pos + face[3];
-        mesh[index * 6 + 4] = pos + face[4];
-        mesh[index * 6 + 5] = pos + face[5];
-
-        let normal0 = normalize(cross(face[1] - face[0], face[2] - face[0]));
-        let normal1 = normalize(cross(face[4] - face[3], face[5] - face[3]));
+        mesh_materials[index] = material;
+        mesh[index * 6 + 0] = pos + f[0];
+        mesh[index * 6 + 1] = pos + f[1];
+        mesh[index * 6 + 2] = pos + f[2];
+        mesh[index * 6 + 3] = pos + f[3];
+        mesh[index * 6 + 4] = pos + f[4];
+        mesh[index * 6 + 5] = pos + f[5];
+        mesh_ao[index * 6 + 0] = a[0];
+        mesh_ao[index * 6 + 1] = a[1];
+        mesh_ao[index * 6 + 2] = a[2];
+        mesh_ao[index * 6 + 3] = a[3];
+        mesh_ao[index * 6 + 4] = a[4];
+        mesh_ao[index * 6 + 5] = a[5];
+
+        let normal0 = normalize(cross(f[1] - f[0], f[2] - f[0]));
+        let normal1 = normalize(cross(f[4] - f[3], f[5] - f[3]));
         mesh_normals[index * 6 + 0] = normal0;
         mesh_normals[index * 6 + 1] = normal0;
         mesh_normals[index * 6 + 2] = normal0;
@@ -120,74 +281,393 @@ fn write_face(pos: vec3<f32>, index: i32, filled: bool, face: face) {
     }
 }
 
-// Generate mesh from voxel_grid_a. Fills face_filled, mesh, and mesh_normals.
-// face_filled must be 0-initialized before calling this; mesh and mesh_normals
-// don't need to be initialized.
+// Linear index of an invocation. Dispatches may be split across x, y, and z
+// to stay within the per-dimension workgroup limit; this undoes that.
+fn linear_invocation(invocation: vec3<u32>, num_workgroups: vec3<u32>, workgroup_size: u32) -> u32 {
+    let row = num_workgroups.x * workgroup_size;
+    return invocation.x + (invocation.y + invocation.z * num_workgroups.y) * row;
+}
+
+// Convert 1 voxel to 6 faces for generate_mesh and generate_mesh_region
+fn generate_mesh_voxel(voxel_index: u32) {
+    let face_index = i32(voxel_index) * 6;
+    let pos_u32 = vec3(
+        voxel_index % args.a_size.x,
+        (voxel_index / args.a_size.x) % args.a_size.y,
+        voxel_index / (args.a_size.x * args.a_size.y)
+    );
+    let pos_i32 = vec3<i32>(pos_u32);
+    let pos_f32 = vec3<f32>(pos_u32);
+
+    let vox_000 = unpack_voxel_a(pos_i32 + vec3<i32>(0, 0, 0));
+    if vox_000.material == 0u {
+        return;
+    }
+    let vox_001 = unpack_voxel_a(pos_i32 + vec3<i32>(0, 0, 1));
+    let vox_010 = unpack_voxel_a(pos_i32 + vec3<i32>(0, 1, 0));
+    let vox_011 = unpack_voxel_a(pos_i32 + vec3<i32>(0, 1, 1));
+    let vox_100 = unpack_voxel_a(pos_i32 + vec3<i32>(1, 0, 0));
+    let vox_101 = unpack_voxel_a(pos_i32 + vec3<i32>(1, 0, 1));
+    let vox_110 = unpack_voxel_a(pos_i32 + vec3<i32>(1, 1, 0));
+    let vox_111 = unpack_voxel_a(pos_i32 + vec3<i32>(1, 1, 1));
+
+    // GENERATE_MESH_IGNORE_OFFSETS puts every corner at its integer
+    // position, so the normals are axis-aligned
+    let corner_scale = select(1.0, 0.0, (args.flags & GENERATE_MESH_IGNORE_OFFSETS) != 0u);
+    let p000 = vec3<f32>(0.0, 0.0, 0.0) + vox_000.corner * corner_scale;
+    let p001 = vec3<f32>(0.0, 0.0, 1.0) + vox_001.corner * corner_scale;
+    let p010 = vec3<f32>(0.0, 1.0, 0.0) + vox_010.corner * corner_scale;
+    let p011 = vec3<f32>(0.0, 1.0, 1.0) + vox_011.corner * corner_scale;
+    let p100 = vec3<f32>(1.0, 0.0, 0.0) + vox_100.corner * corner_scale;
+    let p101 = vec3<f32>(1.0, 0.0, 1.0) + vox_101.corner * corner_scale;
+    let p110 = vec3<f32>(1.0, 1.0, 0.0) + vox_110.corner * corner_scale;
+    let p111 = vec3<f32>(1.0, 1.0, 1.0) + vox_111.corner * corner_scale;
+
+    let c000 = vec3(0, 0, 0);
+    let c001 = vec3(0, 0, 1);
+    let c010 = vec3(0, 1, 0);
+    let c011 = vec3(0, 1, 1);
+    let c100 = vec3(1, 0, 0);
+    let c101 = vec3(1, 0, 1);
+    let c110 = vec3(1, 1, 0);
+    let c111 = vec3(1, 1, 1);
+
+    write_face(pos_f32, face_index + 0, face_visible(vox_000.material, mesh_material_a(pos_i32 + vec3(0, 0, 1))), vox_000.material, face(p001, p101, p111, p111, p011, p001), face_ao(pos_i32, vec3(0, 0, 1), c001, c101, c111, c011)); // z=1
+    write_face(pos_f32, face_index + 1, face_visible(vox_000.material, mesh_material_a(pos_i32 + vec3(1, 0, 0))), vox_000.material, face(p101, p100, p110, p110, p111, p101), face_ao(pos_i32, vec3(1, 0, 0), c101, c100, c110, c111)); // x=1
+    write_face(pos_f32, face_index + 2, face_visible(vox_000.material, mesh_material_a(pos_i32 + vec3(0, 0, -1))), vox_000.material, face(p100, p000, p010, p010, p110, p100), face_ao(pos_i32, vec3(0, 0, -1), c100, c000, c010, c110)); // z=0
+    write_face(pos_f32, face_index + 3, face_visible(vox_000.material, mesh_material_a(pos_i32 + vec3(-1, 0, 0))), vox_000.material, face(p000, p001, p011, p011, p010, p000), face_ao(pos_i32, vec3(-1, 0, 0), c000, c001, c011, c010)); // x=0
+    write_face(pos_f32, face_index + 4, face_visible(vox_000.material, mesh_material_a(pos_i32 + vec3(0, 1, 0))), vox_000.material, face(p011, p111, p110, p110, p010, p011), face_ao(pos_i32, vec3(0, 1, 0), c011, c111, c110, c010)); // y=1
+    write_face(pos_f32, face_index + 5, face_visible(vox_000.material, mesh_material_a(pos_i32 + vec3(0, -1, 0))), vox_000.material, face(p000, p100, p101, p101, p001, p000), face_ao(pos_i32, vec3(0, -1, 0), c000, c100, c101, c001)); // y=0
+} // generate_mesh_voxel
+
+// Generate mesh from voxel_grid_a. Fills face_filled, mesh, mesh_normals,
+// mesh_materials, and mesh_ao. face_filled must be 0-initialized before calling
+// this; GenerateMeshImpl::add_pass clears it. The others don't need to be
+// initialized.
 //
 // args: {
 //      a_size:   size of voxel_grid_a
+//      flags:    Any of: GENERATE_MESH_AO, GENERATE_MESH_IGNORE_OFFSETS,
+//                GENERATE_MESH_CLOSE_BOUNDARY, GENERATE_MESH_SPLIT_LAYOUT,
+//                GENERATE_MESH_SPLIT_OFFSETS
 // }
 //
 // Each invocation converts 5 voxels (30 faces) and fills 1 entry of face_filled.
-// Each workgroup converts a little more than a 6*7*7 cube of voxels.
+// The MESH_WORKGROUP_SIZE shader def sets the workgroup size.
 //
-// This needs ceil((args.a_size.x * args.a_size.y * args.a_size.z) / 64) workgroups.
-@compute @workgroup_size(64)
-fn generate_mesh(@builtin(global_invocation_id) invocation: vec3<u32>) {
+// This needs ceil((args.a_size.x * args.a_size.y * args.a_size.z) / (5 * MESH_WORKGROUP_SIZE)) workgroups.
+@compute @workgroup_size(#{MESH_WORKGROUP_SIZE})
+fn generate_mesh(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = uniform_args;
+    let index = linear_invocation(invocation, num_workgroups, u32(#{MESH_WORKGROUP_SIZE}));
     for (var i = 0u; i < 5u; i += 1u) {
-        let voxel_index = invocation.x * 5u + i;
+        let voxel_index = index * 5u + i;
         if voxel_index >= args.a_size.x * args.a_size.y * args.a_size.z {
             break;
         }
-        let face_index = i32(voxel_index) * 6;
-        let pos_u32 = vec3(
+        generate_mesh_voxel(voxel_index);
+    }
+} // generate_mesh
+
+// Like generate_mesh, but only converts the voxels in the box
+// [args.offset, args.offset + args.size), which must be inside the grid.
+// Faces outside the box aren't filled.
+//
+// args: {
+//      a_size:   size of voxel_grid_a
+//      offset:   lower corner of the box
+//      size:     size of the box
+//      flags:    same as generate_mesh
+// }
+//
+// Each invocation still fills at most 1 entry of face_filled, and no entry
+// is filled by 2 invocations. Each row of the box along x gets
+// (args.size.x / 5 + 2) invocations, one per entry which overlaps the row.
+// An entry overlaps 2 rows when the box is almost as wide as the grid; the
+// row holding the entry's first voxel in the box fills it.
+//
+// This needs ceil((args.size.y * args.size.z * (args.size.x / 5 + 2)) / MESH_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{MESH_WORKGROUP_SIZE})
+fn generate_mesh_region(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = uniform_args;
+    let index = linear_invocation(invocation, num_workgroups, u32(#{MESH_WORKGROUP_SIZE}));
+    let entries_per_row = args.size.x / 5u + 2u;
+    let row = index / entries_per_row;
+    if row >= args.size.y * args.size.z {
+        return;
+    }
+    let box_begin = vec3<u32>(args.offset);
+    let box_end = box_begin + args.size;
+    let row_pos = box_begin + vec3(0u, row % args.size.y, row / args.size.y);
+    let row_begin = row_pos.x + args.a_size.x * (row_pos.y + args.a_size.y * row_pos.z);
+    let entry = row_begin / 5u + index % entries_per_row;
+    if entry * 5u >= row_begin + args.size.x {
+        return;
+    }
+    var owned = false;
+    for (var i = 0u; i < 5u; i += 1u) {
+        let voxel_index = entry * 5u + i;
+        let pos = vec3(
             voxel_index % args.a_size.x,
             (voxel_index / args.a_size.x) % args.a_size.y,
             voxel_index / (args.a_size.x * args.a_size.y)
         );
-        let pos_i32 = vec3<i32>(pos_u32);
-        let pos_f32 = vec3<f32>(pos_u32);
+        if any(pos < box_begin) || any(pos >= box_end) {
+            continue;
+        }
+        if !owned {
+            // This is the entry's first voxel in the box
+            if any(pos.yz != row_pos.yz) {
+                return;
+            }
+            owned = true;
+        }
+        generate_mesh_voxel(voxel_index);
+    }
+} // generate_mesh_region
 
-        let vox_000 = unpack_voxel_a(pos_i32 + vec3<i32>(0, 0, 0));
-        if vox_000.material == 0u {
+// Copy the filled faces of generate_mesh's output into the packed_ arrays,
+// so reading the mesh back only copies those faces. face_offsets is the
+// prefix sum of face_filled's bit counts; the CPU computes it after reading
+// face_filled back.
+//
+// Each invocation copies the faces of 1 entry of face_filled.
+//
+// This needs ceil(arrayLength(&face_offsets) / MESH_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{MESH_WORKGROUP_SIZE})
+fn compact_mesh(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    let entry = linear_invocation(invocation, num_workgroups, u32(#{MESH_WORKGROUP_SIZE}));
+    if entry >= arrayLength(&face_offsets) {
+        return;
+    }
+    // See packed_faces
+    let capacity = arrayLength(&packed_faces) / 55u;
+    let normals_begin = capacity * 24u;
+    let materials_begin = capacity * 48u;
+    let ao_begin = capacity * 49u;
+
+    let bits = face_filled[entry];
+    var dest = face_offsets[entry];
+    for (var i = 0u; i < 30u; i += 1u) {
+        if (bits & (1u << i)) == 0u {
             continue;
         }
-        let vox_001 = unpack_voxel_a(pos_i32 + vec3<i32>(0, 0, 1));
-        let vox_010 = unpack_voxel_a(pos_i32 + vec3<i32>(0, 1, 0));
-        let vox_011 = unpack_voxel_a(pos_i32 + vec3<i32>(0, 1, 1));
-        let vox_100 = unpack_voxel_a(pos_i32 + vec3<i32>(1, 0, 0));
-        let vox_101 = unpack_voxel_a(pos_i32 + vec3<i32>(1, 0, 1));
-        let vox_110 = unpack_voxel_a(pos_i32 + vec3<i32>(1, 1, 0));
-        let vox_111 = unpack_voxel_a(pos_i32 + vec3<i32>(1, 1, 1));
-        let vox_00n = unpack_voxel_a(pos_i32 + vec3<i32>(0, 0, -1));
-        let vox_0n0 = unpack_voxel_a(pos_i32 + vec3<i32>(0, -1, 0));
-        let vox_n00 = unpack_voxel_a(pos_i32 + vec3<i32>(-1, 0, 0));
-
-        let p000 = vec3<f32>(0.0, 0.0, 0.0) + vox_000.corner;
-        let p001 = vec3<f32>(0.0, 0.0, 1.0) + vox_001.corner;
-        let p010 = vec3<f32>(0.0, 1.0, 0.0) + vox_010.corner;
-        let p011 = vec3<f32>(0.0, 1.0, 1.0) + vox_011.corner;
-        let p100 = vec3<f32>(1.0, 0.0, 0.0) + vox_100.corner;
-        let p101 = vec3<f32>(1.0, 0.0, 1.0) + vox_101.corner;
-        let p110 = vec3<f32>(1.0, 1.0, 0.0) + vox_110.corner;
-        let p111 = vec3<f32>(1.0, 1.0, 1.0) + vox_111.corner;
-
-        write_face(pos_f32, face_index + 0, vox_001.material == 0u, face(p001, p101, p111, p111, p011, p001)); // z=1
-        write_face(pos_f32, face_index + 1, vox_100.material == 0u, face(p101, p100, p110, p110, p111, p101)); // x=1
-        write_face(pos_f32, face_index + 2, vox_00n.material == 0u, face(p100, p000, p010, p010, p110, p100)); // z=0
-        write_face(pos_f32, face_index + 3, vox_n00.material == 0u, face(p000, p001, p011, p011, p010, p000)); // x=0
-        write_face(pos_f32, face_index + 4, vox_010.material == 0u, face(p011, p111, p110, p110, p010, p011)); // y=1
-        write_face(pos_f32, face_index + 5, vox_0n0.material == 0u, face(p000, p100, p101, p101, p001, p000)); // y=0
+        let src = entry * 30u + i;
+        packed_faces[materials_begin + dest] = mesh_materials[src];
+        for (var j = 0u; j < 6u; j += 1u) {
+            let k = dest * 6u + j;
+            let v = mesh[src * 6u + j];
+            let n = mesh_normals[src * 6u + j];
+            packed_faces[k * 4u + 0u] = bitcast<u32>(v.x);
+            packed_faces[k * 4u + 1u] = bitcast<u32>(v.y);
+            packed_faces[k * 4u + 2u] = bitcast<u32>(v.z);
+            packed_faces[normals_begin + k * 4u + 0u] = bitcast<u32>(n.x);
+            packed_faces[normals_begin + k * 4u + 1u] = bitcast<u32>(n.y);
+            packed_faces[normals_begin + k * 4u + 2u] = bitcast<u32>(n.z);
+            packed_faces[ao_begin + k] = bitcast<u32>(mesh_ao[src * 6u + j]);
+        }
+        dest += 1u;
     }
-} // generate_mesh
+} // compact_mesh
+
+// Corner c of a marching cubes cell is at (c & 1, (c >> 1) & 1, c >> 2).
+// Edge e connects corners mc_edges[e] & 15 and mc_edges[e] >> 4.
+var<private> mc_edges: array<u32, 12> = array<u32, 12>(
+    0x10u, 0x20u, 0x40u, 0x31u, 0x51u, 0x32u, 0x62u, 0x73u, 0x54u, 0x64u, 0x75u, 0x76u,
+);
+
+// Triangles for each marching cubes case. Bit c of the case is set if corner
+// c is inside. Each case is 2 entries which hold up to 15 edge indexes, 4
+// bits each, starting at the LSBs of the first entry; 15 ends the list. Every
+// 3 edges are a triangle, counter-clockwise when viewed from outside. Faces
+// with 2 diagonal inside corners always separate them, so adjacent cells agree
+// and the surface is watertight.
+var<private> mc_triangles: array<u32, 512> = array<u32, 512>(
+    0xffffffffu, 0xffffffffu, 0xfffff102u, 0xffffffffu, 0xfffff403u, 0xffffffffu, 0xff342132u, 0xffffffffu,
+    0xfffff651u, 0xffffffffu, 0xff502652u, 0xffffffffu, 0xff403651u, 0xffffffffu, 0x42532652u, 0xfffffff3u,
+    0xfffff357u, 0xffffffffu, 0xff357102u, 0xffffffffu, 0xff057407u, 0xffffffffu, 0x42572152u, 0xfffffff7u,
+    0xff731671u, 0xffffffffu, 0x02732672u, 0xfffffff3u, 0x01741671u, 0xfffffff4u, 0xff742672u, 0xffffffffu,
+    0xfffff289u, 0xffffffffu, 0xff089109u, 0xffffffffu, 0xff403289u, 0xffffffffu, 0x89349139u, 0xfffffff4u,
+    0xff289651u, 0xffffffffu, 0x89509659u, 0xfffffff0u, 0x03289651u, 0xfffffff4u, 0x49539659u, 0xffff4893u,
+    0xff357289u, 0xffffffffu, 0x57089109u, 0xfffffff3u, 0x57407289u, 0xfffffff0u, 0x49579159u, 0xffff4897u,
+    0x89731671u, 0xfffffff2u, 0x09739679u, 0xffff0893u, 0x01741671u, 0xffff2894u, 0x89749679u, 0xfffffff4u,
+    0xfffffa84u, 0xffffffffu, 0xffa84102u, 0xffffffffu, 0xff803a83u, 0xffffffffu, 0x823a2132u, 0xfffffffau,
+    0xffa84651u, 0xffffffffu, 0x84502652u, 0xfffffffau, 0x03a83651u, 0xfffffff8u, 0xa2532652u, 0xffffa823u,
+    0xffa84357u, 0xffffffffu, 0x84357102u, 0xfffffffau, 0x57807a87u, 0xfffffff0u, 0xa2572152u, 0xffffa827u,
+    0x84731671u, 0xfffffffau, 0x02732672u, 0xffffa843u, 0x817a1671u, 0xffff801au, 0x827a2672u, 0xfffffffau,
+    0xff4a9249u, 0xffffffffu, 0xa9049109u, 0xfffffff4u, 0xa9039209u, 0xfffffff3u, 0xff3a9139u, 0xffffffffu,
+    0xa9249651u, 0xfffffff4u, 0x49509659u, 0xffff4a90u, 0x39209651u, 0xffff3a90u, 0xa9539659u, 0xfffffff3u,
+    0x574a9249u, 0xfffffff3u, 0xa9049109u, 0xffff3574u, 0x79059209u, 0xffff7a95u, 0xa9579159u, 0xfffffff7u,
+    0x49731671u, 0xffff4a92u, 0x09739679u, 0xf4a90493u, 0x917a1671u, 0xf201921au, 0xff7a9679u, 0xffffffffu,
+    0xfffff9b6u, 0xffffffffu, 0xff9b6102u, 0xffffffffu, 0xff4039b6u, 0xffffffffu, 0xb6342132u, 0xfffffff9u,
+    0xffb519b1u, 0xffffffffu, 0x02b529b2u, 0xfffffff5u, 0x03b519b1u, 0xfffffff4u, 0x32b529b2u, 0xffff3425u,
+    0xff3579b6u, 0xffffffffu, 0x579b6102u, 0xfffffff3u, 0x574079b6u, 0xfffffff0u, 0x42572152u, 0xffff9b67u,
+    0x31b719b1u, 0xfffffff7u, 0x32b729b2u, 0xffff3027u, 0x41b719b1u, 0xffff4017u, 0x42b729b2u, 0xfffffff7u,
+    0xff8b6286u, 0xffffffffu, 0xb6086106u, 0xfffffff8u, 0x038b6286u, 0xfffffff4u, 0x86346136u, 0xffff8b64u,
+    0x518b1281u, 0xfffffffbu, 0xffb508b0u, 0xffffffffu, 0x518b1281u, 0xffff403bu, 0x538b3483u, 0xfffffffbu,
+    0x578b6286u, 0xfffffff3u, 0xb6086106u, 0xffff3578u, 0x078b6286u, 0xffff0574u, 0x46576156u, 0xf8b64867u,
+    0x718b1281u, 0xffff731bu, 0xb7087307u, 0xfffffff8u, 0x718b1281u, 0xf401741bu, 0xff8b7487u, 0xffffffffu,
+    0xffa849b6u, 0xffffffffu, 0x849b6102u, 0xfffffffau, 0x03a839b6u, 0xfffffff8u, 0x823a2132u, 0xffff9b6au,
+    0x84b519b1u, 0xfffffffau, 0x02b529b2u, 0xffffa845u, 0x83b519b1u, 0xffff803au, 0x32b529b2u, 0xfa823a25u,
+    0x843579b6u, 0xfffffffau, 0x579b6102u, 0xffffa843u, 0x07a879b6u, 0xffff0578u, 0xa2572152u, 0xf9b6a827u,
+    0x31b719b1u, 0xffffa847u, 0x32b729b2u, 0xfa843027u, 0xa1b719b1u, 0xf801a817u, 0xa2b729b2u, 0xffffa827u,
+    0xb64a6246u, 0xfffffffau, 0xa6046106u, 0xffffab64u, 0xa6036206u, 0xffffab63u, 0xb63a6136u, 0xfffffffau,
+    0xb14a1241u, 0xffffb51au, 0x04b54ab4u, 0xfffffff5u, 0xa1031201u, 0xfb51ab13u, 0xffb53ab3u, 0xffffffffu,
+    0xb64a6246u, 0xffff357au, 0xa6046106u, 0xf357ab64u, 0x76056206u, 0xfab67a65u, 0xa6576156u, 0xffffab67u,
+    0xb14a1241u, 0xf731b71au, 0xa7047307u, 0xffffab74u, 0xffab7201u, 0xffffffffu, 0xfffffab7u, 0xffffffffu,
+    0xfffff7bau, 0xffffffffu, 0xff7ba102u, 0xffffffffu, 0xff7ba403u, 0xffffffffu, 0xba342132u, 0xfffffff7u,
+    0xff7ba651u, 0xffffffffu, 0xba502652u, 0xfffffff7u, 0xba403651u, 0xfffffff7u, 0x42532652u, 0xffff7ba3u,
+    0xff5ba35au, 0xffffffffu, 0xba35a102u, 0xfffffff5u, 0xba05a40au, 0xfffffff5u, 0xa25b2152u, 0xffffa42bu,
+    0x31ba16b1u, 0xfffffffau, 0x32ba26b2u, 0xffff302au, 0x41ba16b1u, 0xffff401au, 0x42ba26b2u, 0xfffffffau,
+    0xff7ba289u, 0xffffffffu, 0xba089109u, 0xfffffff7u, 0xba403289u, 0xfffffff7u, 0x89349139u, 0xffff7ba4u,
+    0xba289651u, 0xfffffff7u, 0x89509659u, 0xffff7ba0u, 0x03289651u, 0xffff7ba4u, 0x49539659u, 0xf7ba4893u,
+    0xba35a289u, 0xfffffff5u, 0x5a089109u, 0xffff5ba3u, 0x5a40a289u, 0xffff5ba0u, 0xa95b9159u, 0xf489a49bu,
+    0x31ba16b1u, 0xffff289au, 0x39ba96b9u, 0xf089309au, 0x41ba16b1u, 0xf289401au, 0x49ba96b9u, 0xffff489au,
+    0xffb847b4u, 0xffffffffu, 0x847b4102u, 0xfffffffbu, 0x03b837b3u, 0xfffffff8u, 0xb2372132u, 0xffffb827u,
+    0x847b4651u, 0xfffffffbu, 0xb4502652u, 0xffffb847u, 0x837b3651u, 0xffff803bu, 0x72532652u, 0xfb827b23u,
+    0x845b4354u, 0xfffffffbu, 0xb4354102u, 0xffffb845u, 0xff5b8058u, 0xffffffffu, 0x825b2152u, 0xfffffffbu,
+    0x41b816b1u, 0xffff4318u, 0x42b826b2u, 0xf3024328u, 0x01b816b1u, 0xfffffff8u, 0xffb826b2u, 0xffffffffu,
+    0xb9479249u, 0xfffffff7u, 0x79049109u, 0xffff7b94u, 0x79039209u, 0xffff7b93u, 0xb9379139u, 0xfffffff7u,
+    0x79249651u, 0xffff7b94u, 0x49509659u, 0xf7b94790u, 0x39209651u, 0xf7b93790u, 0x79539659u, 0xffff7b93u,
+    0x59439249u, 0xffff5b93u, 0x39049109u, 0xf5b93594u, 0xb9059209u, 0xfffffff5u, 0xff5b9159u, 0xffffffffu,
+    0x21b916b1u, 0xf4312419u, 0xff3046b9u, 0xffffffffu, 0x21b916b1u, 0xffff2019u, 0xfffff6b9u, 0xffffffffu,
+    0xffa769a6u, 0xffffffffu, 0x769a6102u, 0xfffffffau, 0x03a769a6u, 0xfffffff4u, 0xa6342132u, 0xffffa769u,
+    0x51a719a1u, 0xfffffff7u, 0x52a729a2u, 0xffff5027u, 0x51a719a1u, 0xffff4037u, 0x52a729a2u, 0xf3425327u,
+    0x56a369a6u, 0xfffffff3u, 0x369a6102u, 0xffff356au, 0x06a469a6u, 0xffff0564u, 0x92562152u, 0xfa429a26u,
+    0xffa319a1u, 0xffffffffu, 0x02a329a2u, 0xfffffff3u, 0x01a419a1u, 0xfffffff4u, 0xffa429a2u, 0xffffffffu,
+    0x768a6286u, 0xfffffffau, 0xa6086106u, 0xffffa768u, 0x768a6286u, 0xffff403au, 0x86346136u, 0xfa768a64u,
+    0x718a1281u, 0xffff751au, 0x8a50a75au, 0xfffffff0u, 0x718a1281u, 0xf403751au, 0x738a3483u, 0xffff753au,
+    0x368a6286u, 0xffff356au, 0xa6086106u, 0xf356a368u, 0x468a6286u, 0xf056406au, 0xff48a156u, 0xffffffffu,
+    0x318a1281u, 0xfffffffau, 0xff08a30au, 0xffffffffu, 0x418a1281u, 0xffff401au, 0xfffff48au, 0xffffffffu,
+    0x76846986u, 0xfffffff4u, 0x46986102u, 0xffff4768u, 0x36806986u, 0xffff3760u, 0x62372132u, 0xf9826927u,
+    0x71841981u, 0xffff7514u, 0x72842982u, 0xf5027524u, 0x31801981u, 0xf7513710u, 0xff753982u, 0xffffffffu,
+    0x36846986u, 0xffff3564u, 0x46986102u, 0xf3564368u, 0x56806986u, 0xfffffff0u, 0x92562152u, 0xffff9826u,
+    0x31841981u, 0xfffffff4u, 0x32842982u, 0xffff3024u, 0xff801981u, 0xffffffffu, 0xfffff982u, 0xffffffffu,
+    0xff476246u, 0xffffffffu, 0x76046106u, 0xfffffff4u, 0x76036206u, 0xfffffff3u, 0xff376136u, 0xffffffffu,
+    0x51471241u, 0xfffffff7u, 0xff504754u, 0xffffffffu, 0x71031201u, 0xffff7513u, 0xfffff753u, 0xffffffffu,
+    0x56436246u, 0xfffffff3u, 0x36046106u, 0xffff3564u, 0xff056206u, 0xffffffffu, 0xfffff156u, 0xffffffffu,
+    0xff431241u, 0xffffffffu, 0xfffff304u, 0xffffffffu, 0xfffff201u, 0xffffffffu, 0xffffffffu, 0xffffffffu,
+);
+
+const MC_VERTEXES_PER_CELL = 15u;
+
+fn mc_corner(c: u32) -> vec3<i32> {
+    return vec3<i32>(i32(c & 1u), i32((c >> 1u) & 1u), i32(c >> 2u));
+}
+
+// Material of a voxel. Voxels outside the grid, including its padding, are empty.
+fn mc_material(pos: vec3<i32>) -> u32 {
+    if any(pos < vec3(-1)) || any(pos > vec3<i32>(args.a_size)) {
+        return 0u;
+    }
+    return mesh_raw_voxel_a(pos) >> 24u;
+}
+
+fn mc_occupied(pos: vec3<i32>) -> f32 {
+    return f32(mc_material(pos) != 0u);
+}
+
+// Points from inside toward outside
+fn mc_gradient(pos: vec3<i32>) -> vec3<f32> {
+    return vec3(
+        mc_occupied(pos - vec3(1, 0, 0)) - mc_occupied(pos + vec3(1, 0, 0)),
+        mc_occupied(pos - vec3(0, 1, 0)) - mc_occupied(pos + vec3(0, 1, 0)),
+        mc_occupied(pos - vec3(0, 0, 1)) - mc_occupied(pos + vec3(0, 0, 1)),
+    );
+}
+
+// Generate a smooth mesh from voxel_grid_a using marching cubes. Voxels with
+// material > 0 are inside. The cells' corners are at the voxel centers; each
+// cell's min corner is in [-1, a_size - 1], so the surface closes over the
+// padding. Corner offsets are ignored.
+//
+// Fills mesh and mesh_normals (MC_VERTEXES_PER_CELL entries per cell),
+// mesh_materials (1 per cell; the material of the cell's first inside
+// corner), and face_filled (1 per cell; the number of vertexes the cell
+// filled). None need to be initialized.
+//
+// args: {
+//      a_size:   size of voxel_grid_a
+//      flags:    Any of: GENERATE_MESH_SPLIT_LAYOUT, GENERATE_MESH_SPLIT_OFFSETS
+// }
+//
+// This needs ceil(((args.a_size.x+1) * (args.a_size.y+1) * (args.a_size.z+1)) / MESH_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{MESH_WORKGROUP_SIZE})
+fn generate_mesh_marching_cubes(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = uniform_args;
+    let cell = linear_invocation(invocation, num_workgroups, u32(#{MESH_WORKGROUP_SIZE}));
+    let cells = args.a_size + 1u;
+    if cell >= cells.x * cells.y * cells.z {
+        return;
+    }
+    let base = vec3<i32>(vec3(
+        cell % cells.x,
+        (cell / cells.x) % cells.y,
+        cell / (cells.x * cells.y)
+    )) - 1;
+
+    var case_index = 0u;
+    var material = 0u;
+    for (var c = 0u; c < 8u; c += 1u) {
+        let m = mc_material(base + mc_corner(c));
+        if m != 0u {
+            case_index |= 1u << c;
+            if material == 0u {
+                material = m;
+            }
+        }
+    }
+    mesh_materials[cell] = material;
+
+    var count = 0u;
+    for (var i = 0u; i < MC_VERTEXES_PER_CELL; i += 1u) {
+        let edge = (mc_triangles[case_index * 2u + i / 8u] >> ((i % 8u) * 4u)) & 15u;
+        if edge == 15u {
+            break;
+        }
+        var a = base + mc_corner(mc_edges[edge] & 15u);
+        var b = base + mc_corner(mc_edges[edge] >> 4u);
+        if mc_material(a) == 0u {
+            let t = a;
+            a = b;
+            b = t;
+        }
+        // The material is binary, so the surface crosses the edge's middle
+        mesh[cell * MC_VERTEXES_PER_CELL + i] = vec3<f32>(a + b + 1) * 0.5;
+        var normal = mc_gradient(a) + mc_gradient(b);
+        if all(normal == vec3(0.0)) {
+            normal = vec3<f32>(b - a);
+        }
+        mesh_normals[cell * MC_VERTEXES_PER_CELL + i] = normalize(normal);
+        count += 1u;
+    }
+    face_filled[cell] = count;
+} // generate_mesh_marching_cubes
 
 struct paste_state {
     src_size: vec3<u32>,
     src_pos: vec3<i32>,
     dest_pos: vec3<i32>,
     raw: u32,
+    masked: bool, // PASTE_MASK protects this voxel
 }
 
+// Start pasting the voxel at voxel_index in the shape's scan order. Returns false
+// if dest is outside [0, out_size], so shapes are clipped to the grid; the
+// beginning padding is never written. paste_material() also skips the ending
+// padding, so only paste_vertex() writes there.
 fn paste_begin(voxel_index: i32, state: ptr<function, paste_state>) -> bool {
     // Include ending padding from source so we get all vertexes
     let scan_size = vec3(i32((*state).src_size.x) + 1, i32((*state).src_size.y) + 1, i32((*state).src_size.z) + 1);
@@ -210,15 +690,27 @@ fn paste_begin(voxel_index: i32, state: ptr<function, paste_state>) -> bool {
     }
 
     (*state).raw = voxel_grid_out[index(args.out_size, (*state).dest_pos)];
+    paste_check_mask(state);
     return true;
 }
 
-// Paste material if dest isn't in padding
+fn paste_check_mask(state: ptr<function, paste_state>) {
+    let material = (*state).raw >> 24u;
+    (*state).masked = (args.flags & PASTE_MASK) != 0u && material != 0u && material != args.mask_material;
+}
+
+// Paste material if dest isn't in padding. PASTE_ERASE clears the vertex too;
+// paste_vertex() may set it again if it's on the erased shape's surface.
 fn paste_material(state: ptr<function, paste_state>, src_mat: u32) {
+    if (*state).masked {
+        return;
+    }
     if (*state).dest_pos.x < i32(args.out_size.x) && //
        (*state).dest_pos.y < i32(args.out_size.y) && //
        (*state).dest_pos.z < i32(args.out_size.z) {
-        if (args.flags & PASTE_MATERIAL_ARG) != 0u {
+        if (args.flags & PASTE_ERASE) != 0u {
+            (*state).raw = 0u;
+        } else if (args.flags & PASTE_MATERIAL_ARG) != 0u {
             (*state).raw = ((*state).raw & 0x00ffffffu) | (args.material << 24u);
         } else if (args.flags & PASTE_MATERIAL) != 0u {
             (*state).raw = ((*state).raw & 0x00ffffffu) | (src_mat << 24u);
@@ -227,6 +719,9 @@ fn paste_material(state: ptr<function, paste_state>, src_mat: u32) {
 }
 
 fn paste_vertex(state: ptr<function, paste_state>, src_raw: u32) {
+    if (*state).masked {
+        return;
+    }
     (*state).raw = ((*state).raw & 0xff000000u) | (src_raw & 0x00ffffffu);
 }
 
@@ -234,30 +729,114 @@ fn paste_end(state: ptr<function, paste_state>) {
     voxel_grid_out[index(args.out_size, (*state).dest_pos)] = (*state).raw;
 }
 
+// Rotate v by quaternion q
+fn quat_rotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    let t = 2.0 * cross(q.xyz, v);
+    return v + q.w * t + cross(q.xyz, t);
+}
+
+// Convert a position to the rotated cube's frame, relative to its lower-left corner
+fn rotated_cube_local(pos: vec3<f32>) -> vec3<f32> {
+    let inverse = vec4(-args.rotation.xyz, args.rotation.w);
+    return quat_rotate(inverse, pos - args.shape_center) + args.shape_size / 2.0;
+}
+
+fn rotated_cube_inside(pos: vec3<i32>) -> bool {
+    let local = rotated_cube_local(vec3<f32>(pos) + 0.5);
+    return all(local >= vec3(0.0)) && all(local < args.shape_size);
+}
+
+fn rotated_cube_include_vertex(pos: vec3<i32>) -> bool {
+    let count = //
+        u32(rotated_cube_inside(pos + vec3(-1, -1, -1))) + //
+        u32(rotated_cube_inside(pos + vec3(-1, -1, 0))) + //
+        u32(rotated_cube_inside(pos + vec3(-1, 0, -1))) + //
+        u32(rotated_cube_inside(pos + vec3(-1, 0, 0))) + //
+        u32(rotated_cube_inside(pos + vec3(0, -1, -1))) + //
+        u32(rotated_cube_inside(pos + vec3(0, -1, 0))) + //
+        u32(rotated_cube_inside(pos + vec3(0, 0, -1))) + //
+        u32(rotated_cube_inside(pos + vec3(0, 0, 0)));
+    return count != 0u && count != 8u;
+}
+
+// Move a vertex to the nearest point on the rotated cube's surface
+fn rotated_cube_vertex(pos: vec3<i32>) -> u32 {
+    let size = args.shape_size;
+    let unclamped = rotated_cube_local(vec3<f32>(pos));
+    var local = clamp(unclamped, vec3(0.0), size);
+    if all(local == unclamped) {
+        // Inside; move to the nearest face
+        let lo = local;
+        let hi = size - local;
+        let m = min(min(min(lo.x, hi.x), min(lo.y, hi.y)), min(lo.z, hi.z));
+        if m == lo.x {
+            local.x = 0.0;
+        } else if m == hi.x {
+            local.x = size.x;
+        } else if m == lo.y {
+            local.y = 0.0;
+        } else if m == hi.y {
+            local.y = size.y;
+        } else if m == lo.z {
+            local.z = 0.0;
+        } else {
+            local.z = size.z;
+        }
+    }
+    let world = quat_rotate(args.rotation, local - size / 2.0) + args.shape_center;
+    return encode_corner(world - vec3<f32>(pos));
+}
+
 // Paste cube into voxel_grid_out. The cube will be centered on
 // (args.offset + vec3(diameter/2, diameter/2, diameter/2)).
 //
+// If args.rotation isn't the identity, then the cube is rotated around
+// its center. args.offset and args.size cover the rotated cube's bounding box.
+//
 // args: {
-//     out_size:    Size of voxel_grid_out
-//     offset:      Offset cube's coordinates
-//     flags:       Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES.
-//                  Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
-//     material:    Material to paste
-//     size:        Size of cube
+//     out_size:     Size of voxel_grid_out
+//     offset:       Offset cube's coordinates
+//     flags:        Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+//                   Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+//                   PASTE_ERASE empties occupied voxels instead.
+//     material:     Material to paste
+//     mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+//     size:         Size of cube, or of the rotated cube's bounding box
+//     rotation:     Quaternion
+//     shape_size:   Size of rotated cube
+//     shape_center: Center of rotated cube, relative to args.offset
 // }
 //
-// This needs ceil(((args.size.x+1) * (args.size.y+1) * (args.size.z+1)) / 64) workgroups.
-@compute @workgroup_size(64)
-fn paste_cube(@builtin(global_invocation_id) invocation: vec3<u32>) {
-    var state = paste_state(args.size, vec3(0, 0, 0), vec3(0, 0, 0), 0u);
-    if !paste_begin(i32(invocation.x), &state) {
+// This needs ceil(((args.size.x+1) * (args.size.y+1) * (args.size.z+1)) / PASTE_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{PASTE_WORKGROUP_SIZE})
+fn paste_cube(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = uniform_args;
+    let index = linear_invocation(invocation, num_workgroups, u32(#{PASTE_WORKGROUP_SIZE}));
+    paste_cube_invocation(index);
+}
+
+fn paste_cube_invocation(voxel_index: u32) {
+    var state = paste_state(args.size, vec3(0, 0, 0), vec3(0, 0, 0), 0u, false);
+    if !paste_begin(i32(voxel_index), &state) {
         return;
     }
-    if state.src_pos.x < i32(args.size.x) && state.src_pos.y < i32(args.size.y) && state.src_pos.z < i32(args.size.z) {
-        paste_material(&state, args.material);
-    }
-    if state.src_pos.x <= i32(args.size.x) && state.src_pos.y <= i32(args.size.y) && state.src_pos.z <= i32(args.size.z) {
-        paste_vertex(&state, 0u);
+    if all(args.rotation == vec4(0.0, 0.0, 0.0, 1.0)) {
+        if state.src_pos.x < i32(args.size.x) && state.src_pos.y < i32(args.size.y) && state.src_pos.z < i32(args.size.z) {
+            paste_material(&state, args.material);
+        }
+        if state.src_pos.x <= i32(args.size.x) && state.src_pos.y <= i32(args.size.y) && state.src_pos.z <= i32(args.size.z) {
+            paste_vertex(&state, 0u);
+        }
+    } else {
+        if rotated_cube_inside(state.src_pos) {
+            paste_material(&state, args.material);
+        }
+        if rotated_cube_include_vertex(state.src_pos) {
+            paste_vertex(&state, rotated_cube_vertex(state.src_pos));
+        }
     }
     paste_end(&state);
 }
@@ -305,17 +884,28 @@ fn sphere_vertex(pos: vec3<i32>, size: u32) -> u32 {
 // args: {
 //     out_size:    Size of voxel_grid_out
 //     offset:      Offset sphere's coordinates
-//     flags:       Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES.
+//     flags:       Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
 //                  Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+//                  PASTE_ERASE empties occupied voxels instead.
 //     material:    Material to paste
+//     mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
 //     diameter:    Diameter of sphere
 // }
 //
-// This needs ceil(((args.diameter+1) * (args.diameter+1) * (args.diameter+1)) / 64) workgroups.
-@compute @workgroup_size(64)
-fn paste_sphere(@builtin(global_invocation_id) invocation: vec3<u32>) {
-    var state = paste_state(vec3(args.diameter, args.diameter, args.diameter), vec3(0, 0, 0), vec3(0, 0, 0), 0u);
-    if !paste_begin(i32(invocation.x), &state) {
+// This needs ceil(((args.diameter+1) * (args.diameter+1) * (args.diameter+1)) / PASTE_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{PASTE_WORKGROUP_SIZE})
+fn paste_sphere(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = uniform_args;
+    let index = linear_invocation(invocation, num_workgroups, u32(#{PASTE_WORKGROUP_SIZE}));
+    paste_sphere_invocation(index);
+}
+
+fn paste_sphere_invocation(voxel_index: u32) {
+    var state = paste_state(vec3(args.diameter, args.diameter, args.diameter), vec3(0, 0, 0), vec3(0, 0, 0), 0u, false);
+    if !paste_begin(i32(voxel_index), &state) {
         return;
     }
     if sphere_inside(state.src_pos, args.diameter) {
@@ -326,3 +916,1065 @@ fn paste_sphere(@builtin(global_invocation_id) invocation: vec3<u32>) {
     }
     paste_end(&state);
 }
+
+// Paste a sphere at each of instance_offsets. Each invocation handles 1
+// voxel and applies the instances to it in order, so overlapping instances
+// give the same result as pasting them one at a time.
+//
+// args: {
+//      out_size:       size of voxel_grid_out
+//      offset:         lower corner of the instances' bounds
+//      size:           size of the instances' bounds, including the last
+//                      instance's ending padding
+//      diameter:       diameter of sphere
+//      flags:          Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+//      material:       material to paste
+//      mask_material:  with PASTE_MASK, only overwrite empty voxels and voxels with this material
+// }
+//
+// This needs ceil(((args.size.x+1) * (args.size.y+1) * (args.size.z+1)) / PASTE_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{PASTE_WORKGROUP_SIZE})
+fn paste_sphere_instanced(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = uniform_args;
+    let index = linear_invocation(invocation, num_workgroups, u32(#{PASTE_WORKGROUP_SIZE}));
+    var state = paste_state(args.size, vec3(0, 0, 0), vec3(0, 0, 0), 0u, false);
+    if !paste_begin(i32(index), &state) {
+        return;
+    }
+    let diameter = i32(args.diameter);
+    for (var i = 0u; i < arrayLength(&instance_offsets); i += 1u) {
+        let pos = state.dest_pos - instance_offsets[i];
+        if any(pos < vec3(0)) || any(pos > vec3(diameter)) {
+            continue;
+        }
+        // Earlier instances may have changed the voxel
+        paste_check_mask(&state);
+        if sphere_inside(pos, args.diameter) {
+            paste_material(&state, args.material);
+        }
+        if sphere_include_vertex(pos, args.diameter) {
+            paste_vertex(&state, sphere_vertex(pos, args.diameter));
+        }
+    }
+    paste_end(&state);
+}
+
+// Is the voxel's center inside the ellipsoid? The ellipsoid's center is at args.shape_size.
+fn ellipsoid_inside(pos: vec3<i32>) -> bool {
+    let d = (vec3<f32>(pos) + 0.5 - args.shape_size) / args.shape_size;
+    return dot(d, d) < 1.0;
+}
+
+fn ellipsoid_include_vertex(pos: vec3<i32>) -> bool {
+    let count = //
+        u32(ellipsoid_inside(pos + vec3(-1, -1, -1))) + //
+        u32(ellipsoid_inside(pos + vec3(-1, -1, 0))) + //
+        u32(ellipsoid_inside(pos + vec3(-1, 0, -1))) + //
+        u32(ellipsoid_inside(pos + vec3(-1, 0, 0))) + //
+        u32(ellipsoid_inside(pos + vec3(0, -1, -1))) + //
+        u32(ellipsoid_inside(pos + vec3(0, -1, 0))) + //
+        u32(ellipsoid_inside(pos + vec3(0, 0, -1))) + //
+        u32(ellipsoid_inside(pos + vec3(0, 0, 0)));
+    return count != 0u && count != 8u;
+}
+
+// Move a vertex onto the ellipsoid's surface. This takes Newton steps
+// along the gradient of f(p) = (px/rx)^2 + (py/ry)^2 + (pz/rz)^2 - 1,
+// so the vertex moves along the surface's normal.
+fn ellipsoid_vertex(pos: vec3<i32>) -> u32 {
+    let r2 = args.shape_size * args.shape_size;
+    var p = vec3<f32>(pos) - args.shape_size;
+    for (var i = 0; i < 4; i += 1) {
+        let f = dot(p * p, 1.0 / r2) - 1.0;
+        let g = 2.0 * p / r2;
+        let g2 = dot(g, g);
+        if g2 == 0.0 {
+            break;
+        }
+        p -= g * (f / g2);
+    }
+    return encode_corner(p + args.shape_size - vec3<f32>(pos));
+}
+
+// Paste ellipsoid into voxel_grid_out. The ellipsoid will be centered on
+// (args.offset + args.shape_size).
+//
+// args: {
+//     out_size:    Size of voxel_grid_out
+//     offset:      Offset ellipsoid's coordinates
+//     flags:       Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+//                  Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+//                  PASTE_ERASE empties occupied voxels instead.
+//     material:    Material to paste
+//     mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+//     size:        Bounding box; 2 * radii
+//     shape_size:  Radii. Each must be non-0.
+// }
+//
+// This needs ceil(((args.size.x+1) * (args.size.y+1) * (args.size.z+1)) / PASTE_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{PASTE_WORKGROUP_SIZE})
+fn paste_ellipsoid(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = uniform_args;
+    let index = linear_invocation(invocation, num_workgroups, u32(#{PASTE_WORKGROUP_SIZE}));
+    paste_ellipsoid_invocation(index);
+}
+
+fn paste_ellipsoid_invocation(voxel_index: u32) {
+    var state = paste_state(args.size, vec3(0, 0, 0), vec3(0, 0, 0), 0u, false);
+    if !paste_begin(i32(voxel_index), &state) {
+        return;
+    }
+    if ellipsoid_inside(state.src_pos) {
+        paste_material(&state, args.material);
+    }
+    if ellipsoid_include_vertex(state.src_pos) {
+        paste_vertex(&state, ellipsoid_vertex(state.src_pos));
+    }
+    paste_end(&state);
+}
+
+// Encode a corner delta into the 3 offset bytes of a voxel
+fn encode_corner(delta: vec3<f32>) -> u32 {
+    let d = vec3<i32>(clamp(round(delta * 64.0), vec3(-127.0), vec3(127.0)));
+    return (u32(d.x) & 0xffu) | ((u32(d.y) & 0xffu) << 8u) | ((u32(d.z) & 0xffu) << 16u);
+}
+
+// Convert a position to a frame where z runs along axis (0: X, 1: Y, 2: Z)
+fn to_axis_frame(pos: vec3<i32>, axis: u32) -> vec3<i32> {
+    if axis == 0u {
+        return pos.yzx;
+    } else if axis == 1u {
+        return pos.zxy;
+    }
+    return pos;
+}
+
+// Inverse of to_axis_frame
+fn from_axis_frame(v: vec3<f32>, axis: u32) -> vec3<f32> {
+    if axis == 0u {
+        return v.zxy;
+    } else if axis == 1u {
+        return v.yzx;
+    }
+    return v;
+}
+
+fn circle_inside(pos: vec2<i32>, r: f32) -> bool {
+    let d = vec2(f32(pos.x) + 0.5 - r, f32(pos.y) + 0.5 - r);
+    return d.x * d.x + d.y * d.y < r * r;
+}
+
+// pos is in the cylinder's axis frame
+fn cylinder_inside(pos: vec3<i32>) -> bool {
+    return pos.z >= 0 && pos.z < i32(args.height) && circle_inside(pos.xy, f32(args.radius));
+}
+
+// pos is in the cylinder's axis frame
+fn cylinder_include_vertex(pos: vec3<i32>) -> bool {
+    let count = //
+        u32(cylinder_inside(pos + vec3(-1, -1, -1))) + //
+        u32(cylinder_inside(pos + vec3(-1, -1, 0))) + //
+        u32(cylinder_inside(pos + vec3(-1, 0, -1))) + //
+        u32(cylinder_inside(pos + vec3(-1, 0, 0))) + //
+        u32(cylinder_inside(pos + vec3(0, -1, -1))) + //
+        u32(cylinder_inside(pos + vec3(0, -1, 0))) + //
+        u32(cylinder_inside(pos + vec3(0, 0, -1))) + //
+        u32(cylinder_inside(pos + vec3(0, 0, 0)));
+    return count != 0u && count != 8u;
+}
+
+// Move vertexes on the curved side onto the circle. Vertexes on the caps stay put.
+// pos is in the cylinder's axis frame; the result is too.
+fn cylinder_vertex(pos: vec3<i32>) -> vec3<f32> {
+    let r = f32(args.radius);
+    let count = //
+        u32(circle_inside(pos.xy + vec2(-1, -1), r)) + //
+        u32(circle_inside(pos.xy + vec2(-1, 0), r)) + //
+        u32(circle_inside(pos.xy + vec2(0, -1), r)) + //
+        u32(circle_inside(pos.xy + vec2(0, 0), r));
+    if count == 0u || count == 4u {
+        return vec3(0.0, 0.0, 0.0);
+    }
+    let d = vec2(f32(pos.x) - r, f32(pos.y) - r);
+    let factor = r / length(d);
+    return vec3(d * factor - d, 0.0);
+}
+
+// Paste cylinder into voxel_grid_out. The cylinder runs along args.axis,
+// starting at args.offset.
+//
+// args: {
+//     out_size:    Size of voxel_grid_out
+//     offset:      Offset cylinder's coordinates
+//     flags:       Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+//                  Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+//                  PASTE_ERASE empties occupied voxels instead.
+//     material:    Material to paste
+//     mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+//     size:        Bounding box of cylinder
+//     radius:      Radius of cylinder
+//     height:      Height of cylinder
+//     axis:        0: X, 1: Y, 2: Z
+// }
+//
+// This needs ceil(((args.size.x+1) * (args.size.y+1) * (args.size.z+1)) / PASTE_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{PASTE_WORKGROUP_SIZE})
+fn paste_cylinder(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = uniform_args;
+    let index = linear_invocation(invocation, num_workgroups, u32(#{PASTE_WORKGROUP_SIZE}));
+    paste_cylinder_invocation(index);
+}
+
+fn paste_cylinder_invocation(voxel_index: u32) {
+    var state = paste_state(args.size, vec3(0, 0, 0), vec3(0, 0, 0), 0u, false);
+    if !paste_begin(i32(voxel_index), &state) {
+        return;
+    }
+    let pos = to_axis_frame(state.src_pos, args.axis);
+    if cylinder_inside(pos) {
+        paste_material(&state, args.material);
+    }
+    if cylinder_include_vertex(pos) {
+        paste_vertex(&state, encode_corner(from_axis_frame(cylinder_vertex(pos), args.axis)));
+    }
+    paste_end(&state);
+}
+
+// Radius of the cone at height z above its base
+fn cone_radius(z: f32) -> f32 {
+    return f32(args.radius) * (1.0 - z / f32(args.height));
+}
+
+// pos is in the cone's axis frame. Ignores the base and tip if clamp_z is true.
+fn cone_inside_impl(pos: vec3<i32>, clamp_z: bool) -> bool {
+    var z = f32(pos.z) + 0.5;
+    if clamp_z {
+        z = clamp(z, 0.5, f32(args.height) - 0.5);
+    } else if pos.z < 0 || pos.z >= i32(args.height) {
+        return false;
+    }
+    let d = vec2<f32>(pos.xy) + 0.5 - f32(args.radius);
+    let r = cone_radius(z);
+    return dot(d, d) < r * r;
+}
+
+// pos is in the cone's axis frame
+fn cone_inside(pos: vec3<i32>) -> bool {
+    return cone_inside_impl(pos, false);
+}
+
+// pos is in the cone's axis frame
+fn cone_include_vertex(pos: vec3<i32>) -> bool {
+    let count = //
+        u32(cone_inside(pos + vec3(-1, -1, -1))) + //
+        u32(cone_inside(pos + vec3(-1, -1, 0))) + //
+        u32(cone_inside(pos + vec3(-1, 0, -1))) + //
+        u32(cone_inside(pos + vec3(-1, 0, 0))) + //
+        u32(cone_inside(pos + vec3(0, -1, -1))) + //
+        u32(cone_inside(pos + vec3(0, -1, 0))) + //
+        u32(cone_inside(pos + vec3(0, 0, -1))) + //
+        u32(cone_inside(pos + vec3(0, 0, 0)));
+    return count != 0u && count != 8u;
+}
+
+// Move vertexes on the slanted side onto the cone, along the side's normal.
+// Vertexes on the base's rim move outward, staying on the base. Other
+// vertexes on the base stay put. pos is in the cone's axis frame; the
+// result is too.
+fn cone_vertex(pos: vec3<i32>) -> vec3<f32> {
+    let side_count = //
+        u32(cone_inside_impl(pos + vec3(-1, -1, -1), true)) + //
+        u32(cone_inside_impl(pos + vec3(-1, -1, 0), true)) + //
+        u32(cone_inside_impl(pos + vec3(-1, 0, -1), true)) + //
+        u32(cone_inside_impl(pos + vec3(-1, 0, 0), true)) + //
+        u32(cone_inside_impl(pos + vec3(0, -1, -1), true)) + //
+        u32(cone_inside_impl(pos + vec3(0, -1, 0), true)) + //
+        u32(cone_inside_impl(pos + vec3(0, 0, -1), true)) + //
+        u32(cone_inside_impl(pos + vec3(0, 0, 0), true));
+    if side_count == 0u || side_count == 8u {
+        return vec3(0.0, 0.0, 0.0);
+    }
+    let r = f32(args.radius);
+    let h = f32(args.height);
+    let d = vec2<f32>(pos.xy) - r;
+    let rho = length(d);
+    if rho == 0.0 {
+        return vec3(0.0, 0.0, 0.0);
+    }
+    let u = d / rho;
+    if pos.z <= 0 {
+        return vec3(u * (r - rho), 0.0);
+    }
+    // Signed distance from the side, in the (rho, z) plane
+    let l = sqrt(h * h + r * r);
+    let f = (rho * h + f32(pos.z) * r - r * h) / l;
+    let z = f32(pos.z) - f * r / l;
+    if z < 0.0 {
+        return vec3(u * (r - rho), 0.0);
+    }
+    return vec3(-u * (f * h / l), z - f32(pos.z));
+}
+
+// Paste cone into voxel_grid_out. The cone runs along args.axis, with its
+// base at args.offset and its tip args.height away.
+//
+// args: {
+//     out_size:    Size of voxel_grid_out
+//     offset:      Offset cone's coordinates
+//     flags:       Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+//                  Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+//                  PASTE_ERASE empties occupied voxels instead.
+//     material:    Material to paste
+//     mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+//     size:        Bounding box of cone
+//     radius:      Radius of cone's base
+//     height:      Height of cone
+//     axis:        0: X, 1: Y, 2: Z
+// }
+//
+// This needs ceil(((args.size.x+1) * (args.size.y+1) * (args.size.z+1)) / PASTE_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{PASTE_WORKGROUP_SIZE})
+fn paste_cone(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = uniform_args;
+    let index = linear_invocation(invocation, num_workgroups, u32(#{PASTE_WORKGROUP_SIZE}));
+    paste_cone_invocation(index);
+}
+
+fn paste_cone_invocation(voxel_index: u32) {
+    var state = paste_state(args.size, vec3(0, 0, 0), vec3(0, 0, 0), 0u, false);
+    if !paste_begin(i32(voxel_index), &state) {
+        return;
+    }
+    let pos = to_axis_frame(state.src_pos, args.axis);
+    if cone_inside(pos) {
+        paste_material(&state, args.material);
+    }
+    if cone_include_vertex(pos) {
+        paste_vertex(&state, encode_corner(from_axis_frame(cone_vertex(pos), args.axis)));
+    }
+    paste_end(&state);
+}
+
+// Position relative to the torus's center. pos is in the torus's axis frame.
+// args.shape_size.x is the major radius and args.shape_size.y is the minor radius.
+fn torus_local(pos: vec3<f32>) -> vec3<f32> {
+    let major = args.shape_size.x;
+    let minor = args.shape_size.y;
+    return pos - vec3(major + minor, major + minor, minor);
+}
+
+// pos is in the torus's axis frame
+fn torus_inside(pos: vec3<i32>) -> bool {
+    let q = torus_local(vec3<f32>(pos) + 0.5);
+    let d = length(q.xy) - args.shape_size.x;
+    return d * d + q.z * q.z < args.shape_size.y * args.shape_size.y;
+}
+
+// pos is in the torus's axis frame
+fn torus_include_vertex(pos: vec3<i32>) -> bool {
+    let count = //
+        u32(torus_inside(pos + vec3(-1, -1, -1))) + //
+        u32(torus_inside(pos + vec3(-1, -1, 0))) + //
+        u32(torus_inside(pos + vec3(-1, 0, -1))) + //
+        u32(torus_inside(pos + vec3(-1, 0, 0))) + //
+        u32(torus_inside(pos + vec3(0, -1, -1))) + //
+        u32(torus_inside(pos + vec3(0, -1, 0))) + //
+        u32(torus_inside(pos + vec3(0, 0, -1))) + //
+        u32(torus_inside(pos + vec3(0, 0, 0)));
+    return count != 0u && count != 8u;
+}
+
+// Move a vertex to the nearest point on the torus's surface. This moves
+// along the gradient of (sqrt(x^2+y^2)-R)^2 + z^2 - r^2: away from the
+// nearest point on the tube's center circle.
+// pos is in the torus's axis frame; the result is too.
+fn torus_vertex(pos: vec3<i32>) -> vec3<f32> {
+    let q = torus_local(vec3<f32>(pos));
+    let planar = length(q.xy);
+    var ring = vec3(args.shape_size.x, 0.0, 0.0);
+    if planar > 0.0 {
+        ring = vec3(q.xy * (args.shape_size.x / planar), 0.0);
+    }
+    let d = q - ring;
+    let dist = length(d);
+    if dist == 0.0 {
+        return vec3(0.0, 0.0, 0.0);
+    }
+    return d * (args.shape_size.y / dist) - d;
+}
+
+// Paste torus into voxel_grid_out. The torus's hole runs along args.axis.
+// The torus is centered in its bounding box, which starts at args.offset.
+//
+// args: {
+//     out_size:    Size of voxel_grid_out
+//     offset:      Offset torus's coordinates
+//     flags:       Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+//                  Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+//                  PASTE_ERASE empties occupied voxels instead.
+//     material:    Material to paste
+//     mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+//     size:        Bounding box of torus
+//     shape_size:  (major radius, minor radius, unused). The minor radius must be non-0.
+//     axis:        0: X, 1: Y, 2: Z
+// }
+//
+// This needs ceil(((args.size.x+1) * (args.size.y+1) * (args.size.z+1)) / PASTE_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{PASTE_WORKGROUP_SIZE})
+fn paste_torus(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = uniform_args;
+    let index = linear_invocation(invocation, num_workgroups, u32(#{PASTE_WORKGROUP_SIZE}));
+    paste_torus_invocation(index);
+}
+
+fn paste_torus_invocation(voxel_index: u32) {
+    var state = paste_state(args.size, vec3(0, 0, 0), vec3(0, 0, 0), 0u, false);
+    if !paste_begin(i32(voxel_index), &state) {
+        return;
+    }
+    let pos = to_axis_frame(state.src_pos, args.axis);
+    if torus_inside(pos) {
+        paste_material(&state, args.material);
+    }
+    if torus_include_vertex(pos) {
+        paste_vertex(&state, encode_corner(from_axis_frame(torus_vertex(pos), args.axis)));
+    }
+    paste_end(&state);
+}
+
+// Size of the wedge in its axis frame. z is the run and x is the height.
+fn wedge_size() -> vec3<f32> {
+    return vec3<f32>(to_axis_frame(vec3<i32>(args.size), args.axis));
+}
+
+// Is (height, run) below the slope?
+fn wedge_below(height: f32, run: f32, size: vec3<f32>) -> bool {
+    return height * size.z < run * size.x;
+}
+
+// pos is in the wedge's axis frame
+fn wedge_inside(pos: vec3<i32>) -> bool {
+    let size = wedge_size();
+    return all(pos >= vec3(0)) && all(vec3<f32>(pos) < size) && //
+        wedge_below(f32(pos.x) + 0.5, f32(pos.z) + 0.5, size);
+}
+
+// pos is in the wedge's axis frame
+fn wedge_include_vertex(pos: vec3<i32>) -> bool {
+    let count = //
+        u32(wedge_inside(pos + vec3(-1, -1, -1))) + //
+        u32(wedge_inside(pos + vec3(-1, -1, 0))) + //
+        u32(wedge_inside(pos + vec3(-1, 0, -1))) + //
+        u32(wedge_inside(pos + vec3(-1, 0, 0))) + //
+        u32(wedge_inside(pos + vec3(0, -1, -1))) + //
+        u32(wedge_inside(pos + vec3(0, -1, 0))) + //
+        u32(wedge_inside(pos + vec3(0, 0, -1))) + //
+        u32(wedge_inside(pos + vec3(0, 0, 0)));
+    return count != 0u && count != 8u;
+}
+
+// Move vertexes on the sloped face onto its plane. Vertexes on the other faces
+// stay put. The vertex moves along whichever of height or run keeps the move
+// under 1 voxel.
+// pos is in the wedge's axis frame; the result is too.
+fn wedge_vertex(pos: vec3<i32>) -> vec3<f32> {
+    let size = wedge_size();
+    let h = f32(pos.x);
+    let r = f32(pos.z);
+    let count = //
+        u32(wedge_below(h - 0.5, r - 0.5, size)) + //
+        u32(wedge_below(h - 0.5, r + 0.5, size)) + //
+        u32(wedge_below(h + 0.5, r - 0.5, size)) + //
+        u32(wedge_below(h + 0.5, r + 0.5, size));
+    if count == 0u || count == 4u {
+        return vec3(0.0, 0.0, 0.0);
+    }
+    var p = vec3<f32>(pos);
+    if size.x <= size.z {
+        p.x = size.x * r / size.z;
+    } else {
+        p.z = size.z * h / size.x;
+    }
+    return clamp(p, vec3(0.0), size) - vec3<f32>(pos);
+}
+
+// Paste wedge into voxel_grid_out. The wedge is a box cut by a diagonal
+// plane; it rises from 0 height at the start of args.axis to its full height
+// at the end. The height runs along the next axis (Y, Z, X).
+//
+// args: {
+//     out_size:    Size of voxel_grid_out
+//     offset:      Offset wedge's coordinates
+//     flags:       Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+//                  Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+//                  PASTE_ERASE empties occupied voxels instead.
+//     material:    Material to paste
+//     mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+//     size:        Bounding box of wedge. Each component must be non-0.
+//     axis:        0: X, 1: Y, 2: Z
+// }
+//
+// This needs ceil(((args.size.x+1) * (args.size.y+1) * (args.size.z+1)) / PASTE_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{PASTE_WORKGROUP_SIZE})
+fn paste_wedge(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = uniform_args;
+    let index = linear_invocation(invocation, num_workgroups, u32(#{PASTE_WORKGROUP_SIZE}));
+    paste_wedge_invocation(index);
+}
+
+fn paste_wedge_invocation(voxel_index: u32) {
+    var state = paste_state(args.size, vec3(0, 0, 0), vec3(0, 0, 0), 0u, false);
+    if !paste_begin(i32(voxel_index), &state) {
+        return;
+    }
+    let pos = to_axis_frame(state.src_pos, args.axis);
+    if wedge_inside(pos) {
+        paste_material(&state, args.material);
+    }
+    if wedge_include_vertex(pos) {
+        paste_vertex(&state, encode_corner(from_axis_frame(wedge_vertex(pos), args.axis)));
+    }
+    paste_end(&state);
+}
+
+// Is the voxel at region position pos inside both the region and voxel_grid_a, and occupied?
+fn paste_grid_occupied(pos: vec3<i32>) -> bool {
+    let src_pos = pos + args.src_offset;
+    if any(pos < vec3(0)) || any(pos >= vec3<i32>(args.size)) || //
+        any(src_pos < vec3(0)) || any(src_pos >= vec3<i32>(args.a_size)) {
+        return false;
+    }
+    return (raw_voxel_a(src_pos) >> 24u) != 0u;
+}
+
+// Paste a region of voxel_grid_a into voxel_grid_out. voxel_grid_a and
+// voxel_grid_out must be different buffers.
+//
+// args: {
+//     a_size:      Size of voxel_grid_a
+//     out_size:    Size of voxel_grid_out
+//     src_offset:  Lower corner of the region in voxel_grid_a
+//     offset:      Lower corner of the region in voxel_grid_out
+//     size:        Size of the region
+//     flags:       Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+//                  PASTE_ERASE empties voxels which are occupied in voxel_grid_a.
+//     material:    Material to paste if PASTE_MATERIAL_ARG is set
+//     mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+// }
+//
+// This needs ceil(((args.size.x+1) * (args.size.y+1) * (args.size.z+1)) / PASTE_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{PASTE_WORKGROUP_SIZE})
+fn paste_grid(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = uniform_args;
+    let index = linear_invocation(invocation, num_workgroups, u32(#{PASTE_WORKGROUP_SIZE}));
+    var state = paste_state(args.size, vec3(0, 0, 0), vec3(0, 0, 0), 0u, false);
+    if !paste_begin(i32(index), &state) {
+        return;
+    }
+    let pos = state.src_pos;
+    if paste_grid_occupied(pos) {
+        paste_material(&state, raw_voxel_a(pos + args.src_offset) >> 24u);
+    }
+    let count = //
+        u32(paste_grid_occupied(pos + vec3(-1, -1, -1))) + //
+        u32(paste_grid_occupied(pos + vec3(-1, -1, 0))) + //
+        u32(paste_grid_occupied(pos + vec3(-1, 0, -1))) + //
+        u32(paste_grid_occupied(pos + vec3(-1, 0, 0))) + //
+        u32(paste_grid_occupied(pos + vec3(0, -1, -1))) + //
+        u32(paste_grid_occupied(pos + vec3(0, -1, 0))) + //
+        u32(paste_grid_occupied(pos + vec3(0, 0, -1))) + //
+        u32(paste_grid_occupied(pos + vec3(0, 0, 0)));
+    if count != 0u && (args.flags & PASTE_VERTEXES) != 0u {
+        // The vertex may be in voxel_grid_a's ending padding
+        paste_vertex(&state, raw_voxel_a(pos + args.src_offset));
+    }
+    paste_end(&state);
+}
+
+// Height of a heightmap column. 0 outside the heightmap.
+fn heightmap_height(x: i32, y: i32) -> i32 {
+    if x < 0 || y < 0 || x >= i32(args.size.x) || y >= i32(args.size.y) {
+        return 0;
+    }
+    return i32(heights[x + y * i32(args.size.x)]);
+}
+
+// Paste a heightmap into voxel_grid_out. Each column is filled from z=0 up
+// to its height. Z is up.
+//
+// args: {
+//     out_size:    Size of voxel_grid_out
+//     offset:      Offset heightmap's coordinates
+//     flags:       Any of: PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE.
+//     material:    Material to paste
+//     size:        (width, depth, unused)
+// }
+//
+// Each invocation handles 1 column, including the ending padding columns.
+// This needs ceil(((args.size.x+1) * (args.size.y+1)) / PASTE_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{PASTE_WORKGROUP_SIZE})
+fn paste_heightmap(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = uniform_args;
+    let index = linear_invocation(invocation, num_workgroups, u32(#{PASTE_WORKGROUP_SIZE}));
+    let scan_x = i32(args.size.x) + 1;
+    let column = i32(index);
+    if column >= scan_x * (i32(args.size.y) + 1) {
+        return;
+    }
+    let x = column % scan_x;
+    let y = column / scan_x;
+    let height = heightmap_height(x, y);
+
+    // Vertexes on top of neighboring columns
+    let vertex_height = max(
+        max(heightmap_height(x - 1, y - 1), heightmap_height(x - 1, y)),
+        max(heightmap_height(x, y - 1), height)
+    );
+    if vertex_height == 0 {
+        return;
+    }
+
+    for (var z = 0; z <= vertex_height; z += 1) {
+        var state = paste_state(args.size, vec3(x, y, z), vec3(x, y, z) + args.offset, 0u, false);
+        if state.dest_pos.z > i32(args.out_size.z) {
+            break;
+        }
+        // Skip if dest is out of bounds. Allow ending padding.
+        if any(state.dest_pos < vec3(0)) || any(state.dest_pos > vec3<i32>(args.out_size)) {
+            continue;
+        }
+        state.raw = voxel_grid_out[index(args.out_size, state.dest_pos)];
+        paste_check_mask(&state);
+        if z < height {
+            paste_material(&state, args.material);
+        }
+        paste_vertex(&state, 0u);
+        paste_end(&state);
+    }
+}
+
+// Paste a slab into voxel_grid_out. The slab is perpendicular to args.axis
+// and covers the whole grid in the other 2 dimensions. Along the axis, it
+// fills from to_axis_frame(args.offset, args.axis).z up to that plus
+// args.height, clipped to the grid.
+//
+// args: {
+//     out_size:      Size of voxel_grid_out
+//     offset:        Start of the slab, along the axis
+//     axis:          0: X, 1: Y, 2: Z
+//     height:        Thickness of the slab
+//     flags:         Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+//                    Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+//                    PASTE_ERASE empties occupied voxels instead.
+//     material:      Material to paste
+//     mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+// }
+//
+// Each invocation handles 1 column along the axis, including the ending
+// padding columns. With (u, v, _) = to_axis_frame(out_size, axis), this needs
+// ceil(((u+1) * (v+1)) / PASTE_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{PASTE_WORKGROUP_SIZE})
+fn paste_slab(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = uniform_args;
+    let index = linear_invocation(invocation, num_workgroups, u32(#{PASTE_WORKGROUP_SIZE}));
+    let size = to_axis_frame(vec3<i32>(args.out_size), args.axis);
+    let scan_x = size.x + 1;
+    let column = i32(index);
+    if column >= scan_x * (size.y + 1) {
+        return;
+    }
+    let x = column % scan_x;
+    let y = column / scan_x;
+    let begin = to_axis_frame(args.offset, args.axis).z;
+    let end = begin + i32(args.height);
+
+    // Include the vertexes on the slab's far side
+    for (var z = max(begin, 0); z <= min(end, size.z); z += 1) {
+        let dest_pos = vec3<i32>(from_axis_frame(vec3<f32>(vec3(x, y, z)), args.axis));
+        var state = paste_state(args.size, vec3(x, y, z - begin), dest_pos, 0u, false);
+        state.raw = voxel_grid_out[index(args.out_size, state.dest_pos)];
+        paste_check_mask(&state);
+        if z < end {
+            paste_material(&state, args.material);
+        }
+        paste_vertex(&state, 0u);
+        paste_end(&state);
+    }
+}
+
+// Smooth the material field of a region with a 3x3x3 majority filter.
+// voxel_grid_a must be a copy of voxel_grid_out; each pass reads the copy so
+// voxels don't see their neighbors' new values. Corner offsets are kept.
+//
+// args: {
+//     a_size:      Size of voxel_grid_a; the same as out_size
+//     out_size:    Size of voxel_grid_out
+//     offset:      Lower corner of the region. The region must be inside the grid.
+//     size:        Size of the region
+//     threshold:   A voxel is solid if at least this many of the 27 voxels
+//                  around it, including itself, are solid. Its material is
+//                  the most common one among them; ties keep its own material.
+// }
+//
+// This needs ceil((args.size.x * args.size.y * args.size.z) / PASTE_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{PASTE_WORKGROUP_SIZE})
+fn smooth(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = uniform_args;
+    let i = linear_invocation(invocation, num_workgroups, u32(#{PASTE_WORKGROUP_SIZE}));
+    if i >= args.size.x * args.size.y * args.size.z {
+        return;
+    }
+    let pos = args.offset + vec3<i32>(
+        i32(i % args.size.x),
+        i32((i / args.size.x) % args.size.y),
+        i32(i / (args.size.x * args.size.y))
+    );
+
+    var materials: array<u32, 27>;
+    var count = 0u;
+    for (var z = -1; z <= 1; z++) {
+        for (var y = -1; y <= 1; y++) {
+            for (var x = -1; x <= 1; x++) {
+                let m = raw_voxel_a(pos + vec3(x, y, z)) >> 24u;
+                if m != 0u {
+                    materials[count] = m;
+                    count++;
+                }
+            }
+        }
+    }
+
+    let raw = raw_voxel_a(pos);
+    var material = 0u;
+    if f32(count) >= args.threshold {
+        material = raw >> 24u;
+        var best = 0u;
+        for (var j = 0u; j < count; j++) {
+            best += u32(materials[j] == material);
+        }
+        for (var j = 0u; j < count; j++) {
+            var n = 0u;
+            for (var k = 0u; k < count; k++) {
+                n += u32(materials[k] == materials[j]);
+            }
+            if n > best {
+                best = n;
+                material = materials[j];
+            }
+        }
+    }
+    voxel_grid_out[index(args.out_size, pos)] = (raw & 0x00ffffffu) | (material << 24u);
+}
+
+// Replace one material with another in a region of voxel_grid_out. Other
+// voxels and all corner offsets are untouched.
+//
+// args: {
+//     out_size:        Size of voxel_grid_out
+//     offset:          Lower corner of the region. The region must be inside the grid.
+//     size:            Size of the region
+//     mask_material:   Material to replace
+//     material:        Replacement material
+// }
+//
+// This needs ceil((args.size.x * args.size.y * args.size.z) / PASTE_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{PASTE_WORKGROUP_SIZE})
+fn remap_material(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = uniform_args;
+    let i = linear_invocation(invocation, num_workgroups, u32(#{PASTE_WORKGROUP_SIZE}));
+    if i >= args.size.x * args.size.y * args.size.z {
+        return;
+    }
+    let pos = args.offset + vec3<i32>(
+        i32(i % args.size.x),
+        i32((i / args.size.x) % args.size.y),
+        i32(i / (args.size.x * args.size.y))
+    );
+    let j = index(args.out_size, pos);
+    let raw = voxel_grid_out[j];
+    if (raw >> 24u) == args.mask_material {
+        voxel_grid_out[j] = (raw & 0x00ffffffu) | (args.material << 24u);
+    }
+}
+
+// Nearest point on the line's segment. Positions are relative to args.offset.
+fn line_nearest(pos: vec3<f32>) -> vec3<f32> {
+    let a = args.shape_center;
+    let ab = args.shape_size - a;
+    let len2 = dot(ab, ab);
+    var t = 0.0;
+    if len2 > 0.0 {
+        t = clamp(dot(pos - a, ab) / len2, 0.0, 1.0);
+    }
+    return a + ab * t;
+}
+
+fn line_inside(pos: vec3<i32>) -> bool {
+    let p = vec3<f32>(pos) + 0.5;
+    let d = p - line_nearest(p);
+    let r = f32(args.radius);
+    return dot(d, d) < r * r;
+}
+
+fn line_include_vertex(pos: vec3<i32>) -> bool {
+    let count = //
+        u32(line_inside(pos + vec3(-1, -1, -1))) + //
+        u32(line_inside(pos + vec3(-1, -1, 0))) + //
+        u32(line_inside(pos + vec3(-1, 0, -1))) + //
+        u32(line_inside(pos + vec3(-1, 0, 0))) + //
+        u32(line_inside(pos + vec3(0, -1, -1))) + //
+        u32(line_inside(pos + vec3(0, -1, 0))) + //
+        u32(line_inside(pos + vec3(0, 0, -1))) + //
+        u32(line_inside(pos + vec3(0, 0, 0)));
+    return count != 0u && count != 8u;
+}
+
+// Move a vertex onto the capsule's surface, along the capsule's normal
+fn line_vertex(pos: vec3<i32>) -> u32 {
+    let p = vec3<f32>(pos);
+    let q = line_nearest(p);
+    let d = p - q;
+    let dist = length(d);
+    if dist == 0.0 {
+        return 0u;
+    }
+    return encode_corner(q + d * (f32(args.radius) / dist) - p);
+}
+
+// Paste a line into voxel_grid_out. This fills a capsule around the segment
+// from args.shape_center to args.shape_size.
+//
+// args: {
+//     out_size:    Size of voxel_grid_out
+//     offset:      Lower corner of the capsule's bounding box
+//     flags:       Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+//                  Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+//                  PASTE_ERASE empties occupied voxels instead.
+//     material:    Material to paste
+//     mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+//     size:        Size of the capsule's bounding box
+//     radius:      Radius of the capsule
+//     shape_center: Start of the segment, relative to offset
+//     shape_size:  End of the segment, relative to offset
+// }
+//
+// This needs ceil(((args.size.x+1) * (args.size.y+1) * (args.size.z+1)) / PASTE_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{PASTE_WORKGROUP_SIZE})
+fn paste_line(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = uniform_args;
+    let index = linear_invocation(invocation, num_workgroups, u32(#{PASTE_WORKGROUP_SIZE}));
+    paste_line_invocation(index);
+}
+
+fn paste_line_invocation(voxel_index: u32) {
+    var state = paste_state(args.size, vec3(0, 0, 0), vec3(0, 0, 0), 0u, false);
+    if !paste_begin(i32(voxel_index), &state) {
+        return;
+    }
+    if line_inside(state.src_pos) {
+        paste_material(&state, args.material);
+    }
+    if line_include_vertex(state.src_pos) {
+        paste_vertex(&state, line_vertex(state.src_pos));
+    }
+    paste_end(&state);
+}
+
+// Hash a lattice point to [0, 1). This is lowbias32 applied to the
+// coordinates and seed, so it only uses 32-bit integer math and gives
+// the same result on every GPU.
+fn noise_hash(cell: vec3<i32>, seed: u32) -> f32 {
+    var h = seed ^ (bitcast<u32>(cell.x) * 0x8da6b343u) ^ //
+        (bitcast<u32>(cell.y) * 0xd8163841u) ^ //
+        (bitcast<u32>(cell.z) * 0xcb1ab31fu);
+    h ^= h >> 16u;
+    h *= 0x7feb352du;
+    h ^= h >> 15u;
+    h *= 0x846ca68bu;
+    h ^= h >> 16u;
+    return f32(h >> 8u) / 16777216.0;
+}
+
+// 3D value noise in [0, 1). Each lattice point gets noise_hash; points
+// between are trilinearly interpolated using the smoothstep curve
+// 3t^2 - 2t^3. The noise function won't change within a major release,
+// so the same seed and frequency fill the same voxels.
+fn noise(p: vec3<f32>, seed: u32) -> f32 {
+    let cell = vec3<i32>(floor(p));
+    let f = p - floor(p);
+    let t = f * f * (3.0 - 2.0 * f);
+    let c000 = noise_hash(cell + vec3(0, 0, 0), seed);
+    let c100 = noise_hash(cell + vec3(1, 0, 0), seed);
+    let c010 = noise_hash(cell + vec3(0, 1, 0), seed);
+    let c110 = noise_hash(cell + vec3(1, 1, 0), seed);
+    let c001 = noise_hash(cell + vec3(0, 0, 1), seed);
+    let c101 = noise_hash(cell + vec3(1, 0, 1), seed);
+    let c011 = noise_hash(cell + vec3(0, 1, 1), seed);
+    let c111 = noise_hash(cell + vec3(1, 1, 1), seed);
+    let x00 = mix(c000, c100, t.x);
+    let x10 = mix(c010, c110, t.x);
+    let x01 = mix(c001, c101, t.x);
+    let x11 = mix(c011, c111, t.x);
+    return mix(mix(x00, x10, t.y), mix(x01, x11, t.y), t.z);
+}
+
+// Noise is sampled at the voxel's center in voxel_grid_out's coordinates,
+// so adjacent pastes with the same arguments line up.
+fn noise_inside(pos: vec3<i32>) -> bool {
+    if any(pos < vec3(0)) || any(pos >= vec3<i32>(args.size)) {
+        return false;
+    }
+    let p = (vec3<f32>(pos + args.offset) + 0.5) * args.frequency;
+    return noise(p, args.seed) > args.threshold;
+}
+
+fn noise_include_vertex(pos: vec3<i32>) -> bool {
+    let count = //
+        u32(noise_inside(pos + vec3(-1, -1, -1))) + //
+        u32(noise_inside(pos + vec3(-1, -1, 0))) + //
+        u32(noise_inside(pos + vec3(-1, 0, -1))) + //
+        u32(noise_inside(pos + vec3(-1, 0, 0))) + //
+        u32(noise_inside(pos + vec3(0, -1, -1))) + //
+        u32(noise_inside(pos + vec3(0, -1, 0))) + //
+        u32(noise_inside(pos + vec3(0, 0, -1))) + //
+        u32(noise_inside(pos + vec3(0, 0, 0)));
+    return count != 0u;
+}
+
+// Fill voxels in voxel_grid_out where noise is above args.threshold. Filled
+// voxels get 0 offsets.
+//
+// args: {
+//     out_size:    Size of voxel_grid_out
+//     offset:      Lower corner of the region to fill
+//     flags:       Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+//                  Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+//                  PASTE_ERASE empties occupied voxels instead.
+//     material:    Material to paste
+//     mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+//     size:        Size of the region to fill
+//     seed:        Selects the noise pattern
+//     frequency:   Noise cells per voxel
+//     threshold:   Fill where noise > threshold
+// }
+//
+// This needs ceil(((args.size.x+1) * (args.size.y+1) * (args.size.z+1)) / PASTE_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{PASTE_WORKGROUP_SIZE})
+fn paste_noise(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = uniform_args;
+    let index = linear_invocation(invocation, num_workgroups, u32(#{PASTE_WORKGROUP_SIZE}));
+    var state = paste_state(args.size, vec3(0, 0, 0), vec3(0, 0, 0), 0u, false);
+    if !paste_begin(i32(index), &state) {
+        return;
+    }
+    if noise_inside(state.src_pos) {
+        paste_material(&state, args.material);
+    }
+    if noise_include_vertex(state.src_pos) {
+        paste_vertex(&state, 0u);
+    }
+    paste_end(&state);
+}
+
+// Paste several shapes into voxel_grid_out in 1 dispatch. Each entry in
+// batch_args holds the args for 1 shape; args.shape selects the shape and
+// the other fields are the same as the shape's entry point. The shapes run
+// concurrently, so they must not overlap, including the ending padding
+// each one writes vertexes into.
+//
+// workgroup_id.y selects the entry in batch_args. This needs x * z workgroups
+// to cover the largest shape and y = arrayLength(&batch_args).
+@compute @workgroup_size(#{PASTE_WORKGROUP_SIZE})
+fn paste_batch(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(workgroup_id) workgroup: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    args = batch_args[workgroup.y];
+    let shape = args.shape;
+    // y selects the shape, so x and z cover the shape's invocations
+    let index = invocation.x + invocation.z * num_workgroups.x * u32(#{PASTE_WORKGROUP_SIZE});
+    if shape == SHAPE_CUBE {
+        paste_cube_invocation(index);
+    } else if shape == SHAPE_SPHERE {
+        paste_sphere_invocation(index);
+    } else if shape == SHAPE_ELLIPSOID {
+        paste_ellipsoid_invocation(index);
+    } else if shape == SHAPE_CYLINDER {
+        paste_cylinder_invocation(index);
+    } else if shape == SHAPE_TORUS {
+        paste_torus_invocation(index);
+    } else if shape == SHAPE_WEDGE {
+        paste_wedge_invocation(index);
+    } else if shape == SHAPE_LINE {
+        paste_line_invocation(index);
+    } else if shape == SHAPE_CONE {
+        paste_cone_invocation(index);
+    }
+}
+
+// Copy the materials of a packed grid, voxel_grid_a, into a split grid's
+// material buffer, voxel_grid_out. Each u32 of voxel_grid_out holds 4
+// materials, with the first voxel in the LSBs; padding is included. The
+// offsets aren't touched; copy the packed grid into the split grid's offset
+// buffer for those.
+//
+// Each invocation fills 1 entry of voxel_grid_out. This needs
+// ceil(arrayLength(&voxel_grid_out) / PASTE_WORKGROUP_SIZE) workgroups.
+@compute @workgroup_size(#{PASTE_WORKGROUP_SIZE})
+fn split_materials(
+    @builtin(global_invocation_id) invocation: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    let i = linear_invocation(invocation, num_workgroups, u32(#{PASTE_WORKGROUP_SIZE}));
+    if i >= arrayLength(&voxel_grid_out) {
+        return;
+    }
+    var materials = 0u;
+    for (var j = 0u; j < 4u; j += 1u) {
+        let k = i * 4u + j;
+        if k < arrayLength(&voxel_grid_a) {
+            materials |= (voxel_grid_a[k] >> 24u) << (j * 8u);
+        }
+    }
+    voxel_grid_out[i] = materials;
+}
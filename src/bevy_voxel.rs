@@ -1,45 +1,100 @@
 use bevy::{
+    ecs::system::EntityCommands,
     prelude::*,
-    reflect::TypePath,
+    reflect::{TypePath, TypeUuid},
     render::{
         extract_component::{ExtractComponent, ExtractComponentPlugin},
+        mesh::{Indices, MeshVertexAttribute, VertexAttributeValues},
+        primitives::{Aabb, Frustum},
         render_graph::{self, RenderGraph},
         render_resource::{
-            BindGroupLayout, CachedComputePipelineId, ComputePipelineDescriptor, PipelineCache,
+            BindGroupLayout, CachedComputePipelineId, CachedPipelineState,
+            ComputePipelineDescriptor, PipelineCache, ShaderDefVal, VertexFormat,
         },
-        renderer::{RenderContext, RenderDevice},
+        renderer::{RenderContext, RenderDevice, RenderQueue},
         Render, RenderApp, RenderSet,
     },
+    utils::{Duration, Instant},
 };
 use parking_lot::{Mutex, MutexGuard};
 use std::{
     borrow::Cow,
     collections::HashMap,
+    future::Future,
     mem::take,
     ops::{Deref, DerefMut},
-    sync::atomic::{self, AtomicUsize},
+    pin::Pin,
+    sync::atomic::{self, AtomicI32, AtomicUsize},
     sync::Arc,
+    task::{Context, Poll, Waker},
 };
-use wgpu::PrimitiveTopology;
+use wgpu::{BufferAsyncError, PrimitiveTopology};
 
-use crate::command::*;
+use crate::{
+    chunk::ChunkedGrid,
+    command::*,
+    voxel::{unstable::*, *},
+};
+
+/// Handle of the shader, which [VoxelPlugin] adds from [VOX_WGSL]. No
+/// assets need to be copied into the app.
+pub const VOX_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x5d3c_6f0a_91e2_4b17);
 
-pub struct VoxelPlugin;
+/// Runs voxel command lists and converts their results into meshes.
+///
+/// The workgroup sizes are passed to the shader as the `MESH_WORKGROUP_SIZE`
+/// and `PASTE_WORKGROUP_SIZE` shader defs. They apply to the whole process;
+/// see [set_workgroup_sizes].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoxelPlugin {
+    /// Workgroup size of generate_mesh
+    pub mesh_workgroup_size: u32,
+
+    /// Workgroup size of the paste functions
+    pub paste_workgroup_size: u32,
+}
+
+impl Default for VoxelPlugin {
+    fn default() -> Self {
+        Self {
+            mesh_workgroup_size: DEFAULT_MESH_WORKGROUP_SIZE,
+            paste_workgroup_size: DEFAULT_PASTE_WORKGROUP_SIZE,
+        }
+    }
+}
 
 impl Plugin for VoxelPlugin {
     fn build(&self, app: &mut App) {
+        set_workgroup_sizes(self.mesh_workgroup_size, self.paste_workgroup_size);
+        app.world
+            .resource_mut::<Assets<Shader>>()
+            .set_untracked(VOX_SHADER_HANDLE, Shader::from_wgsl(VOX_WGSL, "vox.wgsl"));
         app.add_plugins(ExtractComponentPlugin::<VoxelCommandList>::default());
         app.add_plugins(ExtractComponentPlugin::<GenerateMesh>::default());
-        app.add_systems(First, finalize_generate_mesh);
+        app.init_resource::<MaterialPalette>();
+        app.init_resource::<GridRegistry>();
+        app.add_event::<MeshGenerated>();
+        let status = VoxelPipelineStatus::default();
+        app.insert_resource(status.clone());
+        app.add_systems(
+            First,
+            (
+                finalize_generate_mesh,
+                finalize_generate_collider,
+                fail_timed_out_command_lists,
+            ),
+        );
 
         let render_app = app.sub_app_mut(RenderApp);
+        render_app.insert_resource(status);
         render_app.add_systems(Render, prepare_command_list.in_set(RenderSet::Prepare));
         render_app.add_systems(Render, map_commands.in_set(RenderSet::Cleanup));
 
         let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
-        render_graph.add_node("voxel_command_lists", VoxelCommandListsNode);
+        render_graph.add_node(VOXEL_COMMAND_LISTS_NODE, VoxelCommandListsNode);
         render_graph.add_node_edge(
-            "voxel_command_lists",
+            VOXEL_COMMAND_LISTS_NODE,
             bevy::render::main_graph::node::CAMERA_DRIVER,
         );
     }
@@ -50,6 +105,78 @@ impl Plugin for VoxelPlugin {
     }
 }
 
+/// Whether the shader's pipelines compiled. [VoxelPlugin] adds this
+/// resource to the app; the render world shares it.
+///
+/// While the pipelines are pending or failed, command lists stay
+/// [CommandListState::Busy]; use [VoxelCommandList::new_with_timeout]
+/// to fail them instead.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct VoxelPipelineStatus(Arc<Mutex<Option<String>>>);
+
+impl VoxelPipelineStatus {
+    /// The first pipeline compile error, e.g. a WGSL error in a custom
+    /// entry point, or None if there wasn't one.
+    pub fn pipeline_error(&self) -> Option<String> {
+        self.0.lock().clone()
+    }
+}
+
+/// Name of the render graph node which runs the command lists. It runs
+/// before `CAMERA_DRIVER`.
+pub const VOXEL_COMMAND_LISTS_NODE: &str = "voxel_command_lists";
+
+/// Where a custom render graph node runs relative to the command lists.
+/// See [VoxelRenderGraphExt::add_voxel_compute_node].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VoxelComputeStage {
+    /// Before [VOXEL_COMMAND_LISTS_NODE]
+    BeforeCommandLists,
+
+    /// After [VOXEL_COMMAND_LISTS_NODE] and before `CAMERA_DRIVER`
+    AfterCommandLists,
+}
+
+/// Add custom compute nodes, e.g. a cellular automaton pass which binds
+/// [VoxelGrid::buffer], to the render graph in order with the command lists.
+///
+/// A node runs before or after all command lists. To run a pass between
+/// commands in a list, such as between a [GeometryCommand] and a
+/// [GenerateMeshCommand], implement [VoxelCommand] instead; the list's
+/// passes are encoded in order.
+pub trait VoxelRenderGraphExt {
+    /// Add a node to the render app's graph. Add [VoxelPlugin] first.
+    fn add_voxel_compute_node<T: render_graph::Node>(
+        &mut self,
+        name: &'static str,
+        node: T,
+        stage: VoxelComputeStage,
+    ) -> &mut Self;
+}
+
+impl VoxelRenderGraphExt for App {
+    fn add_voxel_compute_node<T: render_graph::Node>(
+        &mut self,
+        name: &'static str,
+        node: T,
+        stage: VoxelComputeStage,
+    ) -> &mut Self {
+        let render_app = self.sub_app_mut(RenderApp);
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node(name, node);
+        match stage {
+            VoxelComputeStage::BeforeCommandLists => {
+                render_graph.add_node_edge(name, VOXEL_COMMAND_LISTS_NODE);
+            }
+            VoxelComputeStage::AfterCommandLists => {
+                render_graph.add_node_edge(VOXEL_COMMAND_LISTS_NODE, name);
+                render_graph.add_node_edge(name, bevy::render::main_graph::node::CAMERA_DRIVER);
+            }
+        }
+        self
+    }
+}
+
 /// A list of commands that can be run on the GPU.
 ///
 /// This acts as a handle; clones point to the same list.
@@ -63,27 +190,110 @@ impl VoxelCommandList {
         Self(Arc::new(CommandListData {
             state: CommandListState::Init.into(),
             commands: commands.into(),
+            ..Default::default()
+        }))
+    }
+
+    /// Create a command list which fails if a run doesn't reach Done within
+    /// the timeout, e.g. because a buffer never finished mapping. The run
+    /// switches to `Failed`, so [run_again] can retry. Results which arrive
+    /// after the timeout are still delivered to the commands' callbacks,
+    /// but don't change the state.
+    pub fn new_with_timeout(commands: VoxelCommandVec, timeout: Duration) -> Self {
+        Self(Arc::new(CommandListData {
+            state: CommandListState::Init.into(),
+            commands: commands.into(),
+            timeout: Some(timeout),
+            ..Default::default()
         }))
     }
 
     /// Get mutable access to the commands. Returns None if the command list
-    /// is not in the Init or Done state.
+    /// is not in the Init, Done, or Failed state.
     ///
     /// This locks the list's mutex; the guard keeps it locked until dropped.
     pub fn commands_mut(&self) -> Option<CommandGuard<'_>> {
         let guard = self.0.lock();
-        if *guard.state == CommandListState::Init || *guard.state == CommandListState::Done {
-            Some(guard)
-        } else {
-            None
+        match *guard.state {
+            CommandListState::Init | CommandListState::Done | CommandListState::Failed(_) => {
+                Some(guard)
+            }
+            _ => None,
         }
     }
 
+    /// Set the order. Lists which start in the same frame run in increasing
+    /// order; lists with the same order run in an unspecified order. Use this
+    /// when a list reads a grid which another list writes. Defaults to 0.
+    pub fn with_order(self, order: i32) -> Self {
+        self.set_order(order);
+        self
+    }
+
+    /// Set the order. See [with_order]. This takes effect the next time
+    /// the list starts.
+    ///
+    /// This doesn't lock the list's mutex.
+    pub fn set_order(&self, order: i32) {
+        self.0.order.store(order, atomic::Ordering::Release);
+    }
+
+    /// Get the order. See [with_order].
+    ///
+    /// This doesn't lock the list's mutex.
+    pub fn order(&self) -> i32 {
+        self.0.order.load(atomic::Ordering::Acquire)
+    }
+
     /// Get the current state.
     ///
     /// This locks the list's Mutex.
     pub fn state(&self) -> CommandListState {
-        *self.0.state.lock()
+        self.0.state.lock().clone()
+    }
+
+    /// Get (commands whose pass has been encoded, total commands) for the
+    /// current or most recent run. Both are 0 before the first run.
+    ///
+    /// This doesn't lock the list's mutex.
+    pub fn progress(&self) -> (usize, usize) {
+        (
+            self.0.encoded.load(atomic::Ordering::Acquire),
+            self.0.total.load(atomic::Ordering::Acquire),
+        )
+    }
+
+    /// Number of commands in the current or most recent run whose results
+    /// haven't been delivered yet, e.g. because their buffers are still
+    /// mapping. 0 before the list reaches the Mapping state. Use this with
+    /// [progress] to tell whether a stuck list is waiting on the GPU.
+    ///
+    /// This doesn't lock the list's mutex.
+    pub fn pending_maps(&self) -> usize {
+        self.0.pending_maps.lock().load(atomic::Ordering::Acquire)
+    }
+
+    /// Wait for the command list to reach the Done or Failed state. Resolves
+    /// immediately if it's already in one of those states. Use [run_again]
+    /// before calling this to wait for the next run.
+    ///
+    /// This is an alternative to polling [state]. Polling the future locks the
+    /// list's mutex.
+    pub fn on_done(&self) -> impl Future<Output = Result<(), BufferAsyncError>> {
+        CommandListDone(self.0.clone())
+    }
+
+    /// GPU memory held by the commands' own buffers, in bytes. See
+    /// [VoxelCommand::gpu_bytes]; grids aren't included.
+    ///
+    /// This locks the list's mutex.
+    pub fn gpu_bytes(&self) -> u64 {
+        self.0
+            .commands
+            .lock()
+            .iter()
+            .map(|command| command.gpu_bytes())
+            .sum()
     }
 
     /// Switch the command list to the Init state and return true.
@@ -92,9 +302,33 @@ impl VoxelCommandList {
     /// This locks the list's mutex.
     pub fn run_again(&self) -> bool {
         let mut guard = self.0.state.lock();
-        if *guard == CommandListState::Done {
+        if matches!(*guard, CommandListState::Done | CommandListState::Failed(_)) {
+            *guard = CommandListState::Init;
+        }
+        *guard == CommandListState::Init
+    }
+
+    /// Like [run_again], but skips the run if key matches the key passed
+    /// to the last call which started a run, and that run didn't fail.
+    /// Hash the inputs the commands were built from, e.g. brush positions,
+    /// into key. Returns true if the list is in the Init state.
+    ///
+    /// The whole list runs or is skipped. Commands can't be skipped one by
+    /// one, since a grid holds the results of the commands before them.
+    ///
+    /// This locks the list's mutex.
+    pub fn run_if_changed(&self, key: u64) -> bool {
+        let mut guard = self.0.state.lock();
+        let mut last_key = self.0.last_key.lock();
+        if *last_key == Some(key) && !matches!(*guard, CommandListState::Failed(_)) {
+            return *guard == CommandListState::Init;
+        }
+        if matches!(*guard, CommandListState::Done | CommandListState::Failed(_)) {
             *guard = CommandListState::Init;
         }
+        if *guard == CommandListState::Init {
+            *last_key = Some(key);
+        }
         *guard == CommandListState::Init
     }
 }
@@ -121,7 +355,7 @@ impl<'a> DerefMut for CommandGuard<'a> {
 }
 
 /// State a command list can be in.
-#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub enum CommandListState {
     /// The command list is ready to be run.
     #[default]
@@ -135,59 +369,669 @@ pub enum CommandListState {
 
     /// The command list is done.
     Done,
+
+    /// The command list is done, but at least one buffer failed to map.
+    /// Commands whose buffers mapped successfully still delivered their results.
+    /// Also used when a run exceeds the timeout from
+    /// [VoxelCommandList::new_with_timeout].
+    Failed(BufferAsyncError),
 }
 
+/// Per-vertex material of the voxel which produced each face. Meshes
+/// created by [GenerateMesh] have this attribute.
+pub const ATTRIBUTE_VOXEL_MATERIAL: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_VoxelMaterial", 0x6d61_7465, VertexFormat::Uint32);
+
+/// Per-vertex ambient occlusion; a darkening factor in the range `[0.4, 1.0]`.
+/// Meshes created by [GenerateMesh] have this attribute if ambient occlusion
+/// is enabled.
+pub const ATTRIBUTE_VOXEL_AO: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_VoxelAo", 0x616f_6163, VertexFormat::Float32);
+
+/// Per-vertex face direction, one of the `FACE_DIRECTION_*` constants.
+/// Meshes created by [GenerateMesh] have this attribute if
+/// [MeshBuilder::directions] is set.
+pub const ATTRIBUTE_VOXEL_FACE_DIRECTION: MeshVertexAttribute = MeshVertexAttribute::new(
+    "Vertex_VoxelFaceDirection",
+    0x6469_7265,
+    VertexFormat::Uint32,
+);
+
+/// Builds a [`bevy::prelude::Mesh`] from [MeshData], with the attributes
+/// which were requested. Positions are always included, and so are the
+/// triangle indexes if the data has them. Vertex colors come later, from
+/// [MaterialPalette::apply].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshBuilder {
+    /// Include [`Mesh::ATTRIBUTE_NORMAL`]
+    pub normals: bool,
+
+    /// Include [ATTRIBUTE_VOXEL_MATERIAL]. [MaterialPalette] needs this.
+    pub materials: bool,
+
+    /// Include [`Mesh::ATTRIBUTE_UV_0`]
+    pub uvs: bool,
+
+    /// Include [ATTRIBUTE_VOXEL_AO]. [GenerateMesh] sets this from
+    /// [GenerateMesh::with_ambient_occlusion].
+    pub ambient_occlusion: bool,
+
+    /// Include [ATTRIBUTE_VOXEL_FACE_DIRECTION]
+    pub directions: bool,
+}
+
+impl Default for MeshBuilder {
+    fn default() -> Self {
+        Self {
+            normals: true,
+            materials: true,
+            uvs: true,
+            ambient_occlusion: false,
+            directions: false,
+        }
+    }
+}
+
+impl MeshBuilder {
+    pub fn new() -> Self {
+        default()
+    }
+
+    pub fn with_normals(mut self, normals: bool) -> Self {
+        self.normals = normals;
+        self
+    }
+
+    pub fn with_materials(mut self, materials: bool) -> Self {
+        self.materials = materials;
+        self
+    }
+
+    pub fn with_uvs(mut self, uvs: bool) -> Self {
+        self.uvs = uvs;
+        self
+    }
+
+    pub fn with_ambient_occlusion(mut self, ambient_occlusion: bool) -> Self {
+        self.ambient_occlusion = ambient_occlusion;
+        self
+    }
+
+    pub fn with_directions(mut self, directions: bool) -> Self {
+        self.directions = directions;
+        self
+    }
+
+    /// Build a `TriangleList` mesh
+    pub fn build(&self, data: MeshData) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, data.vertexes);
+        if self.normals {
+            mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, data.normals);
+        }
+        if self.materials {
+            mesh.insert_attribute(ATTRIBUTE_VOXEL_MATERIAL, data.materials);
+        }
+        if self.uvs {
+            mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, data.uvs);
+        }
+        if self.ambient_occlusion {
+            mesh.insert_attribute(ATTRIBUTE_VOXEL_AO, data.ao);
+        }
+        if self.directions {
+            mesh.insert_attribute(ATTRIBUTE_VOXEL_FACE_DIRECTION, data.directions);
+        }
+        if let Some(indexes) = data.indexes {
+            mesh.set_indices(Some(Indices::U32(indexes)));
+        }
+        mesh
+    }
+
+    /// Build a `LineList` mesh of the faces' outlines. See [MeshData::face_edges].
+    /// Use [Self::build_triangle_wireframe] for marching cubes meshes.
+    pub fn build_wireframe(&self, data: &MeshData) -> Mesh {
+        line_list_mesh(data.face_edges())
+    }
+
+    /// Build a `LineList` mesh of every triangle's edges. See
+    /// [MeshData::triangle_edges].
+    pub fn build_triangle_wireframe(&self, data: &MeshData) -> Mesh {
+        line_list_mesh(data.triangle_edges())
+    }
+} // impl MeshBuilder
+
+fn line_list_mesh((positions, lines): (Vec<Vec3>, Vec<u32>)) -> Mesh {
+    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.set_indices(Some(Indices::U32(lines)));
+    mesh
+}
+
+/// Receives the wireframe mesh from [GenerateMesh::with_wireframe]. Use the
+/// entity commands to attach it, e.g. by spawning a child.
+pub type GenerateWireframeCallback = Arc<dyn Fn(&mut EntityCommands, Handle<Mesh>) + Send + Sync>;
+
 /// Generate a mesh from a voxel grid.
 ///
 /// `[create_command]` creates a command that can be added to a command list.
 /// This command will generate a mesh from the given voxel grid. After the
 /// command list is run, this component will convert it to a `[bevy::prelude::Mesh]`
 /// and add it to the entity.
-#[derive(Component, Default, Clone, Debug, TypePath, ExtractComponent)]
+///
+/// The mesh's vertex colors come from the [MaterialPalette] resource.
+///
+/// Every successful run replaces the entity's mesh, even when the grid has
+/// no faces, so carving everything away doesn't leave stale geometry
+/// behind. The first run adds a mesh asset; later runs update that asset
+/// in place instead of adding another. A mesh with an empty [`Mesh::ATTRIBUTE_POSITION`] is valid for
+/// the PBR pipeline; it draws nothing.
+#[derive(Component, Default, Clone, TypePath, ExtractComponent)]
 #[component(storage = "SparseSet")]
-pub struct GenerateMesh(Arc<Mutex<Option<Mesh>>>);
+pub struct GenerateMesh {
+    mesh: Arc<Mutex<Option<(Mesh, Vec<UVec3>, Option<Mesh>)>>>,
+    options: GenerateMeshOptions,
+    builder: MeshBuilder,
+    buffer_pool: GenerateMeshBufferPool,
+    wireframe: Option<GenerateWireframeCallback>,
+    compactor: Option<MeshCompactor>,
+
+    // Assets from the first generation; later ones update them in place
+    handle: Option<Handle<Mesh>>,
+    wireframe_handle: Option<Handle<Mesh>>,
+}
+
+impl std::fmt::Debug for GenerateMesh {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("GenerateMesh")
+            .field("mesh", &self.mesh)
+            .field("options", &self.options)
+            .field("builder", &self.builder)
+            .field("buffer_pool", &self.buffer_pool)
+            .field("wireframe", &self.wireframe.is_some())
+            .field("compactor", &self.compactor.is_some())
+            .field("handle", &self.handle)
+            .field("wireframe_handle", &self.wireframe_handle)
+            .finish()
+    }
+}
 
 impl GenerateMesh {
     pub fn new() -> Self {
         default()
     }
 
+    /// Generate an indexed mesh. This shares vertexes between faces.
+    pub fn with_indexed(mut self, indexed: bool) -> Self {
+        self.options.indexed = indexed;
+        self
+    }
+
+    /// Merge adjacent coplanar faces with the same material into larger quads.
+    pub fn with_greedy(mut self, greedy: bool) -> Self {
+        self.options.greedy = greedy;
+        self
+    }
+
+    /// Darken vertex colors using ambient occlusion.
+    pub fn with_ambient_occlusion(mut self, ambient_occlusion: bool) -> Self {
+        self.options.ambient_occlusion = ambient_occlusion;
+        self
+    }
+
+    /// Average normals where faces share a vertex, for smooth shading.
+    pub fn with_smooth(mut self, smooth: bool) -> Self {
+        self.options.smooth = smooth;
+        self
+    }
+
+    /// Size of a voxel in world units. Defaults to 1.0.
+    pub fn with_voxel_scale(mut self, voxel_scale: f32) -> Self {
+        self.options.voxel_scale = voxel_scale;
+        self
+    }
+
+    /// Ignore the voxels' corner offsets, for a blocky look with axis-aligned normals.
+    pub fn with_ignore_offsets(mut self, ignore_offsets: bool) -> Self {
+        self.options.ignore_offsets = ignore_offsets;
+        self
+    }
+
+    /// Generate a smooth surface with marching cubes instead of voxel faces.
+    /// See [GenerateMeshOptions::marching_cubes].
+    pub fn with_marching_cubes(mut self, marching_cubes: bool) -> Self {
+        self.options.marching_cubes = marching_cubes;
+        self
+    }
+
+    /// Generate the grid's outer faces even if the padding has material.
+    /// See [GenerateMeshOptions::close_boundary].
+    pub fn with_close_boundary(mut self, close_boundary: bool) -> Self {
+        self.options.close_boundary = close_boundary;
+        self
+    }
+
+    /// Merge nearly flat faces with greedy meshing. See
+    /// [GenerateMeshOptions::offset_merge_epsilon].
+    pub fn with_offset_merge_epsilon(mut self, offset_merge_epsilon: f32) -> Self {
+        self.options.offset_merge_epsilon = offset_merge_epsilon;
+        self
+    }
+
+    /// Emit clockwise triangles. See [GenerateMeshOptions::flip_winding].
+    pub fn with_flip_winding(mut self, flip_winding: bool) -> Self {
+        self.options.flip_winding = flip_winding;
+        self
+    }
+
+    /// Only generate the faces of voxels in the box at min with the given
+    /// size. The result replaces this entity's whole mesh; it isn't merged
+    /// into the mesh from an earlier run. Use one entity per region, and
+    /// regenerate the entities whose region changed. See
+    /// [GenerateMeshOptions::region].
+    pub fn with_region(mut self, min: UVec3, size: UVec3) -> Self {
+        self.options.region = Some((min, size));
+        self
+    }
+
+    /// Materials which are transparent. Faces are generated between
+    /// different opacity classes. See [GenerateMeshOptions::transparent_mask].
+    pub fn with_transparent_mask(mut self, transparent_mask: [bool; 256]) -> Self {
+        self.options.transparent_mask = transparent_mask;
+        self
+    }
+
+    /// Choose which attributes the mesh gets. The builder's ambient_occlusion
+    /// is replaced by [Self::with_ambient_occlusion]'s setting.
+    pub fn with_mesh_builder(mut self, builder: MeshBuilder) -> Self {
+        self.builder = builder;
+        self
+    }
+
+    /// Also generate a `LineList` mesh of the faces' outlines and pass it to
+    /// the callback each time the mesh is generated. This shows the voxel
+    /// structure without `POLYGON_MODE_LINE`, which WebGPU lacks.
+    /// With marching cubes, it outlines every triangle instead.
+    pub fn with_wireframe(mut self, callback: GenerateWireframeCallback) -> Self {
+        self.wireframe = Some(callback);
+        self
+    }
+
+    /// Only read back the filled faces. Create the compactor with
+    /// [MeshCompactor::from_render]. See [GenerateMeshCommand::with_compaction].
+    pub fn with_compaction(mut self, compactor: MeshCompactor) -> Self {
+        self.compactor = Some(compactor);
+        self
+    }
+
+    /// GPU memory used by the buffers kept between runs, in bytes
+    pub fn gpu_bytes(&self) -> u64 {
+        self.buffer_pool.gpu_bytes()
+    }
+
     pub fn create_command(&self, grid: SharedVoxelGrid) -> GenerateMeshCommand {
-        let shared_mesh = self.0.clone();
-        GenerateMeshCommand::new(
+        let shared_mesh = self.mesh.clone();
+        let builder = self
+            .builder
+            .with_ambient_occlusion(self.options.ambient_occlusion);
+        let wireframe = self.wireframe.is_some();
+        let marching_cubes = self.options.marching_cubes;
+        let mut command = GenerateMeshCommand::new(
             grid,
-            Arc::new(move |vertexes, normals| {
-                let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+            Arc::new(move |mut data| {
+                let wireframe = wireframe.then(|| {
+                    if marching_cubes {
+                        builder.build_triangle_wireframe(&data)
+                    } else {
+                        builder.build_wireframe(&data)
+                    }
+                });
                 // println!("** GenerateMeshCommand: callback");
-                // println!("{:?}\n", vertexes);
-                // println!("{:?}", vertexes);
-                mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertexes);
-                mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-                *shared_mesh.lock() = Some(mesh);
+                // println!("{:?}\n", data.vertexes);
+                // println!("{:?}", data.vertexes);
+                let face_voxels = take(&mut data.face_voxels);
+                *shared_mesh.lock() = Some((builder.build(data), face_voxels, wireframe));
+            }),
+        )
+        .with_options(self.options)
+        .with_buffer_pool(self.buffer_pool.clone());
+        command.compactor = self.compactor.clone();
+        command
+    }
+}
+
+/// Sent when [GenerateMesh] inserts a new mesh into an entity. The
+/// mesh's components are already inserted when systems in `PreUpdate`
+/// or later read this event.
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshGenerated {
+    /// Entity which has the [GenerateMesh] component
+    pub entity: Entity,
+}
+
+/// Voxel which produced each face of the mesh [GenerateMesh] added. Each
+/// face is 2 triangles, so triangle t came from voxel `self.0[t / 2]`.
+/// Use this to map a raycast hit on the mesh back to a voxel.
+#[derive(Component, Default, Clone, Debug, PartialEq, Eq)]
+pub struct MeshFaceVoxels(pub Vec<UVec3>);
+
+impl MeshFaceVoxels {
+    /// Get the voxel which produced a triangle, or None if it's out of range
+    pub fn triangle_voxel(&self, triangle: usize) -> Option<UVec3> {
+        self.0.get(triangle / 2).copied()
+    }
+}
+
+/// Receives a collider's vertex positions and triangles. Use the entity
+/// commands to insert the collider, e.g. `Collider::trimesh`.
+pub type GenerateColliderCallback =
+    Arc<dyn Fn(&mut EntityCommands, Vec<Vec3>, Vec<[u32; 3]>) + Send + Sync>;
+
+/// Generate a trimesh collider's data from a voxel grid.
+///
+/// `[create_command]` creates a command that can be added to a command list.
+/// This command will generate a mesh from the given voxel grid. After the
+/// command list is run, this component calls the callback with the mesh's
+/// positions and triangles. This doesn't depend on a physics crate; the
+/// callback creates the collider.
+#[derive(Component, Clone)]
+#[component(storage = "SparseSet")]
+pub struct GenerateCollider {
+    result: Arc<Mutex<Option<MeshData>>>,
+    callback: GenerateColliderCallback,
+    options: GenerateMeshOptions,
+    buffer_pool: GenerateMeshBufferPool,
+}
+
+impl GenerateCollider {
+    pub fn new(callback: GenerateColliderCallback) -> Self {
+        Self {
+            result: default(),
+            callback,
+            options: GenerateMeshOptions {
+                indexed: true,
+                ..default()
+            },
+            buffer_pool: default(),
+        }
+    }
+
+    /// Merge adjacent coplanar faces into larger quads. This reduces the
+    /// triangle count.
+    pub fn with_greedy(mut self, greedy: bool) -> Self {
+        self.options.greedy = greedy;
+        self
+    }
+
+    /// Size of a voxel in world units. Use the same scale as [GenerateMesh]
+    /// so the collider matches the mesh. Defaults to 1.0.
+    pub fn with_voxel_scale(mut self, voxel_scale: f32) -> Self {
+        self.options.voxel_scale = voxel_scale;
+        self
+    }
+
+    pub fn create_command(&self, grid: SharedVoxelGrid) -> GenerateMeshCommand {
+        let result = self.result.clone();
+        GenerateMeshCommand::new(
+            grid,
+            Arc::new(move |data| {
+                *result.lock() = Some(data);
             }),
         )
+        .with_options(self.options)
+        .with_buffer_pool(self.buffer_pool.clone())
+    }
+}
+
+impl MeshCompactor {
+    /// Create a compactor which uses Bevy's render device and queue. See
+    /// [GenerateMesh::with_compaction].
+    pub fn from_render(render_device: &RenderDevice, render_queue: &RenderQueue) -> Self {
+        let render_device = render_device.clone();
+        Self::with_device_fn(
+            move |f| f(render_device.wgpu_device()),
+            render_queue.0.clone(),
+        )
+    }
+}
+
+impl VoxelCommandListBuilder {
+    /// Add a command which generates generate_mesh's mesh from the grid.
+    /// See [GenerateMesh::create_command].
+    pub fn generate_mesh(self, grid: SharedVoxelGrid, generate_mesh: &GenerateMesh) -> Self {
+        let command = generate_mesh.create_command(grid.clone());
+        self.use_grid(&grid).push(command)
+    }
+}
+
+impl ChunkedGrid {
+    /// Keys of the created chunks whose bounds intersect the frustum, e.g.
+    /// to only mesh and draw what a camera sees. transform maps world voxel
+    /// coordinates to world space, including any voxel scale. Each chunk's
+    /// bounds include a 2-voxel margin, since corner offsets can move its
+    /// vertexes up to 2 voxels.
+    pub fn visible_chunks<'a>(
+        &'a self,
+        frustum: &'a Frustum,
+        transform: &GlobalTransform,
+    ) -> impl Iterator<Item = IVec3> + 'a {
+        const MARGIN: f32 = 2.0;
+        let model_to_world = transform.affine();
+        let size = self.chunk_size().as_vec3();
+        self.chunks().filter_map(move |(key, _)| {
+            let min = self.chunk_origin(key).as_vec3() - MARGIN;
+            let aabb = Aabb::from_min_max(min, min + size + 2.0 * MARGIN);
+            frustum
+                .intersects_obb(&aabb, &model_to_world, true, true)
+                .then_some(key)
+        })
+    }
+}
+
+/// Id of a grid in [GridRegistry]. Ids aren't reused.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GridId(u64);
+
+/// Looks up grids by [GridId], so UI code and other systems can find a
+/// grid without holding its [SharedVoxelGrid]. [VoxelPlugin] adds this
+/// resource. Commands still take a [SharedVoxelGrid]; use [GridRegistry::get]
+/// when building them.
+#[derive(Resource, Debug, Default)]
+pub struct GridRegistry {
+    grids: HashMap<GridId, SharedVoxelGrid>,
+    next_id: u64,
+}
+
+impl GridRegistry {
+    /// Add a grid and return its new id. A grid may be registered more
+    /// than once; each registration gets its own id.
+    pub fn register(&mut self, grid: SharedVoxelGrid) -> GridId {
+        let id = GridId(self.next_id);
+        self.next_id += 1;
+        self.grids.insert(id, grid);
+        id
+    }
+
+    /// Get a grid. Returns None if the id was removed.
+    pub fn get(&self, id: GridId) -> Option<&SharedVoxelGrid> {
+        self.grids.get(&id)
+    }
+
+    /// Remove a grid and return it. Returns None if the id was already removed.
+    pub fn remove(&mut self, id: GridId) -> Option<SharedVoxelGrid> {
+        self.grids.remove(&id)
+    }
+
+    /// Iterate over the registered grids, in an unspecified order
+    pub fn iter(&self) -> impl Iterator<Item = (GridId, &SharedVoxelGrid)> {
+        self.grids.iter().map(|(id, grid)| (*id, grid))
+    }
+}
+
+/// Maps voxel materials to colors. [GenerateMesh] uses this to fill
+/// [`Mesh::ATTRIBUTE_COLOR`]. Changing the palette recolors existing
+/// meshes without regenerating them.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct MaterialPalette {
+    colors: [Color; 256],
+}
+
+impl Default for MaterialPalette {
+    /// Material 0 (empty) is transparent; the rest are white.
+    fn default() -> Self {
+        let mut colors = [Color::WHITE; 256];
+        colors[0] = Color::NONE;
+        Self { colors }
+    }
+}
+
+impl MaterialPalette {
+    /// Get a material's color
+    pub fn get(&self, material: u8) -> Color {
+        self.colors[material as usize]
+    }
+
+    /// Set a material's color
+    pub fn set(&mut self, material: u8, color: Color) {
+        self.colors[material as usize] = color;
+    }
+
+    /// Fill the mesh's [`Mesh::ATTRIBUTE_COLOR`] from its [ATTRIBUTE_VOXEL_MATERIAL].
+    /// If the mesh has [ATTRIBUTE_VOXEL_AO], then the colors are darkened by it.
+    /// Does nothing if the mesh doesn't have materials.
+    pub fn apply(&self, mesh: &mut Mesh) {
+        let Some(VertexAttributeValues::Uint32(materials)) =
+            mesh.attribute(ATTRIBUTE_VOXEL_MATERIAL)
+        else {
+            return;
+        };
+        let ao = match mesh.attribute(ATTRIBUTE_VOXEL_AO) {
+            Some(VertexAttributeValues::Float32(ao)) => Some(ao),
+            _ => None,
+        };
+        let colors: Vec<[f32; 4]> = materials
+            .iter()
+            .enumerate()
+            .map(|(i, m)| {
+                let [r, g, b, a] = self.get(*m as u8).as_linear_rgba_f32();
+                let factor = ao.map_or(1.0, |ao| ao[i]);
+                [r * factor, g * factor, b * factor, a]
+            })
+            .collect();
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
     }
 }
 
 fn finalize_generate_mesh(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut query: Query<(Entity, &GenerateMesh)>,
+    palette: Res<MaterialPalette>,
+    mut events: EventWriter<MeshGenerated>,
+    mut query: Query<(Entity, &mut GenerateMesh, Option<&Handle<Mesh>>)>,
 ) {
-    for (entity, generate_mesh) in query.iter_mut() {
-        let Some(mesh) = generate_mesh.0.lock().take() else {
+    // Update the asset in place if it still exists, otherwise add it
+    fn update(
+        meshes: &mut Assets<Mesh>,
+        handle: &mut Option<Handle<Mesh>>,
+        mesh: Mesh,
+    ) -> Handle<Mesh> {
+        if let Some(existing) = handle.as_ref().and_then(|h| meshes.get_mut(h)) {
+            *existing = mesh;
+        } else {
+            *handle = Some(meshes.add(mesh));
+        }
+        handle.clone().unwrap()
+    }
+
+    for (entity, mut generate_mesh, handle) in query.iter_mut() {
+        let Some((mut mesh, face_voxels, wireframe)) = generate_mesh.mesh.lock().take() else {
+            if palette.is_changed() {
+                if let Some(mesh) = handle.and_then(|handle| meshes.get_mut(handle)) {
+                    palette.apply(mesh);
+                }
+            }
             continue;
         };
         // println!("** finalize_generate_mesh");
-        commands.entity(entity).insert(meshes.add(mesh));
+        // Empty meshes are inserted too; this clears the old geometry
+        palette.apply(&mut mesh);
+        let generate_mesh = &mut *generate_mesh;
+        let mesh_handle = update(&mut meshes, &mut generate_mesh.handle, mesh);
+        commands
+            .entity(entity)
+            .insert((mesh_handle, MeshFaceVoxels(face_voxels)));
+        if let (Some(wireframe), Some(callback)) = (wireframe, &generate_mesh.wireframe) {
+            let wireframe_handle =
+                update(&mut meshes, &mut generate_mesh.wireframe_handle, wireframe);
+            callback(&mut commands.entity(entity), wireframe_handle);
+        }
+        events.send(MeshGenerated { entity });
+    }
+}
+
+fn fail_timed_out_command_lists(query: Query<&VoxelCommandList>) {
+    for command_list in query.iter() {
+        let data = &command_list.0;
+        let Some(timeout) = data.timeout else {
+            continue;
+        };
+        let state = data.state.lock();
+        if !matches!(*state, CommandListState::Busy | CommandListState::Mapping) {
+            continue;
+        }
+        if data
+            .started
+            .lock()
+            .map_or(false, |started| started.elapsed() >= timeout)
+        {
+            // println!("** fail_timed_out_command_lists: timed out");
+            data.run.fetch_add(1, atomic::Ordering::AcqRel);
+            data.finish_locked(state, CommandListState::Failed(BufferAsyncError));
+        }
+    }
+}
+
+fn finalize_generate_collider(mut commands: Commands, query: Query<(Entity, &GenerateCollider)>) {
+    for (entity, generate_collider) in query.iter() {
+        let Some(data) = generate_collider.result.lock().take() else {
+            continue;
+        };
+        let (positions, triangles) = data.trimesh();
+        (generate_collider.callback)(&mut commands.entity(entity), positions, triangles);
     }
 }
 
 #[derive(Default)]
 struct CommandListData {
-    // lock order: commands, state
+    // lock order: commands, state, wakers
     commands: Mutex<VoxelCommandVec>,
     state: Mutex<CommandListState>,
+
+    // Progress of the current run
+    encoded: AtomicUsize,
+    total: AtomicUsize,
+
+    // Lists which start in the same frame run in increasing order
+    order: AtomicI32,
+
+    // Incremented when a run starts or times out. Map callbacks from older
+    // runs don't change state. Change while holding state.
+    run: AtomicUsize,
+
+    // Fail a run which takes longer than this
+    timeout: Option<Duration>,
+
+    // When the current run started. Lock after state.
+    started: Mutex<Option<Instant>>,
+
+    // Woken when the list reaches Done or Failed. Lock after state.
+    wakers: Mutex<Vec<Waker>>,
+
+    // Key of the last run started by run_if_changed. Lock after state.
+    last_key: Mutex<Option<u64>>,
+
+    // Callbacks the current run is waiting for. Each run gets its own
+    // counter, so late callbacks from an older run don't change it.
+    pending_maps: Mutex<Arc<AtomicUsize>>,
 }
 
 impl CommandListData {
@@ -196,6 +1040,55 @@ impl CommandListData {
         let state = self.state.lock();
         CommandGuard { state, commands }
     }
+
+    // Switch to Done or Failed and wake any futures waiting on it
+    fn finish(&self, new_state: CommandListState) {
+        self.finish_locked(self.state.lock(), new_state);
+    }
+
+    // Like finish, but does nothing if another run started or this one timed out
+    fn finish_run(&self, run: usize, new_state: CommandListState) {
+        let state = self.state.lock();
+        if self.run.load(atomic::Ordering::Acquire) == run {
+            self.finish_locked(state, new_state);
+        }
+    }
+
+    fn finish_locked(
+        &self,
+        mut state: MutexGuard<'_, CommandListState>,
+        new_state: CommandListState,
+    ) {
+        *state = new_state;
+        let wakers = take(&mut *self.wakers.lock());
+        drop(state);
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+// Future returned by VoxelCommandList::on_done
+struct CommandListDone(SharedCommandListData);
+
+impl Future for CommandListDone {
+    type Output = Result<(), BufferAsyncError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Hold state while registering so finish() can't miss the waker
+        let state = self.0.state.lock();
+        match &*state {
+            CommandListState::Done => Poll::Ready(Ok(())),
+            CommandListState::Failed(e) => Poll::Ready(Err(e.clone())),
+            _ => {
+                let mut wakers = self.0.wakers.lock();
+                if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                    wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+        }
+    }
 }
 
 type SharedCommandListData = Arc<CommandListData>;
@@ -222,9 +1115,28 @@ fn prepare_command_list(
                 }
             });
         }
+        command_list.0.encoded.store(0, atomic::Ordering::Release);
+        command_list
+            .0
+            .total
+            .store(guard.commands.len(), atomic::Ordering::Release);
         *guard.state = CommandListState::Busy;
-        pipeline.command_lists.push(command_list.0.clone());
+        command_list.0.run.fetch_add(1, atomic::Ordering::AcqRel);
+        *command_list.0.started.lock() = Some(Instant::now());
+        *command_list.0.pending_maps.lock() = default();
+        // A list which timed out while Busy may still be here from its last run
+        if !pipeline
+            .command_lists
+            .iter()
+            .any(|l| Arc::ptr_eq(l, &command_list.0))
+        {
+            pipeline.command_lists.push(command_list.0.clone());
+        }
     }
+    // Stable, so lists with the same order keep the query's order
+    pipeline
+        .command_lists
+        .sort_by_key(|command_list| command_list.order.load(atomic::Ordering::Acquire));
 }
 
 fn map_commands(mut pipeline: ResMut<CommandPipeline>) {
@@ -236,30 +1148,48 @@ fn map_commands(mut pipeline: ResMut<CommandPipeline>) {
         if *state != CommandListState::Busy {
             continue;
         };
+        if command_list.encoded.load(atomic::Ordering::Acquire) < commands.len() {
+            // The pipelines weren't ready; try again next frame
+            drop(state);
+            drop(commands);
+            pipeline.command_lists.push(command_list);
+            continue;
+        }
         *state = CommandListState::Mapping;
 
         drop(state); // avoid deadlock inside callback
 
         if commands.len() == 0 {
             // println!("** map_commands: commands.len() == 0");
-            *command_list.state.lock() = CommandListState::Done;
+            command_list.finish(CommandListState::Done);
         } else {
             let count = Arc::new(AtomicUsize::new(commands.len()));
+            *command_list.pending_maps.lock() = count.clone();
+            let error = Arc::new(Mutex::new(None));
+            let run = command_list.run.load(atomic::Ordering::Acquire);
             // println!(
             //     "** map_commands: starting count: {}",
             //     count.load(atomic::Ordering::Relaxed)
             // );
             let callback = {
                 let command_list = command_list.clone();
-                move |_res| {
-                    // TODO: handle map error
-                    // println!("mapped?: {:?}", _res);
+                move |res: Result<(), BufferAsyncError>| {
+                    // println!("mapped?: {:?}", res);
                     // println!(
                     //     "** map_commands: callback {:?}",
                     //     count.load(atomic::Ordering::Relaxed)
                     // );
-                    if count.fetch_sub(1, atomic::Ordering::Relaxed) == 1 {
-                        *command_list.state.lock() = CommandListState::Done;
+                    if let Err(e) = res {
+                        error.lock().get_or_insert(e);
+                    }
+                    if count.fetch_sub(1, atomic::Ordering::AcqRel) == 1 {
+                        command_list.finish_run(
+                            run,
+                            match error.lock().take() {
+                                Some(e) => CommandListState::Failed(e),
+                                None => CommandListState::Done,
+                            },
+                        );
                     }
                 }
             };
@@ -280,13 +1210,14 @@ struct LayoutAndPipeline {
 struct CommandPipeline {
     map: HashMap<&'static str, LayoutAndPipeline>,
     command_lists: Vec<SharedCommandListData>,
+    status: VoxelPipelineStatus,
 }
 
 impl FromWorld for CommandPipeline {
     fn from_world(world: &mut World) -> Self {
         let device = world.resource::<RenderDevice>().wgpu_device();
         let pipeline_cache = world.resource::<PipelineCache>();
-        let shader = world.resource::<AssetServer>().load("shaders/vox.wgsl");
+        let shader: Handle<Shader> = VOX_SHADER_HANDLE.typed();
         let mut map = HashMap::new();
 
         let mut create_pipeline = |entry_point: &'static str, layout: wgpu::BindGroupLayout| {
@@ -296,26 +1227,24 @@ impl FromWorld for CommandPipeline {
                 layout: vec![layout.clone()],
                 push_constant_ranges: Vec::new(),
                 shader: shader.clone(),
-                shader_defs: vec![],
+                shader_defs: vec![
+                    ShaderDefVal::UInt("MESH_WORKGROUP_SIZE".into(), mesh_workgroup_size()),
+                    ShaderDefVal::UInt("PASTE_WORKGROUP_SIZE".into(), paste_workgroup_size()),
+                ],
                 entry_point: Cow::from(entry_point),
             });
             map.insert(entry_point, LayoutAndPipeline { layout, pipeline });
         };
-        create_pipeline(
-            GenerateMeshCommand::ENTRY_POINT,
-            GenerateMeshCommand::bind_group_layout(device),
-        );
-        create_pipeline(
-            GeometryCommand::PASTE_CUBE_ENTRY_POINT,
-            GeometryCommand::bind_group_layout(device),
-        );
-        create_pipeline(
-            GeometryCommand::PASTE_SPHERE_ENTRY_POINT,
-            GeometryCommand::bind_group_layout(device),
-        );
+        for &entry_point in geometry_entry_points() {
+            create_pipeline(
+                entry_point,
+                entry_point_bind_group_layout(device, entry_point).unwrap(),
+            );
+        }
         Self {
             map,
             command_lists: default(),
+            status: world.resource::<VoxelPipelineStatus>().clone(),
         }
     }
 }
@@ -332,6 +1261,29 @@ impl render_graph::Node for VoxelCommandListsNode {
     ) -> Result<(), render_graph::NodeRunError> {
         let pipeline_cache = world.resource::<PipelineCache>();
         let pipeline = world.resource::<CommandPipeline>();
+
+        // The pipelines all come from one shader, so they're ready at about
+        // the same time. Until then, the lists stay Busy and map_commands
+        // keeps them for the next frame.
+        let mut ready = true;
+        for (name, entry) in pipeline.map.iter() {
+            match pipeline_cache.get_compute_pipeline_state(entry.pipeline) {
+                CachedPipelineState::Ok(_) => (),
+                CachedPipelineState::Err(e) => {
+                    let mut error = pipeline.status.0.lock();
+                    if error.is_none() {
+                        error!("voxel pipeline {} failed: {}", name, e);
+                        *error = Some(format!("{}: {}", name, e));
+                    }
+                    ready = false;
+                }
+                _ => ready = false,
+            }
+        }
+        if !ready {
+            return Ok(());
+        }
+
         let encoder = render_context.command_encoder();
         for command_list in pipeline.command_lists.iter() {
             let guard = command_list.lock();
@@ -341,13 +1293,14 @@ impl render_graph::Node for VoxelCommandListsNode {
             for command in guard.commands.iter() {
                 command.add_pass(encoder, &mut |name| {
                     if let Some(entry) = pipeline.map.get(name) {
-                        // TODO: handle pipeline not yet available
+                        // Checked above
                         pipeline_cache.get_compute_pipeline(entry.pipeline).unwrap()
                     } else {
                         panic!("Unknown pipeline in commands: {}", name)
                     }
                 });
                 command.add_copy(encoder);
+                command_list.encoded.fetch_add(1, atomic::Ordering::AcqRel);
             }
         }
 
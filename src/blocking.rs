@@ -0,0 +1,188 @@
+//! Run the shaders without Bevy. These submit the work then block until
+//! the GPU finishes, so they're meant for tools, tests, and asset baking,
+//! not for use inside a render loop.
+//!
+//! Not available on wasm; the browser completes buffer mapping, so the
+//! thread can't block waiting for it.
+
+use glam::Vec3;
+use std::{borrow::Cow, sync::mpsc};
+use wgpu::{
+    BindGroupLayout, BufferAsyncError, CommandEncoderDescriptor, ComputePipeline,
+    ComputePipelineDescriptor, Device, Maintain, PipelineLayoutDescriptor, Queue,
+    ShaderModuleDescriptor, ShaderSource,
+};
+
+use crate::{
+    command::*,
+    voxel::{unstable::*, *},
+};
+
+pub use crate::voxel::shader_source;
+
+/// Compile the shader and create a pipeline for one of its entry points
+pub fn create_pipeline(
+    device: &Device,
+    entry_point: &str,
+    bind_group_layout: &BindGroupLayout,
+) -> ComputePipeline {
+    let module = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some("vox.wgsl"),
+        source: ShaderSource::Wgsl(Cow::Owned(shader_source())),
+    });
+    let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(entry_point),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some(entry_point),
+        layout: Some(&layout),
+        module: &module,
+        entry_point,
+    })
+}
+
+/// Generate a mesh from the grid. Returns the vertex positions and normals;
+/// each 3 vertexes are a triangle.
+///
+/// This compiles the shader each call. Use [generate_mesh_data_blocking]
+/// with a pipeline from [create_pipeline] to mesh many grids.
+///
+/// Panics if the mesh couldn't be read back.
+pub fn generate_mesh_blocking(
+    device: &Device,
+    queue: &Queue,
+    grid: &VoxelGrid,
+) -> (Vec<Vec3>, Vec<Vec3>) {
+    let layout = generate_mesh_bind_group_layout(device);
+    let pipeline = create_pipeline(device, GENERATE_MESH_ENTRY_POINT, &layout);
+    let mesh =
+        generate_mesh_data_blocking(device, queue, &pipeline, &layout, grid, &Default::default());
+    (mesh.vertexes, mesh.normals)
+}
+
+/// Generate a mesh from the grid, formatted according to options.
+///
+/// * pipeline:          From [create_pipeline] with [GENERATE_MESH_ENTRY_POINT], or
+///                      [GENERATE_MESH_MARCHING_CUBES_ENTRY_POINT] if options.marching_cubes, or
+///                      [GENERATE_MESH_REGION_ENTRY_POINT] if options.region
+/// * bind_group_layout: From [generate_mesh_bind_group_layout]; the one the pipeline uses
+///
+/// Panics if the mesh couldn't be read back.
+pub fn generate_mesh_data_blocking(
+    device: &Device,
+    queue: &Queue,
+    pipeline: &ComputePipeline,
+    bind_group_layout: &BindGroupLayout,
+    grid: &VoxelGrid,
+    options: &GenerateMeshOptions,
+) -> MeshData {
+    let cmd_impl = GenerateMeshImpl::with_options(device, bind_group_layout, grid, None, options);
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("generate_mesh_blocking"),
+    });
+    cmd_impl.add_pass(pipeline, &mut encoder);
+    cmd_impl.add_copy(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    let (sender, receiver) = mpsc::channel();
+    cmd_impl.async_map_buffer(move |cmd_impl, res| {
+        let _ = sender.send((cmd_impl, res));
+    });
+    device.poll(Maintain::Wait);
+    let (cmd_impl, res) = receiver
+        .recv()
+        .expect("generate_mesh_blocking: buffer wasn't mapped");
+    res.expect("generate_mesh_blocking: failed to map buffer");
+    cmd_impl.get_mesh_data(options)
+}
+
+/// Like [generate_mesh_data_blocking], but only read back the filled faces.
+/// This takes 2 submissions; see [CompactMeshImpl].
+///
+/// * pipeline:                  From [create_pipeline] with [GENERATE_MESH_ENTRY_POINT], or
+///                              [GENERATE_MESH_REGION_ENTRY_POINT] if options.region
+/// * bind_group_layout:         From [generate_mesh_bind_group_layout]; the one the pipeline uses
+/// * compact_pipeline:          From [create_pipeline] with [COMPACT_MESH_ENTRY_POINT]
+/// * compact_bind_group_layout: From [compact_mesh_bind_group_layout]; the one compact_pipeline uses
+///
+/// Panics if options.marching_cubes is set, or if the mesh couldn't be read back.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_compact_mesh_data_blocking(
+    device: &Device,
+    queue: &Queue,
+    pipeline: &ComputePipeline,
+    bind_group_layout: &BindGroupLayout,
+    compact_pipeline: &ComputePipeline,
+    compact_bind_group_layout: &BindGroupLayout,
+    grid: &VoxelGrid,
+    options: &GenerateMeshOptions,
+) -> MeshData {
+    let cmd_impl = GenerateMeshImpl::with_options(device, bind_group_layout, grid, None, options);
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("generate_compact_mesh_blocking"),
+    });
+    cmd_impl.add_pass(pipeline, &mut encoder);
+    cmd_impl.add_copy_face_filled(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    let (sender, receiver) = mpsc::channel();
+    cmd_impl.async_map_face_filled(move |cmd_impl, res| {
+        let _ = sender.send((cmd_impl, res));
+    });
+    device.poll(Maintain::Wait);
+    let (cmd_impl, res) = receiver
+        .recv()
+        .expect("generate_compact_mesh_blocking: face_filled wasn't mapped");
+    res.expect("generate_compact_mesh_blocking: failed to map face_filled");
+
+    let compact_impl = cmd_impl.compact(device, compact_bind_group_layout);
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("generate_compact_mesh_blocking"),
+    });
+    compact_impl.add_pass(compact_pipeline, &mut encoder);
+    compact_impl.add_copy(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    let (sender, receiver) = mpsc::channel();
+    compact_impl.async_map_buffer(move |compact_impl, res| {
+        let _ = sender.send((compact_impl, res));
+    });
+    device.poll(Maintain::Wait);
+    let (compact_impl, res) = receiver
+        .recv()
+        .expect("generate_compact_mesh_blocking: buffer wasn't mapped");
+    res.expect("generate_compact_mesh_blocking: failed to map buffer");
+    compact_impl.get_mesh_data(options)
+}
+
+/// Run a command list: prepare the commands, record and submit their work,
+/// then wait for their results. Returns the first buffer mapping error, if
+/// any; commands whose buffers mapped still delivered their results.
+///
+/// This compiles the shader each call. To run many lists, keep a
+/// [CommandRunner] and poll the device yourself.
+pub fn run_command_list(
+    device: &Device,
+    queue: &Queue,
+    commands: &mut VoxelCommandVec,
+) -> Result<(), BufferAsyncError> {
+    let runner = CommandRunner::new(device);
+    let (sender, receiver) = mpsc::channel();
+    runner.run(device, queue, commands, move |res| {
+        let _ = sender.send(res);
+    });
+    // A command may submit more work while it finishes, e.g.
+    // GenerateMeshCommand with compaction, so poll until the result arrives
+    loop {
+        device.poll(Maintain::Wait);
+        match receiver.try_recv() {
+            Ok(res) => return res,
+            Err(mpsc::TryRecvError::Empty) => continue,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                panic!("run_command_list: commands didn't finish")
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use glam::{IVec3, UVec3};
+use std::collections::{hash_map, HashMap};
+
+use crate::command::*;
+
+/// A world made of fixed-size chunks, each in its own [SharedVoxelGrid].
+/// Chunk `key` covers world voxels `key * chunk_size` through
+/// `(key + 1) * chunk_size - 1`. Chunks are created when something is
+/// pasted into them.
+///
+/// Paste functions return commands for every chunk the shape overlaps,
+/// with offsets adjusted to each chunk. They include a chunk if the shape
+/// touches its ending padding, so neighboring chunks agree on the offsets
+/// of the vertexes they share. Meshes are generated per chunk; run
+/// [ChunkedGrid::sync_padding]'s command first so they don't have faces
+/// on the chunk boundaries.
+#[derive(Debug, Clone)]
+pub struct ChunkedGrid {
+    chunk_size: UVec3,
+    chunks: HashMap<IVec3, SharedVoxelGrid>,
+}
+
+impl ChunkedGrid {
+    /// Panics if any component of chunk_size is 0
+    pub fn new(chunk_size: UVec3) -> Self {
+        assert!(
+            chunk_size.cmpgt(UVec3::ZERO).all(),
+            "Chunk size must be non-0"
+        );
+        Self {
+            chunk_size,
+            chunks: HashMap::new(),
+        }
+    }
+
+    /// Size of each chunk, excluding padding
+    pub fn chunk_size(&self) -> UVec3 {
+        self.chunk_size
+    }
+
+    /// Key of the chunk which contains the world voxel
+    pub fn chunk_key(&self, world_pos: IVec3) -> IVec3 {
+        world_pos.div_euclid(self.chunk_size.as_ivec3())
+    }
+
+    /// World coordinates of the chunk's `0,0,0` voxel
+    pub fn chunk_origin(&self, key: IVec3) -> IVec3 {
+        key * self.chunk_size.as_ivec3()
+    }
+
+    /// Get a chunk's grid, if it has been created
+    pub fn get(&self, key: IVec3) -> Option<&SharedVoxelGrid> {
+        self.chunks.get(&key)
+    }
+
+    /// Iterate over the created chunks
+    pub fn chunks(&self) -> impl Iterator<Item = (IVec3, &SharedVoxelGrid)> {
+        self.chunks.iter().map(|(key, grid)| (*key, grid))
+    }
+
+    /// Get a chunk's grid, creating it if needed. New chunks push a
+    /// [CreateGridCommand] onto commands.
+    pub fn get_or_create(&mut self, key: IVec3, commands: &mut VoxelCommandVec) -> SharedVoxelGrid {
+        match self.chunks.entry(key) {
+            hash_map::Entry::Occupied(entry) => entry.get().clone(),
+            hash_map::Entry::Vacant(entry) => {
+                let grid = SharedVoxelGrid::new();
+                commands.push(CreateGridCommand::new(grid.clone(), self.chunk_size).boxed());
+                entry.insert(grid).clone()
+            }
+        }
+    }
+
+    /// Create a command which copies the neighboring chunks' edge voxels
+    /// into a chunk's padding. Run it after pasting and before generating
+    /// the chunk's mesh.
+    pub fn sync_padding(&self, key: IVec3) -> SyncPaddingCommand {
+        let neighbor = |axis: usize, dir: i32| {
+            let mut k = key;
+            k[axis] += dir;
+            self.chunks.get(&k).cloned()
+        };
+        SyncPaddingCommand::new(
+            self.chunks.get(&key).cloned().unwrap_or_default(),
+            [
+                neighbor(0, -1),
+                neighbor(0, 1),
+                neighbor(1, -1),
+                neighbor(1, 1),
+                neighbor(2, -1),
+                neighbor(2, 1),
+            ],
+        )
+    }
+
+    /// Create commands for a shape which covers the world voxels `[min, max]`,
+    /// inclusive. make receives each overlapping chunk's grid and origin;
+    /// subtract the origin from the shape's world offset.
+    pub fn paste_with(
+        &mut self,
+        min: IVec3,
+        max: IVec3,
+        mut make: impl FnMut(SharedVoxelGrid, IVec3) -> GeometryCommand,
+    ) -> VoxelCommandVec {
+        let mut commands = VoxelCommandVec::new();
+        // The shape's vertexes run from min to max + 1. Include the chunks
+        // which hold them in their ending padding too.
+        let first = self.chunk_key(min - 1);
+        let last = self.chunk_key(max + 1);
+        for z in first.z..=last.z {
+            for y in first.y..=last.y {
+                for x in first.x..=last.x {
+                    let key = IVec3::new(x, y, z);
+                    let grid = self.get_or_create(key, &mut commands);
+                    commands.push(make(grid, self.chunk_origin(key)).boxed());
+                }
+            }
+        }
+        commands
+    }
+
+    /// Paste a sphere. See [GeometryCommand::sphere].
+    pub fn paste_sphere(
+        &mut self,
+        world_offset: IVec3,
+        diameter: u32,
+        flags: u32,
+        material: u32,
+    ) -> VoxelCommandVec {
+        if diameter == 0 {
+            return VoxelCommandVec::new();
+        }
+        self.paste_with(
+            world_offset,
+            world_offset + diameter as i32 - 1,
+            |grid, origin| {
+                GeometryCommand::sphere(grid, diameter, world_offset - origin, flags, material)
+            },
+        )
+    }
+}
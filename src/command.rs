@@ -1,43 +1,231 @@
 use bytemuck::cast_slice;
-use glam::{IVec3, UVec3, Vec3};
+use glam::{IVec3, Quat, UVec3, Vec3};
 use parking_lot::Mutex;
 use std::{
+    borrow::Cow,
+    collections::HashMap,
     fmt::Debug,
     mem::size_of,
     ops::{Deref, DerefMut},
-    sync::Arc,
+    sync::{
+        atomic::{fence, AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
 };
 use wgpu::{
     BindGroupLayout, Buffer, BufferAsyncError, BufferDescriptor, BufferUsages, CommandEncoder,
-    ComputePipeline, Device, MapMode,
+    CommandEncoderDescriptor, ComputePipeline, ComputePipelineDescriptor, Device, MapMode,
+    PipelineLayoutDescriptor, Queue, ShaderModuleDescriptor, ShaderSource,
+};
+
+use crate::{
+    shape,
+    voxel::{unstable::ShaderArgs, *},
 };
 
-use crate::voxel::*;
+// Size of a grid, readable without a lock. This is a sequence lock: seq is
+// odd while a write is in progress, and readers retry if it changed while
+// they read. Only write while the grid is locked, so there's 1 writer.
+#[derive(Debug, Default)]
+struct GridSize {
+    seq: AtomicU32,
+    exists: AtomicBool,
+    size: [AtomicU32; 3],
+}
+
+impl GridSize {
+    fn get(&self) -> Option<UVec3> {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            let exists = self.exists.load(Ordering::Relaxed);
+            let size = UVec3::new(
+                self.size[0].load(Ordering::Relaxed),
+                self.size[1].load(Ordering::Relaxed),
+                self.size[2].load(Ordering::Relaxed),
+            );
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == seq {
+                return exists.then_some(size);
+            }
+        }
+    }
+
+    fn set(&self, size: Option<UVec3>) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.exists.store(size.is_some(), Ordering::Relaxed);
+        for (a, v) in self.size.iter().zip(size.unwrap_or_default().to_array()) {
+            a.store(v, Ordering::Relaxed);
+        }
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+} // impl GridSize
 
-// lock order: SharedVoxelGridContent, SharedVoxelGrid
+// lock order: SharedVoxelGridContent, SharedVoxelGrid (PasteGrid or SplitGrid source), SharedVoxelGrid
 #[derive(Debug, Clone, Default)]
-pub struct SharedVoxelGrid(Arc<Mutex<Option<VoxelGrid>>>);
+pub struct SharedVoxelGrid {
+    grid: Arc<Mutex<Option<VoxelGrid>>>,
+
+    // Size of grid. Only set while grid is locked.
+    size: Arc<GridSize>,
+}
 
 impl SharedVoxelGrid {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Size of the grid, excluding padding, or None if it hasn't been
+    /// created. This doesn't take any lock, so it doesn't wait on commands
+    /// which are using the grid.
+    ///
+    /// The size is cached by [CreateGridCommand], [SplitGridCommand], and
+    /// [SharedVoxelGrid::set].
+    /// Assigning through the mutex directly doesn't update it.
+    pub fn size(&self) -> Option<UVec3> {
+        self.size.get()
+    }
+
+    /// Replace the grid and update the cached size.
+    ///
+    /// This locks the grid's mutex.
+    pub fn set(&self, grid: Option<VoxelGrid>) {
+        let mut guard = self.grid.lock();
+        self.size.set(grid.as_ref().map(|grid| grid.size));
+        *guard = grid;
+    }
+
+    /// GPU memory used by the grid, in bytes. 0 if it hasn't been created.
+    ///
+    /// This locks the grid's mutex.
+    pub fn gpu_bytes(&self) -> u64 {
+        self.lock().as_ref().map_or(0, |grid| grid.gpu_bytes())
+    }
 }
 
 impl Deref for SharedVoxelGrid {
     type Target = Arc<Mutex<Option<VoxelGrid>>>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.grid
     }
 }
 
 impl DerefMut for SharedVoxelGrid {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.grid
+    }
+}
+
+/// A CPU copy of a grid's content, e.g. from [GetVoxelsCommand]. Operations
+/// which need to read voxels, such as [GeometryOp::FloodFill], use this and
+/// keep it in sync with the grid.
+#[derive(Debug, Clone)]
+pub struct SharedVoxelGridContent(Arc<Mutex<VoxelGridVec>>);
+
+impl SharedVoxelGridContent {
+    pub fn new(content: VoxelGridVec) -> Self {
+        Self(Arc::new(Mutex::new(content)))
+    }
+}
+
+impl Deref for SharedVoxelGridContent {
+    type Target = Arc<Mutex<VoxelGridVec>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for SharedVoxelGridContent {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.0
     }
 }
 
+/// Two grids; commands read the front while others write the back.
+/// [SwapGridCommand] exchanges their contents.
+///
+/// The swap happens when the command list is prepared, in list order.
+/// Commands capture their grids' buffers during prepare, so commands before
+/// the [SwapGridCommand] use the buffers from before the swap and commands
+/// after it use the buffers from after, even though every prepare runs
+/// before any pass is encoded. Put the swap between the passes that should
+/// see each side. After a swap, the back holds the old front's content, not
+/// a copy of the new front.
+// lock order: front, back
+#[derive(Debug, Clone, Default)]
+pub struct DoubleBufferedGrid {
+    front: SharedVoxelGrid,
+    back: SharedVoxelGrid,
+}
+
+impl DoubleBufferedGrid {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Grid to read from
+    pub fn front(&self) -> SharedVoxelGrid {
+        self.front.clone()
+    }
+
+    /// Grid to write to
+    pub fn back(&self) -> SharedVoxelGrid {
+        self.back.clone()
+    }
+
+    /// Exchange the front and back grids' contents and cached sizes
+    pub fn swap(&self) {
+        let mut front = self.front.lock();
+        let mut back = self.back.lock();
+        std::mem::swap(&mut *front, &mut *back);
+        self.front.size.set(front.as_ref().map(|grid| grid.size));
+        self.back.size.set(back.as_ref().map(|grid| grid.size));
+    }
+}
+
+/// Swap a [DoubleBufferedGrid]'s front and back. This happens during
+/// `[prepare]`; see [DoubleBufferedGrid] for the ordering rules.
+#[derive(Clone, Debug, Default)]
+pub struct SwapGridCommand {
+    grid: DoubleBufferedGrid,
+}
+
+impl SwapGridCommand {
+    pub fn new(grid: DoubleBufferedGrid) -> Self {
+        Self { grid }
+    }
+}
+
+impl VoxelCommand for SwapGridCommand {
+    fn prepare<'a>(
+        &mut self,
+        _device: &Device,
+        _get_bind_group_layout: &mut dyn FnMut(&str) -> &'a BindGroupLayout,
+    ) {
+        self.grid.swap();
+    }
+
+    fn add_pass<'a>(
+        &self,
+        _encoder: &mut CommandEncoder,
+        _get_pipeline: &mut dyn FnMut(&str) -> &'a ComputePipeline,
+    ) {
+    }
+
+    fn add_copy(&self, _encoder: &mut CommandEncoder) {}
+
+    fn async_finish(&mut self, mut done: Box<dyn FnMut(Result<(), BufferAsyncError>) + Send>) {
+        done(Ok(()));
+    }
+} // impl Command for SwapGridCommand
+
 /// A command to be executed
 ///
 /// Call the following in order:
@@ -82,15 +270,221 @@ pub trait VoxelCommand {
 
     /// Map the copy buffers if needed and perform any finalization steps, then call the callback
     fn async_finish(&mut self, done: Box<dyn FnMut(Result<(), BufferAsyncError>) + Send>);
+
+    /// GPU memory held by the command's own buffers, in bytes. This excludes
+    /// the grids it operates on, which other commands may share; use
+    /// [SharedVoxelGrid::gpu_bytes] for those. Defaults to 0.
+    fn gpu_bytes(&self) -> u64 {
+        0
+    }
 }
 
 pub type VoxelCommandVec = Vec<Box<dyn VoxelCommand + Send + Sync>>;
 
+/// Builds a [VoxelCommandVec], boxing each command.
+///
+/// Panics on obvious ordering mistakes: creating a grid after an earlier
+/// command in the list already used it.
+#[derive(Default)]
+pub struct VoxelCommandListBuilder {
+    commands: VoxelCommandVec,
+
+    // Grids which commands in the list use
+    used: Vec<SharedVoxelGrid>,
+}
+
+impl VoxelCommandListBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that a command uses grid. Commands added with
+    /// [VoxelCommandListBuilder::push] aren't checked; call this for them
+    /// to keep the ordering checks.
+    pub fn use_grid(mut self, grid: &SharedVoxelGrid) -> Self {
+        if !self.used.iter().any(|g| Arc::ptr_eq(g, grid)) {
+            self.used.push(grid.clone());
+        }
+        self
+    }
+
+    /// Add a command
+    pub fn push(mut self, command: impl VoxelCommand + Send + Sync + 'static) -> Self {
+        self.commands.push(command.boxed());
+        self
+    }
+
+    /// Add a [CreateGridCommand].
+    ///
+    /// Panics if an earlier command in the list uses the grid.
+    pub fn create_grid(self, grid: SharedVoxelGrid, size: UVec3) -> Self {
+        assert!(
+            !self.used.iter().any(|g| Arc::ptr_eq(g, &grid)),
+            "Grid is used before its CreateGridCommand"
+        );
+        self.use_grid(&grid)
+            .push(CreateGridCommand::new(grid, size))
+    }
+
+    /// Add a [GeometryCommand]
+    pub fn geometry(self, command: GeometryCommand) -> Self {
+        self.use_grid(&command.grid).push(command)
+    }
+
+    /// Add a cube. See [GeometryCommand::cube].
+    pub fn cube(
+        self,
+        grid: SharedVoxelGrid,
+        size: UVec3,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+    ) -> Self {
+        self.geometry(GeometryCommand::cube(grid, size, offset, flags, material))
+    }
+
+    /// Add a sphere. See [GeometryCommand::sphere].
+    pub fn sphere(
+        self,
+        grid: SharedVoxelGrid,
+        diameter: u32,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+    ) -> Self {
+        self.geometry(GeometryCommand::sphere(
+            grid, diameter, offset, flags, material,
+        ))
+    }
+
+    /// Get the commands
+    pub fn build(self) -> VoxelCommandVec {
+        self.commands
+    }
+} // impl VoxelCommandListBuilder
+
+/// Runs command lists without Bevy. It holds a bind group layout and
+/// pipeline for each entry point in [geometry_entry_points], and follows
+/// the same steps as the Bevy plugin. The caller decides when to submit
+/// and poll, so the work can be ordered relative to its own.
+///
+/// Use either [CommandRunner::run], or:
+/// * [CommandRunner::record] into your own encoder
+/// * Submit the encoder
+/// * [CommandRunner::finish]
+/// * Poll the device until the callback runs
+pub struct CommandRunner {
+    pipelines: HashMap<&'static str, (BindGroupLayout, ComputePipeline)>,
+}
+
+impl CommandRunner {
+    /// Compile the shader and create the pipelines
+    pub fn new(device: &Device) -> Self {
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("vox.wgsl"),
+            source: ShaderSource::Wgsl(Cow::Owned(shader_source())),
+        });
+        let mut pipelines = HashMap::new();
+        for &entry_point in geometry_entry_points() {
+            let layout = entry_point_bind_group_layout(device, entry_point).unwrap();
+            let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some(entry_point),
+                bind_group_layouts: &[&layout],
+                push_constant_ranges: &[],
+            });
+            let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point,
+            });
+            pipelines.insert(entry_point, (layout, pipeline));
+        }
+        Self { pipelines }
+    }
+
+    fn get(&self, name: &str) -> &(BindGroupLayout, ComputePipeline) {
+        if let Some(entry) = self.pipelines.get(name) {
+            entry
+        } else {
+            panic!("Unknown pipeline in commands: {}", name)
+        }
+    }
+
+    /// Prepare the commands, then add their passes and copies to the
+    /// encoder in order. Submit the encoder before calling [CommandRunner::finish].
+    pub fn record(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        commands: &mut VoxelCommandVec,
+    ) {
+        for command in commands.iter_mut() {
+            command.prepare(device, &mut |name| &self.get(name).0);
+        }
+        for command in commands.iter() {
+            command.add_pass(encoder, &mut |name| &self.get(name).1);
+            command.add_copy(encoder);
+        }
+    }
+
+    /// Map the commands' buffers and deliver their results. Only call this
+    /// after the work from [CommandRunner::record] has been submitted.
+    /// Calls done once every command has finished, with the first buffer
+    /// mapping error, if any. Mapping completes while the device is polled.
+    pub fn finish(
+        &self,
+        commands: &mut VoxelCommandVec,
+        done: impl FnOnce(Result<(), BufferAsyncError>) + Send + 'static,
+    ) {
+        if commands.is_empty() {
+            done(Ok(()));
+            return;
+        }
+        let count = Arc::new(AtomicUsize::new(commands.len()));
+        let error = Arc::new(Mutex::new(None));
+        let done = Arc::new(Mutex::new(Some(done)));
+        let callback = move |res: Result<(), BufferAsyncError>| {
+            if let Err(e) = res {
+                error.lock().get_or_insert(e);
+            }
+            if count.fetch_sub(1, Ordering::AcqRel) == 1 {
+                if let Some(done) = done.lock().take() {
+                    done(match error.lock().take() {
+                        Some(e) => Err(e),
+                        None => Ok(()),
+                    });
+                }
+            }
+        };
+        for command in commands.iter_mut() {
+            command.async_finish(Box::new(callback.clone()));
+        }
+    }
+
+    /// Record the commands into a new encoder, submit it, then call
+    /// [CommandRunner::finish]. Poll the device until done runs.
+    pub fn run(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        commands: &mut VoxelCommandVec,
+        done: impl FnOnce(Result<(), BufferAsyncError>) + Send + 'static,
+    ) {
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("CommandRunner"),
+        });
+        self.record(device, &mut encoder, commands);
+        queue.submit(Some(encoder.finish()));
+        self.finish(commands, done);
+    }
+} // impl CommandRunner
+
 /// Create a voxel grid with the given size.
 #[derive(Clone, Debug, Default)]
 pub struct CreateGridCommand {
-    /// Destination. Reuse the existing buffer without clearing if it already exists
-    /// and its size matches.
+    /// Destination. Reuse the existing buffer without clearing if it already exists,
+    /// its size matches, and it's [GridLayout::Packed].
     grid: SharedVoxelGrid,
 
     /// Size of the voxel grid, excluding padding
@@ -111,12 +505,13 @@ impl VoxelCommand for CreateGridCommand {
     ) {
         let mut guard = self.grid.lock();
         if let Some(grid) = &*guard {
-            if grid.size == self.size {
+            if grid.size == self.size && grid.layout == GridLayout::Packed {
                 return;
             }
         }
         // println!("** Creating grid: {:?}", self.size);
         *guard = Some(VoxelGrid::new(self.size, device, false));
+        self.grid.size.set(Some(self.size));
     }
 
     fn add_pass<'a>(
@@ -134,355 +529,3049 @@ impl VoxelCommand for CreateGridCommand {
     }
 } // impl Command for CreateGridCommand
 
-#[derive(Clone)]
-/// Create a voxel grid with the given size.
-pub struct GetVoxelsCommand {
-    // Retrieve voxels from this grid
+/// Fill an existing voxel grid with empty voxels, without reallocating
+/// its buffer. Put this before the geometry commands in a list.
+#[derive(Clone, Debug, Default)]
+pub struct ClearGridCommand {
+    /// Grid to clear. Does nothing if the grid doesn't exist.
     grid: SharedVoxelGrid,
 
-    // Receives result
-    callback: Arc<dyn Fn(VoxelGridVec) + Send + Sync>,
-
-    // Size of grid at the time it gets copied
-    size: UVec3,
-
-    // Size of the buffer to copy
-    buffer_size: usize,
-
-    // Retrieves the content. COPY_DST | MAP_READ
-    copy_buffer: Arc<Mutex<Option<Buffer>>>,
+    // Grid's buffers at the time prepare ran
+    buffer: Option<Arc<Buffer>>,
+    offsets: Option<Arc<Buffer>>,
 }
 
-impl GetVoxelsCommand {
-    pub fn new(grid: SharedVoxelGrid, callback: Arc<dyn Fn(VoxelGridVec) + Send + Sync>) -> Self {
+impl ClearGridCommand {
+    pub fn new(grid: SharedVoxelGrid) -> Self {
         Self {
             grid,
-            callback,
-            size: Default::default(),
-            buffer_size: Default::default(),
-            copy_buffer: Default::default(),
+            buffer: None,
+            offsets: None,
         }
     }
 }
 
-impl VoxelCommand for GetVoxelsCommand {
+impl VoxelCommand for ClearGridCommand {
     fn prepare<'a>(
         &mut self,
-        device: &Device,
+        _device: &Device,
         _get_bind_group_layout: &mut dyn FnMut(&str) -> &'a BindGroupLayout,
     ) {
-        let guard = self.grid.lock();
-        let Some(grid) = &*guard else { return };
-        self.size = grid.size;
-        self.buffer_size = get_buf_size(grid.size);
-        *self.copy_buffer.lock() = Some(device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: self.buffer_size as u64,
-            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
-            mapped_at_creation: false,
-        }));
+        let mut guard = self.grid.lock();
+        self.buffer = guard.as_ref().map(|grid| grid.buffer.clone());
+        self.offsets = guard.as_ref().and_then(|grid| grid.offsets.clone());
+        if let Some(grid) = guard.as_mut() {
+            grid.offsets_written = false;
+        }
     }
 
     fn add_pass<'a>(
         &self,
-        _encoder: &mut CommandEncoder,
+        encoder: &mut CommandEncoder,
         _get_pipeline: &mut dyn FnMut(&str) -> &'a ComputePipeline,
     ) {
+        for buffer in self.buffer.iter().chain(&self.offsets) {
+            encoder.clear_buffer(buffer, 0, None);
+        }
     }
 
-    fn add_copy(&self, encoder: &mut CommandEncoder) {
-        let src = self.grid.lock();
-        let dest = self.copy_buffer.lock();
-        encoder.copy_buffer_to_buffer(
-            &src.as_ref().unwrap().buffer,
-            0,
-            dest.as_ref().unwrap(),
-            0,
-            self.buffer_size as u64,
-        );
-    }
+    fn add_copy(&self, _encoder: &mut CommandEncoder) {}
 
     fn async_finish(&mut self, mut done: Box<dyn FnMut(Result<(), BufferAsyncError>) + Send>) {
-        let callback = self.callback.clone();
-        let size = self.size;
-        let copy_buffer = self.copy_buffer.clone();
-        // println!("@@@ GetVoxelsCommand::async_finish mapping...");
-        self.copy_buffer
-            .lock()
-            .as_ref()
-            .unwrap()
-            .slice(..)
-            .map_async(MapMode::Read, move |result| {
-                // println!("@@@ GetVoxelsCommand::async_finish mapped: {:?}", result);
-                if result.is_ok() {
-                    let guard = copy_buffer.lock();
-                    let raw = guard.as_ref().unwrap().slice(..).get_mapped_range();
-                    let mut data = Vec::new();
-                    data.resize(raw.len() / size_of::<u32>(), 0);
-                    data.copy_from_slice(cast_slice::<u8, u32>(&raw));
-                    callback(VoxelGridVec { size, data });
-                }
-                done(result);
-            });
+        done(Ok(()));
     }
-} // impl VoxelCommand for GetVoxelsCommand
+} // impl Command for ClearGridCommand
 
-/// Convert a voxel grid to a mesh.
-pub struct GenerateMeshCommand {
-    /// Grid to turn into a mesh
+/// Copy the edge voxels of neighboring grids into a grid's padding, so
+/// generate_mesh doesn't produce faces between the grid and its neighbors.
+/// Put this after the geometry commands and before [GenerateMeshCommand].
+///
+/// Only the 6 faces of the padding are copied, not its edges or corners;
+/// generate_mesh only checks the 6 face neighbors for material.
+#[derive(Clone, Debug, Default)]
+pub struct SyncPaddingCommand {
+    /// Grid whose padding is filled. Does nothing if the grid doesn't exist.
     pub grid: SharedVoxelGrid,
 
-    /// Receives the generated vertexes and normals
-    pub receive_result: Arc<dyn Fn(Vec<Vec3>, Vec<Vec3>) + 'static + Sync + Send>,
+    /// Neighbors in order -X, +X, -Y, +Y, -Z, +Z. Missing neighbors, and
+    /// neighbors whose grids don't exist, leave that side's padding alone.
+    /// Each must have the same size as grid along the other 2 axes. Sides
+    /// where either grid isn't [GridLayout::Packed] are also left alone.
+    pub neighbors: [Option<SharedVoxelGrid>; 6],
 
-    cmd_impl: Option<GenerateMeshImpl>,
+    // Size and buffer of each (neighbor, grid) pair at the time prepare ran
+    pairs: [Option<(CapturedGrid, CapturedGrid)>; 6],
 }
 
-impl GenerateMeshCommand {
-    /// Shader entry point
-    pub const ENTRY_POINT: &'static str = GENERATE_MESH_ENTRY_POINT;
-
-    /// Create bind group layout
-    pub fn bind_group_layout(device: &Device) -> BindGroupLayout {
-        generate_mesh_bind_group_layout(device)
-    }
+// A grid's size and buffer, captured while the grid was locked
+type CapturedGrid = (UVec3, Arc<Buffer>);
 
-    pub fn new(
-        grid: SharedVoxelGrid,
-        receive_result: Arc<dyn Fn(Vec<Vec3>, Vec<Vec3>) + 'static + Sync + Send>,
-    ) -> Self {
+impl SyncPaddingCommand {
+    pub fn new(grid: SharedVoxelGrid, neighbors: [Option<SharedVoxelGrid>; 6]) -> Self {
         Self {
             grid,
-            receive_result,
-            cmd_impl: Default::default(),
+            neighbors,
+            pairs: Default::default(),
         }
     }
 }
 
-impl VoxelCommand for GenerateMeshCommand {
+impl VoxelCommand for SyncPaddingCommand {
     fn prepare<'a>(
         &mut self,
-        device: &Device,
-        get_bind_group_layout: &mut dyn FnMut(&str) -> &'a BindGroupLayout,
+        _device: &Device,
+        _get_bind_group_layout: &mut dyn FnMut(&str) -> &'a BindGroupLayout,
     ) {
-        let guard = self.grid.lock();
-        self.cmd_impl = Some(GenerateMeshImpl::new(
-            device,
-            get_bind_group_layout(Self::ENTRY_POINT),
-            guard.as_ref().expect("Missing grid in GenerateMeshCommand"),
-        ));
+        for (i, neighbor) in self.neighbors.iter().enumerate() {
+            self.pairs[i] = None;
+            let Some(neighbor) = neighbor else {
+                continue;
+            };
+            // lock order: neighbor is a source, so it's locked first
+            let src_guard = neighbor.lock();
+            let mut guard = self.grid.lock();
+            let (Some(src), Some(grid)) = (&*src_guard, &mut *guard) else {
+                continue;
+            };
+            if src.layout != GridLayout::Packed || grid.layout != GridLayout::Packed {
+                continue;
+            }
+            let axis = i / 2;
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            assert!(
+                src.size[u] == grid.size[u] && src.size[v] == grid.size[v],
+                "SyncPaddingCommand neighbor size {} doesn't match grid size {}",
+                src.size,
+                grid.size
+            );
+            grid.offsets_written |= src.offsets_written;
+            self.pairs[i] = Some((
+                (src.size, src.buffer.clone()),
+                (grid.size, grid.buffer.clone()),
+            ));
+        }
     }
 
     fn add_pass<'a>(
         &self,
         encoder: &mut CommandEncoder,
-        get_pipeline: &mut dyn FnMut(&str) -> &'a ComputePipeline,
+        _get_pipeline: &mut dyn FnMut(&str) -> &'a ComputePipeline,
     ) {
-        self.cmd_impl
-            .as_ref()
-            .unwrap()
-            .add_pass(get_pipeline(Self::ENTRY_POINT), encoder);
-    }
+        for (i, pair) in self.pairs.iter().enumerate() {
+            let Some(((src_size, src_buffer), (grid_size, grid_buffer))) = pair else {
+                continue;
+            };
+            let (src_size, grid_size) = (*src_size, *grid_size);
+            let axis = i / 2;
+            let mut src_pos = IVec3::ZERO;
+            let mut dest_pos = IVec3::ZERO;
+            if i % 2 == 0 {
+                src_pos[axis] = src_size[axis] as i32 - 1;
+                dest_pos[axis] = -1;
+            } else {
+                src_pos[axis] = 0;
+                dest_pos[axis] = grid_size[axis] as i32;
+            }
 
-    fn add_copy(&self, encoder: &mut CommandEncoder) {
-        self.cmd_impl.as_ref().unwrap().add_copy(encoder);
+            // Rows along x are contiguous unless x is the axis being synced
+            let row_len = if axis == 0 { 1 } else { grid_size.x };
+            for z in 0..if axis == 2 { 1 } else { grid_size.z } {
+                for y in 0..if axis == 1 { 1 } else { grid_size.y } {
+                    let mut s = src_pos;
+                    let mut d = dest_pos;
+                    if axis != 2 {
+                        s.z = z as i32;
+                        d.z = z as i32;
+                    }
+                    if axis != 1 {
+                        s.y = y as i32;
+                        d.y = y as i32;
+                    }
+                    let offset = |size: UVec3, p: IVec3| {
+                        (voxel_index_i32(size, p.x, p.y, p.z) * size_of::<u32>()) as u64
+                    };
+                    encoder.copy_buffer_to_buffer(
+                        src_buffer,
+                        offset(src_size, s),
+                        grid_buffer,
+                        offset(grid_size, d),
+                        (row_len as usize * size_of::<u32>()) as u64,
+                    );
+                }
+            }
+        }
     }
 
+    fn add_copy(&self, _encoder: &mut CommandEncoder) {}
+
     fn async_finish(&mut self, mut done: Box<dyn FnMut(Result<(), BufferAsyncError>) + Send>) {
-        let receive_result = self.receive_result.clone();
-        // println!("@@@ GenerateMeshCommand::async_finish mapping...");
-        self.cmd_impl
-            .take()
+        done(Ok(()));
+    }
+} // impl Command for SyncPaddingCommand
+
+/// Copy a [GridLayout::Packed] grid into a [GridLayout::Split] grid. Without
+/// offsets, the split grid is a quarter of the size, so [GenerateMeshCommand]
+/// reads less. Put this after the geometry commands and [SyncPaddingCommand],
+/// and before [GenerateMeshCommand] on dest.
+///
+/// dest only gets an offset buffer if src's [VoxelGrid::offsets_written] is
+/// set, i.e. if a command which pastes vertexes ran on it.
+#[derive(Debug, Default)]
+pub struct SplitGridCommand {
+    /// Packed grid to copy. Does nothing if the grid doesn't exist.
+    src: SharedVoxelGrid,
+
+    /// Destination. Reuse the existing buffers without clearing if they
+    /// already exist and match src; otherwise replace the grid.
+    dest: SharedVoxelGrid,
+
+    cmd_impl: Option<GeometryImpl>,
+
+    // src's buffer and dest's offset buffer at the time prepare ran
+    offsets_copy: Option<(Arc<Buffer>, Arc<Buffer>)>,
+}
+
+impl SplitGridCommand {
+    /// Shader entry point
+    pub const ENTRY_POINT: &'static str = SPLIT_MATERIALS_ENTRY_POINT;
+
+    pub fn new(src: SharedVoxelGrid, dest: SharedVoxelGrid) -> Self {
+        Self {
+            src,
+            dest,
+            ..Default::default()
+        }
+    }
+}
+
+impl VoxelCommand for SplitGridCommand {
+    fn prepare<'a>(
+        &mut self,
+        device: &Device,
+        get_bind_group_layout: &mut dyn FnMut(&str) -> &'a BindGroupLayout,
+    ) {
+        assert!(
+            !Arc::ptr_eq(&self.src, &self.dest),
+            "SplitGridCommand source and destination must be different grids"
+        );
+        self.cmd_impl = None;
+        self.offsets_copy = None;
+        let src_guard = self.src.lock();
+        let Some(src) = &*src_guard else { return };
+        assert!(
+            src.layout == GridLayout::Packed,
+            "SplitGridCommand needs a packed source grid"
+        );
+        let mut guard = self.dest.lock();
+        let reuse = matches!(&*guard, Some(dest) if dest.layout == GridLayout::Split
+            && dest.size == src.size
+            && dest.offsets.is_some() == src.offsets_written);
+        if !reuse {
+            *guard = Some(
+                VoxelGrid::try_new_split(src.size, device, false, src.offsets_written)
+                    .unwrap_or_else(|e| panic!("{}", e)),
+            );
+            self.dest.size.set(Some(src.size));
+        }
+        let dest = guard.as_ref().unwrap();
+        self.cmd_impl = Some(GeometryImpl::split_materials(
+            device,
+            get_bind_group_layout(Self::ENTRY_POINT),
+            dest,
+            src,
+        ));
+        self.offsets_copy = dest
+            .offsets
+            .as_ref()
+            .map(|offsets| (src.buffer.clone(), offsets.clone()));
+    }
+
+    fn add_pass<'a>(
+        &self,
+        encoder: &mut CommandEncoder,
+        get_pipeline: &mut dyn FnMut(&str) -> &'a ComputePipeline,
+    ) {
+        if let Some(cmd_impl) = &self.cmd_impl {
+            cmd_impl.add_pass(get_pipeline(Self::ENTRY_POINT), encoder);
+        }
+        if let Some((src, offsets)) = &self.offsets_copy {
+            encoder.copy_buffer_to_buffer(src, 0, offsets, 0, src.size());
+        }
+    }
+
+    fn add_copy(&self, _encoder: &mut CommandEncoder) {}
+
+    fn async_finish(&mut self, mut done: Box<dyn FnMut(Result<(), BufferAsyncError>) + Send>) {
+        done(Ok(()));
+    }
+} // impl Command for SplitGridCommand
+
+#[derive(Clone)]
+/// Create a voxel grid with the given size.
+pub struct GetVoxelsCommand {
+    // Retrieve voxels from this grid
+    grid: SharedVoxelGrid,
+
+    // Receives result
+    callback: Arc<dyn Fn(VoxelGridVec) + Send + Sync>,
+
+    // Inclusive (min, max) to copy. None copies the whole grid.
+    region: Option<(UVec3, UVec3)>,
+
+    // Size of grid at the time it gets copied
+    grid_size: UVec3,
+
+    // Grid's buffer at the time prepare ran
+    src_buffer: Option<Arc<Buffer>>,
+
+    // Grid's layout and offsets at the time prepare ran. Split grids are
+    // copied whole and converted when they're read.
+    layout: GridLayout,
+    src_offsets: Option<Arc<Buffer>>,
+
+    // Lower corner and size of the region at the time it gets copied
+    min: UVec3,
+    size: UVec3,
+
+    // Size of the buffer to copy
+    buffer_size: usize,
+
+    // Retrieves the content. COPY_DST | MAP_READ
+    copy_buffer: Arc<Mutex<Option<Buffer>>>,
+}
+
+impl GetVoxelsCommand {
+    pub fn new(grid: SharedVoxelGrid, callback: Arc<dyn Fn(VoxelGridVec) + Send + Sync>) -> Self {
+        Self {
+            grid,
+            callback,
+            region: None,
+            grid_size: Default::default(),
+            src_buffer: None,
+            layout: Default::default(),
+            src_offsets: None,
+            min: Default::default(),
+            size: Default::default(),
+            buffer_size: Default::default(),
+            copy_buffer: Default::default(),
+        }
+    }
+
+    /// Only retrieve the inclusive region `[min, max]`, which is clamped to
+    /// the grid. This copies 1 row at a time, so it reads back much less
+    /// than [new] for small regions of large grids.
+    ///
+    /// The callback receives a grid sized to the region. Its padding is
+    /// empty, so the offsets at the region's upper edges aren't included.
+    ///
+    /// If the region is entirely outside the grid, nothing is read back and
+    /// the callback receives an empty grid with size 0. [GridLayout::Split]
+    /// grids are read back whole, then cropped.
+    pub fn new_region(
+        grid: SharedVoxelGrid,
+        min: UVec3,
+        max: UVec3,
+        callback: Arc<dyn Fn(VoxelGridVec) + Send + Sync>,
+    ) -> Self {
+        Self {
+            region: Some((min.min(max), min.max(max))),
+            ..Self::new(grid, callback)
+        }
+    }
+}
+
+impl VoxelCommand for GetVoxelsCommand {
+    fn prepare<'a>(
+        &mut self,
+        device: &Device,
+        _get_bind_group_layout: &mut dyn FnMut(&str) -> &'a BindGroupLayout,
+    ) {
+        let guard = self.grid.lock();
+        let Some(grid) = &*guard else { return };
+        self.grid_size = grid.size;
+        self.src_buffer = Some(grid.buffer.clone());
+        self.layout = grid.layout;
+        self.src_offsets = grid.offsets.clone();
+        match self.region {
+            Some((min, max)) => {
+                let max = max.min(grid.size.max(UVec3::ONE) - 1);
+                if !(min.cmple(max).all() && min.cmplt(grid.size).all()) {
+                    // Outside the grid; async_finish delivers an empty grid
+                    self.min = UVec3::ZERO;
+                    self.size = UVec3::ZERO;
+                    self.buffer_size = 0;
+                    *self.copy_buffer.lock() = None;
+                    return;
+                }
+                self.min = min;
+                self.size = max - min + 1;
+                self.buffer_size =
+                    (self.size.x * self.size.y * self.size.z) as usize * size_of::<u32>();
+            }
+            None => {
+                self.min = UVec3::ZERO;
+                self.size = grid.size;
+                self.buffer_size = get_buf_size(grid.size);
+            }
+        }
+        if grid.layout == GridLayout::Split {
+            self.buffer_size = (grid.buffer.size()
+                + grid.offsets.as_ref().map_or(0, |offsets| offsets.size()))
+                as usize;
+        }
+        *self.copy_buffer.lock() = Some(device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: self.buffer_size as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        }));
+    }
+
+    fn add_pass<'a>(
+        &self,
+        _encoder: &mut CommandEncoder,
+        _get_pipeline: &mut dyn FnMut(&str) -> &'a ComputePipeline,
+    ) {
+    }
+
+    fn add_copy(&self, encoder: &mut CommandEncoder) {
+        let dest = self.copy_buffer.lock();
+        if dest.is_none() {
+            return;
+        }
+        let src = self.src_buffer.as_ref().unwrap();
+        if self.layout == GridLayout::Split {
+            encoder.copy_buffer_to_buffer(src, 0, dest.as_ref().unwrap(), 0, src.size());
+            if let Some(offsets) = &self.src_offsets {
+                encoder.copy_buffer_to_buffer(
+                    offsets,
+                    0,
+                    dest.as_ref().unwrap(),
+                    src.size(),
+                    offsets.size(),
+                );
+            }
+            return;
+        }
+        if self.region.is_none() {
+            encoder.copy_buffer_to_buffer(
+                src,
+                0,
+                dest.as_ref().unwrap(),
+                0,
+                self.buffer_size as u64,
+            );
+            return;
+        }
+
+        // Each row along x is contiguous in the grid; pack them tightly
+        let row_size = (self.size.x as usize * size_of::<u32>()) as u64;
+        let mut dest_offset = 0;
+        for z in 0..self.size.z {
+            for y in 0..self.size.y {
+                let index = voxel_index(self.grid_size, self.min.x, self.min.y + y, self.min.z + z);
+                encoder.copy_buffer_to_buffer(
+                    src,
+                    (index * size_of::<u32>()) as u64,
+                    dest.as_ref().unwrap(),
+                    dest_offset,
+                    row_size,
+                );
+                dest_offset += row_size;
+            }
+        }
+    }
+
+    fn async_finish(&mut self, mut done: Box<dyn FnMut(Result<(), BufferAsyncError>) + Send>) {
+        let callback = self.callback.clone();
+        let size = self.size;
+        let is_region = self.region.is_some();
+        let (grid_size, min) = (self.grid_size, self.min);
+        let split_materials_size = (self.layout == GridLayout::Split)
+            .then(|| self.src_buffer.as_ref().unwrap().size() as usize);
+        let copy_buffer = self.copy_buffer.clone();
+        if copy_buffer.lock().is_none() {
+            // Region is outside the grid, or the grid doesn't exist
+            callback(VoxelGridVec::new(UVec3::ZERO, 0));
+            done(Ok(()));
+            return;
+        }
+        // println!("@@@ GetVoxelsCommand::async_finish mapping...");
+        self.copy_buffer
+            .lock()
+            .as_ref()
             .unwrap()
-            .async_map_buffer(move |cmd_impl, res| {
-                // println!("@@@ GenerateMeshCommand::async_finish mapped: {:?}", res);
-                if res.is_ok() {
-                    let (m, n) = cmd_impl.get_mesh();
-                    receive_result(m, n);
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                // println!("@@@ GetVoxelsCommand::async_finish mapped: {:?}", result);
+                if result.is_ok() {
+                    let guard = copy_buffer.lock();
+                    let raw = guard.as_ref().unwrap().slice(..).get_mapped_range();
+                    if let Some(materials_size) = split_materials_size {
+                        let (materials, offsets) = raw.split_at(materials_size);
+                        let offsets = (!offsets.is_empty()).then(|| cast_slice::<u8, u32>(offsets));
+                        let full = VoxelGridVec::from_split(grid_size, materials, offsets);
+                        if is_region {
+                            let mut grid = VoxelGridVec::new(size, 0);
+                            let row_len = size.x as usize;
+                            for z in 0..size.z {
+                                for y in 0..size.y {
+                                    let src = voxel_index(grid_size, min.x, min.y + y, min.z + z);
+                                    let dest = voxel_index(size, 0, y, z);
+                                    grid.data[dest..dest + row_len]
+                                        .copy_from_slice(&full.data[src..src + row_len]);
+                                }
+                            }
+                            callback(grid);
+                        } else {
+                            callback(full);
+                        }
+                    } else {
+                        let raw = cast_slice::<u8, u32>(&raw);
+                        if is_region {
+                            let mut grid = VoxelGridVec::new(size, 0);
+                            for (i, row) in raw.chunks_exact(size.x as usize).enumerate() {
+                                let y = i as u32 % size.y;
+                                let z = i as u32 / size.y;
+                                let begin = voxel_index(size, 0, y, z);
+                                grid.data[begin..begin + row.len()].copy_from_slice(row);
+                            }
+                            callback(grid);
+                        } else {
+                            let mut data = Vec::new();
+                            data.resize(raw.len(), 0);
+                            data.copy_from_slice(raw);
+                            callback(VoxelGridVec { size, data });
+                        }
+                    }
+                }
+                done(result);
+            });
+    }
+
+    fn gpu_bytes(&self) -> u64 {
+        self.copy_buffer.lock().as_ref().map_or(0, |b| b.size())
+    }
+} // impl VoxelCommand for GetVoxelsCommand
+
+#[derive(Debug, Default)]
+struct GridReaderCache {
+    // Most recent read, even if it's stale
+    content: Option<VoxelGridVec>,
+
+    // Incremented by each invalidate
+    generation: u64,
+
+    // content was read after the most recent invalidate
+    valid: bool,
+}
+
+/// A CPU cache of a grid's content, for cheap synchronous queries such as
+/// [Self::get] and [Self::raycast] between edits.
+///
+/// [Self::read_command] fills the cache and [Self::invalidate_command]
+/// marks it stale. Put an invalidate command in each command list that
+/// modifies the grid; it takes effect when the list is prepared, in list
+/// order. The cache is valid again once a read command which was prepared
+/// after the last invalidate completes. Queries return None while the
+/// cache is stale.
+///
+/// This acts as a handle; clones share the same cache.
+#[derive(Debug, Clone)]
+pub struct GridReader {
+    grid: SharedVoxelGrid,
+    cache: Arc<Mutex<GridReaderCache>>,
+}
+
+impl GridReader {
+    pub fn new(grid: SharedVoxelGrid) -> Self {
+        Self {
+            grid,
+            cache: Default::default(),
+        }
+    }
+
+    /// Create a command which reads the whole grid into the cache
+    pub fn read_command(&self) -> GridReadCommand {
+        let cache = self.cache.clone();
+        let generation = Arc::new(AtomicU64::new(0));
+        let read_generation = generation.clone();
+        GridReadCommand {
+            cache: self.cache.clone(),
+            generation,
+            get_voxels: GetVoxelsCommand::new(
+                self.grid.clone(),
+                Arc::new(move |content| {
+                    let mut cache = cache.lock();
+                    cache.valid = read_generation.load(Ordering::Acquire) == cache.generation;
+                    cache.content = Some(content);
+                }),
+            ),
+        }
+    }
+
+    /// Create a command which marks the cache stale
+    pub fn invalidate_command(&self) -> InvalidateGridReaderCommand {
+        InvalidateGridReaderCommand {
+            reader: self.clone(),
+        }
+    }
+
+    /// Mark the cache stale now
+    pub fn invalidate(&self) {
+        let mut cache = self.cache.lock();
+        cache.generation += 1;
+        cache.valid = false;
+    }
+
+    /// Does the cache hold the grid's current content?
+    pub fn is_valid(&self) -> bool {
+        self.cache.lock().valid
+    }
+
+    /// Get the raw voxel at the given coordinates. Returns None if the
+    /// cache is stale or the coordinates are out of range.
+    pub fn get(&self, x: u32, y: u32, z: u32) -> Option<u32> {
+        let cache = self.cache.lock();
+        let content = cache.content.as_ref().filter(|_| cache.valid)?;
+        UVec3::new(x, y, z)
+            .cmplt(content.size)
+            .all()
+            .then(|| content.get(x, y, z))
+    }
+
+    /// Cast a ray through the cached content. See [VoxelGridVec::raycast].
+    /// Returns None if the cache is stale or nothing was hit.
+    pub fn raycast(&self, origin: Vec3, dir: Vec3, max_dist: f32) -> Option<RayHit> {
+        let cache = self.cache.lock();
+        let content = cache.content.as_ref().filter(|_| cache.valid)?;
+        content.raycast(origin, dir, max_dist)
+    }
+} // impl GridReader
+
+/// Read a grid into a [GridReader]'s cache. See [GridReader::read_command].
+#[derive(Clone)]
+pub struct GridReadCommand {
+    cache: Arc<Mutex<GridReaderCache>>,
+
+    // Cache's generation when this was prepared
+    generation: Arc<AtomicU64>,
+
+    get_voxels: GetVoxelsCommand,
+}
+
+impl VoxelCommand for GridReadCommand {
+    fn prepare<'a>(
+        &mut self,
+        device: &Device,
+        get_bind_group_layout: &mut dyn FnMut(&str) -> &'a BindGroupLayout,
+    ) {
+        self.generation
+            .store(self.cache.lock().generation, Ordering::Release);
+        self.get_voxels.prepare(device, get_bind_group_layout);
+    }
+
+    fn add_pass<'a>(
+        &self,
+        encoder: &mut CommandEncoder,
+        get_pipeline: &mut dyn FnMut(&str) -> &'a ComputePipeline,
+    ) {
+        self.get_voxels.add_pass(encoder, get_pipeline);
+    }
+
+    fn add_copy(&self, encoder: &mut CommandEncoder) {
+        self.get_voxels.add_copy(encoder);
+    }
+
+    fn async_finish(&mut self, done: Box<dyn FnMut(Result<(), BufferAsyncError>) + Send>) {
+        self.get_voxels.async_finish(done);
+    }
+
+    fn gpu_bytes(&self) -> u64 {
+        self.get_voxels.gpu_bytes()
+    }
+} // impl VoxelCommand for GridReadCommand
+
+/// Mark a [GridReader]'s cache stale. See [GridReader::invalidate_command].
+#[derive(Debug, Clone)]
+pub struct InvalidateGridReaderCommand {
+    reader: GridReader,
+}
+
+impl VoxelCommand for InvalidateGridReaderCommand {
+    fn prepare<'a>(
+        &mut self,
+        _device: &Device,
+        _get_bind_group_layout: &mut dyn FnMut(&str) -> &'a BindGroupLayout,
+    ) {
+        self.reader.invalidate();
+    }
+
+    fn add_pass<'a>(
+        &self,
+        _encoder: &mut CommandEncoder,
+        _get_pipeline: &mut dyn FnMut(&str) -> &'a ComputePipeline,
+    ) {
+    }
+
+    fn add_copy(&self, _encoder: &mut CommandEncoder) {}
+
+    fn async_finish(&mut self, mut done: Box<dyn FnMut(Result<(), BufferAsyncError>) + Send>) {
+        done(Ok(()));
+    }
+} // impl VoxelCommand for InvalidateGridReaderCommand
+
+/// Convert a voxel grid to a mesh.
+pub struct GenerateMeshCommand {
+    /// Grid to turn into a mesh
+    pub grid: SharedVoxelGrid,
+
+    /// Receives the generated mesh
+    pub receive_result: Arc<dyn Fn(MeshData) + 'static + Sync + Send>,
+
+    /// Called instead of receive_result if the mesh couldn't be read back
+    pub on_error: Option<Arc<dyn Fn(BufferAsyncError) + 'static + Sync + Send>>,
+
+    /// Controls the format of the generated mesh
+    pub options: GenerateMeshOptions,
+
+    /// Reuse buffers from this pool instead of allocating new ones
+    pub buffer_pool: Option<GenerateMeshBufferPool>,
+
+    /// Receives the mesh's stats, before receive_result receives the mesh
+    pub receive_stats: Option<Arc<dyn Fn(MeshStats) + 'static + Sync + Send>>,
+
+    /// Only read back the filled faces. See [GenerateMeshCommand::with_compaction].
+    pub compactor: Option<MeshCompactor>,
+
+    cmd_impl: Option<GenerateMeshImpl>,
+}
+
+impl GenerateMeshCommand {
+    /// Shader entry point
+    pub const ENTRY_POINT: &'static str = GENERATE_MESH_ENTRY_POINT;
+
+    /// Shader entry point the options select
+    pub fn entry_point(&self) -> &'static str {
+        if self.options.marching_cubes {
+            GENERATE_MESH_MARCHING_CUBES_ENTRY_POINT
+        } else if self.options.region.is_some() {
+            GENERATE_MESH_REGION_ENTRY_POINT
+        } else {
+            Self::ENTRY_POINT
+        }
+    }
+
+    /// Create bind group layout
+    pub fn bind_group_layout(device: &Device) -> BindGroupLayout {
+        generate_mesh_bind_group_layout(device)
+    }
+
+    pub fn new(
+        grid: SharedVoxelGrid,
+        receive_result: Arc<dyn Fn(MeshData) + 'static + Sync + Send>,
+    ) -> Self {
+        Self {
+            grid,
+            receive_result,
+            on_error: None,
+            options: Default::default(),
+            buffer_pool: Default::default(),
+            receive_stats: None,
+            compactor: None,
+            cmd_impl: Default::default(),
+        }
+    }
+
+    /// Create a command which calls on_error if the mesh couldn't be read back
+    pub fn new_with_error_handler(
+        grid: SharedVoxelGrid,
+        receive_result: Arc<dyn Fn(MeshData) + 'static + Sync + Send>,
+        on_error: Arc<dyn Fn(BufferAsyncError) + 'static + Sync + Send>,
+    ) -> Self {
+        Self {
+            on_error: Some(on_error),
+            ..Self::new(grid, receive_result)
+        }
+    }
+
+    /// Replace the options
+    pub fn with_options(mut self, options: GenerateMeshOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Reuse buffers from the pool. Share a pool between runs on the
+    /// same grid to avoid reallocating buffers each time.
+    pub fn with_buffer_pool(mut self, pool: GenerateMeshBufferPool) -> Self {
+        self.buffer_pool = Some(pool);
+        self
+    }
+
+    /// Call receive_stats with the mesh's face count, vertex count, and buffer size
+    pub fn with_stats_callback(
+        mut self,
+        receive_stats: Arc<dyn Fn(MeshStats) + 'static + Sync + Send>,
+    ) -> Self {
+        self.receive_stats = Some(receive_stats);
+        self
+    }
+
+    /// Only read back the filled faces instead of the worst-case buffers.
+    /// The command list's submission reads back face_filled; once the face
+    /// count is known, the compactor submits a second pass which packs the
+    /// filled faces, and only those are read back. The list finishes after
+    /// the second submission's buffer is mapped, so keep polling the device.
+    /// See [CompactMeshImpl].
+    ///
+    /// Ignored with marching_cubes, which always reads back the whole buffer.
+    pub fn with_compaction(mut self, compactor: MeshCompactor) -> Self {
+        self.compactor = Some(compactor);
+        self
+    }
+
+    // The compactor, if the options allow compaction
+    fn active_compactor(&self) -> Option<&MeshCompactor> {
+        self.compactor
+            .as_ref()
+            .filter(|_| !self.options.marching_cubes)
+    }
+}
+
+impl VoxelCommand for GenerateMeshCommand {
+    fn prepare<'a>(
+        &mut self,
+        device: &Device,
+        get_bind_group_layout: &mut dyn FnMut(&str) -> &'a BindGroupLayout,
+    ) {
+        let guard = self.grid.lock();
+        let grid = guard.as_ref().expect("Missing grid in GenerateMeshCommand");
+        if grid.size.cmpeq(UVec3::ZERO).any() {
+            // No voxels; async_finish delivers an empty mesh
+            self.cmd_impl = None;
+            return;
+        }
+        let layout = get_bind_group_layout(self.entry_point());
+        self.cmd_impl = Some(GenerateMeshImpl::with_options(
+            device,
+            layout,
+            grid,
+            self.buffer_pool.as_ref(),
+            &self.options,
+        ));
+    }
+
+    fn add_pass<'a>(
+        &self,
+        encoder: &mut CommandEncoder,
+        get_pipeline: &mut dyn FnMut(&str) -> &'a ComputePipeline,
+    ) {
+        if let Some(cmd_impl) = &self.cmd_impl {
+            cmd_impl.add_pass(get_pipeline(self.entry_point()), encoder);
+        }
+    }
+
+    fn add_copy(&self, encoder: &mut CommandEncoder) {
+        if let Some(cmd_impl) = &self.cmd_impl {
+            if self.active_compactor().is_some() {
+                cmd_impl.add_copy_face_filled(encoder);
+            } else {
+                cmd_impl.add_copy(encoder);
+            }
+        }
+    }
+
+    fn async_finish(&mut self, mut done: Box<dyn FnMut(Result<(), BufferAsyncError>) + Send>) {
+        let receive_result = self.receive_result.clone();
+        let on_error = self.on_error.clone();
+        let receive_stats = self.receive_stats.clone();
+        let options = self.options;
+        let compactor = self.active_compactor().cloned();
+        let Some(cmd_impl) = self.cmd_impl.take() else {
+            // Empty grid. Still deliver a result so the old mesh is replaced.
+            if let Some(receive_stats) = &receive_stats {
+                receive_stats(MeshStats::default());
+            }
+            receive_result(MeshData::default());
+            done(Ok(()));
+            return;
+        };
+        if let Some(compactor) = compactor {
+            cmd_impl.async_map_face_filled(move |cmd_impl, res| {
+                if let Err(e) = res {
+                    if let Some(on_error) = &on_error {
+                        on_error(e.clone());
+                    }
+                    done(Err(e));
+                    return;
+                }
+                compactor.submit(cmd_impl, move |compact_impl, res| {
+                    match &res {
+                        Ok(()) => {
+                            let buffer_size = compact_impl.buffer_size();
+                            let mesh = compact_impl.get_mesh_data(&options);
+                            if let Some(receive_stats) = &receive_stats {
+                                receive_stats(MeshStats {
+                                    num_faces: mesh.num_faces(),
+                                    num_vertexes: mesh.vertexes.len(),
+                                    buffer_size,
+                                });
+                            }
+                            receive_result(mesh)
+                        }
+                        Err(e) => {
+                            if let Some(on_error) = &on_error {
+                                on_error(e.clone());
+                            }
+                        }
+                    }
+                    done(res);
+                });
+            });
+            return;
+        }
+        // println!("@@@ GenerateMeshCommand::async_finish mapping...");
+        cmd_impl.async_map_buffer(move |cmd_impl, res| {
+            // println!("@@@ GenerateMeshCommand::async_finish mapped: {:?}", res);
+            match &res {
+                Ok(()) => {
+                    let buffer_size = cmd_impl.buffer_size();
+                    let mesh = cmd_impl.get_mesh_data(&options);
+                    if let Some(receive_stats) = &receive_stats {
+                        receive_stats(MeshStats {
+                            num_faces: mesh.num_faces(),
+                            num_vertexes: mesh.vertexes.len(),
+                            buffer_size,
+                        });
+                    }
+                    receive_result(mesh)
+                }
+                Err(e) => {
+                    if let Some(on_error) = &on_error {
+                        on_error(e.clone());
+                    }
+                }
+            }
+            done(res);
+        });
+    }
+
+    fn gpu_bytes(&self) -> u64 {
+        self.cmd_impl
+            .as_ref()
+            .map_or(0, |cmd_impl| cmd_impl.gpu_bytes())
+    }
+} // impl Command for GenerateMeshCommand
+
+/// Submits [GenerateMeshCommand]'s second pass, which packs the filled
+/// faces once their count is known. See [GenerateMeshCommand::with_compaction].
+/// Clones share the pipeline.
+///
+/// The second submission is made from the first submission's buffer
+/// mapping callback, so the queue must allow submitting while the device
+/// is polled.
+#[derive(Clone)]
+pub struct MeshCompactor {
+    // Calls its argument with the device
+    with_device: Arc<dyn Fn(&mut dyn FnMut(&Device)) + Send + Sync>,
+    queue: Arc<Queue>,
+    layout: Arc<BindGroupLayout>,
+    pipeline: Arc<ComputePipeline>,
+}
+
+impl MeshCompactor {
+    /// Compile the shader and create the compact_mesh pipeline
+    pub fn new(device: Arc<Device>, queue: Arc<Queue>) -> Self {
+        Self::with_device_fn(move |f| f(device.as_ref()), queue)
+    }
+
+    /// Like [MeshCompactor::new], for devices which aren't in an [Arc].
+    /// with_device must call its argument with the device.
+    pub fn with_device_fn(
+        with_device: impl Fn(&mut dyn FnMut(&Device)) + Send + Sync + 'static,
+        queue: Arc<Queue>,
+    ) -> Self {
+        let mut created = None;
+        with_device(&mut |device| {
+            let module = device.create_shader_module(ShaderModuleDescriptor {
+                label: Some("vox.wgsl"),
+                source: ShaderSource::Wgsl(Cow::Owned(shader_source())),
+            });
+            let layout = compact_mesh_bind_group_layout(device);
+            let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some(COMPACT_MESH_ENTRY_POINT),
+                bind_group_layouts: &[&layout],
+                push_constant_ranges: &[],
+            });
+            let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some(COMPACT_MESH_ENTRY_POINT),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point: COMPACT_MESH_ENTRY_POINT,
+            });
+            created = Some((layout, pipeline));
+        });
+        let (layout, pipeline) =
+            created.expect("MeshCompactor: with_device didn't call its argument");
+        Self {
+            with_device: Arc::new(with_device),
+            queue,
+            layout: Arc::new(layout),
+            pipeline: Arc::new(pipeline),
+        }
+    }
+
+    /// Call [GenerateMeshImpl::compact], submit its pass and copy, then map
+    /// the packed faces and call the callback. Only call this after
+    /// [GenerateMeshImpl::async_map_face_filled] has called its callback.
+    pub fn submit(
+        &self,
+        cmd_impl: GenerateMeshImpl,
+        done: impl FnOnce(CompactMeshImpl, Result<(), BufferAsyncError>) + Send + 'static,
+    ) {
+        let mut cmd_impl = Some(cmd_impl);
+        let mut compact_impl = None;
+        (self.with_device)(&mut |device| {
+            let Some(cmd_impl) = cmd_impl.take() else {
+                return;
+            };
+            let compact = cmd_impl.compact(device, &self.layout);
+            let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("MeshCompactor"),
+            });
+            compact.add_pass(&self.pipeline, &mut encoder);
+            compact.add_copy(&mut encoder);
+            self.queue.submit(Some(encoder.finish()));
+            compact_impl = Some(compact);
+        });
+        compact_impl
+            .expect("MeshCompactor: with_device didn't call its argument")
+            .async_map_buffer(done);
+    }
+} // impl MeshCompactor
+
+/// Type of geometry operation to perform
+#[derive(Debug, Clone)]
+pub enum GeometryOp {
+    PasteCube {
+        /// Size of cube
+        size: UVec3,
+
+        /// Offset cube's coordinates, before rotating
+        offset: IVec3,
+
+        /// Rotation around the cube's center
+        rotation: Quat,
+
+        /// Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+        /// Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+        /// PASTE_ERASE empties occupied voxels instead.
+        flags: u32,
+
+        /// Material to paste
+        material: u32,
+
+        /// With PASTE_MASK, only overwrite empty voxels and voxels with this material
+        mask_material: u32,
+    },
+
+    PasteSphere {
+        /// Diameter of sphere
+        diameter: u32,
+
+        /// Offset sphere's coordinates
+        offset: IVec3,
+
+        /// Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+        /// Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+        /// PASTE_ERASE empties occupied voxels instead.
+        flags: u32,
+
+        /// Material to paste
+        material: u32,
+
+        /// With PASTE_MASK, only overwrite empty voxels and voxels with this material
+        mask_material: u32,
+    },
+
+    PasteSphereInstanced {
+        /// Diameter of each sphere
+        diameter: u32,
+
+        /// Offset of each sphere's coordinates. Later instances overwrite
+        /// earlier ones where they overlap, like separate PasteSphere ops.
+        offsets: Arc<Vec<IVec3>>,
+
+        /// Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+        /// Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+        /// PASTE_ERASE empties occupied voxels instead.
+        flags: u32,
+
+        /// Material to paste
+        material: u32,
+
+        /// With PASTE_MASK, only overwrite empty voxels and voxels with this material
+        mask_material: u32,
+    },
+
+    PasteEllipsoid {
+        /// Radius along each axis. An ellipsoid with a 0 radius is empty.
+        radii: UVec3,
+
+        /// Offset ellipsoid's coordinates
+        offset: IVec3,
+
+        /// Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+        /// Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+        /// PASTE_ERASE empties occupied voxels instead.
+        flags: u32,
+
+        /// Material to paste
+        material: u32,
+
+        /// With PASTE_MASK, only overwrite empty voxels and voxels with this material
+        mask_material: u32,
+    },
+
+    PasteCylinder {
+        /// Radius of cylinder
+        radius: u32,
+
+        /// Height of cylinder
+        height: u32,
+
+        /// Axis the cylinder runs along. 0: X, 1: Y, 2: Z
+        axis: u8,
+
+        /// Offset cylinder's coordinates
+        offset: IVec3,
+
+        /// Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+        /// Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+        /// PASTE_ERASE empties occupied voxels instead.
+        flags: u32,
+
+        /// Material to paste
+        material: u32,
+
+        /// With PASTE_MASK, only overwrite empty voxels and voxels with this material
+        mask_material: u32,
+    },
+
+    PasteCone {
+        /// Radius of the cone's base
+        base_radius: u32,
+
+        /// Height of cone. The base is at the low end of axis.
+        height: u32,
+
+        /// Axis the cone runs along. 0: X, 1: Y, 2: Z
+        axis: u8,
+
+        /// Offset cone's coordinates
+        offset: IVec3,
+
+        /// Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+        /// Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+        /// PASTE_ERASE empties occupied voxels instead.
+        flags: u32,
+
+        /// Material to paste
+        material: u32,
+
+        /// With PASTE_MASK, only overwrite empty voxels and voxels with this material
+        mask_material: u32,
+    },
+
+    PasteTorus {
+        /// Distance from the torus's center to the center of its tube
+        major_radius: u32,
+
+        /// Radius of the tube. A torus with a 0 minor radius is empty.
+        minor_radius: u32,
+
+        /// Axis the torus's hole runs along. 0: X, 1: Y, 2: Z
+        axis: u8,
+
+        /// Offset torus's coordinates
+        offset: IVec3,
+
+        /// Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+        /// Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+        /// PASTE_ERASE empties occupied voxels instead.
+        flags: u32,
+
+        /// Material to paste
+        material: u32,
+
+        /// With PASTE_MASK, only overwrite empty voxels and voxels with this material
+        mask_material: u32,
+    },
+
+    PasteWedge {
+        /// Bounding box of wedge. A wedge with a 0 size is empty.
+        size: UVec3,
+
+        /// Axis the slope rises along. 0: X, 1: Y, 2: Z. The height is along
+        /// the next axis (Y, Z, X) and the wedge extends straight along the
+        /// remaining one.
+        axis: u8,
+
+        /// Offset wedge's coordinates
+        offset: IVec3,
+
+        /// Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+        /// Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+        /// PASTE_ERASE empties occupied voxels instead.
+        flags: u32,
+
+        /// Material to paste
+        material: u32,
+
+        /// With PASTE_MASK, only overwrite empty voxels and voxels with this material
+        mask_material: u32,
+    },
+
+    PasteLine {
+        /// Voxel at one end of the line
+        start: IVec3,
+
+        /// Voxel at the other end of the line
+        end: IVec3,
+
+        /// Radius of the capsule around the line. A 0 radius is empty.
+        radius: u32,
+
+        /// Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+        /// Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+        /// PASTE_ERASE empties occupied voxels instead.
+        flags: u32,
+
+        /// Material to paste
+        material: u32,
+
+        /// With PASTE_MASK, only overwrite empty voxels and voxels with this material
+        mask_material: u32,
+    },
+
+    PasteBox {
+        /// Inclusive lower corner. Clamped to the grid.
+        min: IVec3,
+
+        /// Inclusive upper corner. Clamped to the grid.
+        max: IVec3,
+
+        /// Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+        /// Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+        /// PASTE_ERASE empties occupied voxels instead.
+        flags: u32,
+
+        /// Material to paste
+        material: u32,
+
+        /// With PASTE_MASK, only overwrite empty voxels and voxels with this material
+        mask_material: u32,
+    },
+
+    PasteNoise {
+        /// Lower corner of the region to fill
+        offset: IVec3,
+
+        /// Size of the region to fill
+        size: UVec3,
+
+        /// Noise cells per voxel. e.g. 0.1 makes features about 10 voxels wide.
+        frequency: f32,
+
+        /// Fill voxels where the noise, which is in `[0, 1)`, is greater than this
+        threshold: f32,
+
+        /// Selects the noise pattern. The same seed and frequency always
+        /// fill the same voxels.
+        seed: u32,
+
+        /// Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+        /// Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+        /// PASTE_ERASE empties occupied voxels instead.
+        flags: u32,
+
+        /// Material to paste
+        material: u32,
+
+        /// With PASTE_MASK, only overwrite empty voxels and voxels with this material
+        mask_material: u32,
+    },
+
+    PasteGrid {
+        /// Grid to copy from. Must be a different grid than the destination.
+        src: SharedVoxelGrid,
+
+        /// Lower corner of the region in src
+        src_offset: IVec3,
+
+        /// Size of the region
+        size: UVec3,
+
+        /// Lower corner of the region in the destination
+        dest_offset: IVec3,
+
+        /// Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+        /// PASTE_MATERIAL_ARG pastes material 0.
+        /// PASTE_ERASE empties voxels which are occupied in src.
+        flags: u32,
+
+        /// With PASTE_MASK, only overwrite empty voxels and voxels with this material
+        mask_material: u32,
+    },
+
+    VoxelizeMesh {
+        /// Vertex positions
+        positions: Arc<Vec<Vec3>>,
+
+        /// 3 per triangle. The mesh should be closed.
+        indices: Arc<Vec<u32>>,
+
+        /// Multiplies positions; voxels are 1 unit apart
+        scale: f32,
+
+        /// Offset scaled mesh's coordinates
+        offset: IVec3,
+
+        /// Material to fill the inside of the mesh with
+        material: u32,
+    },
+
+    PasteSlab {
+        /// Axis the slab is perpendicular to. 0: X, 1: Y, 2: Z
+        axis: u8,
+
+        /// First layer of the slab along the axis. May be outside the grid.
+        from: i32,
+
+        /// Number of layers. A slab with 0 thickness is empty.
+        thickness: u32,
+
+        /// Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+        /// Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+        /// PASTE_ERASE empties occupied voxels instead.
+        flags: u32,
+
+        /// Material to paste
+        material: u32,
+
+        /// With PASTE_MASK, only overwrite empty voxels and voxels with this material
+        mask_material: u32,
+    },
+
+    PasteHeightmap {
+        /// Height of each column, `width * depth` entries, packed by x then y
+        heights: Arc<Vec<u16>>,
+
+        /// Size of heightmap along X
+        width: u32,
+
+        /// Size of heightmap along Y
+        depth: u32,
+
+        /// Offset heightmap's coordinates. Columns run up along Z.
+        offset: IVec3,
+
+        /// Material to fill
+        material: u32,
+    },
+
+    FloodFill {
+        /// CPU copy of the grid's content. The fill runs on this, then the
+        /// changed voxels are pasted into the grid. It must match the grid.
+        content: SharedVoxelGridContent,
+
+        /// Voxel to start from
+        seed: IVec3,
+
+        /// Only voxels with this material are replaced. Does nothing if the
+        /// seed doesn't have this material.
+        from_material: u32,
+
+        /// Replacement material
+        to_material: u32,
+    },
+
+    Smooth {
+        /// Number of filter passes. Each pass sees the previous one's result.
+        iterations: u32,
+
+        /// Lower corner of the region. The region is clipped to the grid.
+        offset: IVec3,
+
+        /// Size of the region
+        size: UVec3,
+
+        /// A voxel is solid if at least this many of the 27 voxels around
+        /// it, including itself, are solid; 14 is a majority. Its material
+        /// becomes the most common one among them. Corner offsets are kept.
+        threshold: u32,
+    },
+
+    RemapMaterial {
+        /// Material to replace
+        from: u32,
+
+        /// Replacement material
+        to: u32,
+
+        /// Lower corner and size of the region, clipped to the grid. None
+        /// for the whole grid.
+        region: Option<(IVec3, UVec3)>,
+    },
+}
+
+impl GeometryOp {
+    /// Whether the op may write nonzero corner offsets. This sets
+    /// [VoxelGrid::offsets_written], so [SplitGridCommand] knows whether the
+    /// split grid needs an offset buffer.
+    pub fn writes_offsets(&self) -> bool {
+        match self {
+            GeometryOp::PasteCube { flags, .. }
+            | GeometryOp::PasteSphere { flags, .. }
+            | GeometryOp::PasteSphereInstanced { flags, .. }
+            | GeometryOp::PasteEllipsoid { flags, .. }
+            | GeometryOp::PasteCylinder { flags, .. }
+            | GeometryOp::PasteCone { flags, .. }
+            | GeometryOp::PasteTorus { flags, .. }
+            | GeometryOp::PasteWedge { flags, .. }
+            | GeometryOp::PasteLine { flags, .. }
+            | GeometryOp::PasteBox { flags, .. }
+            | GeometryOp::PasteNoise { flags, .. }
+            | GeometryOp::PasteSlab { flags, .. }
+            | GeometryOp::PasteGrid { flags, .. } => flags & PASTE_VERTEXES_FLAG != 0,
+            GeometryOp::VoxelizeMesh { .. } | GeometryOp::PasteHeightmap { .. } => true,
+            GeometryOp::FloodFill { .. }
+            | GeometryOp::Smooth { .. }
+            | GeometryOp::RemapMaterial { .. } => false,
+        }
+    }
+
+    /// Create an op from an entry point name and numeric parameters, e.g.
+    /// for a scripting layer. Returns None if the name isn't one of the
+    /// following, the number of parameters doesn't match, a parameter isn't
+    /// finite, an axis isn't 0, 1, or 2, or a parameter other than an
+    /// offset, start, end, frequency, or threshold is negative.
+    /// mask_material is 0 and rotation is identity.
+    ///
+    /// * paste_cube:       size.x, size.y, size.z, offset.x, offset.y, offset.z, flags, material
+    /// * paste_sphere:     diameter, offset.x, offset.y, offset.z, flags, material
+    /// * paste_ellipsoid:  radii.x, radii.y, radii.z, offset.x, offset.y, offset.z, flags, material
+    /// * paste_cylinder:   radius, height, axis, offset.x, offset.y, offset.z, flags, material
+    /// * paste_cone:       base_radius, height, axis, offset.x, offset.y, offset.z, flags, material
+    /// * paste_torus:      major_radius, minor_radius, axis, offset.x, offset.y, offset.z, flags, material
+    /// * paste_wedge:      size.x, size.y, size.z, axis, offset.x, offset.y, offset.z, flags, material
+    /// * paste_line:       start.x, start.y, start.z, end.x, end.y, end.z, radius, flags, material
+    /// * paste_noise:      offset.x, offset.y, offset.z, size.x, size.y, size.z, frequency, threshold, seed, flags, material
+    ///
+    /// The other entry points need buffers, so they aren't supported.
+    pub fn from_name(name: &str, params: &[f32]) -> Option<Self> {
+        let u = |i: usize| (params[i] >= 0.0).then_some(params[i] as u32);
+        let uvec3 = |i: usize| Some(UVec3::new(u(i)?, u(i + 1)?, u(i + 2)?));
+        let axis = |i: usize| {
+            [0.0, 1.0, 2.0]
+                .iter()
+                .position(|a| *a == params[i])
+                .map(|a| a as u8)
+        };
+        let ivec3 =
+            |i: usize| IVec3::new(params[i] as i32, params[i + 1] as i32, params[i + 2] as i32);
+        let num_params = match name {
+            PASTE_SPHERE_ENTRY_POINT => 6,
+            PASTE_CUBE_ENTRY_POINT
+            | PASTE_ELLIPSOID_ENTRY_POINT
+            | PASTE_CYLINDER_ENTRY_POINT
+            | PASTE_CONE_ENTRY_POINT
+            | PASTE_TORUS_ENTRY_POINT => 8,
+            PASTE_WEDGE_ENTRY_POINT | PASTE_LINE_ENTRY_POINT => 9,
+            PASTE_NOISE_ENTRY_POINT => 11,
+            _ => return None,
+        };
+        if params.len() != num_params || !params.iter().all(|p| p.is_finite()) {
+            return None;
+        }
+        Some(match name {
+            PASTE_CUBE_ENTRY_POINT => GeometryOp::PasteCube {
+                size: uvec3(0)?,
+                offset: ivec3(3),
+                rotation: Quat::IDENTITY,
+                flags: u(6)?,
+                material: u(7)?,
+                mask_material: 0,
+            },
+            PASTE_SPHERE_ENTRY_POINT => GeometryOp::PasteSphere {
+                diameter: u(0)?,
+                offset: ivec3(1),
+                flags: u(4)?,
+                material: u(5)?,
+                mask_material: 0,
+            },
+            PASTE_ELLIPSOID_ENTRY_POINT => GeometryOp::PasteEllipsoid {
+                radii: uvec3(0)?,
+                offset: ivec3(3),
+                flags: u(6)?,
+                material: u(7)?,
+                mask_material: 0,
+            },
+            PASTE_CYLINDER_ENTRY_POINT => GeometryOp::PasteCylinder {
+                radius: u(0)?,
+                height: u(1)?,
+                axis: axis(2)?,
+                offset: ivec3(3),
+                flags: u(6)?,
+                material: u(7)?,
+                mask_material: 0,
+            },
+            PASTE_CONE_ENTRY_POINT => GeometryOp::PasteCone {
+                base_radius: u(0)?,
+                height: u(1)?,
+                axis: axis(2)?,
+                offset: ivec3(3),
+                flags: u(6)?,
+                material: u(7)?,
+                mask_material: 0,
+            },
+            PASTE_TORUS_ENTRY_POINT => GeometryOp::PasteTorus {
+                major_radius: u(0)?,
+                minor_radius: u(1)?,
+                axis: axis(2)?,
+                offset: ivec3(3),
+                flags: u(6)?,
+                material: u(7)?,
+                mask_material: 0,
+            },
+            PASTE_WEDGE_ENTRY_POINT => GeometryOp::PasteWedge {
+                size: uvec3(0)?,
+                axis: axis(3)?,
+                offset: ivec3(4),
+                flags: u(7)?,
+                material: u(8)?,
+                mask_material: 0,
+            },
+            PASTE_LINE_ENTRY_POINT => GeometryOp::PasteLine {
+                start: ivec3(0),
+                end: ivec3(3),
+                radius: u(6)?,
+                flags: u(7)?,
+                material: u(8)?,
+                mask_material: 0,
+            },
+            _ => GeometryOp::PasteNoise {
+                offset: ivec3(0),
+                size: uvec3(3)?,
+                frequency: params[6],
+                threshold: params[7],
+                seed: u(8)?,
+                flags: u(9)?,
+                material: u(10)?,
+                mask_material: 0,
+            },
+        })
+    }
+}
+
+/// Apply geometry to a mesh
+///
+/// Shapes are clipped to the grid, so offsets may be negative or reach past
+/// the grid's end. Materials are only written to voxels in `[0, size)`.
+/// Vertexes are also written to the ending padding, which holds the upper
+/// corners of the last voxels; the beginning padding is never written.
+#[derive(Debug)]
+pub struct GeometryCommand {
+    /// Grid to operate on
+    pub grid: SharedVoxelGrid,
+
+    /// Type of geometry operation to perform
+    pub geometry: GeometryOp,
+
+    cmd_impl: Option<GeometryImpl>,
+
+    // Source grid for VoxelizeMesh and FloodFill; scratch grid for Smooth
+    staging: Option<VoxelGrid>,
+
+    // Grid's buffer at the time prepare ran; Smooth copies it into staging
+    grid_buffer: Option<Arc<Buffer>>,
+}
+
+impl GeometryCommand {
+    /// Shader entry point
+    pub const PASTE_CUBE_ENTRY_POINT: &'static str = PASTE_CUBE_ENTRY_POINT;
+
+    /// Shader entry point
+    pub const PASTE_SPHERE_ENTRY_POINT: &'static str = PASTE_SPHERE_ENTRY_POINT;
+
+    /// Shader entry point
+    pub const PASTE_SPHERE_INSTANCED_ENTRY_POINT: &'static str = PASTE_SPHERE_INSTANCED_ENTRY_POINT;
+
+    /// Shader entry point
+    pub const PASTE_ELLIPSOID_ENTRY_POINT: &'static str = PASTE_ELLIPSOID_ENTRY_POINT;
+
+    /// Shader entry point
+    pub const PASTE_CYLINDER_ENTRY_POINT: &'static str = PASTE_CYLINDER_ENTRY_POINT;
+
+    /// Shader entry point
+    pub const PASTE_CONE_ENTRY_POINT: &'static str = PASTE_CONE_ENTRY_POINT;
+
+    /// Shader entry point
+    pub const PASTE_TORUS_ENTRY_POINT: &'static str = PASTE_TORUS_ENTRY_POINT;
+
+    /// Shader entry point
+    pub const PASTE_WEDGE_ENTRY_POINT: &'static str = PASTE_WEDGE_ENTRY_POINT;
+
+    /// Shader entry point
+    pub const PASTE_LINE_ENTRY_POINT: &'static str = PASTE_LINE_ENTRY_POINT;
+
+    /// Shader entry point
+    pub const PASTE_NOISE_ENTRY_POINT: &'static str = PASTE_NOISE_ENTRY_POINT;
+
+    /// Shader entry point
+    pub const PASTE_GRID_ENTRY_POINT: &'static str = PASTE_GRID_ENTRY_POINT;
+
+    /// Shader entry point
+    pub const PASTE_HEIGHTMAP_ENTRY_POINT: &'static str = PASTE_HEIGHTMAP_ENTRY_POINT;
+
+    /// Shader entry point
+    pub const PASTE_SLAB_ENTRY_POINT: &'static str = PASTE_SLAB_ENTRY_POINT;
+
+    /// Shader entry point
+    pub const SMOOTH_ENTRY_POINT: &'static str = SMOOTH_ENTRY_POINT;
+
+    /// Shader entry point
+    pub const REMAP_MATERIAL_ENTRY_POINT: &'static str = REMAP_MATERIAL_ENTRY_POINT;
+
+    /// Create bind group layout. This is the same for all geometry operations
+    /// except PasteGrid, Smooth, PasteHeightmap, and PasteSphereInstanced.
+    pub fn bind_group_layout(device: &Device) -> BindGroupLayout {
+        geometry_bind_group_layout(device)
+    }
+
+    /// Create bind group layout for PasteGrid and Smooth
+    pub fn paste_grid_bind_group_layout(device: &Device) -> BindGroupLayout {
+        paste_grid_bind_group_layout(device)
+    }
+
+    /// Create bind group layout for PasteHeightmap
+    pub fn paste_heightmap_bind_group_layout(device: &Device) -> BindGroupLayout {
+        paste_heightmap_bind_group_layout(device)
+    }
+
+    /// Create bind group layout for PasteSphereInstanced
+    pub fn paste_sphere_instanced_bind_group_layout(device: &Device) -> BindGroupLayout {
+        paste_sphere_instanced_bind_group_layout(device)
+    }
+
+    /// Create a command
+    pub fn new(grid: SharedVoxelGrid, geometry: GeometryOp) -> Self {
+        // println!("@@@ GeometryCommand::new");
+        Self {
+            grid,
+            geometry,
+            cmd_impl: None,
+            staging: None,
+            grid_buffer: None,
+        }
+    }
+
+    /// Create a cube command
+    pub fn cube(
+        grid: SharedVoxelGrid,
+        size: UVec3,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+    ) -> Self {
+        Self::new(
+            grid,
+            GeometryOp::PasteCube {
+                size,
+                offset,
+                rotation: Quat::IDENTITY,
+                flags,
+                material,
+                mask_material: 0,
+            },
+        )
+    }
+
+    /// Create a cube command which rotates the cube around its center
+    pub fn rotated_cube(
+        grid: SharedVoxelGrid,
+        size: UVec3,
+        offset: IVec3,
+        rotation: Quat,
+        flags: u32,
+        material: u32,
+    ) -> Self {
+        Self::new(
+            grid,
+            GeometryOp::PasteCube {
+                size,
+                offset,
+                rotation,
+                flags,
+                material,
+                mask_material: 0,
+            },
+        )
+    }
+
+    /// Create a sphere command
+    pub fn sphere(
+        grid: SharedVoxelGrid,
+        diameter: u32,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+    ) -> Self {
+        Self::new(
+            grid,
+            GeometryOp::PasteSphere {
+                diameter,
+                offset,
+                flags,
+                material,
+                mask_material: 0,
+            },
+        )
+    }
+
+    /// Create a command which pastes the same sphere at each offset in 1
+    /// dispatch. Overlapping spheres act like separate sphere commands run
+    /// in the order of offsets.
+    pub fn sphere_instanced(
+        grid: SharedVoxelGrid,
+        diameter: u32,
+        offsets: Arc<Vec<IVec3>>,
+        flags: u32,
+        material: u32,
+    ) -> Self {
+        Self::new(
+            grid,
+            GeometryOp::PasteSphereInstanced {
+                diameter,
+                offsets,
+                flags,
+                material,
+                mask_material: 0,
+            },
+        )
+    }
+
+    /// Create an ellipsoid command
+    pub fn ellipsoid(
+        grid: SharedVoxelGrid,
+        radii: UVec3,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+    ) -> Self {
+        Self::new(
+            grid,
+            GeometryOp::PasteEllipsoid {
+                radii,
+                offset,
+                flags,
+                material,
+                mask_material: 0,
+            },
+        )
+    }
+
+    /// Create a cylinder command
+    #[allow(clippy::too_many_arguments)]
+    pub fn cylinder(
+        grid: SharedVoxelGrid,
+        radius: u32,
+        height: u32,
+        axis: u8,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+    ) -> Self {
+        Self::new(
+            grid,
+            GeometryOp::PasteCylinder {
+                radius,
+                height,
+                axis,
+                offset,
+                flags,
+                material,
+                mask_material: 0,
+            },
+        )
+    }
+
+    /// Create a cone command
+    #[allow(clippy::too_many_arguments)]
+    pub fn cone(
+        grid: SharedVoxelGrid,
+        base_radius: u32,
+        height: u32,
+        axis: u8,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+    ) -> Self {
+        Self::new(
+            grid,
+            GeometryOp::PasteCone {
+                base_radius,
+                height,
+                axis,
+                offset,
+                flags,
+                material,
+                mask_material: 0,
+            },
+        )
+    }
+
+    /// Create a torus command
+    #[allow(clippy::too_many_arguments)]
+    pub fn torus(
+        grid: SharedVoxelGrid,
+        major_radius: u32,
+        minor_radius: u32,
+        axis: u8,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+    ) -> Self {
+        Self::new(
+            grid,
+            GeometryOp::PasteTorus {
+                major_radius,
+                minor_radius,
+                axis,
+                offset,
+                flags,
+                material,
+                mask_material: 0,
+            },
+        )
+    }
+
+    /// Create a wedge (ramp) command
+    pub fn wedge(
+        grid: SharedVoxelGrid,
+        size: UVec3,
+        axis: u8,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+    ) -> Self {
+        Self::new(
+            grid,
+            GeometryOp::PasteWedge {
+                size,
+                axis,
+                offset,
+                flags,
+                material,
+                mask_material: 0,
+            },
+        )
+    }
+
+    /// Create a command which fills a capsule between the centers of 2 voxels
+    pub fn line(
+        grid: SharedVoxelGrid,
+        start: IVec3,
+        end: IVec3,
+        radius: u32,
+        flags: u32,
+        material: u32,
+    ) -> Self {
+        Self::new(
+            grid,
+            GeometryOp::PasteLine {
+                start,
+                end,
+                radius,
+                flags,
+                material,
+                mask_material: 0,
+            },
+        )
+    }
+
+    /// Create a command which fills the inclusive voxel range `[min, max]`.
+    /// The corners are swapped on any axis where min > max.
+    pub fn box_region(
+        grid: SharedVoxelGrid,
+        min: IVec3,
+        max: IVec3,
+        flags: u32,
+        material: u32,
+    ) -> Self {
+        Self::new(
+            grid,
+            GeometryOp::PasteBox {
+                min: min.min(max),
+                max: min.max(max),
+                flags,
+                material,
+                mask_material: 0,
+            },
+        )
+    }
+
+    /// Create a command which fills voxels where 3D value noise is above threshold
+    #[allow(clippy::too_many_arguments)]
+    pub fn noise(
+        grid: SharedVoxelGrid,
+        offset: IVec3,
+        size: UVec3,
+        frequency: f32,
+        threshold: f32,
+        seed: u32,
+        flags: u32,
+        material: u32,
+    ) -> Self {
+        Self::new(
+            grid,
+            GeometryOp::PasteNoise {
+                offset,
+                size,
+                frequency,
+                threshold,
+                seed,
+                flags,
+                material,
+                mask_material: 0,
+            },
+        )
+    }
+
+    /// Create a command which fills the inside of a triangle mesh. This
+    /// voxelizes on the CPU then pastes the result into grid. Returns an
+    /// error if an index is out of range.
+    pub fn voxelize_mesh(
+        grid: SharedVoxelGrid,
+        positions: Arc<Vec<Vec3>>,
+        indices: Arc<Vec<u32>>,
+        scale: f32,
+        offset: IVec3,
+        material: u32,
+    ) -> Result<Self, VoxelGridError> {
+        shape::check_mesh_indices(positions.len(), &indices)?;
+        Ok(Self::new(
+            grid,
+            GeometryOp::VoxelizeMesh {
+                positions,
+                indices,
+                scale,
+                offset,
+                material,
+            },
+        ))
+    }
+
+    /// Create a command which fills each column up to its height
+    pub fn heightmap(
+        grid: SharedVoxelGrid,
+        heights: Arc<Vec<u16>>,
+        width: u32,
+        depth: u32,
+        offset: IVec3,
+        material: u32,
+    ) -> Self {
+        Self::new(
+            grid,
+            GeometryOp::PasteHeightmap {
+                heights,
+                width,
+                depth,
+                offset,
+                material,
+            },
+        )
+    }
+
+    /// Create a command which fills the layers `[from, from + thickness)`
+    /// along an axis, across the whole grid, e.g. for a floor
+    pub fn slab(
+        grid: SharedVoxelGrid,
+        axis: u8,
+        from: i32,
+        thickness: u32,
+        flags: u32,
+        material: u32,
+    ) -> Self {
+        Self::new(
+            grid,
+            GeometryOp::PasteSlab {
+                axis,
+                from,
+                thickness,
+                flags,
+                material,
+                mask_material: 0,
+            },
+        )
+    }
+
+    /// Create a command which replaces the connected region of from_material
+    /// around seed with to_material. This fills on the CPU using content,
+    /// then pastes the result into grid.
+    pub fn flood_fill(
+        grid: SharedVoxelGrid,
+        content: SharedVoxelGridContent,
+        seed: IVec3,
+        from_material: u32,
+        to_material: u32,
+    ) -> Self {
+        Self::new(
+            grid,
+            GeometryOp::FloodFill {
+                content,
+                seed,
+                from_material,
+                to_material,
+            },
+        )
+    }
+
+    /// Create a command which copies a region of src into grid
+    pub fn paste_grid(
+        grid: SharedVoxelGrid,
+        src: SharedVoxelGrid,
+        src_offset: IVec3,
+        size: UVec3,
+        dest_offset: IVec3,
+        flags: u32,
+    ) -> Self {
+        Self::new(
+            grid,
+            GeometryOp::PasteGrid {
+                src,
+                src_offset,
+                size,
+                dest_offset,
+                flags,
+                mask_material: 0,
+            },
+        )
+    }
+
+    /// Create a command which rounds off a region of the grid with a 3x3x3
+    /// majority filter, e.g. to soften noise-based terrain. A voxel stays or
+    /// becomes solid if most of the voxels around it are solid.
+    pub fn smooth(grid: SharedVoxelGrid, iterations: u32, offset: IVec3, size: UVec3) -> Self {
+        Self::new(
+            grid,
+            GeometryOp::Smooth {
+                iterations,
+                offset,
+                size,
+                threshold: 14,
+            },
+        )
+    }
+
+    /// Create a command which replaces one material with another across the
+    /// whole grid, e.g. turning dirt into grass. Corner offsets are kept.
+    pub fn remap_material(grid: SharedVoxelGrid, from: u32, to: u32) -> Self {
+        Self::new(
+            grid,
+            GeometryOp::RemapMaterial {
+                from,
+                to,
+                region: None,
+            },
+        )
+    }
+
+    /// Set the mask material for operations which have one. Include PASTE_MASK
+    /// in flags to only overwrite empty voxels and voxels with this material.
+    pub fn with_mask_material(mut self, mask: u32) -> Self {
+        match &mut self.geometry {
+            GeometryOp::PasteCube { mask_material, .. }
+            | GeometryOp::PasteSphere { mask_material, .. }
+            | GeometryOp::PasteSphereInstanced { mask_material, .. }
+            | GeometryOp::PasteEllipsoid { mask_material, .. }
+            | GeometryOp::PasteCylinder { mask_material, .. }
+            | GeometryOp::PasteCone { mask_material, .. }
+            | GeometryOp::PasteTorus { mask_material, .. }
+            | GeometryOp::PasteWedge { mask_material, .. }
+            | GeometryOp::PasteLine { mask_material, .. }
+            | GeometryOp::PasteBox { mask_material, .. }
+            | GeometryOp::PasteNoise { mask_material, .. }
+            | GeometryOp::PasteSlab { mask_material, .. }
+            | GeometryOp::PasteGrid { mask_material, .. } => *mask_material = mask,
+            GeometryOp::VoxelizeMesh { .. }
+            | GeometryOp::PasteHeightmap { .. }
+            | GeometryOp::FloodFill { .. }
+            | GeometryOp::Smooth { .. }
+            | GeometryOp::RemapMaterial { .. } => {}
+        }
+        self
+    }
+}
+
+impl VoxelCommand for GeometryCommand {
+    fn prepare<'a>(
+        &mut self,
+        device: &Device,
+        get_bind_group_layout: &mut dyn FnMut(&str) -> &'a BindGroupLayout,
+    ) {
+        // println!("@@@ GeometryCommand::prepare");
+        let mut content_guard = match &self.geometry {
+            GeometryOp::FloodFill { content, .. } => Some(content.lock()),
+            _ => None,
+        };
+        let src_guard = match &self.geometry {
+            GeometryOp::PasteGrid { src, .. } => {
+                assert!(
+                    !Arc::ptr_eq(src, &self.grid),
+                    "PasteGrid source and destination must be different grids"
+                );
+                Some(src.lock())
+            }
+            _ => None,
+        };
+        let mut guard = self.grid.lock();
+        let grid = guard.as_mut().expect("Missing grid in GeometryCommand");
+        assert!(
+            grid.layout == GridLayout::Packed,
+            "GeometryCommand needs a packed grid; split it after editing"
+        );
+        grid.offsets_written |= self.geometry.writes_offsets();
+        let grid = &*grid;
+        match &self.geometry {
+            GeometryOp::PasteCube {
+                size,
+                offset,
+                rotation,
+                flags,
+                material,
+                mask_material,
+            } => {
+                // println!("@@@ GeometryCommand::prepare: PasteCube");
+                if *rotation == Quat::IDENTITY {
+                    self.cmd_impl = Some(GeometryImpl::paste_cube(
+                        device,
+                        get_bind_group_layout(Self::PASTE_CUBE_ENTRY_POINT),
+                        grid,
+                        *size,
+                        *offset,
+                        *flags,
+                        *material,
+                        *mask_material,
+                    ));
+                } else {
+                    self.cmd_impl = Some(GeometryImpl::paste_rotated_cube(
+                        device,
+                        get_bind_group_layout(Self::PASTE_CUBE_ENTRY_POINT),
+                        grid,
+                        *size,
+                        *offset,
+                        *rotation,
+                        *flags,
+                        *material,
+                        *mask_material,
+                    ));
+                }
+            }
+
+            GeometryOp::PasteSphere {
+                diameter,
+                offset,
+                flags,
+                material,
+                mask_material,
+            } => {
+                // println!(
+                //     "@@@ GeometryCommand::prepare: PasteSphere: diameter: {}",
+                //     diameter
+                // );
+                self.cmd_impl = Some(GeometryImpl::paste_sphere(
+                    device,
+                    get_bind_group_layout(Self::PASTE_SPHERE_ENTRY_POINT),
+                    grid,
+                    *diameter,
+                    *offset,
+                    *flags,
+                    *material,
+                    *mask_material,
+                ));
+            }
+
+            GeometryOp::PasteSphereInstanced {
+                diameter,
+                offsets,
+                flags,
+                material,
+                mask_material,
+            } => {
+                self.cmd_impl = if offsets.is_empty() {
+                    None
+                } else {
+                    Some(GeometryImpl::paste_sphere_instanced(
+                        device,
+                        get_bind_group_layout(Self::PASTE_SPHERE_INSTANCED_ENTRY_POINT),
+                        grid,
+                        *diameter,
+                        offsets,
+                        *flags,
+                        *material,
+                        *mask_material,
+                    ))
+                };
+            }
+
+            GeometryOp::PasteEllipsoid {
+                radii,
+                offset,
+                flags,
+                material,
+                mask_material,
+            } => {
+                self.cmd_impl = if radii.cmpgt(UVec3::ZERO).all() {
+                    Some(GeometryImpl::paste_ellipsoid(
+                        device,
+                        get_bind_group_layout(Self::PASTE_ELLIPSOID_ENTRY_POINT),
+                        grid,
+                        *radii,
+                        *offset,
+                        *flags,
+                        *material,
+                        *mask_material,
+                    ))
+                } else {
+                    None
+                };
+            }
+
+            GeometryOp::PasteCylinder {
+                radius,
+                height,
+                axis,
+                offset,
+                flags,
+                material,
+                mask_material,
+            } => {
+                self.cmd_impl = Some(GeometryImpl::paste_cylinder(
+                    device,
+                    get_bind_group_layout(Self::PASTE_CYLINDER_ENTRY_POINT),
+                    grid,
+                    *radius,
+                    *height,
+                    *axis,
+                    *offset,
+                    *flags,
+                    *material,
+                    *mask_material,
+                ));
+            }
+
+            GeometryOp::PasteCone {
+                base_radius,
+                height,
+                axis,
+                offset,
+                flags,
+                material,
+                mask_material,
+            } => {
+                self.cmd_impl = if *base_radius > 0 && *height > 0 {
+                    Some(GeometryImpl::paste_cone(
+                        device,
+                        get_bind_group_layout(Self::PASTE_CONE_ENTRY_POINT),
+                        grid,
+                        *base_radius,
+                        *height,
+                        *axis,
+                        *offset,
+                        *flags,
+                        *material,
+                        *mask_material,
+                    ))
+                } else {
+                    None
+                };
+            }
+
+            GeometryOp::PasteTorus {
+                major_radius,
+                minor_radius,
+                axis,
+                offset,
+                flags,
+                material,
+                mask_material,
+            } => {
+                self.cmd_impl = if *minor_radius > 0 {
+                    Some(GeometryImpl::paste_torus(
+                        device,
+                        get_bind_group_layout(Self::PASTE_TORUS_ENTRY_POINT),
+                        grid,
+                        *major_radius,
+                        *minor_radius,
+                        *axis,
+                        *offset,
+                        *flags,
+                        *material,
+                        *mask_material,
+                    ))
+                } else {
+                    None
+                };
+            }
+
+            GeometryOp::PasteWedge {
+                size,
+                axis,
+                offset,
+                flags,
+                material,
+                mask_material,
+            } => {
+                self.cmd_impl = if size.cmpgt(UVec3::ZERO).all() {
+                    Some(GeometryImpl::paste_wedge(
+                        device,
+                        get_bind_group_layout(Self::PASTE_WEDGE_ENTRY_POINT),
+                        grid,
+                        *size,
+                        *axis,
+                        *offset,
+                        *flags,
+                        *material,
+                        *mask_material,
+                    ))
+                } else {
+                    None
+                };
+            }
+
+            GeometryOp::PasteLine {
+                start,
+                end,
+                radius,
+                flags,
+                material,
+                mask_material,
+            } => {
+                self.cmd_impl = if *radius > 0 {
+                    Some(GeometryImpl::paste_line(
+                        device,
+                        get_bind_group_layout(Self::PASTE_LINE_ENTRY_POINT),
+                        grid,
+                        *start,
+                        *end,
+                        *radius,
+                        *flags,
+                        *material,
+                        *mask_material,
+                    ))
+                } else {
+                    None
+                };
+            }
+
+            GeometryOp::PasteBox {
+                min,
+                max,
+                flags,
+                material,
+                mask_material,
+            } => {
+                let lo = min.min(*max).max(IVec3::ZERO);
+                let hi = max.max(*min).min(grid.size.as_ivec3() - 1);
+                self.cmd_impl = if lo.cmple(hi).all() {
+                    Some(GeometryImpl::paste_cube(
+                        device,
+                        get_bind_group_layout(Self::PASTE_CUBE_ENTRY_POINT),
+                        grid,
+                        (hi - lo + 1).as_uvec3(),
+                        lo,
+                        *flags,
+                        *material,
+                        *mask_material,
+                    ))
+                } else {
+                    // Entirely outside the grid
+                    None
+                };
+            }
+
+            GeometryOp::PasteNoise {
+                offset,
+                size,
+                frequency,
+                threshold,
+                seed,
+                flags,
+                material,
+                mask_material,
+            } => {
+                self.cmd_impl = if size.cmpgt(UVec3::ZERO).all() {
+                    Some(GeometryImpl::paste_noise(
+                        device,
+                        get_bind_group_layout(Self::PASTE_NOISE_ENTRY_POINT),
+                        grid,
+                        *offset,
+                        *size,
+                        *frequency,
+                        *threshold,
+                        *seed,
+                        *flags,
+                        *material,
+                        *mask_material,
+                    ))
+                } else {
+                    None
+                };
+            }
+
+            GeometryOp::PasteGrid {
+                src_offset,
+                size,
+                dest_offset,
+                flags,
+                mask_material,
+                ..
+            } => {
+                let src = src_guard
+                    .as_ref()
+                    .unwrap()
+                    .as_ref()
+                    .expect("Missing source grid in GeometryCommand");
+                assert!(
+                    src.layout == GridLayout::Packed,
+                    "PasteGrid needs a packed source grid"
+                );
+                self.cmd_impl = Some(GeometryImpl::paste_grid(
+                    device,
+                    get_bind_group_layout(Self::PASTE_GRID_ENTRY_POINT),
+                    grid,
+                    src,
+                    *src_offset,
+                    *size,
+                    *dest_offset,
+                    *flags,
+                    0,
+                    *mask_material,
+                ));
+            }
+
+            GeometryOp::VoxelizeMesh {
+                positions,
+                indices,
+                scale,
+                offset,
+                material,
+            } => {
+                self.cmd_impl = None;
+                self.staging = None;
+                // Self::voxelize_mesh checked the indices
+                if let Ok(Some((content, min))) =
+                    shape::voxelize_mesh(positions, indices, *scale, *material as u8)
+                {
+                    let staging = VoxelGrid::from_content(&content, device);
+                    self.cmd_impl = Some(GeometryImpl::paste_grid(
+                        device,
+                        get_bind_group_layout(Self::PASTE_GRID_ENTRY_POINT),
+                        grid,
+                        &staging,
+                        IVec3::ZERO,
+                        content.size,
+                        *offset + min,
+                        PASTE,
+                        0,
+                        0,
+                    ));
+                    self.staging = Some(staging);
                 }
-                done(res);
-            });
-    }
-} // impl Command for GenerateMeshCommand
+            }
 
-/// Type of geometry operation to perform
-#[derive(Debug, Clone)]
-pub enum GeometryOp {
-    PasteCube {
-        /// Size of cube
-        size: UVec3,
+            GeometryOp::PasteSlab {
+                axis,
+                from,
+                thickness,
+                flags,
+                material,
+                mask_material,
+            } => {
+                self.cmd_impl = if *thickness > 0 {
+                    Some(GeometryImpl::paste_slab(
+                        device,
+                        get_bind_group_layout(Self::PASTE_SLAB_ENTRY_POINT),
+                        grid,
+                        *axis,
+                        *from,
+                        *thickness,
+                        *flags,
+                        *material,
+                        *mask_material,
+                    ))
+                } else {
+                    None
+                };
+            }
 
-        /// Offset cube's coordinates
-        offset: IVec3,
+            GeometryOp::PasteHeightmap {
+                heights,
+                width,
+                depth,
+                offset,
+                material,
+            } => {
+                self.cmd_impl = Some(GeometryImpl::paste_heightmap(
+                    device,
+                    get_bind_group_layout(Self::PASTE_HEIGHTMAP_ENTRY_POINT),
+                    grid,
+                    heights,
+                    *width,
+                    *depth,
+                    *offset,
+                    *material,
+                ));
+            }
 
-        /// Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES.
-        /// Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
-        flags: u32,
+            GeometryOp::FloodFill {
+                seed,
+                from_material,
+                to_material,
+                ..
+            } => {
+                self.cmd_impl = None;
+                self.staging = None;
+                let content = content_guard.as_mut().unwrap();
+                assert!(
+                    content.size == grid.size,
+                    "FloodFill content size doesn't match the grid"
+                );
+                if let Some((min, max)) =
+                    content.flood_fill(*seed, *from_material as u8, *to_material as u8)
+                {
+                    // Mark the voxels to replace. Voxels in the region which
+                    // already had to_material get marked too; that's harmless.
+                    let size = max - min + 1;
+                    let mut changed = VoxelGridVec::new(size, 0);
+                    for z in 0..size.z {
+                        for y in 0..size.y {
+                            for x in 0..size.x {
+                                let pos = min + UVec3::new(x, y, z);
+                                if content.material(pos.x, pos.y, pos.z) == *to_material as u8 {
+                                    changed.set(x, y, z, 1 << 24);
+                                }
+                            }
+                        }
+                    }
+                    let staging = VoxelGrid::from_content(&changed, device);
+                    self.cmd_impl = Some(GeometryImpl::paste_grid(
+                        device,
+                        get_bind_group_layout(Self::PASTE_GRID_ENTRY_POINT),
+                        grid,
+                        &staging,
+                        IVec3::ZERO,
+                        size,
+                        min.as_ivec3(),
+                        PASTE_MATERIAL_ARG_FLAG,
+                        *to_material,
+                        0,
+                    ));
+                    self.staging = Some(staging);
+                }
+            }
 
-        /// Material to paste
-        material: u32,
-    },
+            GeometryOp::Smooth {
+                iterations,
+                offset,
+                size,
+                threshold,
+            } => {
+                self.cmd_impl = None;
+                self.staging = None;
+                self.grid_buffer = None;
+                let begin = offset.max(IVec3::ZERO);
+                let end = (*offset + size.as_ivec3()).min(grid.size.as_ivec3());
+                if *iterations > 0 && begin.cmplt(end).all() {
+                    let staging = VoxelGrid::new(grid.size, device, false);
+                    self.grid_buffer = Some(grid.buffer.clone());
+                    self.cmd_impl = Some(GeometryImpl::smooth(
+                        device,
+                        get_bind_group_layout(Self::SMOOTH_ENTRY_POINT),
+                        grid,
+                        &staging,
+                        begin,
+                        (end - begin).as_uvec3(),
+                        *threshold,
+                    ));
+                    self.staging = Some(staging);
+                }
+            }
 
-    PasteSphere {
-        /// Diameter of sphere
-        diameter: u32,
+            GeometryOp::RemapMaterial { from, to, region } => {
+                let (offset, size) = region.unwrap_or((IVec3::ZERO, grid.size));
+                let begin = offset.max(IVec3::ZERO);
+                let end = (offset + size.as_ivec3()).min(grid.size.as_ivec3());
+                self.cmd_impl = if begin.cmplt(end).all() && from != to {
+                    Some(GeometryImpl::remap_material(
+                        device,
+                        get_bind_group_layout(Self::REMAP_MATERIAL_ENTRY_POINT),
+                        grid,
+                        begin,
+                        (end - begin).as_uvec3(),
+                        *from,
+                        *to,
+                    ))
+                } else {
+                    None
+                };
+            }
+        }
+    }
 
-        /// Offset sphere's coordinates
-        offset: IVec3,
+    fn add_pass<'a>(
+        &self,
+        encoder: &mut CommandEncoder,
+        get_pipeline: &mut dyn FnMut(&str) -> &'a ComputePipeline,
+    ) {
+        // println!("@@@ GeometryCommand::add_pass");
+        let entry_point = match &self.geometry {
+            GeometryOp::PasteCube { .. } => Self::PASTE_CUBE_ENTRY_POINT,
+            GeometryOp::PasteSphere { .. } => Self::PASTE_SPHERE_ENTRY_POINT,
+            GeometryOp::PasteSphereInstanced { .. } => Self::PASTE_SPHERE_INSTANCED_ENTRY_POINT,
+            GeometryOp::PasteEllipsoid { .. } => Self::PASTE_ELLIPSOID_ENTRY_POINT,
+            GeometryOp::PasteCylinder { .. } => Self::PASTE_CYLINDER_ENTRY_POINT,
+            GeometryOp::PasteCone { .. } => Self::PASTE_CONE_ENTRY_POINT,
+            GeometryOp::PasteTorus { .. } => Self::PASTE_TORUS_ENTRY_POINT,
+            GeometryOp::PasteWedge { .. } => Self::PASTE_WEDGE_ENTRY_POINT,
+            GeometryOp::PasteLine { .. } => Self::PASTE_LINE_ENTRY_POINT,
+            GeometryOp::PasteBox { .. } => Self::PASTE_CUBE_ENTRY_POINT,
+            GeometryOp::PasteNoise { .. } => Self::PASTE_NOISE_ENTRY_POINT,
+            GeometryOp::PasteGrid { .. } => Self::PASTE_GRID_ENTRY_POINT,
+            GeometryOp::VoxelizeMesh { .. } => Self::PASTE_GRID_ENTRY_POINT,
+            GeometryOp::PasteHeightmap { .. } => Self::PASTE_HEIGHTMAP_ENTRY_POINT,
+            GeometryOp::PasteSlab { .. } => Self::PASTE_SLAB_ENTRY_POINT,
+            GeometryOp::FloodFill { .. } => Self::PASTE_GRID_ENTRY_POINT,
+            GeometryOp::Smooth { .. } => Self::SMOOTH_ENTRY_POINT,
+            GeometryOp::RemapMaterial { .. } => Self::REMAP_MATERIAL_ENTRY_POINT,
+        };
+        if let Some(cmd_impl) = &self.cmd_impl {
+            let pipeline = get_pipeline(entry_point);
+            if let (GeometryOp::Smooth { iterations, .. }, Some(staging), Some(grid_buffer)) =
+                (&self.geometry, &self.staging, &self.grid_buffer)
+            {
+                // Ping-pong through staging so each pass reads the last one's result
+                for _ in 0..*iterations {
+                    encoder.copy_buffer_to_buffer(
+                        grid_buffer,
+                        0,
+                        &staging.buffer,
+                        0,
+                        staging.buffer.size(),
+                    );
+                    cmd_impl.add_pass(pipeline, encoder);
+                }
+            } else {
+                cmd_impl.add_pass(pipeline, encoder);
+            }
+        }
+    }
 
-        /// Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES.
-        /// Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
-        flags: u32,
+    fn add_copy(&self, _encoder: &mut CommandEncoder) {}
 
-        /// Material to paste
-        material: u32,
-    },
-}
+    fn async_finish(&mut self, mut done: Box<dyn FnMut(Result<(), BufferAsyncError>) + Send>) {
+        // println!("@@@ GeometryCommand::async_finish: nop");
+        done(Ok(()));
+    }
+} // impl Command for GeometryCommand
 
-/// Apply geometry to a mesh
+/// Apply several shapes to a grid in a single compute pass. This saves the
+/// per-pass overhead of a [GeometryCommand] for each shape.
+///
+/// The shapes run concurrently, so they must not overlap, including the
+/// voxel past each shape's upper bounds, which gets its vertex. Overlapping
+/// shapes produce undefined results; use separate commands for those.
+///
+/// Supports PasteCube, PasteSphere, PasteEllipsoid, PasteCylinder, PasteCone,
+/// PasteTorus, PasteWedge, PasteLine, and PasteBox. [Self::new] rejects
+/// other ops.
 #[derive(Debug)]
-pub struct GeometryCommand {
+pub struct GeometryBatchCommand {
     /// Grid to operate on
     pub grid: SharedVoxelGrid,
 
-    /// Type of geometry operation to perform
-    pub geometry: GeometryOp,
+    // Shapes to paste. new checks that they're all supported.
+    geometry: Vec<GeometryOp>,
 
     cmd_impl: Option<GeometryImpl>,
 }
 
-impl GeometryCommand {
-    /// Shader entry point
-    pub const PASTE_CUBE_ENTRY_POINT: &'static str = PASTE_CUBE_ENTRY_POINT;
-
+impl GeometryBatchCommand {
     /// Shader entry point
-    pub const PASTE_SPHERE_ENTRY_POINT: &'static str = PASTE_SPHERE_ENTRY_POINT;
+    pub const ENTRY_POINT: &'static str = PASTE_BATCH_ENTRY_POINT;
 
-    /// Create bind group layout. This is the same for all geometry operations.
+    /// Create bind group layout
     pub fn bind_group_layout(device: &Device) -> BindGroupLayout {
-        geometry_bind_group_layout(device)
+        paste_batch_bind_group_layout(device)
     }
 
-    /// Create a command
-    pub fn new(grid: SharedVoxelGrid, geometry: GeometryOp) -> Self {
-        // println!("@@@ GeometryCommand::new");
-        Self {
+    /// Create a command. Returns None if any op in geometry isn't
+    /// [supported](Self::supports).
+    pub fn new(grid: SharedVoxelGrid, geometry: Vec<GeometryOp>) -> Option<Self> {
+        geometry.iter().all(Self::supports).then_some(Self {
             grid,
             geometry,
             cmd_impl: None,
-        }
+        })
     }
 
-    /// Create a cube command
-    pub fn cube(
-        grid: SharedVoxelGrid,
-        size: UVec3,
-        offset: IVec3,
-        flags: u32,
-        material: u32,
-    ) -> Self {
-        Self::new(
-            grid,
+    /// Can a batch paste op?
+    pub fn supports(op: &GeometryOp) -> bool {
+        matches!(
+            op,
+            GeometryOp::PasteCube { .. }
+                | GeometryOp::PasteSphere { .. }
+                | GeometryOp::PasteEllipsoid { .. }
+                | GeometryOp::PasteCylinder { .. }
+                | GeometryOp::PasteCone { .. }
+                | GeometryOp::PasteTorus { .. }
+                | GeometryOp::PasteWedge { .. }
+                | GeometryOp::PasteLine { .. }
+                | GeometryOp::PasteBox { .. }
+        )
+    }
+
+    /// Shapes to paste
+    pub fn geometry(&self) -> &[GeometryOp] {
+        &self.geometry
+    }
+
+    // Args for paste_batch. None if the shape is empty, or for PasteBox, if
+    // it's entirely outside the grid. The shader clips the other shapes.
+    fn shape_args(op: &GeometryOp, out_size: UVec3) -> Option<(ShaderArgs, UVec3)> {
+        match op {
             GeometryOp::PasteCube {
                 size,
                 offset,
+                rotation,
                 flags,
                 material,
-            },
-        )
-    }
+                mask_material,
+            } => Some(if *rotation == Quat::IDENTITY {
+                GeometryImpl::paste_cube_args(
+                    out_size,
+                    *size,
+                    *offset,
+                    *flags,
+                    *material,
+                    *mask_material,
+                )
+            } else {
+                GeometryImpl::paste_rotated_cube_args(
+                    out_size,
+                    *size,
+                    *offset,
+                    *rotation,
+                    *flags,
+                    *material,
+                    *mask_material,
+                )
+            }),
 
-    /// Create a sphere command
-    pub fn sphere(
-        grid: SharedVoxelGrid,
-        diameter: u32,
-        offset: IVec3,
-        flags: u32,
-        material: u32,
-    ) -> Self {
-        Self::new(
-            grid,
             GeometryOp::PasteSphere {
                 diameter,
                 offset,
                 flags,
                 material,
-            },
-        )
-    }
-}
+                mask_material,
+            } => Some(GeometryImpl::paste_sphere_args(
+                out_size,
+                *diameter,
+                *offset,
+                *flags,
+                *material,
+                *mask_material,
+            )),
 
-impl VoxelCommand for GeometryCommand {
-    fn prepare<'a>(
-        &mut self,
-        device: &Device,
-        get_bind_group_layout: &mut dyn FnMut(&str) -> &'a BindGroupLayout,
-    ) {
-        // println!("@@@ GeometryCommand::prepare");
-        let guard = self.grid.lock();
-        let grid = guard.as_ref().expect("Missing grid in GeometryCommand");
-        match &self.geometry {
-            GeometryOp::PasteCube {
-                size,
+            GeometryOp::PasteEllipsoid {
+                radii,
                 offset,
                 flags,
                 material,
-            } => {
-                // println!("@@@ GeometryCommand::prepare: PasteCube");
-                self.cmd_impl = Some(GeometryImpl::paste_cube(
-                    device,
-                    get_bind_group_layout(Self::PASTE_CUBE_ENTRY_POINT),
-                    grid,
-                    *size,
+                mask_material,
+            } => radii.cmpgt(UVec3::ZERO).all().then(|| {
+                GeometryImpl::paste_ellipsoid_args(
+                    out_size,
+                    *radii,
                     *offset,
                     *flags,
                     *material,
-                ));
-            }
+                    *mask_material,
+                )
+            }),
 
-            GeometryOp::PasteSphere {
-                diameter,
+            GeometryOp::PasteCylinder {
+                radius,
+                height,
+                axis,
                 offset,
                 flags,
                 material,
-            } => {
-                // println!(
-                //     "@@@ GeometryCommand::prepare: PasteSphere: diameter: {}",
-                //     diameter
-                // );
-                self.cmd_impl = Some(GeometryImpl::paste_sphere(
-                    device,
-                    get_bind_group_layout(Self::PASTE_SPHERE_ENTRY_POINT),
-                    grid,
-                    *diameter,
+                mask_material,
+            } => Some(GeometryImpl::paste_cylinder_args(
+                out_size,
+                *radius,
+                *height,
+                *axis,
+                *offset,
+                *flags,
+                *material,
+                *mask_material,
+            )),
+
+            GeometryOp::PasteCone {
+                base_radius,
+                height,
+                axis,
+                offset,
+                flags,
+                material,
+                mask_material,
+            } => (*base_radius > 0 && *height > 0).then(|| {
+                GeometryImpl::paste_cone_args(
+                    out_size,
+                    *base_radius,
+                    *height,
+                    *axis,
                     *offset,
                     *flags,
                     *material,
-                ));
+                    *mask_material,
+                )
+            }),
+
+            GeometryOp::PasteTorus {
+                major_radius,
+                minor_radius,
+                axis,
+                offset,
+                flags,
+                material,
+                mask_material,
+            } => (*minor_radius > 0).then(|| {
+                GeometryImpl::paste_torus_args(
+                    out_size,
+                    *major_radius,
+                    *minor_radius,
+                    *axis,
+                    *offset,
+                    *flags,
+                    *material,
+                    *mask_material,
+                )
+            }),
+
+            GeometryOp::PasteWedge {
+                size,
+                axis,
+                offset,
+                flags,
+                material,
+                mask_material,
+            } => size.cmpgt(UVec3::ZERO).all().then(|| {
+                GeometryImpl::paste_wedge_args(
+                    out_size,
+                    *size,
+                    *axis,
+                    *offset,
+                    *flags,
+                    *material,
+                    *mask_material,
+                )
+            }),
+
+            GeometryOp::PasteLine {
+                start,
+                end,
+                radius,
+                flags,
+                material,
+                mask_material,
+            } => (*radius > 0).then(|| {
+                GeometryImpl::paste_line_args(
+                    out_size,
+                    *start,
+                    *end,
+                    *radius,
+                    *flags,
+                    *material,
+                    *mask_material,
+                )
+            }),
+
+            GeometryOp::PasteBox {
+                min,
+                max,
+                flags,
+                material,
+                mask_material,
+            } => {
+                let lo = min.min(*max).max(IVec3::ZERO);
+                let hi = max.max(*min).min(out_size.as_ivec3() - 1);
+                lo.cmple(hi).all().then(|| {
+                    GeometryImpl::paste_cube_args(
+                        out_size,
+                        (hi - lo + 1).as_uvec3(),
+                        lo,
+                        *flags,
+                        *material,
+                        *mask_material,
+                    )
+                })
             }
+
+            // new rejects the other ops
+            _ => None,
         }
     }
+}
+
+impl VoxelCommand for GeometryBatchCommand {
+    fn prepare<'a>(
+        &mut self,
+        device: &Device,
+        get_bind_group_layout: &mut dyn FnMut(&str) -> &'a BindGroupLayout,
+    ) {
+        let mut guard = self.grid.lock();
+        let grid = guard
+            .as_mut()
+            .expect("Missing grid in GeometryBatchCommand");
+        assert!(
+            grid.layout == GridLayout::Packed,
+            "GeometryBatchCommand needs a packed grid; split it after editing"
+        );
+        grid.offsets_written |= self.geometry.iter().any(GeometryOp::writes_offsets);
+        let grid = &*grid;
+        let shapes: Vec<_> = self
+            .geometry
+            .iter()
+            .filter_map(|op| Self::shape_args(op, grid.size))
+            .collect();
+        self.cmd_impl = if shapes.is_empty() {
+            None
+        } else {
+            Some(GeometryImpl::paste_batch(
+                device,
+                get_bind_group_layout(Self::ENTRY_POINT),
+                grid,
+                &shapes,
+            ))
+        };
+    }
 
     fn add_pass<'a>(
         &self,
         encoder: &mut CommandEncoder,
         get_pipeline: &mut dyn FnMut(&str) -> &'a ComputePipeline,
     ) {
-        // println!("@@@ GeometryCommand::add_pass");
-        let entry_point = match &self.geometry {
-            GeometryOp::PasteCube { .. } => Self::PASTE_CUBE_ENTRY_POINT,
-            GeometryOp::PasteSphere { .. } => Self::PASTE_SPHERE_ENTRY_POINT,
-        };
-        self.cmd_impl
-            .as_ref()
-            .unwrap()
-            .add_pass(get_pipeline(entry_point), encoder);
+        if let Some(cmd_impl) = &self.cmd_impl {
+            cmd_impl.add_pass(get_pipeline(Self::ENTRY_POINT), encoder);
+        }
     }
 
     fn add_copy(&self, _encoder: &mut CommandEncoder) {}
 
     fn async_finish(&mut self, mut done: Box<dyn FnMut(Result<(), BufferAsyncError>) + Send>) {
-        // println!("@@@ GeometryCommand::async_finish: nop");
         done(Ok(()));
     }
-} // impl Command for GeometryCommand
+} // impl Command for GeometryBatchCommand
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_size_round_trip() {
+        let size = GridSize::default();
+        assert_eq!(size.get(), None);
+        size.set(Some(UVec3::new(3, 1, 7)));
+        assert_eq!(size.get(), Some(UVec3::new(3, 1, 7)));
+        size.set(Some(UVec3::ZERO));
+        assert_eq!(size.get(), Some(UVec3::ZERO));
+        size.set(None);
+        assert_eq!(size.get(), None);
+    }
+
+    #[test]
+    fn batch_rejects_unsupported_ops() {
+        let sphere = GeometryOp::PasteSphere {
+            diameter: 4,
+            offset: IVec3::ZERO,
+            flags: PASTE,
+            material: 1,
+            mask_material: 0,
+        };
+        let remap = GeometryOp::RemapMaterial {
+            from: 1,
+            to: 2,
+            region: None,
+        };
+        let grid = SharedVoxelGrid::new();
+        assert!(GeometryBatchCommand::new(grid.clone(), vec![sphere.clone()]).is_some());
+        assert!(GeometryBatchCommand::new(grid, vec![sphere, remap]).is_none());
+    }
+
+    #[test]
+    fn writes_offsets_follows_flags() {
+        let sphere = |flags| GeometryOp::PasteSphere {
+            diameter: 4,
+            offset: IVec3::ZERO,
+            flags,
+            material: 1,
+            mask_material: 0,
+        };
+        assert!(sphere(PASTE).writes_offsets());
+        assert!(!sphere(PASTE_MATERIAL_FLAG).writes_offsets());
+        assert!(!GeometryOp::RemapMaterial {
+            from: 1,
+            to: 2,
+            region: None,
+        }
+        .writes_offsets());
+    }
+
+    #[test]
+    fn from_name_rejects_bad_params() {
+        let cylinder = |axis: f32, radius: f32| {
+            GeometryOp::from_name(
+                PASTE_CYLINDER_ENTRY_POINT,
+                &[radius, 4.0, axis, 0.0, -1.0, 0.0, PASTE as f32, 1.0],
+            )
+        };
+        assert!(cylinder(2.0, 3.0).is_some());
+        assert!(cylinder(7.0, 3.0).is_none());
+        assert!(cylinder(1.5, 3.0).is_none());
+        assert!(cylinder(-1.0, 3.0).is_none());
+        assert!(cylinder(f32::NAN, 3.0).is_none());
+        assert!(cylinder(0.0, -3.0).is_none());
+        assert!(cylinder(0.0, f32::INFINITY).is_none());
+        assert!(GeometryOp::from_name(
+            PASTE_NOISE_ENTRY_POINT,
+            &[
+                0.0,
+                0.0,
+                0.0,
+                8.0,
+                8.0,
+                8.0,
+                f32::NAN,
+                0.5,
+                1.0,
+                PASTE as f32,
+                1.0
+            ],
+        )
+        .is_none());
+    }
+}
@@ -1,9 +1,17 @@
 mod bevy_voxel;
+mod chunk;
 mod command;
+mod mesh_cpu;
 mod voxel;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub mod blocking;
+pub mod shape;
+
 pub use bevy_voxel::*;
+pub use chunk::*;
 pub use command::*;
+pub use mesh_cpu::*;
 pub use voxel::*;
 
 /// Unstable constants and types for communicating with the shaders.
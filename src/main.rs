@@ -19,7 +19,12 @@ fn main() {
         .add_plugins((
             DefaultPlugins.set(RenderPlugin {
                 wgpu_settings: WgpuSettings {
-                    features: WgpuFeatures::POLYGON_MODE_LINE,
+                    // WebGPU doesn't have this; requesting it fails the device
+                    features: if cfg!(target_arch = "wasm32") {
+                        WgpuFeatures::empty()
+                    } else {
+                        WgpuFeatures::POLYGON_MODE_LINE
+                    },
                     ..default()
                 },
             }),
@@ -27,7 +32,7 @@ fn main() {
             PanOrbitCameraPlugin,
             ScreenDiagnosticsPlugin::default(),
             ScreenFrameDiagnosticsPlugin,
-            VoxelPlugin,
+            VoxelPlugin::default(),
         ))
         .add_systems(Startup, setup)
         .add_systems(Update, generate_grid)
@@ -121,22 +126,18 @@ fn generate_grid(
     };
 
     let grid = SharedVoxelGrid::new();
-    *voxel_commands = vec![
-        CreateGridCommand::new(grid.clone(), stage.size).boxed(),
-        GeometryCommand::cube(grid.clone(), stage.size, default(), PASTE, 1).boxed(),
-    ];
+    let mut builder = VoxelCommandListBuilder::new()
+        .create_grid(grid.clone(), stage.size)
+        .cube(grid.clone(), stage.size, default(), PASTE, 1);
 
     for mut circle in circles.iter_mut() {
-        voxel_commands.push(
-            GeometryCommand::sphere(
-                grid.clone(),
-                circle.diameter as u32,
-                stage.size.as_ivec3() / 2 + circle.position.as_ivec3()
-                    - IVec3::splat(circle.diameter as i32 / 2),
-                PASTE,
-                0,
-            )
-            .boxed(),
+        builder = builder.sphere(
+            grid.clone(),
+            circle.diameter as u32,
+            stage.size.as_ivec3() / 2 + circle.position.as_ivec3()
+                - IVec3::splat(circle.diameter as i32 / 2),
+            PASTE,
+            0,
         );
         let speed = circle.speed;
         circle.position += speed;
@@ -151,7 +152,7 @@ fn generate_grid(
         }
     }
 
-    voxel_commands.push(generate_mesh.create_command(grid.clone()).boxed());
+    *voxel_commands = builder.generate_mesh(grid, generate_mesh).build();
     drop(voxel_commands);
     voxel_command_list.run_again();
 }
@@ -0,0 +1,260 @@
+//! Generate meshes on the CPU. [generate_mesh_cpu] repeats the shader's
+//! generate_mesh, so it's useful for golden tests and as a fallback where
+//! there's no GPU.
+
+use glam::{IVec3, UVec3, Vec3, Vec4};
+
+use crate::voxel::{unstable::*, *};
+
+/// Faces in the shader's order: (normal, corners). Each corner is 0 or 1
+/// on each axis. The quad is (c0, c1, c2, c2, c3, c0).
+const FACES: [(IVec3, [IVec3; 4]); FACES_PER_VOXEL] = [
+    (
+        IVec3::new(0, 0, 1),
+        [
+            IVec3::new(0, 0, 1),
+            IVec3::new(1, 0, 1),
+            IVec3::new(1, 1, 1),
+            IVec3::new(0, 1, 1),
+        ],
+    ), // z=1
+    (
+        IVec3::new(1, 0, 0),
+        [
+            IVec3::new(1, 0, 1),
+            IVec3::new(1, 0, 0),
+            IVec3::new(1, 1, 0),
+            IVec3::new(1, 1, 1),
+        ],
+    ), // x=1
+    (
+        IVec3::new(0, 0, -1),
+        [
+            IVec3::new(1, 0, 0),
+            IVec3::new(0, 0, 0),
+            IVec3::new(0, 1, 0),
+            IVec3::new(1, 1, 0),
+        ],
+    ), // z=0
+    (
+        IVec3::new(-1, 0, 0),
+        [
+            IVec3::new(0, 0, 0),
+            IVec3::new(0, 0, 1),
+            IVec3::new(0, 1, 1),
+            IVec3::new(0, 1, 0),
+        ],
+    ), // x=0
+    (
+        IVec3::new(0, 1, 0),
+        [
+            IVec3::new(0, 1, 1),
+            IVec3::new(1, 1, 1),
+            IVec3::new(1, 1, 0),
+            IVec3::new(0, 1, 0),
+        ],
+    ), // y=1
+    (
+        IVec3::new(0, -1, 0),
+        [
+            IVec3::new(0, 0, 0),
+            IVec3::new(1, 0, 0),
+            IVec3::new(1, 0, 1),
+            IVec3::new(0, 0, 1),
+        ],
+    ), // y=0
+];
+
+/// Matches the shader's unpack: unpack4x8snorm, then scale to voxel units
+fn unpack_corner(raw: u32) -> Vec3 {
+    let snorm = |byte: u32| (byte as u8 as i8 as f32 / 127.0).max(-1.0);
+    Vec3::new(
+        snorm(raw) * 127.0 / 64.0,
+        snorm(raw >> 8) * 127.0 / 64.0,
+        snorm(raw >> 16) * 127.0 / 64.0,
+    )
+}
+
+/// Matches WGSL's normalize
+fn normalize(v: Vec3) -> Vec3 {
+    v / v.length()
+}
+
+// Reads the grid the way generate_mesh reads voxel_grid_a
+struct MeshSource<'a> {
+    grid: &'a VoxelGridVec,
+    options: &'a GenerateMeshOptions,
+}
+
+impl<'a> MeshSource<'a> {
+    fn raw(&self, pos: IVec3) -> u32 {
+        self.grid.data[voxel_index_i32(self.grid.size, pos.x, pos.y, pos.z)]
+    }
+
+    // Matches mesh_material_a
+    fn neighbor_material(&self, pos: IVec3) -> u32 {
+        if self.options.close_boundary
+            && (pos.cmplt(IVec3::ZERO).any() || pos.cmpge(self.grid.size.as_ivec3()).any())
+        {
+            return 0;
+        }
+        self.raw(pos) >> 24
+    }
+
+    // Matches is_transparent and face_visible
+    fn face_visible(&self, material: u32, neighbor: u32) -> bool {
+        let transparent = |m: u32| self.options.transparent_mask[m as usize];
+        neighbor == 0 || transparent(material) != transparent(neighbor)
+    }
+
+    // Matches corner_ao
+    fn corner_ao(&self, pos: IVec3, normal: IVec3, corner: IVec3) -> f32 {
+        let n = normal.abs();
+        let u = IVec3::new(n.y, n.z, n.x);
+        let v = IVec3::new(n.z, n.x, n.y);
+        let t1 = u * (corner.dot(u) * 2 - 1);
+        let t2 = v * (corner.dot(v) * 2 - 1);
+        let solid = |p: IVec3| (self.neighbor_material(p) != 0) as u32;
+        let side1 = solid(pos + normal + t1);
+        let side2 = solid(pos + normal + t2);
+        let mut occlusion = side1 + side2 + solid(pos + normal + t1 + t2);
+        if side1 == 1 && side2 == 1 {
+            occlusion = 3;
+        }
+        1.0 - 0.2 * occlusion as f32
+    }
+}
+
+/// Convert a voxel grid to a mesh on the CPU. This repeats the shader's
+/// generate_mesh: the same face culling, corner offsets, ambient occlusion,
+/// and triangle split, using the same f32 operations in the same order,
+/// then the same post-processing as [GenerateMeshImpl::get_mesh_data].
+/// GPUs are allowed some latitude in `normalize` and `unpack4x8snorm`, so
+/// a GPU's output may differ from this in the last bit.
+///
+/// Panics if options.marching_cubes is set; the marching cubes tables
+/// only exist in the shader.
+pub fn generate_mesh_cpu(grid: &VoxelGridVec, options: &GenerateMeshOptions) -> MeshData {
+    assert!(
+        !options.marching_cubes,
+        "generate_mesh_cpu doesn't support marching_cubes"
+    );
+    let size = grid.size;
+    let num_voxels = size.x as usize * size.y as usize * size.z as usize;
+    let num_faces = num_voxels * FACES_PER_VOXEL;
+    let mut face_filled =
+        vec![0u32; (num_faces + FACE_FILLED_NUM_BITS as usize - 1) / FACE_FILLED_NUM_BITS as usize];
+    let mut vertexes = vec![Vec4::ZERO; num_faces * VERTEXES_PER_FACE];
+    let mut normals = vec![Vec4::ZERO; num_faces * VERTEXES_PER_FACE];
+    let mut materials = vec![0u32; num_faces];
+    let mut ao = vec![0f32; num_faces * VERTEXES_PER_FACE];
+
+    let source = MeshSource { grid, options };
+    let corner_scale = if options.ignore_offsets { 0.0 } else { 1.0 };
+    // Matches generate_mesh_region's clipping
+    let (region_begin, region_end) =
+        options
+            .region
+            .map_or((UVec3::ZERO, size), |(min, region_size)| {
+                let min = min.min(size);
+                (min, min + region_size.min(size - min))
+            });
+    for z in region_begin.z..region_end.z {
+        for y in region_begin.y..region_end.y {
+            for x in region_begin.x..region_end.x {
+                let pos = UVec3::new(x, y, z);
+                let pos_i32 = pos.as_ivec3();
+                let pos_f32 = pos.as_vec3();
+                let material = source.raw(pos_i32) >> 24;
+                if material == 0 {
+                    continue;
+                }
+                let point = |corner: IVec3| {
+                    corner.as_vec3() + unpack_corner(source.raw(pos_i32 + corner)) * corner_scale
+                };
+
+                for (face, (normal, corners)) in FACES.iter().enumerate() {
+                    if !source.face_visible(material, source.neighbor_material(pos_i32 + *normal)) {
+                        continue;
+                    }
+                    let q = corners.map(point);
+                    let c = if options.ambient_occlusion {
+                        corners.map(|corner| source.corner_ao(pos_i32, *normal, corner))
+                    } else {
+                        [1.0; 4]
+                    };
+
+                    // Matches write_face
+                    let (f, a) = if c[0] + c[2] < c[1] + c[3] {
+                        (
+                            [q[1], q[2], q[3], q[3], q[0], q[1]],
+                            [c[1], c[2], c[3], c[3], c[0], c[1]],
+                        )
+                    } else {
+                        (
+                            [q[0], q[1], q[2], q[2], q[3], q[0]],
+                            [c[0], c[1], c[2], c[2], c[3], c[0]],
+                        )
+                    };
+                    let index = face_index(size, x, y, z, face as u8);
+                    face_filled[index / FACE_FILLED_NUM_BITS as usize] |=
+                        1 << (index % FACE_FILLED_NUM_BITS as usize);
+                    materials[index] = material;
+                    let normal0 = normalize((f[1] - f[0]).cross(f[2] - f[0]));
+                    let normal1 = normalize((f[4] - f[3]).cross(f[5] - f[3]));
+                    let begin = index * VERTEXES_PER_FACE;
+                    for (i, (p, a)) in f.iter().zip(a).enumerate() {
+                        let n = if i < 3 { normal0 } else { normal1 };
+                        vertexes[begin + i] = (pos_f32 + *p).extend(0.0);
+                        normals[begin + i] = n.extend(0.0);
+                        ao[begin + i] = a;
+                    }
+                }
+            }
+        }
+    }
+
+    let faces = FaceArrays {
+        face_filled: &face_filled,
+        vertexes: &vertexes,
+        normals: &normals,
+        materials: &materials,
+        ao: &ao,
+        face_offsets: None,
+    };
+    let mesh = read_faces(size, &faces, options.greedy, options.offset_merge_epsilon);
+    format_mesh(mesh, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count_faces(mesh: &MeshData, direction: u32) -> usize {
+        mesh.directions.iter().filter(|d| **d == direction).count() / VERTEXES_PER_FACE
+    }
+
+    #[test]
+    fn thin_grid_has_top_and_bottom() {
+        let grid = VoxelGridVec::new(UVec3::new(3, 3, 1), 1);
+        let mesh = generate_mesh_cpu(&grid, &Default::default());
+        assert_eq!(mesh.face_voxels.len(), 9 + 9 + 4 * 3);
+        assert_eq!(count_faces(&mesh, FACE_DIRECTION_POS_Z), 9);
+        assert_eq!(count_faces(&mesh, FACE_DIRECTION_NEG_Z), 9);
+        for (v, d) in mesh.vertexes.iter().zip(&mesh.directions) {
+            match *d {
+                FACE_DIRECTION_POS_Z => assert_eq!(v.z, 1.0),
+                FACE_DIRECTION_NEG_Z => assert_eq!(v.z, 0.0),
+                _ => assert!(v.z == 0.0 || v.z == 1.0),
+            }
+        }
+
+        let options = GenerateMeshOptions {
+            greedy: true,
+            ..Default::default()
+        };
+        let mesh = generate_mesh_cpu(&grid, &options);
+        assert_eq!(count_faces(&mesh, FACE_DIRECTION_POS_Z), 1);
+        assert_eq!(count_faces(&mesh, FACE_DIRECTION_NEG_Z), 1);
+    }
+}
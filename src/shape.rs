@@ -0,0 +1,255 @@
+//! Generate shapes on the CPU. These produce the same voxels as pasting
+//! the matching shape into an empty grid on the GPU, so they're useful
+//! for testing without a GPU.
+
+use glam::{IVec3, UVec3, Vec3};
+
+use crate::voxel::*;
+
+/// Encode a corner delta into the 3 offset bytes of a voxel. Matches
+/// the shader's encode_corner.
+fn encode_corner(delta: Vec3) -> u32 {
+    let d = (delta * 64.0)
+        .round()
+        .clamp(Vec3::splat(-127.0), Vec3::splat(127.0));
+    (d.x as i32 as u32 & 0xff)
+        | ((d.y as i32 as u32 & 0xff) << 8)
+        | ((d.z as i32 as u32 & 0xff) << 16)
+}
+
+/// Create a solid box. Every voxel gets the material; the offsets are
+/// 0 since the faces are axis-aligned.
+///
+/// Panics if the size is too large.
+pub fn cube(size: UVec3, material: u8) -> VoxelGridVec {
+    VoxelGridVec::new(size, material)
+}
+
+/// Create a box with walls `wall` voxels thick. The inside is empty.
+///
+/// Panics if the size is too large.
+pub fn hollow_cube(size: UVec3, material: u8, wall: u32) -> VoxelGridVec {
+    let mut grid = VoxelGridVec::new(size, 0);
+    for z in 0..size.z {
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let pos = UVec3::new(x, y, z);
+                if pos.cmplt(UVec3::splat(wall)).any()
+                    || (0..3).any(|i| pos[i].saturating_add(wall) >= size[i])
+                {
+                    grid.set(x, y, z, (material as u32) << 24);
+                }
+            }
+        }
+    }
+    grid
+}
+
+fn ellipsoid_inside(pos: IVec3, radii: Vec3) -> bool {
+    let d = (pos.as_vec3() + 0.5 - radii) / radii;
+    d.dot(d) < 1.0
+}
+
+fn ellipsoid_include_vertex(pos: IVec3, radii: Vec3) -> bool {
+    let mut count = 0;
+    for z in -1..=0 {
+        for y in -1..=0 {
+            for x in -1..=0 {
+                count += ellipsoid_inside(pos + IVec3::new(x, y, z), radii) as u32;
+            }
+        }
+    }
+    count != 0 && count != 8
+}
+
+fn ellipsoid_vertex(pos: IVec3, radii: Vec3) -> u32 {
+    let d = pos.as_vec3() - radii;
+    if radii.x == radii.y && radii.y == radii.z {
+        // Sphere: move along the radius, like the shader's sphere_vertex
+        let factor = radii.x / d.length();
+        return encode_corner(d * factor - d);
+    }
+    // Newton steps along the gradient, like the shader's ellipsoid_vertex
+    let r2 = radii * radii;
+    let mut p = d;
+    for _ in 0..4 {
+        let f = (p * p / r2).dot(Vec3::ONE) - 1.0;
+        let g = 2.0 * p / r2;
+        let g2 = g.dot(g);
+        if g2 == 0.0 {
+            break;
+        }
+        p -= g * (f / g2);
+    }
+    encode_corner(p - d)
+}
+
+/// Fill an ellipsoid which touches each face of a grid of the given size
+fn ellipsoid_in_box(size: UVec3, material: u8) -> VoxelGridVec {
+    let radii = size.as_vec3() / 2.0;
+    let mut grid = VoxelGridVec::new(size, 0);
+    // Include ending padding so we get all vertexes
+    for z in 0..=size.z {
+        for y in 0..=size.y {
+            for x in 0..=size.x {
+                let pos = UVec3::new(x, y, z);
+                let index = voxel_index(grid.size, x, y, z);
+                if pos.cmplt(grid.size).all() && ellipsoid_inside(pos.as_ivec3(), radii) {
+                    grid.data[index] |= (material as u32) << 24;
+                }
+                if ellipsoid_include_vertex(pos.as_ivec3(), radii) {
+                    grid.data[index] =
+                        (grid.data[index] & 0xff000000) | ellipsoid_vertex(pos.as_ivec3(), radii);
+                }
+            }
+        }
+    }
+    grid
+}
+
+/// Create an ellipsoid with the given radii. The grid's size is `2 * radii`.
+/// Vertexes on the surface are moved onto the ellipsoid, like the shader's
+/// paste_ellipsoid.
+///
+/// Panics if any radius is 0 or the size is too large.
+pub fn ellipsoid(radii: UVec3, material: u8) -> VoxelGridVec {
+    assert!(
+        radii.cmpgt(UVec3::ZERO).all(),
+        "Ellipsoid radii must be non-0"
+    );
+    ellipsoid_in_box(radii * 2, material)
+}
+
+/// Create a sphere with the given diameter. The grid's size is the diameter.
+/// Vertexes on the surface are moved onto the sphere, like the shader's
+/// paste_sphere.
+///
+/// Panics if the size is too large.
+pub fn sphere_uniform(size: u32, material: u8) -> VoxelGridVec {
+    ellipsoid_in_box(UVec3::splat(size), material)
+}
+
+/// Fill voxels inside a closed triangle mesh. Each voxel is filled if its
+/// center is inside the mesh, using even-odd ray crossings along X.
+///
+/// * positions:  Vertex positions
+/// * indices:    3 per triangle
+/// * scale:      Multiplies positions; voxels are 1 unit apart
+/// * material:   Material to fill
+///
+/// Returns the grid, which covers the scaled mesh's bounding box, and the
+/// coordinates of the grid's lower-left corner. Returns None if the mesh
+/// has no triangles, or an error if an index is out of range.
+///
+/// Panics if the size is too large.
+pub fn voxelize_mesh(
+    positions: &[Vec3],
+    indices: &[u32],
+    scale: f32,
+    material: u8,
+) -> Result<Option<(VoxelGridVec, IVec3)>, VoxelGridError> {
+    let position = |index: u32| match positions.get(index as usize) {
+        Some(p) => Ok(*p * scale),
+        None => Err(VoxelGridError::IndexOutOfRange {
+            index,
+            len: positions.len(),
+        }),
+    };
+    let triangles = indices
+        .chunks_exact(3)
+        .map(|t| Ok([position(t[0])?, position(t[1])?, position(t[2])?]))
+        .collect::<Result<Vec<[Vec3; 3]>, _>>()?;
+    if triangles.is_empty() {
+        return Ok(None);
+    }
+    let mut min = Vec3::splat(f32::INFINITY);
+    let mut max = Vec3::splat(f32::NEG_INFINITY);
+    for v in triangles.iter().flatten() {
+        min = min.min(*v);
+        max = max.max(*v);
+    }
+    let min = min.floor();
+    let size = (max.ceil() - min).max(Vec3::ONE).as_uvec3();
+    let mut grid = VoxelGridVec::new(size, 0);
+    let mut crossings = Vec::new();
+    for z in 0..size.z {
+        for y in 0..size.y {
+            // Ray through the voxel centers, relative to min
+            let ray_y = y as f32 + 0.5;
+            let ray_z = z as f32 + 0.5;
+            crossings.clear();
+            for t in &triangles {
+                let [a, b, c] = t.map(|v| v - min);
+                // Barycentric coordinates of the ray in the YZ plane
+                let det = (b.y - a.y) * (c.z - a.z) - (c.y - a.y) * (b.z - a.z);
+                if det == 0.0 {
+                    continue;
+                }
+                let u = ((ray_y - a.y) * (c.z - a.z) - (c.y - a.y) * (ray_z - a.z)) / det;
+                let v = ((b.y - a.y) * (ray_z - a.z) - (ray_y - a.y) * (b.z - a.z)) / det;
+                if u < 0.0 || v < 0.0 || u + v > 1.0 {
+                    continue;
+                }
+                crossings.push(a.x + u * (b.x - a.x) + v * (c.x - a.x));
+            }
+            crossings.sort_by(|a, b| a.total_cmp(b));
+            for pair in crossings.chunks_exact(2) {
+                let begin = (pair[0] - 0.5).ceil().max(0.0) as u32;
+                let end = ((pair[1] - 0.5).ceil().max(0.0) as u32).min(size.x);
+                for x in begin..end {
+                    grid.set(x, y, z, (material as u32) << 24);
+                }
+            }
+        }
+    }
+    Ok(Some((grid, min.as_ivec3())))
+}
+
+/// Check that every index refers to one of `len` positions
+pub fn check_mesh_indices(len: usize, indices: &[u32]) -> Result<(), VoxelGridError> {
+    match indices.iter().find(|&&i| i as usize >= len) {
+        Some(&index) => Err(VoxelGridError::IndexOutOfRange { index, len }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thin_shapes() {
+        let size = UVec3::new(5, 4, 1);
+        assert_eq!(cube(size, 2).count_filled(), 20);
+        // Every voxel of a 1-thick box is in its top and bottom walls
+        assert_eq!(hollow_cube(size, 2, 1).count_filled(), 20);
+        // A wall near u32::MAX fills everything instead of overflowing
+        assert_eq!(hollow_cube(size, 2, u32::MAX).count_filled(), 20);
+
+        // Out-of-range mesh indices are an error instead of a panic
+        let positions = [Vec3::ZERO, Vec3::X, Vec3::Y];
+        assert_eq!(
+            voxelize_mesh(&positions, &[0, 1, 3], 1.0, 1).err(),
+            Some(VoxelGridError::IndexOutOfRange { index: 3, len: 3 })
+        );
+        assert_eq!(
+            check_mesh_indices(positions.len(), &[0, 1, 3]),
+            Err(VoxelGridError::IndexOutOfRange { index: 3, len: 3 })
+        );
+        assert!(voxelize_mesh(&positions, &[], 1.0, 1).unwrap().is_none());
+
+        // A 1-thick ellipsoid is a disk
+        let disk = ellipsoid_in_box(UVec3::new(6, 6, 1), 3);
+        let radii = Vec3::new(3.0, 3.0, 0.5);
+        for y in 0..6 {
+            for x in 0..6 {
+                let d = (Vec3::new(x as f32, y as f32, 0.0) + 0.5 - radii) / radii;
+                let expected = if d.dot(d) < 1.0 { 3 } else { 0 };
+                assert_eq!(disk.material(x, y, 0), expected, "({}, {})", x, y);
+                for z in [-1, 1] {
+                    assert_eq!(disk.try_get(x as i32, y as i32, z).unwrap() >> 24, 0);
+                }
+            }
+        }
+    }
+}
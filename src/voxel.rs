@@ -1,6 +1,16 @@
 use bytemuck::{cast_slice, checked::from_bytes_mut};
-use glam::{IVec3, UVec3, Vec3, Vec4};
-use std::{mem::size_of, num::NonZeroU64, sync::Arc};
+use glam::{IVec3, IVec4, Quat, UVec3, UVec4, Vec2, Vec3, Vec4};
+use parking_lot::Mutex;
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Read, Write},
+    mem::size_of,
+    num::NonZeroU64,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
 use wgpu::{
     BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
     BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferAsyncError, BufferBinding,
@@ -8,9 +18,110 @@ use wgpu::{
     ComputePipeline, Device, MapMode, ShaderStages,
 };
 
+/// The shader's source. It expects the `MESH_WORKGROUP_SIZE` and
+/// `PASTE_WORKGROUP_SIZE` shader defs. Custom entry points may be appended
+/// to it; they can use anything the shader declares.
+pub const VOX_WGSL: &str = include_str!("../assets/shaders/vox.wgsl");
+
+/// The shader's source, with the workgroup sizes from [unstable::set_workgroup_sizes]
+/// substituted. Bevy does this substitution when it loads the shader.
+pub fn shader_source() -> String {
+    VOX_WGSL
+        .replace(
+            "#{MESH_WORKGROUP_SIZE}",
+            &unstable::mesh_workgroup_size().to_string(),
+        )
+        .replace(
+            "#{PASTE_WORKGROUP_SIZE}",
+            &unstable::paste_workgroup_size().to_string(),
+        )
+}
+
 pub const GENERATE_MESH_ENTRY_POINT: &str = "generate_mesh";
+pub const GENERATE_MESH_MARCHING_CUBES_ENTRY_POINT: &str = "generate_mesh_marching_cubes";
+pub const GENERATE_MESH_REGION_ENTRY_POINT: &str = "generate_mesh_region";
+pub const COMPACT_MESH_ENTRY_POINT: &str = "compact_mesh";
 pub const PASTE_CUBE_ENTRY_POINT: &str = "paste_cube";
 pub const PASTE_SPHERE_ENTRY_POINT: &str = "paste_sphere";
+pub const PASTE_SPHERE_INSTANCED_ENTRY_POINT: &str = "paste_sphere_instanced";
+pub const PASTE_ELLIPSOID_ENTRY_POINT: &str = "paste_ellipsoid";
+pub const PASTE_CYLINDER_ENTRY_POINT: &str = "paste_cylinder";
+pub const PASTE_TORUS_ENTRY_POINT: &str = "paste_torus";
+pub const PASTE_WEDGE_ENTRY_POINT: &str = "paste_wedge";
+pub const PASTE_LINE_ENTRY_POINT: &str = "paste_line";
+pub const PASTE_CONE_ENTRY_POINT: &str = "paste_cone";
+pub const PASTE_NOISE_ENTRY_POINT: &str = "paste_noise";
+pub const PASTE_GRID_ENTRY_POINT: &str = "paste_grid";
+pub const PASTE_HEIGHTMAP_ENTRY_POINT: &str = "paste_heightmap";
+pub const PASTE_SLAB_ENTRY_POINT: &str = "paste_slab";
+pub const SMOOTH_ENTRY_POINT: &str = "smooth";
+pub const REMAP_MATERIAL_ENTRY_POINT: &str = "remap_material";
+pub const PASTE_BATCH_ENTRY_POINT: &str = "paste_batch";
+pub const SPLIT_MATERIALS_ENTRY_POINT: &str = "split_materials";
+
+const ENTRY_POINTS: &[&str] = &[
+    GENERATE_MESH_ENTRY_POINT,
+    GENERATE_MESH_MARCHING_CUBES_ENTRY_POINT,
+    GENERATE_MESH_REGION_ENTRY_POINT,
+    COMPACT_MESH_ENTRY_POINT,
+    PASTE_CUBE_ENTRY_POINT,
+    PASTE_SPHERE_ENTRY_POINT,
+    PASTE_SPHERE_INSTANCED_ENTRY_POINT,
+    PASTE_ELLIPSOID_ENTRY_POINT,
+    PASTE_CYLINDER_ENTRY_POINT,
+    PASTE_CONE_ENTRY_POINT,
+    PASTE_TORUS_ENTRY_POINT,
+    PASTE_WEDGE_ENTRY_POINT,
+    PASTE_LINE_ENTRY_POINT,
+    PASTE_NOISE_ENTRY_POINT,
+    PASTE_GRID_ENTRY_POINT,
+    PASTE_HEIGHTMAP_ENTRY_POINT,
+    PASTE_SLAB_ENTRY_POINT,
+    SMOOTH_ENTRY_POINT,
+    REMAP_MATERIAL_ENTRY_POINT,
+    PASTE_BATCH_ENTRY_POINT,
+    SPLIT_MATERIALS_ENTRY_POINT,
+];
+
+/// Every shader entry point. The Bevy plugin creates a pipeline for each,
+/// using [entry_point_bind_group_layout].
+pub fn geometry_entry_points() -> &'static [&'static str] {
+    ENTRY_POINTS
+}
+
+/// Create the bind group layout which an entry point in [geometry_entry_points]
+/// uses. Returns None if the entry point is unknown.
+pub fn entry_point_bind_group_layout(
+    device: &Device,
+    entry_point: &str,
+) -> Option<BindGroupLayout> {
+    match entry_point {
+        GENERATE_MESH_ENTRY_POINT
+        | GENERATE_MESH_MARCHING_CUBES_ENTRY_POINT
+        | GENERATE_MESH_REGION_ENTRY_POINT => Some(generate_mesh_bind_group_layout(device)),
+        COMPACT_MESH_ENTRY_POINT => Some(compact_mesh_bind_group_layout(device)),
+        PASTE_CUBE_ENTRY_POINT
+        | PASTE_SPHERE_ENTRY_POINT
+        | PASTE_ELLIPSOID_ENTRY_POINT
+        | PASTE_CYLINDER_ENTRY_POINT
+        | PASTE_CONE_ENTRY_POINT
+        | PASTE_TORUS_ENTRY_POINT
+        | PASTE_WEDGE_ENTRY_POINT
+        | PASTE_LINE_ENTRY_POINT
+        | PASTE_NOISE_ENTRY_POINT
+        | PASTE_SLAB_ENTRY_POINT
+        | REMAP_MATERIAL_ENTRY_POINT => Some(geometry_bind_group_layout(device)),
+        PASTE_GRID_ENTRY_POINT | SMOOTH_ENTRY_POINT | SPLIT_MATERIALS_ENTRY_POINT => {
+            Some(paste_grid_bind_group_layout(device))
+        }
+        PASTE_HEIGHTMAP_ENTRY_POINT => Some(paste_heightmap_bind_group_layout(device)),
+        PASTE_SPHERE_INSTANCED_ENTRY_POINT => {
+            Some(paste_sphere_instanced_bind_group_layout(device))
+        }
+        PASTE_BATCH_ENTRY_POINT => Some(paste_batch_bind_group_layout(device)),
+        _ => None,
+    }
+}
 
 pub mod unstable {
     use bytemuck::{Pod, Zeroable};
@@ -19,7 +130,7 @@ pub mod unstable {
     use super::*;
 
     #[repr(C)]
-    #[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Pod, Zeroable)]
+    #[derive(Debug, Copy, Clone, Default, PartialEq, Pod, Zeroable)]
     pub struct ShaderArgs {
         pub a_size: UVec3,
         pub _0: u32, // padding
@@ -33,8 +144,47 @@ pub mod unstable {
         pub flags: u32,
         pub material: u32,
         pub diameter: u32,
-        pub _4: u32,
-        pub _5: u32,
+        pub radius: u32,
+        pub height: u32,
+        pub axis: u32,
+        pub mask_material: u32,
+        pub shape: u32,     // paste_batch only
+        pub seed: u32,      // paste_noise only
+        pub rotation: Vec4, // quaternion
+        pub shape_size: Vec3,
+        pub frequency: f32, // paste_noise only
+        pub shape_center: Vec3,
+        pub threshold: f32, // paste_noise and smooth only
+        pub src_offset: IVec3,
+        pub _9: u32,
+        pub transparent: [UVec4; 2], // generate_mesh only; bit per material
+    }
+
+    /// Prints the fields without padding, 1 per line
+    impl std::fmt::Display for ShaderArgs {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            writeln!(f, "a_size:        {}", self.a_size)?;
+            writeln!(f, "b_size:        {}", self.b_size)?;
+            writeln!(f, "out_size:      {}", self.out_size)?;
+            writeln!(f, "offset:        {}", self.offset)?;
+            writeln!(f, "size:          {}", self.size)?;
+            writeln!(f, "flags:         {:#x}", self.flags)?;
+            writeln!(f, "material:      {}", self.material)?;
+            writeln!(f, "diameter:      {}", self.diameter)?;
+            writeln!(f, "radius:        {}", self.radius)?;
+            writeln!(f, "height:        {}", self.height)?;
+            writeln!(f, "axis:          {}", self.axis)?;
+            writeln!(f, "mask_material: {}", self.mask_material)?;
+            writeln!(f, "shape:         {}", self.shape)?;
+            writeln!(f, "seed:          {}", self.seed)?;
+            writeln!(f, "rotation:      {}", self.rotation)?;
+            writeln!(f, "shape_size:    {}", self.shape_size)?;
+            writeln!(f, "frequency:     {}", self.frequency)?;
+            writeln!(f, "shape_center:  {}", self.shape_center)?;
+            writeln!(f, "threshold:     {}", self.threshold)?;
+            writeln!(f, "src_offset:    {}", self.src_offset)?;
+            write!(f, "transparent:   {:x?}", self.transparent)
+        }
     }
 
     pub const WGSL_ARGS_BINDING: u32 = 0;
@@ -44,6 +194,13 @@ pub mod unstable {
     pub const WGSL_FACE_FILLED_BINDING: u32 = 4;
     pub const WGSL_MESH_BINDING: u32 = 5;
     pub const WGSL_MESH_NORMALS_BINDING: u32 = 6;
+    pub const WGSL_MESH_MATERIALS_BINDING: u32 = 7;
+    pub const WGSL_MESH_AO_BINDING: u32 = 8;
+    pub const WGSL_HEIGHTS_BINDING: u32 = 9;
+    pub const WGSL_BATCH_ARGS_BINDING: u32 = 10;
+    pub const WGSL_INSTANCE_OFFSETS_BINDING: u32 = 11;
+    pub const WGSL_FACE_OFFSETS_BINDING: u32 = 12;
+    pub const WGSL_PACKED_FACES_BINDING: u32 = 13;
 
     pub const WGSL_VEC3_STRIDE: usize = size_of::<Vec4>(); // WGSL pads vec3
     pub const WGSL_FACE_STRIDE: usize = WGSL_VEC3_STRIDE * VERTEXES_PER_FACE;
@@ -52,13 +209,71 @@ pub mod unstable {
     pub const VERTEXES_PER_FACE: usize = 6;
     pub const FACES_PER_VOXEL: usize = 6;
     pub const FACE_FILLED_NUM_BITS: u32 = 30;
-    pub const GENERATE_MESH_WORKGROUP_SIZE: u32 = 64;
+    pub const STORAGE_OFFSET_ALIGNMENT: usize = 256;
+    pub const DEFAULT_MESH_WORKGROUP_SIZE: u32 = 64;
+    pub const MAX_WORKGROUPS_PER_DIMENSION: u32 = 65535;
     pub const GENERATE_MESH_VOXELS_PER_INVOCATION: u32 = 5;
-    pub const GENERATE_MESH_VOXELS_PER_WORKGROUP: u32 =
-        GENERATE_MESH_VOXELS_PER_INVOCATION * GENERATE_MESH_WORKGROUP_SIZE;
+    pub const MARCHING_CUBES_VERTEXES_PER_CELL: usize = 15;
+    pub const GENERATE_MESH_AO_FLAG: u32 = 1;
+    pub const GENERATE_MESH_IGNORE_OFFSETS_FLAG: u32 = 2;
+    pub const GENERATE_MESH_CLOSE_BOUNDARY_FLAG: u32 = 4;
+    pub const GENERATE_MESH_SPLIT_LAYOUT_FLAG: u32 = 8;
+    pub const GENERATE_MESH_SPLIT_OFFSETS_FLAG: u32 = 16;
+
+    pub const DEFAULT_PASTE_WORKGROUP_SIZE: u32 = 64;
+
+    // Values of ShaderArgs::shape for paste_batch
+    pub const SHAPE_CUBE: u32 = 0;
+    pub const SHAPE_SPHERE: u32 = 1;
+    pub const SHAPE_ELLIPSOID: u32 = 2;
+    pub const SHAPE_CYLINDER: u32 = 3;
+    pub const SHAPE_TORUS: u32 = 4;
+    pub const SHAPE_WEDGE: u32 = 5;
+    pub const SHAPE_LINE: u32 = 6;
+    pub const SHAPE_CONE: u32 = 7;
+
+    static MESH_WORKGROUP_SIZE: AtomicU32 = AtomicU32::new(DEFAULT_MESH_WORKGROUP_SIZE);
+    static PASTE_WORKGROUP_SIZE: AtomicU32 = AtomicU32::new(DEFAULT_PASTE_WORKGROUP_SIZE);
+
+    /// Set the workgroup sizes used to compute the number of workgroups to
+    /// dispatch. These must match the `MESH_WORKGROUP_SIZE` and
+    /// `PASTE_WORKGROUP_SIZE` shader defs the pipelines were created with.
+    /// [crate::bevy_voxel::VoxelPlugin] calls this.
+    pub fn set_workgroup_sizes(mesh: u32, paste: u32) {
+        assert!(mesh > 0 && paste > 0, "Workgroup sizes must be non-0");
+        MESH_WORKGROUP_SIZE.store(mesh, Ordering::Relaxed);
+        PASTE_WORKGROUP_SIZE.store(paste, Ordering::Relaxed);
+    }
+
+    /// Workgroup size of generate_mesh
+    pub fn mesh_workgroup_size() -> u32 {
+        MESH_WORKGROUP_SIZE.load(Ordering::Relaxed)
+    }
 
-    pub const PASTE_CUBE_VOXELS_PER_WORKGROUP: u32 = 64;
-    pub const PASTE_SPHERE_VOXELS_PER_WORKGROUP: u32 = 64;
+    /// Workgroup size of the paste functions
+    pub fn paste_workgroup_size() -> u32 {
+        PASTE_WORKGROUP_SIZE.load(Ordering::Relaxed)
+    }
+
+    /// Number of workgroups needed to cover num_items with the paste functions
+    pub fn paste_workgroups(num_items: u32) -> u32 {
+        (num_items + paste_workgroup_size() - 1) / paste_workgroup_size()
+    }
+
+    /// Split a workgroup count across x, y, and z so no dimension exceeds
+    /// [MAX_WORKGROUPS_PER_DIMENSION]. This may dispatch a few extra
+    /// workgroups; the shaders rebuild the linear index from
+    /// `global_invocation_id` and `num_workgroups`, then skip indexes past
+    /// the end.
+    pub fn split_workgroups(count: u32) -> UVec3 {
+        if count == 0 {
+            return UVec3::new(0, 1, 1);
+        }
+        let x = count.min(MAX_WORKGROUPS_PER_DIMENSION);
+        let rest = (count + x - 1) / x;
+        let y = rest.min(MAX_WORKGROUPS_PER_DIMENSION);
+        UVec3::new(x, y, (rest + y - 1) / y)
+    }
 }
 
 use unstable::*;
@@ -66,7 +281,27 @@ use unstable::*;
 pub const PASTE_MATERIAL_FLAG: u32 = 1;
 pub const PASTE_MATERIAL_ARG_FLAG: u32 = 2;
 pub const PASTE_VERTEXES_FLAG: u32 = 4;
+pub const PASTE_ERASE_FLAG: u32 = 8;
+pub const PASTE_MASK_FLAG: u32 = 16;
 pub const PASTE: u32 = PASTE_MATERIAL_FLAG | PASTE_VERTEXES_FLAG;
+pub const ERASE: u32 = PASTE_ERASE_FLAG | PASTE_VERTEXES_FLAG;
+
+/// Result of [VoxelGridVec::raycast]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    /// Coordinates of the voxel which was hit
+    pub voxel: UVec3,
+
+    /// Material of the voxel which was hit
+    pub material: u8,
+
+    /// Normal of the face the ray entered through. 0 if the ray started
+    /// inside the voxel.
+    pub normal: IVec3,
+
+    /// Distance along the ray to the entry point, in voxels
+    pub distance: f32,
+}
 
 /// Voxels stored in a [Vec].
 ///
@@ -83,10 +318,14 @@ pub const PASTE: u32 = PASTE_MATERIAL_FLAG | PASTE_VERTEXES_FLAG;
 /// The voxels are packed by x, then y, then z. Each dimension is padded on both
 /// sides by 1 voxel. The offsets at the start padding don't matter. The
 /// offsets at the end padding complete the voxel bounds. Non-0 material in padding
-/// excludes the faces at the edges of the voxel grid.
+/// excludes the faces at the edges of the voxel grid, unless
+/// [GenerateMeshOptions::close_boundary] is set.
 ///
 /// `index = (x + 1) + (y + 1) * (size.x + 2) + (z + 1) * (size.x + 2) * (size.y + 2)`,
 /// where `0,0,0` is the lower-left voxel, skipping padding.
+///
+/// A dimension may be 1, e.g. `UVec3::new(w, h, 1)` for a 2D tile map. The
+/// padding on both sides of the layer is what gives it top and bottom faces.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct VoxelGridVec {
     /// Size of the voxel grid, excluding padding
@@ -116,556 +355,4368 @@ impl VoxelGridVec {
         }
         Self { size, data }
     }
-}
 
-fn check_grid_size(size: UVec3) -> (usize, usize) {
-    if size.x >= (i32::MAX - 2) as u32
-        || size.y >= (i32::MAX - 2) as u32
-        || size.z >= (i32::MAX - 2) as u32
-    {
-        panic!("Voxel grid size is too large");
+    fn checked_index(&self, x: u32, y: u32, z: u32) -> usize {
+        if x >= self.size.x || y >= self.size.y || z >= self.size.z {
+            panic!(
+                "Voxel coordinates ({}, {}, {}) are out of range for grid size {}",
+                x, y, z, self.size
+            );
+        }
+        voxel_index(self.size, x, y, z)
     }
-    let vec_size = (size.x as usize + 2) * (size.y as usize + 2) * (size.z as usize + 2);
-    let buf_size = vec_size * size_of::<u32>();
-    if buf_size >= i32::MAX as usize {
-        panic!("Voxel grid size is too large");
+
+    /// Get the raw voxel at the given coordinates. `0,0,0` is the first
+    /// voxel, skipping padding.
+    ///
+    /// Panics if the coordinates are out of range.
+    pub fn get(&self, x: u32, y: u32, z: u32) -> u32 {
+        self.data[self.checked_index(x, y, z)]
     }
-    (vec_size, buf_size)
-}
 
-/// Get the length of the data vector for a voxel grid with the given size.
-/// The size does not include padding, but the returned value does.
-///
-/// Panics if the size is too large.
-pub fn get_vec_size(size: UVec3) -> usize {
-    check_grid_size(size).0
-}
+    /// Set the raw voxel at the given coordinates. `0,0,0` is the first
+    /// voxel, skipping padding.
+    ///
+    /// Panics if the coordinates are out of range.
+    pub fn set(&mut self, x: u32, y: u32, z: u32, value: u32) {
+        let index = self.checked_index(x, y, z);
+        self.data[index] = value;
+    }
 
-/// Get the length of the gpu buffer, in bytes, for a voxel grid with the given size.
-/// The size does not include padding, but the returned value does.
-///
-/// Panics if the size is too large.
-pub fn get_buf_size(size: UVec3) -> usize {
-    check_grid_size(size).1
-}
+    /// Get the raw voxel at the given coordinates, including the padding:
+    /// -1 and `size.<c>` are padding. Returns None if the coordinates are
+    /// out of range.
+    pub fn try_get(&self, x: i32, y: i32, z: i32) -> Option<u32> {
+        voxel_index_checked(self.size, x, y, z).map(|index| self.data[index])
+    }
 
-/// Get the index of a voxel in the data vector. `0,0,0` gets the first voxel,
-/// skipping the padding. `size.<c>` for coordinate `c` (x, y, or z) gets ending padding.
-///
-/// This function doesn't check for out-of-bounds coordinates.
-pub fn voxel_index(size: UVec3, x: u32, y: u32, z: u32) -> usize {
-    ((x + 1) + (y + 1) * (size.x + 2) + (z + 1) * (size.x + 2) * (size.y + 2)) as usize
-}
+    /// Set the raw voxel at the given coordinates, including the padding;
+    /// see [VoxelGridVec::try_get]. Returns false, without changing the
+    /// grid, if the coordinates are out of range.
+    pub fn try_set(&mut self, x: i32, y: i32, z: i32, value: u32) -> bool {
+        match voxel_index_checked(self.size, x, y, z) {
+            Some(index) => {
+                self.data[index] = value;
+                true
+            }
+            None => false,
+        }
+    }
 
-/// Get the index of a voxel in the data vector. `0,0,0` gets the first voxel,
-/// skipping the padding. -1 for any coordinate gets beginning padding.
-/// `size.<c>` for coordinate `c` (x, y, or z) gets ending padding.
-///
-/// This function doesn't check for out-of-bounds coordinates.
-pub fn voxel_index_i32(size: UVec3, x: i32, y: i32, z: i32) -> usize {
-    ((x + 1) + (y + 1) * (size.x as i32 + 2) + (z + 1) * (size.x as i32 + 2) * (size.y as i32 + 2))
-        as usize
-}
+    /// Get the material of the voxel at the given coordinates. 0 means empty.
+    ///
+    /// Panics if the coordinates are out of range.
+    pub fn material(&self, x: u32, y: u32, z: u32) -> u8 {
+        (self.get(x, y, z) >> 24) as u8
+    }
 
-/// Voxels readable and writable by the GPU. See [VoxelGridContent] for the format.
-#[derive(Debug)]
-pub struct VoxelGrid {
-    /// Size of the voxel grid, excluding padding
-    pub size: UVec3,
+    /// Get the offset of the voxel's lower-left corner at the given coordinates,
+    /// in units of 1/64. 0x80 is treated as 0x81, so each component is in
+    /// the range `[-127, 127]`.
+    ///
+    /// Panics if the coordinates are out of range.
+    pub fn offset(&self, x: u32, y: u32, z: u32) -> IVec3 {
+        let raw = self.get(x, y, z);
+        let decode = |byte: u32| (byte as u8 as i8).max(-127) as i32;
+        IVec3::new(decode(raw), decode(raw >> 8), decode(raw >> 16))
+    }
 
-    /// Voxel data, including padding. Usage flags are
-    /// `[BufferUsages::STORAGE] | [BufferUsages::COPY_SRC]`.
-    pub buffer: Buffer,
-}
+    /// Returns true if no voxel has a material. Padding is ignored.
+    pub fn is_empty(&self) -> bool {
+        for z in 0..self.size.z {
+            for y in 0..self.size.y {
+                for x in 0..self.size.x {
+                    if self.data[voxel_index(self.size, x, y, z)] >> 24 != 0 {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
 
-impl VoxelGrid {
-    /// Create a new voxel grid with the given size. The size does
-    /// not include padding, but the result includes it.
-    ///
-    /// Panics if the size is too large.
-    pub fn new(size: UVec3, device: &Device, mapped_at_creation: bool) -> Self {
-        // println!(
-        //     "** VoxelGrid::new {} {} {}",
-        //     size,
-        //     get_buf_size(size),
-        //     mapped_at_creation
-        // );
-        let buffer = device.create_buffer(&BufferDescriptor {
-            label: Some("voxel_grid_buffer"),
-            size: get_buf_size(size) as u64,
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
-            mapped_at_creation,
-        });
-        // println!("** buffer {} {:?}", buffer.size(), buffer.usage());
-        Self { size, buffer }
+    /// Call f with the coordinates and material of every voxel which has a
+    /// material. Visits x fastest, then y, then z. Padding is skipped.
+    pub fn for_each_filled(&self, mut f: impl FnMut(UVec3, u8)) {
+        for z in 0..self.size.z {
+            for y in 0..self.size.y {
+                for x in 0..self.size.x {
+                    let material = (self.data[voxel_index(self.size, x, y, z)] >> 24) as u8;
+                    if material != 0 {
+                        f(UVec3::new(x, y, z), material);
+                    }
+                }
+            }
+        }
     }
 
-    /// Create a new voxel grid and copy the given content into it.
-    pub fn from_content(content: &VoxelGridVec, device: &Device) -> Self {
-        let buffer = Self::new(content.size, device, true);
-        buffer
-            .buffer
-            .slice(..)
-            .get_mapped_range_mut()
-            .copy_from_slice(cast_slice::<u32, u8>(&content.data));
-        buffer.buffer.unmap();
-        buffer
+    /// Count the voxels which have a material. Padding is ignored.
+    pub fn count_filled(&self) -> usize {
+        let mut count = 0;
+        self.for_each_filled(|_, _| count += 1);
+        count
     }
-}
 
-/// Create BindGroupLayout for the shader's generate_mesh function.
-pub fn generate_mesh_bind_group_layout(device: &Device) -> BindGroupLayout {
-    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-        label: Some("generate_mesh_bind_group_layout"),
-        entries: &[
-            BindGroupLayoutEntry {
-                binding: WGSL_ARGS_BINDING,
-                visibility: ShaderStages::COMPUTE,
-                ty: BindingType::Buffer {
-                    ty: BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-            BindGroupLayoutEntry {
-                binding: WGSL_VOXEL_GRID_A_BINDING,
-                visibility: ShaderStages::COMPUTE,
-                ty: BindingType::Buffer {
-                    ty: BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-            BindGroupLayoutEntry {
-                binding: WGSL_FACE_FILLED_BINDING,
-                visibility: ShaderStages::COMPUTE,
-                ty: BindingType::Buffer {
-                    ty: BufferBindingType::Storage { read_only: false },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-            BindGroupLayoutEntry {
-                binding: WGSL_MESH_BINDING,
-                visibility: ShaderStages::COMPUTE,
-                ty: BindingType::Buffer {
-                    ty: BufferBindingType::Storage { read_only: false },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-            BindGroupLayoutEntry {
-                binding: WGSL_MESH_NORMALS_BINDING,
-                visibility: ShaderStages::COMPUTE,
-                ty: BindingType::Buffer {
-                    ty: BufferBindingType::Storage { read_only: false },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-        ],
-    })
-}
+    /// Count the voxels which have a material, e.g. for resource amounts.
+    /// The same as [VoxelGridVec::count_filled].
+    pub fn volume(&self) -> u64 {
+        self.count_filled() as u64
+    }
 
-/// Use the the shader's generate_mesh function to convert a
-/// voxel grid in VoxelGridBuffer to a mesh.
-///
-/// Call the following in order:
-/// * `[new]`
-/// * `[add_pass]`
-/// * `[add_copy]`. This may be on a different queue, but the
-///   copy's execution must happen after the pass's execution.
-/// * `[async_map_buffer]`. Only call this after the copy has
-///   finished executing on the GPU.
-/// * `[get_mesh]`. Only call this after async_map_buffer has
-///   called its callback.
-#[derive(Debug)]
-pub struct GenerateMeshImpl {
-    // Excludes padding
-    num_voxels: usize,
+    /// Count the faces of filled voxels whose neighbor is empty. This is the
+    /// number of faces generate_mesh emits with the default options; the
+    /// padding counts as a neighbor, so it's usually empty.
+    pub fn surface_area(&self) -> u64 {
+        const NEIGHBORS: [IVec3; 6] = [
+            IVec3::X,
+            IVec3::NEG_X,
+            IVec3::Y,
+            IVec3::NEG_Y,
+            IVec3::Z,
+            IVec3::NEG_Z,
+        ];
+        let mut count = 0;
+        self.for_each_filled(|pos, _| {
+            for n in NEIGHBORS {
+                let n = pos.as_ivec3() + n;
+                if self.data[voxel_index_i32(self.size, n.x, n.y, n.z)] >> 24 == 0 {
+                    count += 1;
+                }
+            }
+        });
+        count
+    }
 
-    // Offset of normals in storage_buffer
-    normals_offset: usize,
+    /// Get the inclusive (min, max) coordinates of voxels which have a
+    /// material, or None if the grid is empty. Padding is ignored.
+    pub fn bounds(&self) -> Option<(UVec3, UVec3)> {
+        let mut result: Option<(UVec3, UVec3)> = None;
+        for z in 0..self.size.z {
+            for y in 0..self.size.y {
+                for x in 0..self.size.x {
+                    if self.data[voxel_index(self.size, x, y, z)] >> 24 == 0 {
+                        continue;
+                    }
+                    let pos = UVec3::new(x, y, z);
+                    result = match result {
+                        None => Some((pos, pos)),
+                        Some((min, max)) => Some((min.min(pos), max.max(pos))),
+                    };
+                }
+            }
+        }
+        result
+    }
 
-    // Offset of face_filled in storage_buffer
-    face_filled_offset: usize,
+    /// Find the first voxel with a material along a ray. Voxels are unit
+    /// cubes with `0,0,0` at the lower-left corner of the grid; offsets are
+    /// ignored. Uses a DDA traversal, so every voxel the ray touches is
+    /// checked in order.
+    ///
+    /// * origin:     Start of the ray, in the grid's coordinates. May be outside the grid.
+    /// * dir:        Direction of the ray. Doesn't need to be normalized.
+    /// * max_dist:   Stop after this distance, in voxels
+    pub fn raycast(&self, origin: Vec3, dir: Vec3, max_dist: f32) -> Option<RayHit> {
+        // An empty grid has no voxels to hit
+        if self.size.cmpeq(UVec3::ZERO).any() {
+            return None;
+        }
+        let dir = dir.try_normalize()?;
+        let size = self.size.as_vec3();
 
-    // Size of storage_buffer and copy_buffer
-    buffer_size: usize,
+        // Clip the ray to the grid's bounds
+        let mut t_enter = 0.0;
+        let mut t_exit = max_dist;
+        let mut normal = IVec3::ZERO;
+        for axis in 0..3 {
+            if dir[axis] == 0.0 {
+                if origin[axis] < 0.0 || origin[axis] >= size[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let a = -origin[axis] / dir[axis];
+            let b = (size[axis] - origin[axis]) / dir[axis];
+            if a.min(b) > t_enter {
+                t_enter = a.min(b);
+                normal = IVec3::ZERO;
+                normal[axis] = if dir[axis] > 0.0 { -1 } else { 1 };
+            }
+            t_exit = t_exit.min(a.max(b));
+        }
+        if t_enter > t_exit {
+            return None;
+        }
 
-    // Receives the raw mesh from the shader. STORAGE | COPY_SRC
-    storage_buffer: Buffer,
+        let pos = origin + dir * t_enter;
+        let mut voxel = pos
+            .floor()
+            .as_ivec3()
+            .clamp(IVec3::ZERO, self.size.as_ivec3() - 1);
+        let step = IVec3::new(
+            dir.x.signum() as i32 * (dir.x != 0.0) as i32,
+            dir.y.signum() as i32 * (dir.y != 0.0) as i32,
+            dir.z.signum() as i32 * (dir.z != 0.0) as i32,
+        );
+        let t_delta = dir.recip().abs();
+        let mut t_max = Vec3::splat(f32::INFINITY);
+        for axis in 0..3 {
+            if step[axis] > 0 {
+                t_max[axis] = t_enter + ((voxel[axis] + 1) as f32 - pos[axis]) / dir[axis];
+            } else if step[axis] < 0 {
+                t_max[axis] = t_enter + (voxel[axis] as f32 - pos[axis]) / dir[axis];
+            }
+        }
 
-    // Copy of storage_buffer. COPY_DST | MAP_READ
-    copy_buffer: Arc<Buffer>,
+        let mut t = t_enter;
+        loop {
+            if voxel.cmplt(IVec3::ZERO).any() || voxel.cmpge(self.size.as_ivec3()).any() {
+                return None;
+            }
+            let v = voxel.as_uvec3();
+            let material = self.material(v.x, v.y, v.z);
+            if material != 0 {
+                return Some(RayHit {
+                    voxel: v,
+                    material,
+                    normal,
+                    distance: t,
+                });
+            }
+            let axis = if t_max.x < t_max.y {
+                if t_max.x < t_max.z {
+                    0
+                } else {
+                    2
+                }
+            } else if t_max.y < t_max.z {
+                1
+            } else {
+                2
+            };
+            t = t_max[axis];
+            if t > t_exit {
+                return None;
+            }
+            voxel[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+            normal = IVec3::ZERO;
+            normal[axis] = -step[axis];
+        }
+    }
 
-    bind_group: BindGroup,
-}
+    /// Replace the material of every voxel reachable from seed through
+    /// 6-connected neighbors which have from_material. Offsets are kept.
+    /// Does nothing if seed is out of range, seed's material isn't
+    /// from_material, or from_material == to_material.
+    ///
+    /// Returns the inclusive (min, max) coordinates of the changed voxels,
+    /// or None if nothing changed.
+    pub fn flood_fill(
+        &mut self,
+        seed: IVec3,
+        from_material: u8,
+        to_material: u8,
+    ) -> Option<(UVec3, UVec3)> {
+        if from_material == to_material
+            || seed.cmplt(IVec3::ZERO).any()
+            || seed.cmpge(self.size.as_ivec3()).any()
+        {
+            return None;
+        }
+        let seed = seed.as_uvec3();
+        if self.material(seed.x, seed.y, seed.z) != from_material {
+            return None;
+        }
+        let mut result = (seed, seed);
+        let mut stack = vec![seed];
+        let index = voxel_index(self.size, seed.x, seed.y, seed.z);
+        self.data[index] = (self.data[index] & 0x00ff_ffff) | ((to_material as u32) << 24);
+        while let Some(pos) = stack.pop() {
+            result = (result.0.min(pos), result.1.max(pos));
+            for (axis, dir) in [(0, -1), (0, 1), (1, -1), (1, 1), (2, -1), (2, 1)] {
+                let mut next = pos.as_ivec3();
+                next[axis] += dir;
+                if next[axis] < 0 || next[axis] >= self.size[axis] as i32 {
+                    continue;
+                }
+                let next = next.as_uvec3();
+                let index = voxel_index(self.size, next.x, next.y, next.z);
+                if (self.data[index] >> 24) as u8 == from_material {
+                    // Change it now so it's only pushed once
+                    self.data[index] =
+                        (self.data[index] & 0x00ff_ffff) | ((to_material as u32) << 24);
+                    stack.push(next);
+                }
+            }
+        }
+        Some(result)
+    }
 
-pub fn vec4_to_3(v: &Vec4) -> Vec3 {
-    Vec3::new(v.x, v.y, v.z)
-}
+    /// Convert the content of a [GridLayout::Split] grid's buffers, read
+    /// back from the GPU. materials is [VoxelGrid::buffer]'s content and
+    /// offsets is [VoxelGrid::offsets]'s, if the grid has them; otherwise
+    /// every offset is 0.
+    ///
+    /// Panics if the size is too large or a buffer is too short for it.
+    pub fn from_split(size: UVec3, materials: &[u8], offsets: Option<&[u32]>) -> Self {
+        let vec_size = get_vec_size(size);
+        let data = match offsets {
+            Some(offsets) => materials[..vec_size]
+                .iter()
+                .zip(&offsets[..vec_size])
+                .map(|(m, o)| ((*m as u32) << 24) | (o & 0x00ff_ffff))
+                .collect(),
+            None => materials[..vec_size]
+                .iter()
+                .map(|m| (*m as u32) << 24)
+                .collect(),
+        };
+        Self { size, data }
+    }
 
-impl GenerateMeshImpl {
-    /// Create buffers and bind group
-    pub fn new(
-        device: &Device,
-        bind_group_layout: &BindGroupLayout,
-        grid_buffer: &VoxelGrid,
-    ) -> Self {
-        // println!("** GenerateMeshImpl::new");
-        let num_voxels =
-            grid_buffer.size.x as usize * grid_buffer.size.y as usize * grid_buffer.size.z as usize;
-        // println!("   num_voxels: {:?}", num_voxels);
-        let normals_offset = num_voxels * WGSL_FACES_STRIDE;
-        let face_filled_offset = normals_offset + num_voxels * WGSL_FACES_STRIDE;
-        // println!("   face_filled_offset: {:?}", face_filled_offset);
-        let num_faces = num_voxels * FACES_PER_VOXEL;
-        let buffer_size = face_filled_offset
-            + (num_faces + FACE_FILLED_NUM_BITS as usize - 1) / FACE_FILLED_NUM_BITS as usize * 4;
-        // println!(
-        //     "    grid_buffer {} {:?}",
-        //     grid_buffer.buffer.size(),
-        //     grid_buffer.buffer.usage()
-        // );
+    /// Copy the inclusive region `[min, max]` into a new grid. The region
+    /// is clamped to the grid's size; the result is empty if the region is.
+    /// Offsets are kept. The end padding gets the offsets of the voxels
+    /// just past the region, so the edge voxels keep their bounds, but no
+    /// material, so faces at the edges are still generated.
+    ///
+    /// Panics if the size is too large.
+    pub fn crop(&self, min: UVec3, max: UVec3) -> VoxelGridVec {
+        let end = self.size.min(max.min(UVec3::splat(u32::MAX - 1)) + 1);
+        let min = min.min(end);
+        let mut result = VoxelGridVec::new(end - min, 0);
+        // Include ending padding so we get all vertexes
+        for z in 0..=result.size.z {
+            for y in 0..=result.size.y {
+                for x in 0..=result.size.x {
+                    let pos = UVec3::new(x, y, z);
+                    let src = min + pos;
+                    let mut value = self.data[voxel_index(self.size, src.x, src.y, src.z)];
+                    if pos.cmpeq(result.size).any() {
+                        value &= 0x00ff_ffff;
+                    }
+                    result.data[voxel_index(result.size, x, y, z)] = value;
+                }
+            }
+        }
+        result
+    }
 
-        let args = ShaderArgs {
-            a_size: grid_buffer.size,
-            ..Default::default()
-        };
-        let args_buffer = device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: size_of::<ShaderArgs>() as u64,
-            usage: BufferUsages::UNIFORM,
-            mapped_at_creation: true,
-        });
-        *from_bytes_mut::<ShaderArgs>(&mut args_buffer.slice(..).get_mapped_range_mut()) = args;
-        args_buffer.unmap();
+    /// Copy the grid into a new grid of size new_size, with this grid's
+    /// `0,0,0` at place in the new grid. Voxels outside the new grid are
+    /// dropped and the rest of the new grid is empty. Offsets are kept,
+    /// including the offsets in this grid's end padding, so the edge
+    /// voxels keep their bounds.
+    ///
+    /// Panics if the size is too large.
+    pub fn resized(&self, new_size: UVec3, place: IVec3) -> VoxelGridVec {
+        let mut result = VoxelGridVec::new(new_size, 0);
+        // Include ending padding so we get all vertexes
+        for z in 0..=self.size.z {
+            for y in 0..=self.size.y {
+                for x in 0..=self.size.x {
+                    let src = UVec3::new(x, y, z);
+                    let dest = place + src.as_ivec3();
+                    if dest.cmplt(IVec3::ZERO).any() || dest.cmpgt(new_size.as_ivec3()).any() {
+                        continue;
+                    }
+                    let mut value = self.data[voxel_index(self.size, x, y, z)];
+                    if src.cmpeq(self.size).any() || dest.cmpeq(new_size.as_ivec3()).any() {
+                        value &= 0x00ff_ffff;
+                    }
+                    result.data[voxel_index_i32(new_size, dest.x, dest.y, dest.z)] = value;
+                }
+            }
+        }
+        result
+    }
 
-        let storage_buffer = device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: buffer_size as u64,
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
-            mapped_at_creation: false,
-        });
-        let copy_buffer = device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: buffer_size as u64,
-            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
-            mapped_at_creation: false,
-        });
+    /// Flip the grid along an axis: 0 for x, 1 for y, or 2 for z. The
+    /// offsets along that axis are negated so the surface is mirrored too;
+    /// an offset of -128 is treated as -127. Material in the padding is
+    /// mirrored between the start and end padding. The offsets in the
+    /// start padding are cleared, since they don't matter; mirroring twice
+    /// otherwise gives the original grid.
+    ///
+    /// Panics if axis isn't 0, 1, or 2.
+    pub fn mirror(&self, axis: u8) -> VoxelGridVec {
+        assert!(axis < 3, "Mirror axis must be 0, 1, or 2");
+        let axis = axis as usize;
+        let size = self.size.as_ivec3();
+        let shift = axis * 8;
+        let mut result = VoxelGridVec::new(self.size, 0);
+        for z in -1..=size.z {
+            for y in -1..=size.y {
+                for x in -1..=size.x {
+                    let pos = IVec3::new(x, y, z);
 
-        let bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some("generate_mesh_bind_group"),
-            layout: bind_group_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: WGSL_ARGS_BINDING,
-                    resource: BindingResource::Buffer(BufferBinding {
-                        buffer: &args_buffer,
-                        offset: 0,
-                        size: None,
-                    }),
-                },
-                BindGroupEntry {
-                    binding: WGSL_VOXEL_GRID_A_BINDING,
-                    resource: BindingResource::Buffer(BufferBinding {
-                        buffer: &grid_buffer.buffer,
-                        offset: 0,
-                        size: None,
-                    }),
-                },
-                BindGroupEntry {
-                    binding: WGSL_FACE_FILLED_BINDING,
-                    resource: BindingResource::Buffer(BufferBinding {
-                        buffer: &storage_buffer,
-                        offset: face_filled_offset as u64,
-                        size: None,
-                    }),
-                },
-                BindGroupEntry {
-                    binding: WGSL_MESH_BINDING,
-                    resource: BindingResource::Buffer(BufferBinding {
-                        buffer: &storage_buffer,
-                        offset: 0,
-                        size: NonZeroU64::new(normals_offset as u64),
-                    }),
-                },
-                BindGroupEntry {
-                    binding: WGSL_MESH_NORMALS_BINDING,
-                    resource: BindingResource::Buffer(BufferBinding {
-                        buffer: &storage_buffer,
-                        offset: normals_offset as u64,
-                        size: NonZeroU64::new((face_filled_offset - normals_offset) as u64),
-                    }),
-                },
-            ],
-        });
+                    // Voxel pos covers [pos, pos + 1]; its material moves
+                    // to the mirrored voxel and its lower corner's vertex
+                    // comes from the mirrored corner
+                    let mut material_src = pos;
+                    material_src[axis] = size[axis] - 1 - pos[axis];
+                    let material = self.data[voxel_index_i32(
+                        self.size,
+                        material_src.x,
+                        material_src.y,
+                        material_src.z,
+                    )] & 0xff00_0000;
 
-        Self {
-            num_voxels,
-            normals_offset,
-            face_filled_offset,
-            buffer_size,
-            storage_buffer,
-            copy_buffer: copy_buffer.into(),
-            bind_group,
+                    let mut vertex_src = pos;
+                    vertex_src[axis] = size[axis] - pos[axis];
+                    let mut offsets = 0;
+                    if vertex_src[axis] <= size[axis] {
+                        offsets = self.data
+                            [voxel_index_i32(self.size, vertex_src.x, vertex_src.y, vertex_src.z)]
+                            & 0x00ff_ffff;
+                        let delta = ((offsets >> shift) as u8 as i8).max(-127);
+                        offsets = (offsets & !(0xff << shift)) | (((-delta) as u8 as u32) << shift);
+                    }
+
+                    result.data[voxel_index_i32(self.size, x, y, z)] = material | offsets;
+                }
+            }
         }
+        result
     }
 
-    /// Add the compute pass to the command encoder
-    pub fn add_pass(&self, pipeline: &ComputePipeline, encoder: &mut CommandEncoder) {
-        // println!("** GenerateMeshImpl::add_pass");
-        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
-            label: Some("generate_mesh_pass"),
-        });
-        pass.set_bind_group(0, &self.bind_group, &[]);
-        pass.set_pipeline(pipeline);
-        pass.dispatch_workgroups(
-            (self.num_voxels as u32 + GENERATE_MESH_VOXELS_PER_WORKGROUP - 1)
-                / GENERATE_MESH_VOXELS_PER_WORKGROUP,
-            1,
-            1,
-        );
+    /// Get the voxels which differ from older, for an undo stack. Padding
+    /// is included, since it holds vertexes. Returns an error if the sizes
+    /// don't match.
+    pub fn diff(&self, older: &VoxelGridVec) -> Result<GridDiff, VoxelGridError> {
+        check_same_size(self.size, older.size)?;
+        let stride = self.size.as_ivec3() + 2;
+        let changes = self
+            .data
+            .iter()
+            .zip(&older.data)
+            .enumerate()
+            .filter(|(_, (new, old))| new != old)
+            .map(|(i, (new, old))| {
+                let i = i as i32;
+                GridDiffEntry {
+                    pos: IVec3::new(
+                        i % stride.x,
+                        (i / stride.x) % stride.y,
+                        i / (stride.x * stride.y),
+                    ) - 1,
+                    old: *old,
+                    new: *new,
+                }
+            })
+            .collect();
+        Ok(GridDiff {
+            size: self.size,
+            changes,
+        })
     }
 
-    /// Add the buffer copy to the command encoder
-    pub fn add_copy(&self, encoder: &mut CommandEncoder) {
-        // println!("** GenerateMeshImpl::add_copy");
-        encoder.copy_buffer_to_buffer(
-            &self.storage_buffer,
-            0,
-            &self.copy_buffer,
-            0,
-            self.buffer_size as u64,
-        );
+    /// Set the changed voxels to their new values, redoing the change.
+    /// Returns an error if the sizes don't match.
+    pub fn apply_diff(&mut self, diff: &GridDiff) -> Result<(), VoxelGridError> {
+        check_same_size(diff.size, self.size)?;
+        for change in &diff.changes {
+            let p = change.pos;
+            self.data[voxel_index_i32(self.size, p.x, p.y, p.z)] = change.new;
+        }
+        Ok(())
     }
 
-    /// Map the copy buffer (async) then call the callback
-    pub fn async_map_buffer(
-        self,
-        done: impl FnOnce(GenerateMeshImpl, Result<(), BufferAsyncError>) + Send + 'static,
-    ) {
-        self.copy_buffer
-            .clone()
-            .slice(..)
-            .map_async(MapMode::Read, |result| done(self, result));
+    /// Set the changed voxels to their old values, undoing the change.
+    /// Returns an error if the sizes don't match.
+    pub fn revert_diff(&mut self, diff: &GridDiff) -> Result<(), VoxelGridError> {
+        check_same_size(diff.size, self.size)?;
+        for change in &diff.changes {
+            let p = change.pos;
+            self.data[voxel_index_i32(self.size, p.x, p.y, p.z)] = change.old;
+        }
+        Ok(())
     }
 
-    /// Get the mesh and normals from the copy buffer
-    pub fn get_mesh(self) -> (Vec<Vec3>, Vec<Vec3>) {
-        let raw = self.copy_buffer.slice(..).get_mapped_range();
-        let src_vertexes = cast_slice::<u8, Vec4>(&raw[..self.normals_offset]);
-        let src_normals =
-            cast_slice::<u8, Vec4>(&raw[self.normals_offset..self.face_filled_offset]);
-        let face_filled = cast_slice::<u8, u32>(&raw[self.face_filled_offset..]);
-
-        let mut num_faces = 0;
-        for mask in face_filled {
-            // println!("   mask: {:#08x}", mask);
-            num_faces += mask.count_ones() as usize;
+    /// Write the grid in a binary format:
+    /// * Magic: `VOXG`
+    /// * Version: 1 byte, [VOXEL_FILE_VERSION]
+    /// * size: 3 `u32`, little-endian
+    /// * data length: `u64`, little-endian
+    /// * data: `u32` each, little-endian
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(VOXEL_FILE_MAGIC)?;
+        w.write_all(&[VOXEL_FILE_VERSION])?;
+        for v in self.size.to_array() {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        w.write_all(&(self.data.len() as u64).to_le_bytes())?;
+        let mut bytes = Vec::with_capacity(self.data.len() * size_of::<u32>());
+        for v in &self.data {
+            bytes.extend_from_slice(&v.to_le_bytes());
         }
+        w.write_all(&bytes)
+    }
 
-        let mut vertexes: Vec<Vec3> = Vec::new();
-        let mut normals: Vec<Vec3> = Vec::new();
-        vertexes.resize(num_faces * VERTEXES_PER_FACE, Default::default());
-        normals.resize(num_faces * VERTEXES_PER_FACE, Default::default());
+    /// Read a grid written by [Self::write_to].
+    ///
+    /// Returns an error if the header doesn't match, the size is too large,
+    /// or the data length doesn't match the size.
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let (size, vec_size) = read_header(r, VOXEL_FILE_MAGIC)?;
+        let mut u64_bytes = [0u8; 8];
+        r.read_exact(&mut u64_bytes)?;
+        let len = u64::from_le_bytes(u64_bytes);
+        if len != vec_size as u64 {
+            return Err(invalid(format!(
+                "Voxel grid data length {} doesn't match size {}; expected {}",
+                len, size, vec_size
+            )));
+        }
+        let mut bytes = vec![0u8; vec_size * size_of::<u32>()];
+        r.read_exact(&mut bytes)?;
+        let data = bytes
+            .chunks_exact(size_of::<u32>())
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        Ok(Self { size, data })
+    }
 
-        let mut filled = 0;
-        for i in 0..self.num_voxels * FACES_PER_VOXEL {
-            if face_filled[i / FACE_FILLED_NUM_BITS as usize]
-                & (1 << (i % FACE_FILLED_NUM_BITS as usize))
-                != 0
+    /// Write the grid in a run-length encoded binary format:
+    /// * Magic: `VOXR`
+    /// * Version: 1 byte, [VOXEL_FILE_VERSION]
+    /// * size: 3 `u32`, little-endian
+    /// * runs: `u32` count then `u32` voxel, little-endian, until all
+    ///   voxels, including padding, are covered
+    ///
+    /// Empty regions compress well since they are long runs of 0.
+    pub fn write_rle<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(VOXEL_RLE_FILE_MAGIC)?;
+        w.write_all(&[VOXEL_FILE_VERSION])?;
+        for v in self.size.to_array() {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        let mut bytes = Vec::new();
+        let mut i = 0;
+        while i < self.data.len() {
+            let value = self.data[i];
+            let mut count = 1;
+            while i + count < self.data.len()
+                && self.data[i + count] == value
+                && count < u32::MAX as usize
             {
-                // println!("   fill face: {:?}", i);
-                for j in 0..VERTEXES_PER_FACE {
-                    let v = src_vertexes[i * VERTEXES_PER_FACE + j];
-                    vertexes[filled * VERTEXES_PER_FACE + j] = vec4_to_3(&v);
+                count += 1;
+            }
+            bytes.extend_from_slice(&(count as u32).to_le_bytes());
+            bytes.extend_from_slice(&value.to_le_bytes());
+            i += count;
+        }
+        w.write_all(&bytes)
+    }
+
+    /// Read a grid written by [Self::write_rle].
+    ///
+    /// Returns an error if the header doesn't match, the size is too large,
+    /// or the runs don't exactly cover the size.
+    pub fn read_rle<R: Read>(r: &mut R) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let (size, vec_size) = read_header(r, VOXEL_RLE_FILE_MAGIC)?;
+        let mut data = Vec::with_capacity(vec_size);
+        let mut run = [0u8; 8];
+        while data.len() < vec_size {
+            r.read_exact(&mut run)?;
+            let count = u32::from_le_bytes([run[0], run[1], run[2], run[3]]) as usize;
+            let value = u32::from_le_bytes([run[4], run[5], run[6], run[7]]);
+            if count == 0 || count > vec_size - data.len() {
+                return Err(invalid("Voxel grid runs don't match size"));
+            }
+            data.resize(data.len() + count, value);
+        }
+        Ok(Self { size, data })
+    }
+}
+
+/// Materials stored in a [Vec], without offsets. This uses a quarter of
+/// the memory of [VoxelGridVec], for gameplay logic which doesn't need the
+/// voxels' shapes. Upload it with [VoxelGrid::from_materials] to mesh it,
+/// or convert it with [VoxelMaterialGrid::to_grid] to edit it on the GPU.
+///
+/// Each voxel is 1 byte: its material. 0 means empty. The padding and
+/// packing are the same as [VoxelGridVec], so [voxel_index] works on it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VoxelMaterialGrid {
+    /// Size of the voxel grid, excluding padding
+    pub size: UVec3,
 
-                    let n = src_normals[i * VERTEXES_PER_FACE + j];
-                    normals[filled * VERTEXES_PER_FACE + j] = vec4_to_3(&n);
+    /// Materials, including padding
+    pub data: Vec<u8>,
+}
+
+impl VoxelMaterialGrid {
+    /// Create a new grid with the given size and material. The padding
+    /// is empty.
+    ///
+    /// Panics if the size is too large.
+    pub fn new(size: UVec3, material: u8) -> Self {
+        let mut data = Vec::new();
+        data.resize(get_vec_size(size), 0);
+        if material != 0 {
+            for z in 0..size.z {
+                for y in 0..size.y {
+                    for x in 0..size.x {
+                        data[voxel_index(size, x, y, z)] = material;
+                    }
                 }
-                filled += 1;
             }
         }
-        // println!("   filled: {:?}", filled);
-        // println!("   num_faces: {:?}", num_faces);
-        assert!(filled == num_faces);
-        (vertexes, normals)
+        Self { size, data }
     }
-} // GenerateMeshImpl
 
-/// Create BindGroupLayout for the shader's geometry functions.
-pub fn geometry_bind_group_layout(device: &Device) -> BindGroupLayout {
-    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-        label: Some("geometry_bind_group_layout"),
-        entries: &[
-            BindGroupLayoutEntry {
-                binding: WGSL_ARGS_BINDING,
-                visibility: ShaderStages::COMPUTE,
-                ty: BindingType::Buffer {
-                    ty: BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-            BindGroupLayoutEntry {
-                binding: WGSL_VOXEL_GRID_OUT_BINDING,
-                visibility: ShaderStages::COMPUTE,
-                ty: BindingType::Buffer {
-                    ty: BufferBindingType::Storage { read_only: false },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-        ],
-    })
+    /// Copy the materials from a grid, including the padding's. Offsets
+    /// are dropped.
+    pub fn from_grid(grid: &VoxelGridVec) -> Self {
+        Self {
+            size: grid.size,
+            data: grid.data.iter().map(|v| (v >> 24) as u8).collect(),
+        }
+    }
+
+    /// Convert to a [VoxelGridVec]. All offsets are 0.
+    pub fn to_grid(&self) -> VoxelGridVec {
+        VoxelGridVec {
+            size: self.size,
+            data: self.data.iter().map(|m| (*m as u32) << 24).collect(),
+        }
+    }
+
+    fn checked_index(&self, x: u32, y: u32, z: u32) -> usize {
+        if x >= self.size.x || y >= self.size.y || z >= self.size.z {
+            panic!(
+                "Voxel coordinates ({}, {}, {}) are out of range for grid size {}",
+                x, y, z, self.size
+            );
+        }
+        voxel_index(self.size, x, y, z)
+    }
+
+    /// Get the material of the voxel at the given coordinates. 0 means empty.
+    ///
+    /// Panics if the coordinates are out of range.
+    pub fn material(&self, x: u32, y: u32, z: u32) -> u8 {
+        self.data[self.checked_index(x, y, z)]
+    }
+
+    /// Set the material of the voxel at the given coordinates.
+    ///
+    /// Panics if the coordinates are out of range.
+    pub fn set_material(&mut self, x: u32, y: u32, z: u32, material: u8) {
+        let index = self.checked_index(x, y, z);
+        self.data[index] = material;
+    }
 }
 
-/// Use one of the shader's geometry functions.
-///
-/// Call the following in order:
-/// * `[new_*]`
-/// * `[add_pass]`
-#[derive(Debug)]
-pub struct GeometryImpl {
-    bind_group: BindGroup,
-    workgroup_size: UVec3,
+/// Read the magic, version, and size. Returns the size and the
+/// length of the data vector.
+fn read_header<R: Read>(r: &mut R, expected_magic: &[u8; 4]) -> io::Result<(UVec3, usize)> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != expected_magic {
+        return Err(invalid("Not a voxel grid file".into()));
+    }
+    let mut version = [0u8; 1];
+    r.read_exact(&mut version)?;
+    if version[0] != VOXEL_FILE_VERSION {
+        return Err(invalid(format!(
+            "Unsupported voxel grid file version {}",
+            version[0]
+        )));
+    }
+    let mut u32_bytes = [0u8; 4];
+    let mut size = UVec3::ZERO;
+    for i in 0..3 {
+        r.read_exact(&mut u32_bytes)?;
+        size[i] = u32::from_le_bytes(u32_bytes);
+    }
+    let vec_size = get_vec_size_checked(size).map_err(|e| invalid(e.to_string()))?;
+    Ok((size, vec_size))
+}
+
+/// Magic bytes at the start of files written by [VoxelGridVec::write_to]
+pub const VOXEL_FILE_MAGIC: &[u8; 4] = b"VOXG";
+
+/// Magic bytes at the start of files written by [VoxelGridVec::write_rle]
+pub const VOXEL_RLE_FILE_MAGIC: &[u8; 4] = b"VOXR";
+
+/// Version of the formats written by [VoxelGridVec::write_to] and
+/// [VoxelGridVec::write_rle]
+pub const VOXEL_FILE_VERSION: u8 = 1;
+
+/// Largest voxel grid buffer, in bytes
+pub const MAX_GRID_BUF_SIZE: usize = i32::MAX as usize;
+
+/// A change between 2 versions of a grid, from [VoxelGridVec::diff]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct GridDiff {
+    /// Size of both grids, excluding padding
+    pub size: UVec3,
+
+    /// Voxels which changed, in index order
+    pub changes: Vec<GridDiffEntry>,
+}
+
+impl GridDiff {
+    /// Returns true if no voxels changed
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// A voxel which changed. See [GridDiff].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GridDiffEntry {
+    /// Coordinates of the voxel. -1 and size are the padding.
+    pub pos: IVec3,
+
+    /// Voxel in the older grid
+    pub old: u32,
+
+    /// Voxel in the newer grid
+    pub new: u32,
 }
 
-impl GeometryImpl {
-    fn new_impl(
+/// Errors from creating or combining voxel grids
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VoxelGridError {
+    /// The grid's buffer would exceed `max` bytes
+    TooLarge {
+        /// Requested size, excluding padding
+        size: UVec3,
+
+        /// Largest allowed buffer, in bytes
+        max: usize,
+    },
+
+    /// The grids' sizes don't match
+    SizeMismatch {
+        /// Size which was expected
+        expected: UVec3,
+
+        /// Size which was found
+        actual: UVec3,
+    },
+
+    /// A mesh index is past the end of its positions
+    IndexOutOfRange {
+        /// Index which was found
+        index: u32,
+
+        /// Number of positions
+        len: usize,
+    },
+}
+
+impl std::fmt::Display for VoxelGridError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VoxelGridError::TooLarge { size, max } => write!(
+                f,
+                "Voxel grid size {} is too large; its buffer would exceed {} bytes",
+                size, max
+            ),
+            VoxelGridError::SizeMismatch { expected, actual } => write!(
+                f,
+                "Voxel grid size {} doesn't match the expected size {}",
+                actual, expected
+            ),
+            VoxelGridError::IndexOutOfRange { index, len } => write!(
+                f,
+                "Mesh index {} is out of range for {} positions",
+                index, len
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VoxelGridError {}
+
+fn check_same_size(expected: UVec3, actual: UVec3) -> Result<(), VoxelGridError> {
+    if expected != actual {
+        return Err(VoxelGridError::SizeMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+/// Number of voxels, including padding. The shaders index voxels with an
+/// `i32`, so this limits every layout.
+fn check_num_voxels(size: UVec3) -> Result<usize, VoxelGridError> {
+    let too_large = VoxelGridError::TooLarge {
+        size,
+        max: MAX_GRID_BUF_SIZE,
+    };
+    if size.x >= (i32::MAX - 2) as u32
+        || size.y >= (i32::MAX - 2) as u32
+        || size.z >= (i32::MAX - 2) as u32
+    {
+        return Err(too_large);
+    }
+    let vec_size = (size.x as usize + 2)
+        .checked_mul(size.y as usize + 2)
+        .and_then(|v| v.checked_mul(size.z as usize + 2))
+        .ok_or(too_large)?;
+    if vec_size >= MAX_GRID_BUF_SIZE {
+        return Err(too_large);
+    }
+    Ok(vec_size)
+}
+
+fn check_grid_size(size: UVec3) -> Result<(usize, usize), VoxelGridError> {
+    let too_large = VoxelGridError::TooLarge {
+        size,
+        max: MAX_GRID_BUF_SIZE,
+    };
+    let vec_size = check_num_voxels(size)?;
+    let buf_size = vec_size.checked_mul(size_of::<u32>()).ok_or(too_large)?;
+    if buf_size >= MAX_GRID_BUF_SIZE {
+        return Err(too_large);
+    }
+    Ok((vec_size, buf_size))
+}
+
+/// Get the length of the data vector for a voxel grid with the given size.
+/// The size does not include padding, but the returned value does.
+///
+/// Returns an error if the size is too large.
+pub fn get_vec_size_checked(size: UVec3) -> Result<usize, VoxelGridError> {
+    Ok(check_grid_size(size)?.0)
+}
+
+/// Get the length of the gpu buffer, in bytes, for a voxel grid with the given size.
+/// The size does not include padding, but the returned value does.
+///
+/// Returns an error if the size is too large.
+pub fn get_buf_size_checked(size: UVec3) -> Result<usize, VoxelGridError> {
+    Ok(check_grid_size(size)?.1)
+}
+
+/// Get the length of the data vector for a voxel grid with the given size.
+/// The size does not include padding, but the returned value does.
+///
+/// Panics if the size is too large.
+pub fn get_vec_size(size: UVec3) -> usize {
+    get_vec_size_checked(size).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Get the length of the gpu buffer, in bytes, for a voxel grid with the given size.
+/// The size does not include padding, but the returned value does.
+///
+/// Panics if the size is too large.
+pub fn get_buf_size(size: UVec3) -> usize {
+    get_buf_size_checked(size).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Get the length of a [GridLayout::Split] grid's material buffer, in bytes,
+/// for a voxel grid with the given size. This is 1 byte per voxel, rounded up
+/// to a multiple of 4. The size does not include padding, but the returned
+/// value does.
+///
+/// Returns an error if the size is too large.
+pub fn get_split_buf_size_checked(size: UVec3) -> Result<usize, VoxelGridError> {
+    Ok((check_num_voxels(size)? + 3) / 4 * 4)
+}
+
+/// Get the index of a voxel in the data vector. `0,0,0` gets the first voxel,
+/// skipping the padding. `size.<c>` for coordinate `c` (x, y, or z) gets ending padding.
+///
+/// This function doesn't check for out-of-bounds coordinates.
+pub fn voxel_index(size: UVec3, x: u32, y: u32, z: u32) -> usize {
+    ((x + 1) + (y + 1) * (size.x + 2) + (z + 1) * (size.x + 2) * (size.y + 2)) as usize
+}
+
+/// Get the index of a voxel in the data vector. `0,0,0` gets the first voxel,
+/// skipping the padding. -1 for any coordinate gets beginning padding.
+/// `size.<c>` for coordinate `c` (x, y, or z) gets ending padding.
+///
+/// This function doesn't check for out-of-bounds coordinates, and its i32
+/// math can overflow for large grids, giving a wrong index. Use
+/// [voxel_index_checked] outside hot loops.
+pub fn voxel_index_i32(size: UVec3, x: i32, y: i32, z: i32) -> usize {
+    ((x + 1) + (y + 1) * (size.x as i32 + 2) + (z + 1) * (size.x as i32 + 2) * (size.y as i32 + 2))
+        as usize
+}
+
+/// Like [voxel_index_i32], but returns None if the coordinates are outside
+/// the padded grid, i.e. any coordinate `c` is outside `-1..=size.<c>`.
+/// The math is done in usize, so it doesn't overflow for valid sizes.
+pub fn voxel_index_checked(size: UVec3, x: i32, y: i32, z: i32) -> Option<usize> {
+    let padded = |c: i32, s: u32| {
+        if c < -1 || c as i64 > s as i64 {
+            None
+        } else {
+            Some((c + 1) as usize)
+        }
+    };
+    let (x, y, z) = (padded(x, size.x)?, padded(y, size.y)?, padded(z, size.z)?);
+    let (sx, sy) = (size.x as usize + 2, size.y as usize + 2);
+    Some(x + y * sx + z * sx * sy)
+}
+
+/// Direction a face points; see [MeshData::directions]
+pub const FACE_DIRECTION_POS_X: u32 = 0;
+pub const FACE_DIRECTION_NEG_X: u32 = 1;
+pub const FACE_DIRECTION_POS_Y: u32 = 2;
+pub const FACE_DIRECTION_NEG_Y: u32 = 3;
+pub const FACE_DIRECTION_POS_Z: u32 = 4;
+pub const FACE_DIRECTION_NEG_Z: u32 = 5;
+
+/// Get the index of a face in generate_mesh's face_filled bitset. Face `i`
+/// is bit `i % FACE_FILLED_NUM_BITS` of word `i / FACE_FILLED_NUM_BITS`.
+/// Coordinates exclude padding; `face` is `0..FACES_PER_VOXEL`, in the
+/// shader's order: z=1, x=1, z=0, x=0, y=1, y=0.
+///
+/// This function doesn't check for out-of-bounds coordinates.
+pub fn face_index(size: UVec3, x: u32, y: u32, z: u32, face: u8) -> usize {
+    (x as usize + y as usize * size.x as usize + z as usize * size.x as usize * size.y as usize)
+        * FACES_PER_VOXEL
+        + face as usize
+}
+
+fn max_device_buf_size(device: &Device) -> usize {
+    device.limits().max_storage_buffer_binding_size as usize
+}
+
+/// Get the largest cube-shaped grid size, excluding padding, whose buffer
+/// fits in both [MAX_GRID_BUF_SIZE] and the device's
+/// `max_storage_buffer_binding_size`.
+pub fn max_grid_size(device: &Device) -> UVec3 {
+    let max_voxels = max_device_buf_size(device).min(MAX_GRID_BUF_SIZE - 1) / size_of::<u32>();
+    let fits = |n: usize| (n + 2) * (n + 2) * (n + 2) <= max_voxels;
+    let mut n = ((max_voxels as f64).cbrt() as usize).saturating_sub(2);
+    while n > 0 && !fits(n) {
+        n -= 1;
+    }
+    while fits(n + 1) {
+        n += 1;
+    }
+    UVec3::splat(n as u32)
+}
+
+/// How a [VoxelGrid] stores its voxels on the GPU
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum GridLayout {
+    /// 1 `u32` per voxel, in [VoxelGridVec]'s format. The geometry
+    /// functions read and write this layout.
+    #[default]
+    Packed,
+
+    /// 1 `u8` of material per voxel in [VoxelGrid::buffer], in
+    /// [VoxelMaterialGrid]'s format, and the corner offsets in
+    /// [VoxelGrid::offsets], which is only allocated if the grid has any.
+    /// Without offsets this is a quarter of [GridLayout::Packed]'s size.
+    ///
+    /// The generate_mesh functions read this layout, but the geometry
+    /// functions don't write it: edit a packed grid, then convert it with
+    /// [GeometryImpl::split_materials].
+    Split,
+}
+
+/// Voxels readable and writable by the GPU. See [VoxelGridContent] for the format.
+#[derive(Debug)]
+pub struct VoxelGrid {
+    /// Size of the voxel grid, excluding padding
+    pub size: UVec3,
+
+    /// How [VoxelGrid::buffer] and [VoxelGrid::offsets] store the voxels
+    pub layout: GridLayout,
+
+    /// Voxel data, including padding. Usage flags are
+    /// `[BufferUsages::STORAGE] | [BufferUsages::COPY_SRC] | [BufferUsages::COPY_DST]`,
+    /// plus any extra flags passed to [VoxelGrid::with_usage].
+    ///
+    /// Custom passes may bind this. With [GridLayout::Packed], the layout
+    /// matches [VoxelGridVec::data]: 1 `u32` per voxel, packed by x, then y,
+    /// then z, with 1 voxel of padding on each side; see [voxel_index]. The
+    /// buffer is exactly [get_buf_size]`(size)` bytes. With [GridLayout::Split],
+    /// it matches [VoxelMaterialGrid::data] instead, 4 materials per `u32`
+    /// with the first voxel in the LSBs, and is [get_split_buf_size_checked]`(size)`
+    /// bytes. The buffer is replaced, not resized, if the grid's size changes.
+    ///
+    /// Commands clone this during prepare, so their passes and copies use
+    /// the buffer the grid had at that point even if it's replaced later.
+    pub buffer: Arc<Buffer>,
+
+    /// With [GridLayout::Split], the corner offsets in [VoxelGridVec]'s
+    /// format; the materials in it are ignored. None if every offset is 0.
+    /// Always None with [GridLayout::Packed].
+    pub offsets: Option<Arc<Buffer>>,
+
+    /// Whether the grid may have nonzero offsets. Commands which paste
+    /// vertexes set this, and splitting the grid only allocates
+    /// [VoxelGrid::offsets] if it's set.
+    pub offsets_written: bool,
+}
+
+impl VoxelGrid {
+    /// Create a new voxel grid with the given size. The size does
+    /// not include padding, but the result includes it.
+    ///
+    /// Panics if the size is too large.
+    pub fn new(size: UVec3, device: &Device, mapped_at_creation: bool) -> Self {
+        Self::try_new(size, device, mapped_at_creation).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Create a new voxel grid with the given size. The size does
+    /// not include padding, but the result includes it.
+    ///
+    /// Returns an error if the size is too large, either for [MAX_GRID_BUF_SIZE]
+    /// or for the device's `max_storage_buffer_binding_size`.
+    pub fn try_new(
+        size: UVec3,
+        device: &Device,
+        mapped_at_creation: bool,
+    ) -> Result<Self, VoxelGridError> {
+        Self::try_with_usage(size, device, mapped_at_creation, BufferUsages::empty())
+    }
+
+    /// Create a new voxel grid whose buffer also has the extra usage flags,
+    /// e.g. `[BufferUsages::VERTEX]` for a custom renderer. The flags the
+    /// shaders need are always included.
+    ///
+    /// Panics if the size is too large.
+    pub fn with_usage(
+        size: UVec3,
+        device: &Device,
+        mapped_at_creation: bool,
+        extra: BufferUsages,
+    ) -> Self {
+        Self::try_with_usage(size, device, mapped_at_creation, extra)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Create a new voxel grid whose buffer also has the extra usage flags.
+    /// See [VoxelGrid::with_usage].
+    ///
+    /// Returns an error if the size is too large, either for [MAX_GRID_BUF_SIZE]
+    /// or for the device's `max_storage_buffer_binding_size`.
+    pub fn try_with_usage(
+        size: UVec3,
+        device: &Device,
+        mapped_at_creation: bool,
+        extra: BufferUsages,
+    ) -> Result<Self, VoxelGridError> {
+        let buf_size = get_buf_size_checked(size)?;
+        let device_max = max_device_buf_size(device);
+        if buf_size > device_max {
+            return Err(VoxelGridError::TooLarge {
+                size,
+                max: device_max,
+            });
+        }
+        // println!(
+        //     "** VoxelGrid::new {} {} {}",
+        //     size,
+        //     get_buf_size(size),
+        //     mapped_at_creation
+        // );
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("voxel_grid_buffer"),
+            size: buf_size as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST | extra,
+            mapped_at_creation,
+        });
+        // println!("** buffer {} {:?}", buffer.size(), buffer.usage());
+        Ok(Self {
+            size,
+            layout: GridLayout::Packed,
+            buffer: Arc::new(buffer),
+            offsets: None,
+            offsets_written: false,
+        })
+    }
+
+    /// Create a new [GridLayout::Split] voxel grid with the given size. The
+    /// size does not include padding, but the result includes it. The offset
+    /// buffer is only created if with_offsets is true.
+    ///
+    /// Returns an error if the size is too large, either for [MAX_GRID_BUF_SIZE]
+    /// or for the device's `max_storage_buffer_binding_size`.
+    pub fn try_new_split(
+        size: UVec3,
+        device: &Device,
+        mapped_at_creation: bool,
+        with_offsets: bool,
+    ) -> Result<Self, VoxelGridError> {
+        let buf_size = get_split_buf_size_checked(size)?;
+        let offsets_size = if with_offsets {
+            Some(get_buf_size_checked(size)?)
+        } else {
+            None
+        };
+        let device_max = max_device_buf_size(device);
+        if buf_size.max(offsets_size.unwrap_or(0)) > device_max {
+            return Err(VoxelGridError::TooLarge {
+                size,
+                max: device_max,
+            });
+        }
+        let create = |label, size| {
+            Arc::new(device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size: size as u64,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+                mapped_at_creation,
+            }))
+        };
+        Ok(Self {
+            size,
+            layout: GridLayout::Split,
+            buffer: create("voxel_grid_materials_buffer", buf_size),
+            offsets: offsets_size.map(|size| create("voxel_grid_offsets_buffer", size)),
+            offsets_written: with_offsets,
+        })
+    }
+
+    /// Create a new voxel grid and copy the given content into it.
+    pub fn from_content(content: &VoxelGridVec, device: &Device) -> Self {
+        let mut buffer = Self::new(content.size, device, true);
+        buffer
+            .buffer
+            .slice(..)
+            .get_mapped_range_mut()
+            .copy_from_slice(cast_slice::<u32, u8>(&content.data));
+        buffer.buffer.unmap();
+        buffer.offsets_written = content.data.iter().any(|v| v & 0x00ff_ffff != 0);
+        buffer
+    }
+
+    /// Create a new [GridLayout::Split] voxel grid and copy the given
+    /// content into it. The offset buffer is only created if the content
+    /// has a nonzero offset.
+    ///
+    /// Panics if the size is too large.
+    pub fn from_content_split(content: &VoxelGridVec, device: &Device) -> Self {
+        let with_offsets = content.data.iter().any(|v| v & 0x00ff_ffff != 0);
+        let grid = Self::try_new_split(content.size, device, true, with_offsets)
+            .unwrap_or_else(|e| panic!("{}", e));
+        let mut materials = grid.buffer.slice(..).get_mapped_range_mut();
+        for (dest, v) in materials.iter_mut().zip(&content.data) {
+            *dest = (v >> 24) as u8;
+        }
+        drop(materials);
+        grid.buffer.unmap();
+        if let Some(offsets) = &grid.offsets {
+            offsets
+                .slice(..)
+                .get_mapped_range_mut()
+                .copy_from_slice(cast_slice::<u32, u8>(&content.data));
+            offsets.unmap();
+        }
+        grid
+    }
+
+    /// Create a new [GridLayout::Split] voxel grid, without offsets, and
+    /// copy the given materials into it.
+    ///
+    /// Panics if the size is too large.
+    pub fn from_materials(content: &VoxelMaterialGrid, device: &Device) -> Self {
+        let grid = Self::try_new_split(content.size, device, true, false)
+            .unwrap_or_else(|e| panic!("{}", e));
+        grid.buffer.slice(..).get_mapped_range_mut()[..content.data.len()]
+            .copy_from_slice(&content.data);
+        grid.buffer.unmap();
+        grid
+    }
+
+    /// GPU memory used by the buffers, in bytes
+    pub fn gpu_bytes(&self) -> u64 {
+        self.buffer.size() + self.offsets.as_ref().map_or(0, |offsets| offsets.size())
+    }
+}
+
+/// Create BindGroupLayout for the shader's generate_mesh function.
+pub fn generate_mesh_bind_group_layout(device: &Device) -> BindGroupLayout {
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("generate_mesh_bind_group_layout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: WGSL_ARGS_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: WGSL_VOXEL_GRID_A_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: WGSL_VOXEL_GRID_B_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: WGSL_FACE_FILLED_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: WGSL_MESH_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: WGSL_MESH_NORMALS_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: WGSL_MESH_MATERIALS_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: WGSL_MESH_AO_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+/// Create BindGroupLayout for the shader's compact_mesh function.
+pub fn compact_mesh_bind_group_layout(device: &Device) -> BindGroupLayout {
+    let storage = |binding: u32, read_only: bool| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("compact_mesh_bind_group_layout"),
+        entries: &[
+            storage(WGSL_FACE_FILLED_BINDING, false),
+            storage(WGSL_MESH_BINDING, false),
+            storage(WGSL_MESH_NORMALS_BINDING, false),
+            storage(WGSL_MESH_MATERIALS_BINDING, false),
+            storage(WGSL_MESH_AO_BINDING, false),
+            storage(WGSL_FACE_OFFSETS_BINDING, true),
+            storage(WGSL_PACKED_FACES_BINDING, false),
+        ],
+    })
+}
+
+/// Options for converting a voxel grid to a mesh
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GenerateMeshOptions {
+    /// Share vertexes which have the same position and normal, and produce
+    /// triangle indexes.
+    pub indexed: bool,
+
+    /// Merge adjacent coplanar faces which have the same material into
+    /// larger quads. Only faces whose corners have 0 offsets, within
+    /// offset_merge_epsilon, and no ambient occlusion are merged.
+    pub greedy: bool,
+
+    /// With greedy, faces whose corner offsets are all within this of 0,
+    /// in the voxel format's 1/64 units, count as flat and are merged; the
+    /// merged quad ignores the offsets. Larger values trade surface detail
+    /// for fewer triangles. Defaults to 0, which only merges exactly flat faces.
+    pub offset_merge_epsilon: f32,
+
+    /// Compute per-vertex ambient occlusion. Faces are split along the
+    /// diagonal which keeps the occlusion from looking anisotropic.
+    pub ambient_occlusion: bool,
+
+    /// Average the normals of faces which share a vertex position, for
+    /// smooth shading. Faces still follow the voxels' corner offsets.
+    pub smooth: bool,
+
+    /// Multiply vertex positions by this; it's the size of a voxel in
+    /// world units. Normals aren't affected. Defaults to 1.0.
+    pub voxel_scale: f32,
+
+    /// Treat every voxel's corner offsets as 0, for a blocky look with
+    /// axis-aligned normals. The grid isn't modified.
+    pub ignore_offsets: bool,
+
+    /// Run marching cubes over the materials instead of emitting voxel
+    /// faces. Voxels with material > 0 are inside; corner offsets are
+    /// ignored. The result is a smooth, watertight surface with normals
+    /// from the material's gradient. Uses the
+    /// [GENERATE_MESH_MARCHING_CUBES_ENTRY_POINT] pipeline.
+    ///
+    /// Each face is 2 triangles from the same cell; if a cell has an odd
+    /// number of triangles, then its last face's second triangle is
+    /// degenerate. greedy and ambient_occlusion are ignored.
+    pub marching_cubes: bool,
+
+    /// Materials which are transparent, indexed by material. A face is
+    /// generated where the neighbor is empty or has a different opacity
+    /// class, so water next to air or stone gets a face, but water next to
+    /// water doesn't. Both sides of a water/stone boundary get a face.
+    /// Defaults to all opaque. Ignored by marching_cubes.
+    pub transparent_mask: [bool; 256],
+
+    /// Treat everything outside the grid as empty, so the grid's outer
+    /// faces are generated even if the padding has material; the result
+    /// is closed, like a standalone object. When false, material in the
+    /// padding hides the edge faces, which suits chunked terrain. The
+    /// padding's corner offsets are used either way.
+    pub close_boundary: bool,
+
+    /// Emit triangles in clockwise order instead of counter-clockwise, for
+    /// pipelines which cull the other way. Normals are unchanged.
+    pub flip_winding: bool,
+
+    /// Only generate the faces of voxels in this box, given as its lower
+    /// corner and size. The box is clipped to the grid. Uses the
+    /// [GENERATE_MESH_REGION_ENTRY_POINT] pipeline, which only dispatches
+    /// the box's voxels. The buffers are still sized for the whole grid.
+    ///
+    /// The result holds only the box's faces from this run, at their usual
+    /// positions; faces from earlier runs which reused the buffers aren't
+    /// included. It isn't merged into an existing mesh. To edit a large
+    /// grid, mesh it as several boxes on separate entities and regenerate
+    /// the ones which changed. Ignored by marching_cubes.
+    pub region: Option<(UVec3, UVec3)>,
+}
+
+impl Default for GenerateMeshOptions {
+    fn default() -> Self {
+        Self {
+            indexed: false,
+            greedy: false,
+            offset_merge_epsilon: 0.0,
+            ambient_occlusion: false,
+            smooth: false,
+            voxel_scale: 1.0,
+            ignore_offsets: false,
+            marching_cubes: false,
+            transparent_mask: [false; 256],
+            close_boundary: false,
+            flip_winding: false,
+            region: None,
+        }
+    }
+}
+
+/// Mesh produced by [GenerateMeshImpl]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MeshData {
+    /// Vertex positions
+    pub vertexes: Vec<Vec3>,
+
+    /// Vertex normals
+    pub normals: Vec<Vec3>,
+
+    /// Vertex materials. This is the material of the voxel which produced each face.
+    pub materials: Vec<u32>,
+
+    /// Vertex ambient occlusion. This is a darkening factor in the range
+    /// `[0.4, 1.0]`, where 1.0 means unoccluded. All 1.0 unless
+    /// [GenerateMeshOptions::ambient_occlusion] is set.
+    pub ao: Vec<f32>,
+
+    /// Vertex texture coordinates. This is the position, in voxels, projected
+    /// onto the plane of the face's dominant axis. A tiling texture repeats
+    /// every voxel and doesn't seam across adjacent or merged faces.
+    pub uvs: Vec<Vec2>,
+
+    /// Vertex face directions, one of the `FACE_DIRECTION_*` constants. Unlike
+    /// the normal, corner offsets don't tilt this. Marching cubes meshes use
+    /// the normal's dominant axis.
+    pub directions: Vec<u32>,
+
+    /// Voxel which produced each face. Face f is vertexes, or indexes if
+    /// the mesh is indexed, `f * VERTEXES_PER_FACE..(f + 1) * VERTEXES_PER_FACE`;
+    /// that's triangles `2 * f` and `2 * f + 1`. Faces merged by greedy
+    /// meshing have their lowest voxel.
+    pub face_voxels: Vec<UVec3>,
+
+    /// Triangle indexes. None if the mesh isn't indexed.
+    pub indexes: Option<Vec<u32>>,
+}
+
+impl MeshData {
+    /// Get positions and triangles suitable for a trimesh collider. Vertexes
+    /// with the same position are shared, after quantizing to 1/1024;
+    /// normals, materials, and ambient occlusion are dropped, as are
+    /// degenerate triangles.
+    pub fn trimesh(&self) -> (Vec<Vec3>, Vec<[u32; 3]>) {
+        let (positions, indexes) = self.shared_indexes();
+        let triangles = indexes
+            .chunks_exact(3)
+            .filter(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0])
+            .map(|t| [t[0], t[1], t[2]])
+            .collect();
+        (positions, triangles)
+    }
+
+    // Positions shared like trimesh, and 3 indexes per triangle, including
+    // degenerate triangles so faces stay aligned
+    fn shared_indexes(&self) -> (Vec<Vec3>, Vec<u32>) {
+        let quantize = |v: Vec3| (v * 1024.0).round().as_ivec3();
+        let mut map = HashMap::new();
+        let mut positions = Vec::new();
+        let mut remap = |v: Vec3| {
+            *map.entry(quantize(v)).or_insert_with(|| {
+                positions.push(v);
+                (positions.len() - 1) as u32
+            })
+        };
+        let indexes: Vec<u32> = match &self.indexes {
+            Some(indexes) => indexes
+                .iter()
+                .map(|i| remap(self.vertexes[*i as usize]))
+                .collect(),
+            None => self.vertexes.iter().map(|v| remap(*v)).collect(),
+        };
+        (positions, indexes)
+    }
+
+    /// Get the faces' outlines as a line list: positions, and 2 indexes per
+    /// line. Each face contributes its 4 boundary edges; the diagonal between
+    /// its 2 triangles is dropped. Edges shared by adjacent faces appear once.
+    /// Vertexes are shared like [Self::trimesh].
+    ///
+    /// This is for meshes made of voxel faces. A marching cubes mesh's
+    /// faces aren't quads, so use [Self::triangle_edges] for it.
+    pub fn face_edges(&self) -> (Vec<Vec3>, Vec<u32>) {
+        let (positions, indexes) = self.shared_indexes();
+        let mut seen = HashSet::new();
+        let mut lines = Vec::new();
+        for face in indexes.chunks_exact(VERTEXES_PER_FACE) {
+            let mut edges = Vec::with_capacity(6);
+            for t in face.chunks_exact(3) {
+                for (a, b) in [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])] {
+                    if a != b {
+                        edges.push((a.min(b), a.max(b)));
+                    }
+                }
+            }
+            for &edge in &edges {
+                // The diagonal is in both triangles
+                let is_diagonal = edges.iter().filter(|e| **e == edge).count() > 1;
+                if !is_diagonal && seen.insert(edge) {
+                    lines.push(edge.0);
+                    lines.push(edge.1);
+                }
+            }
+        }
+        (positions, lines)
+    }
+
+    /// Get every triangle's edges as a line list: positions, and 2 indexes
+    /// per line. Each triangle is walked on its own, so this works for any
+    /// mesh, including marching cubes meshes. Edges shared by adjacent
+    /// triangles appear once. Vertexes are shared like [Self::trimesh].
+    pub fn triangle_edges(&self) -> (Vec<Vec3>, Vec<u32>) {
+        let (positions, triangles) = self.trimesh();
+        let mut seen = HashSet::new();
+        let mut lines = Vec::new();
+        for t in &triangles {
+            for (a, b) in [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])] {
+                if seen.insert((a.min(b), a.max(b))) {
+                    lines.push(a.min(b));
+                    lines.push(a.max(b));
+                }
+            }
+        }
+        (positions, lines)
+    }
+
+    /// Number of faces. Each face is 2 triangles.
+    pub fn num_faces(&self) -> usize {
+        match &self.indexes {
+            Some(indexes) => indexes.len() / VERTEXES_PER_FACE,
+            None => self.vertexes.len() / VERTEXES_PER_FACE,
+        }
+    }
+}
+
+/// Size of a mesh produced by [GenerateMeshImpl]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MeshStats {
+    /// Number of faces. Each face is 2 triangles.
+    pub num_faces: usize,
+
+    /// Number of vertexes. Indexed meshes share vertexes between faces.
+    pub num_vertexes: usize,
+
+    /// Number of bytes of GPU buffer used to generate the mesh
+    pub buffer_size: usize,
+}
+
+/// Use the the shader's generate_mesh function to convert a
+/// voxel grid in VoxelGridBuffer to a mesh.
+///
+/// Call the following in order:
+/// * `[new]` or `[with_buffers]`
+/// * `[add_pass]`
+/// * `[add_copy]`. This may be on a different queue, but the
+///   copy's execution must happen after the pass's execution.
+/// * `[async_map_buffer]`. Only call this after the copy has
+///   finished executing on the GPU.
+/// * `[get_mesh]`. Only call this after async_map_buffer has
+///   called its callback.
+#[derive(Debug)]
+pub struct GenerateMeshImpl {
+    // Size of grid, excluding padding
+    size: UVec3,
+
+    // Excludes padding
+    num_voxels: usize,
+
+    // Run generate_mesh_marching_cubes instead of generate_mesh
+    marching_cubes: bool,
+
+    // Run generate_mesh_region over this box, clipped to the grid
+    region: Option<(UVec3, UVec3)>,
+
+    // Offset of normals in storage_buffer
+    normals_offset: usize,
+
+    // Offset of face_filled in storage_buffer
+    face_filled_offset: usize,
+
+    // Size of face_filled, in bytes
+    face_filled_size: usize,
+
+    // Offset of per-face materials in storage_buffer
+    materials_offset: usize,
+
+    // Offset of per-vertex ambient occlusion in storage_buffer
+    ao_offset: usize,
+
+    // Number of bytes used in storage_buffer and copy_buffer
+    buffer_size: usize,
+
+    // Receives the raw mesh from the shader. STORAGE | COPY_SRC | COPY_DST
+    storage_buffer: Arc<Buffer>,
+
+    // Copy of storage_buffer. COPY_DST | MAP_READ
+    copy_buffer: Arc<Buffer>,
+
+    // Receives the buffers after the mesh is read
+    pool: Option<GenerateMeshBufferPool>,
+
+    bind_group: BindGroup,
+}
+
+/// Buffers used by [GenerateMeshImpl]. These may be larger than needed.
+#[derive(Debug)]
+pub struct GenerateMeshBuffers {
+    // Size of storage_buffer and copy_buffer
+    capacity: usize,
+    storage_buffer: Arc<Buffer>,
+    copy_buffer: Arc<Buffer>,
+}
+
+impl GenerateMeshBuffers {
+    fn new(device: &Device, capacity: usize) -> Self {
+        // println!("** GenerateMeshBuffers::new {}", capacity);
+        let storage_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: capacity as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let copy_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: capacity as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Self {
+            capacity,
+            storage_buffer: storage_buffer.into(),
+            copy_buffer: copy_buffer.into(),
+        }
+    }
+
+    /// Size of the buffers, in bytes
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+/// Holds [GenerateMeshBuffers] between runs of [GenerateMeshImpl]. Reusing
+/// a pool avoids reallocating the buffers each time a grid is converted to
+/// a mesh; they are only reallocated when a larger grid needs them, or
+/// when a grid needs less than half of their capacity, so a long session
+/// doesn't hold on to buffers sized for a grid which has since shrunk. If
+/// the buffers are still in use by another [GenerateMeshImpl], then a new
+/// set is allocated.
+#[derive(Debug, Clone, Default)]
+pub struct GenerateMeshBufferPool(Arc<Mutex<Option<GenerateMeshBuffers>>>);
+
+impl GenerateMeshBufferPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take buffers which hold at least `size` bytes, reusing the
+    /// pool's buffers if they are available, large enough, and not more
+    /// than twice as large as needed.
+    fn take(&self, device: &Device, size: usize) -> GenerateMeshBuffers {
+        match self.0.lock().take() {
+            Some(buffers) if buffers.capacity >= size && buffers.capacity / 2 <= size => buffers,
+            _ => GenerateMeshBuffers::new(device, size),
+        }
+    }
+
+    /// Return buffers to the pool. The largest buffers are kept.
+    fn give(&self, buffers: GenerateMeshBuffers) {
+        let mut guard = self.0.lock();
+        if guard
+            .as_ref()
+            .map_or(true, |b| b.capacity <= buffers.capacity)
+        {
+            *guard = Some(buffers);
+        }
+    }
+
+    /// Capacity of the buffers currently in the pool, if any
+    pub fn capacity(&self) -> Option<usize> {
+        self.0.lock().as_ref().map(|b| b.capacity)
+    }
+
+    /// GPU memory used by the buffers currently in the pool, in bytes
+    pub fn gpu_bytes(&self) -> u64 {
+        self.capacity().map_or(0, |capacity| 2 * capacity as u64)
+    }
+}
+
+pub fn vec4_to_3(v: &Vec4) -> Vec3 {
+    Vec3::new(v.x, v.y, v.z)
+}
+
+/// Pack a per-material mask into the shader's 256-bit form
+fn pack_material_mask(mask: &[bool; 256]) -> [UVec4; 2] {
+    let mut words = [0u32; 8];
+    for (material, &set) in mask.iter().enumerate() {
+        words[material / 32] |= (set as u32) << (material % 32);
+    }
+    [
+        UVec4::new(words[0], words[1], words[2], words[3]),
+        UVec4::new(words[4], words[5], words[6], words[7]),
+    ]
+}
+
+/// Number of cells generate_mesh_marching_cubes covers. Cells are between
+/// voxel centers, including the padding's.
+fn num_marching_cubes_cells(size: UVec3) -> usize {
+    (size.x as usize + 1) * (size.y as usize + 1) * (size.z as usize + 1)
+}
+
+/// Convert generate_mesh_marching_cubes's output to a mesh. Each cell's
+/// triangles are paired into faces; an odd triangle is paired with a
+/// degenerate one.
+fn read_marching_cubes(
+    size: UVec3,
+    counts: &[u32],
+    src_vertexes: &[Vec4],
+    src_normals: &[Vec4],
+    src_materials: &[u32],
+) -> MeshData {
+    let mut mesh = MeshData::default();
+    let cells = size + 1;
+    for (cell, &count) in counts.iter().enumerate() {
+        let count = count as usize;
+        if count == 0 {
+            continue;
+        }
+        let begin = cell * MARCHING_CUBES_VERTEXES_PER_CELL;
+        let num_faces = (count + VERTEXES_PER_FACE - 1) / VERTEXES_PER_FACE;
+        for i in 0..num_faces * VERTEXES_PER_FACE {
+            let j = begin + i.min(count - 1);
+            mesh.vertexes.push(vec4_to_3(&src_vertexes[j]));
+            mesh.normals.push(vec4_to_3(&src_normals[j]));
+            mesh.materials.push(src_materials[cell]);
+            mesh.ao.push(1.0);
+            mesh.directions
+                .push(normal_direction(vec4_to_3(&src_normals[j])));
+        }
+        // The cell's min corner is 1 below this; keep the voxel in the grid
+        let cell = cell as u32;
+        let pos = UVec3::new(
+            cell % cells.x,
+            (cell / cells.x) % cells.y,
+            cell / (cells.x * cells.y),
+        );
+        let voxel = (pos.max(UVec3::ONE) - 1).min(size - 1);
+        mesh.face_voxels
+            .extend(std::iter::repeat(voxel).take(num_faces));
+    }
+    mesh
+}
+
+/// Round up a storage buffer binding offset to STORAGE_OFFSET_ALIGNMENT
+fn align_storage_offset(offset: usize) -> usize {
+    (offset + STORAGE_OFFSET_ALIGNMENT - 1) / STORAGE_OFFSET_ALIGNMENT * STORAGE_OFFSET_ALIGNMENT
+}
+
+impl GenerateMeshImpl {
+    /// Create buffers and bind group
+    pub fn new(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        grid_buffer: &VoxelGrid,
+    ) -> Self {
+        Self::with_options(
+            device,
+            bind_group_layout,
+            grid_buffer,
+            None,
+            &Default::default(),
+        )
+    }
+
+    /// Create bind group, reusing buffers from the pool if possible.
+    /// The buffers return to the pool after the mesh is read.
+    pub fn with_buffers(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        grid_buffer: &VoxelGrid,
+        pool: &GenerateMeshBufferPool,
+    ) -> Self {
+        Self::with_options(
+            device,
+            bind_group_layout,
+            grid_buffer,
+            Some(pool),
+            &Default::default(),
+        )
+    }
+
+    /// Create bind group, reusing buffers from the pool if there is one.
+    /// The options control what the shader computes.
+    pub fn with_options(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        grid_buffer: &VoxelGrid,
+        pool: Option<&GenerateMeshBufferPool>,
+        options: &GenerateMeshOptions,
+    ) -> Self {
+        // println!("** GenerateMeshImpl::new");
+        let num_voxels =
+            grid_buffer.size.x as usize * grid_buffer.size.y as usize * grid_buffer.size.z as usize;
+        // println!("   num_voxels: {:?}", num_voxels);
+        // Sizes of each part of the storage buffer. With marching cubes,
+        // face_filled holds each cell's vertex count and ao is unused.
+        let (mesh_size, face_filled_size, materials_size, ao_size) = if options.marching_cubes {
+            let num_cells = num_marching_cubes_cells(grid_buffer.size);
+            (
+                num_cells * MARCHING_CUBES_VERTEXES_PER_CELL * WGSL_VEC3_STRIDE,
+                num_cells * size_of::<u32>(),
+                num_cells * size_of::<u32>(),
+                size_of::<f32>(),
+            )
+        } else {
+            let num_faces = num_voxels * FACES_PER_VOXEL;
+            (
+                num_voxels * WGSL_FACES_STRIDE,
+                (num_faces + FACE_FILLED_NUM_BITS as usize - 1) / FACE_FILLED_NUM_BITS as usize * 4,
+                num_faces * size_of::<u32>(),
+                num_faces * VERTEXES_PER_FACE * size_of::<f32>(),
+            )
+        };
+        let normals_offset = mesh_size;
+        let face_filled_offset = normals_offset + mesh_size;
+        // println!("   face_filled_offset: {:?}", face_filled_offset);
+        let materials_offset = align_storage_offset(face_filled_offset + face_filled_size);
+        let ao_offset = align_storage_offset(materials_offset + materials_size);
+        let buffer_size = ao_offset + ao_size;
+        // println!(
+        //     "    grid_buffer {} {:?}",
+        //     grid_buffer.buffer.size(),
+        //     grid_buffer.buffer.usage()
+        // );
+
+        let region = options
+            .region
+            .filter(|_| !options.marching_cubes)
+            .map(|(min, size)| {
+                let min = min.min(grid_buffer.size);
+                (min, size.min(grid_buffer.size - min))
+            });
+        let (offset, size) = region.unwrap_or_default();
+
+        let args = ShaderArgs {
+            a_size: grid_buffer.size,
+            offset: offset.as_ivec3(),
+            size,
+            flags: if options.ambient_occlusion {
+                GENERATE_MESH_AO_FLAG
+            } else {
+                0
+            } | if options.ignore_offsets {
+                GENERATE_MESH_IGNORE_OFFSETS_FLAG
+            } else {
+                0
+            } | if options.close_boundary {
+                GENERATE_MESH_CLOSE_BOUNDARY_FLAG
+            } else {
+                0
+            } | match (grid_buffer.layout, &grid_buffer.offsets) {
+                (GridLayout::Packed, _) => 0,
+                (GridLayout::Split, None) => GENERATE_MESH_SPLIT_LAYOUT_FLAG,
+                (GridLayout::Split, Some(_)) => {
+                    GENERATE_MESH_SPLIT_LAYOUT_FLAG | GENERATE_MESH_SPLIT_OFFSETS_FLAG
+                }
+            },
+            transparent: pack_material_mask(&options.transparent_mask),
+            ..Default::default()
+        };
+        let args_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: size_of::<ShaderArgs>() as u64,
+            usage: BufferUsages::UNIFORM,
+            mapped_at_creation: true,
+        });
+        *from_bytes_mut::<ShaderArgs>(&mut args_buffer.slice(..).get_mapped_range_mut()) = args;
+        args_buffer.unmap();
+
+        let buffers = match pool {
+            Some(pool) => pool.take(device, buffer_size),
+            None => GenerateMeshBuffers::new(device, buffer_size),
+        };
+        let storage_buffer = buffers.storage_buffer;
+        let copy_buffer = buffers.copy_buffer;
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("generate_mesh_bind_group"),
+            layout: bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: WGSL_ARGS_BINDING,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &args_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                BindGroupEntry {
+                    binding: WGSL_VOXEL_GRID_A_BINDING,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &grid_buffer.buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                BindGroupEntry {
+                    // Unread unless the grid has split offsets
+                    binding: WGSL_VOXEL_GRID_B_BINDING,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: grid_buffer.offsets.as_ref().unwrap_or(&grid_buffer.buffer),
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                BindGroupEntry {
+                    binding: WGSL_FACE_FILLED_BINDING,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &storage_buffer,
+                        offset: face_filled_offset as u64,
+                        size: NonZeroU64::new(face_filled_size as u64),
+                    }),
+                },
+                BindGroupEntry {
+                    binding: WGSL_MESH_BINDING,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &storage_buffer,
+                        offset: 0,
+                        size: NonZeroU64::new(normals_offset as u64),
+                    }),
+                },
+                BindGroupEntry {
+                    binding: WGSL_MESH_NORMALS_BINDING,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &storage_buffer,
+                        offset: normals_offset as u64,
+                        size: NonZeroU64::new((face_filled_offset - normals_offset) as u64),
+                    }),
+                },
+                BindGroupEntry {
+                    binding: WGSL_MESH_MATERIALS_BINDING,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &storage_buffer,
+                        offset: materials_offset as u64,
+                        size: NonZeroU64::new((ao_offset - materials_offset) as u64),
+                    }),
+                },
+                BindGroupEntry {
+                    binding: WGSL_MESH_AO_BINDING,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &storage_buffer,
+                        offset: ao_offset as u64,
+                        size: NonZeroU64::new((buffer_size - ao_offset) as u64),
+                    }),
+                },
+            ],
+        });
+
+        Self {
+            size: grid_buffer.size,
+            num_voxels,
+            marching_cubes: options.marching_cubes,
+            region,
+            normals_offset,
+            face_filled_offset,
+            face_filled_size,
+            materials_offset,
+            ao_offset,
+            buffer_size,
+            storage_buffer,
+            copy_buffer,
+            pool: pool.cloned(),
+            bind_group,
+        }
+    }
+
+    /// Shader entry point which add_pass's pipeline must use
+    pub fn entry_point(&self) -> &'static str {
+        if self.marching_cubes {
+            GENERATE_MESH_MARCHING_CUBES_ENTRY_POINT
+        } else if self.region.is_some() {
+            GENERATE_MESH_REGION_ENTRY_POINT
+        } else {
+            GENERATE_MESH_ENTRY_POINT
+        }
+    }
+
+    /// Add the compute pass to the command encoder. The pipeline must use
+    /// [Self::entry_point]. This clears face_filled first, since the shader
+    /// only sets its bits and pooled buffers hold the last run's faces.
+    pub fn add_pass(&self, pipeline: &ComputePipeline, encoder: &mut CommandEncoder) {
+        // println!("** GenerateMeshImpl::add_pass");
+        self.clear_face_filled(encoder);
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("generate_mesh_pass"),
+        });
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_pipeline(pipeline);
+        let workgroups = self.workgroups();
+        pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+    }
+
+    fn clear_face_filled(&self, encoder: &mut CommandEncoder) {
+        encoder.clear_buffer(
+            &self.storage_buffer,
+            self.face_filled_offset as u64,
+            NonZeroU64::new(self.face_filled_size as u64),
+        );
+    }
+
+    /// Workgroup counts which [Self::add_pass] dispatches
+    pub fn workgroups(&self) -> UVec3 {
+        if self.marching_cubes {
+            let num_cells = num_marching_cubes_cells(self.size) as u32;
+            split_workgroups((num_cells + mesh_workgroup_size() - 1) / mesh_workgroup_size())
+        } else if let Some((_, size)) = self.region {
+            // generate_mesh_region covers each row with (size.x / 5 + 2) invocations
+            let entries_per_row = size.x / GENERATE_MESH_VOXELS_PER_INVOCATION + 2;
+            let num_invocations = size.y * size.z * entries_per_row;
+            split_workgroups((num_invocations + mesh_workgroup_size() - 1) / mesh_workgroup_size())
+        } else {
+            let voxels_per_workgroup = GENERATE_MESH_VOXELS_PER_INVOCATION * mesh_workgroup_size();
+            split_workgroups(
+                (self.num_voxels as u32 + voxels_per_workgroup - 1) / voxels_per_workgroup,
+            )
+        }
+    }
+
+    /// Like [Self::add_pass], but read the workgroup counts from
+    /// indirect_buffer, e.g. written by an earlier compute pass. The buffer
+    /// needs [BufferUsages::INDIRECT] and holds 3 `u32`s at indirect_offset.
+    /// Dispatching fewer workgroups than [Self::workgroups] covers a prefix
+    /// of the voxels; dispatching more does no extra work.
+    pub fn add_pass_indirect(
+        &self,
+        pipeline: &ComputePipeline,
+        encoder: &mut CommandEncoder,
+        indirect_buffer: &Buffer,
+        indirect_offset: u64,
+    ) {
+        self.clear_face_filled(encoder);
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("generate_mesh_pass"),
+        });
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_pipeline(pipeline);
+        pass.dispatch_workgroups_indirect(indirect_buffer, indirect_offset);
+    }
+
+    /// Add the buffer copy to the command encoder. This copies the whole
+    /// worst-case buffer, since the number of faces isn't known until
+    /// face_filled is read back. [Self::compact] copies only the filled
+    /// faces, using a second submission.
+    pub fn add_copy(&self, encoder: &mut CommandEncoder) {
+        // println!("** GenerateMeshImpl::add_copy");
+        encoder.copy_buffer_to_buffer(
+            &self.storage_buffer,
+            0,
+            &self.copy_buffer,
+            0,
+            self.buffer_size as u64,
+        );
+    }
+
+    /// Map the copy buffer (async) then call the callback
+    pub fn async_map_buffer(
+        self,
+        done: impl FnOnce(GenerateMeshImpl, Result<(), BufferAsyncError>) + Send + 'static,
+    ) {
+        self.copy_buffer
+            .clone()
+            .slice(..self.buffer_size as u64)
+            .map_async(MapMode::Read, |result| done(self, result));
+    }
+
+    /// Number of bytes used in the storage and copy buffers
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Size of the storage and copy buffers, in bytes. This may be larger
+    /// than [Self::buffer_size] when the buffers came from a pool.
+    pub fn capacity(&self) -> usize {
+        self.copy_buffer.size() as usize
+    }
+
+    /// GPU memory used by the storage and copy buffers combined, in bytes
+    pub fn gpu_bytes(&self) -> u64 {
+        self.storage_buffer.size() + self.copy_buffer.size()
+    }
+
+    /// Get the mesh, normals, and materials from the copy buffer
+    pub fn get_mesh(self) -> MeshData {
+        let mesh = self.read_mesh(false, 0.0);
+        self.release();
+        mesh
+    }
+
+    fn read_mesh(&self, greedy: bool, merge_epsilon: f32) -> MeshData {
+        let raw = self
+            .copy_buffer
+            .slice(..self.buffer_size as u64)
+            .get_mapped_range();
+        let src_vertexes = cast_slice::<u8, Vec4>(&raw[..self.normals_offset]);
+        let src_normals =
+            cast_slice::<u8, Vec4>(&raw[self.normals_offset..self.face_filled_offset]);
+        let face_filled = cast_slice::<u8, u32>(
+            &raw[self.face_filled_offset..self.face_filled_offset + self.face_filled_size],
+        );
+        let src_materials = cast_slice::<u8, u32>(&raw[self.materials_offset..self.ao_offset]);
+        let src_ao = cast_slice::<u8, f32>(&raw[self.ao_offset..self.buffer_size]);
+        if self.marching_cubes {
+            let mut mesh = read_marching_cubes(
+                self.size,
+                face_filled,
+                src_vertexes,
+                src_normals,
+                src_materials,
+            );
+            add_uvs(&mut mesh);
+            return mesh;
+        }
+        let faces = FaceArrays {
+            face_filled,
+            vertexes: src_vertexes,
+            normals: src_normals,
+            materials: src_materials,
+            ao: src_ao,
+            face_offsets: None,
+        };
+        read_faces(self.size, &faces, greedy, merge_epsilon)
+    }
+
+    /// Unmap the copy buffer and return the buffers to the pool, if any
+    fn release(self) {
+        if self.pool.is_some() {
+            self.copy_buffer.unmap();
+        }
+        self.give_to_pool();
+    }
+
+    // Return the buffers to the pool, if any. The copy buffer must be unmapped.
+    fn give_to_pool(self) {
+        if let Some(pool) = self.pool {
+            pool.give(GenerateMeshBuffers {
+                capacity: self.copy_buffer.size() as usize,
+                storage_buffer: self.storage_buffer,
+                copy_buffer: self.copy_buffer,
+            });
+        }
+    }
+
+    /// Add a copy of only face_filled to the command encoder. Use this
+    /// instead of [Self::add_copy] before [Self::compact].
+    pub fn add_copy_face_filled(&self, encoder: &mut CommandEncoder) {
+        encoder.copy_buffer_to_buffer(
+            &self.storage_buffer,
+            self.face_filled_offset as u64,
+            &self.copy_buffer,
+            self.face_filled_offset as u64,
+            self.face_filled_size as u64,
+        );
+    }
+
+    /// Map face_filled in the copy buffer (async) then call the callback
+    pub fn async_map_face_filled(
+        self,
+        done: impl FnOnce(GenerateMeshImpl, Result<(), BufferAsyncError>) + Send + 'static,
+    ) {
+        let begin = self.face_filled_offset as u64;
+        let end = begin + self.face_filled_size as u64;
+        self.copy_buffer
+            .clone()
+            .slice(begin..end)
+            .map_async(MapMode::Read, |result| done(self, result));
+    }
+
+    /// Read face_filled from the copy buffer, then create the buffers and
+    /// bind group which pack the filled faces. Only call this after
+    /// async_map_face_filled has called its callback. See [CompactMeshImpl].
+    ///
+    /// * bind_group_layout: From [compact_mesh_bind_group_layout]
+    ///
+    /// Panics if the options had marching_cubes.
+    pub fn compact(self, device: &Device, bind_group_layout: &BindGroupLayout) -> CompactMeshImpl {
+        assert!(
+            !self.marching_cubes,
+            "compact doesn't support marching_cubes"
+        );
+        let begin = self.face_filled_offset as u64;
+        let end = begin + self.face_filled_size as u64;
+        let face_filled =
+            cast_slice::<u8, u32>(&self.copy_buffer.slice(begin..end).get_mapped_range()).to_vec();
+        self.copy_buffer.unmap();
+
+        let mut num_faces = 0;
+        let face_offsets: Vec<u32> = face_filled
+            .iter()
+            .map(|bits| {
+                let offset = num_faces;
+                num_faces += bits.count_ones();
+                offset
+            })
+            .collect();
+        let num_faces = num_faces as usize;
+        // println!("** GenerateMeshImpl::compact num_faces: {}", num_faces);
+
+        let offsets_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: (face_offsets.len() * size_of::<u32>()) as u64,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: true,
+        });
+        offsets_buffer
+            .slice(..)
+            .get_mapped_range_mut()
+            .copy_from_slice(cast_slice::<u32, u8>(&face_offsets));
+        offsets_buffer.unmap();
+
+        // Layout of the shader's packed_faces. Bindings can't be empty, so
+        // room for 1 face is the minimum.
+        let capacity = num_faces.max(1);
+        let normals_offset = capacity * WGSL_FACE_STRIDE;
+        let materials_offset = normals_offset + capacity * WGSL_FACE_STRIDE;
+        let ao_offset = materials_offset + capacity * size_of::<u32>();
+        let buffer_size = ao_offset + capacity * VERTEXES_PER_FACE * size_of::<f32>();
+        let storage_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: buffer_size as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let copy_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: buffer_size as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        fn range(buffer: &Buffer, binding: u32, offset: usize, end: usize) -> BindGroupEntry {
+            BindGroupEntry {
+                binding,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer,
+                    offset: offset as u64,
+                    size: NonZeroU64::new((end - offset) as u64),
+                }),
+            }
+        }
+        let sparse = &*self.storage_buffer;
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("compact_mesh_bind_group"),
+            layout: bind_group_layout,
+            entries: &[
+                range(
+                    sparse,
+                    WGSL_FACE_FILLED_BINDING,
+                    self.face_filled_offset,
+                    self.face_filled_offset + self.face_filled_size,
+                ),
+                range(sparse, WGSL_MESH_BINDING, 0, self.normals_offset),
+                range(
+                    sparse,
+                    WGSL_MESH_NORMALS_BINDING,
+                    self.normals_offset,
+                    self.face_filled_offset,
+                ),
+                range(
+                    sparse,
+                    WGSL_MESH_MATERIALS_BINDING,
+                    self.materials_offset,
+                    self.ao_offset,
+                ),
+                range(
+                    sparse,
+                    WGSL_MESH_AO_BINDING,
+                    self.ao_offset,
+                    self.buffer_size,
+                ),
+                range(
+                    &offsets_buffer,
+                    WGSL_FACE_OFFSETS_BINDING,
+                    0,
+                    face_offsets.len() * size_of::<u32>(),
+                ),
+                range(&storage_buffer, WGSL_PACKED_FACES_BINDING, 0, buffer_size),
+            ],
+        });
+
+        CompactMeshImpl {
+            mesh_impl: self,
+            face_filled,
+            face_offsets,
+            num_faces,
+            normals_offset,
+            materials_offset,
+            ao_offset,
+            buffer_size,
+            storage_buffer,
+            copy_buffer: copy_buffer.into(),
+            bind_group,
+        }
+    }
+
+    /// Get the mesh, normals, materials, and triangle indexes from the copy buffer.
+    /// Vertexes with the same position, normal, and material are shared.
+    pub fn get_indexed_mesh(self) -> MeshData {
+        index_mesh(self.get_mesh())
+    }
+
+    /// Get the mesh, normals, and materials from the copy buffer. Adjacent
+    /// coplanar faces with the same material are merged into larger quads.
+    pub fn get_greedy_mesh(self) -> MeshData {
+        let mesh = self.read_mesh(true, 0.0);
+        self.release();
+        mesh
+    }
+
+    /// Like [Self::get_mesh_data], but also return the raw face_filled
+    /// bitset the shader produced. Use [face_index] to find a face's bit.
+    /// With marching_cubes, it instead holds each cell's vertex count.
+    pub fn get_mesh_data_with_face_filled(
+        self,
+        options: &GenerateMeshOptions,
+    ) -> (MeshData, Vec<u32>) {
+        let face_filled = {
+            let raw = self
+                .copy_buffer
+                .slice(..self.buffer_size as u64)
+                .get_mapped_range();
+            cast_slice::<u8, u32>(
+                &raw[self.face_filled_offset..self.face_filled_offset + self.face_filled_size],
+            )
+            .to_vec()
+        };
+        (self.get_mesh_data(options), face_filled)
+    }
+
+    /// Get the mesh from the copy buffer, formatted according to options
+    pub fn get_mesh_data(self, options: &GenerateMeshOptions) -> MeshData {
+        let mesh = self.read_mesh(options.greedy, options.offset_merge_epsilon);
+        self.release();
+        format_mesh(mesh, options)
+    }
+} // GenerateMeshImpl
+
+/// Reads back a mesh without copying [GenerateMeshImpl]'s worst-case
+/// buffers. The first submission reads back only face_filled; the second
+/// packs the filled faces on the GPU and reads back only those. Use:
+///
+/// * [GenerateMeshImpl::add_pass] and [GenerateMeshImpl::add_copy_face_filled],
+///   then submit.
+/// * [GenerateMeshImpl::async_map_face_filled]
+/// * [GenerateMeshImpl::compact]. Only call this after
+///   async_map_face_filled has called its callback.
+/// * [Self::add_pass] and [Self::add_copy], then submit.
+/// * [Self::async_map_buffer]
+/// * [Self::get_mesh_data]. Only call this after async_map_buffer has
+///   called its callback.
+#[derive(Debug)]
+pub struct CompactMeshImpl {
+    // Holds the sparse faces which compact_mesh reads. Its buffers return
+    // to the pool after the mesh is read.
+    mesh_impl: GenerateMeshImpl,
+
+    face_filled: Vec<u32>,
+
+    // Number of filled faces before each entry of face_filled
+    face_offsets: Vec<u32>,
+
+    num_faces: usize,
+
+    // Offsets of the arrays in the shader's packed_faces. Vertexes are at 0.
+    normals_offset: usize,
+    materials_offset: usize,
+    ao_offset: usize,
+
+    // Number of bytes used in storage_buffer and copy_buffer
+    buffer_size: usize,
+
+    // Receives the packed faces from the shader. STORAGE | COPY_SRC
+    storage_buffer: Buffer,
+
+    // Copy of storage_buffer. COPY_DST | MAP_READ
+    copy_buffer: Arc<Buffer>,
+
+    bind_group: BindGroup,
+}
+
+impl CompactMeshImpl {
+    /// Shader entry point which add_pass's pipeline must use
+    pub fn entry_point(&self) -> &'static str {
+        COMPACT_MESH_ENTRY_POINT
+    }
+
+    /// Add the compute pass to the command encoder. The pipeline must use
+    /// [Self::entry_point].
+    pub fn add_pass(&self, pipeline: &ComputePipeline, encoder: &mut CommandEncoder) {
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("compact_mesh_pass"),
+        });
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_pipeline(pipeline);
+        let workgroups = split_workgroups(
+            (self.face_offsets.len() as u32 + mesh_workgroup_size() - 1) / mesh_workgroup_size(),
+        );
+        pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+    }
+
+    /// Add the buffer copy to the command encoder. This only copies the
+    /// filled faces.
+    pub fn add_copy(&self, encoder: &mut CommandEncoder) {
+        encoder.copy_buffer_to_buffer(
+            &self.storage_buffer,
+            0,
+            &self.copy_buffer,
+            0,
+            self.buffer_size as u64,
+        );
+    }
+
+    /// Map the copy buffer (async) then call the callback
+    pub fn async_map_buffer(
+        self,
+        done: impl FnOnce(CompactMeshImpl, Result<(), BufferAsyncError>) + Send + 'static,
+    ) {
+        self.copy_buffer
+            .clone()
+            .slice(..self.buffer_size as u64)
+            .map_async(MapMode::Read, |result| done(self, result));
+    }
+
+    /// Number of filled faces
+    pub fn num_faces(&self) -> usize {
+        self.num_faces
+    }
+
+    /// Number of bytes which add_copy copies
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Get the mesh from the copy buffer, formatted according to options.
+    /// Matches [GenerateMeshImpl::get_mesh_data].
+    pub fn get_mesh_data(self, options: &GenerateMeshOptions) -> MeshData {
+        let mesh = {
+            let raw = self
+                .copy_buffer
+                .slice(..self.buffer_size as u64)
+                .get_mapped_range();
+            let faces = FaceArrays {
+                face_filled: &self.face_filled,
+                vertexes: cast_slice::<u8, Vec4>(&raw[..self.normals_offset]),
+                normals: cast_slice::<u8, Vec4>(&raw[self.normals_offset..self.materials_offset]),
+                materials: cast_slice::<u8, u32>(&raw[self.materials_offset..self.ao_offset]),
+                ao: cast_slice::<u8, f32>(&raw[self.ao_offset..self.buffer_size]),
+                face_offsets: Some(&self.face_offsets),
+            };
+            read_faces(
+                self.mesh_impl.size,
+                &faces,
+                options.greedy,
+                options.offset_merge_epsilon,
+            )
+        };
+        self.mesh_impl.give_to_pool();
+        format_mesh(mesh, options)
+    }
+} // CompactMeshImpl
+
+/// Apply the options which don't affect the shader: smooth, indexed, and
+/// voxel_scale
+pub(crate) fn format_mesh(mut mesh: MeshData, options: &GenerateMeshOptions) -> MeshData {
+    if options.smooth {
+        smooth_normals(&mut mesh);
+    }
+    if options.indexed {
+        mesh = index_mesh(mesh);
+    }
+    if options.voxel_scale != 1.0 {
+        for v in &mut mesh.vertexes {
+            *v *= options.voxel_scale;
+        }
+    }
+    if options.flip_winding {
+        flip_winding(&mut mesh);
+    }
+    mesh
+}
+
+/// Reverse the order of each triangle's corners. Faces keep their vertexes;
+/// only the order within each triangle changes.
+fn flip_winding(mesh: &mut MeshData) {
+    fn flip<T>(v: &mut [T]) {
+        for triangle in v.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+    if let Some(indexes) = &mut mesh.indexes {
+        flip(indexes);
+    } else {
+        flip(&mut mesh.vertexes);
+        flip(&mut mesh.normals);
+        flip(&mut mesh.materials);
+        flip(&mut mesh.ao);
+        flip(&mut mesh.uvs);
+        flip(&mut mesh.directions);
+    }
+}
+
+/// generate_mesh's output, as read by [read_faces]
+pub(crate) struct FaceArrays<'a> {
+    pub face_filled: &'a [u32],
+    pub vertexes: &'a [Vec4],
+    pub normals: &'a [Vec4],
+    pub materials: &'a [u32],
+    pub ao: &'a [f32],
+
+    // Number of filled faces before each face_filled entry, if compact_mesh
+    // packed the other arrays. None if they're indexed by face.
+    pub face_offsets: Option<&'a [u32]>,
+}
+
+impl FaceArrays<'_> {
+    fn is_filled(&self, i: usize) -> bool {
+        self.face_filled[i / FACE_FILLED_NUM_BITS as usize]
+            & (1 << (i % FACE_FILLED_NUM_BITS as usize))
+            != 0
+    }
+
+    // Index of face i in the per-face arrays
+    fn slot(&self, i: usize) -> usize {
+        match self.face_offsets {
+            Some(offsets) => {
+                let entry = i / FACE_FILLED_NUM_BITS as usize;
+                let below = (1 << (i % FACE_FILLED_NUM_BITS as usize)) - 1;
+                offsets[entry] as usize + (self.face_filled[entry] & below).count_ones() as usize
+            }
+            None => i,
+        }
+    }
+}
+
+/// Convert generate_mesh's output to a mesh. Filled faces are kept in
+/// face order; see [face_index].
+pub(crate) fn read_faces(
+    size: UVec3,
+    faces: &FaceArrays,
+    greedy: bool,
+    merge_epsilon: f32,
+) -> MeshData {
+    let num_voxels = size.x as usize * size.y as usize * size.z as usize;
+    if greedy {
+        let mut mesh = merge_faces(size, faces, merge_epsilon);
+        add_uvs(&mut mesh);
+        return mesh;
+    }
+
+    let mut num_faces = 0;
+    for mask in faces.face_filled {
+        // println!("   mask: {:#08x}", mask);
+        num_faces += mask.count_ones() as usize;
+    }
+
+    let mut vertexes: Vec<Vec3> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut materials: Vec<u32> = Vec::new();
+    let mut ao: Vec<f32> = Vec::new();
+    let mut directions: Vec<u32> = Vec::new();
+    let mut face_voxels: Vec<UVec3> = Vec::with_capacity(num_faces);
+    vertexes.resize(num_faces * VERTEXES_PER_FACE, Default::default());
+    normals.resize(num_faces * VERTEXES_PER_FACE, Default::default());
+    materials.resize(num_faces * VERTEXES_PER_FACE, Default::default());
+    ao.resize(num_faces * VERTEXES_PER_FACE, Default::default());
+    directions.resize(num_faces * VERTEXES_PER_FACE, Default::default());
+
+    let mut filled = 0;
+    for i in 0..num_voxels * FACES_PER_VOXEL {
+        if faces.is_filled(i) {
+            // println!("   fill face: {:?}", i);
+            let slot = faces.slot(i);
+            for j in 0..VERTEXES_PER_FACE {
+                let v = faces.vertexes[slot * VERTEXES_PER_FACE + j];
+                vertexes[filled * VERTEXES_PER_FACE + j] = vec4_to_3(&v);
+
+                let n = faces.normals[slot * VERTEXES_PER_FACE + j];
+                normals[filled * VERTEXES_PER_FACE + j] = vec4_to_3(&n);
+
+                materials[filled * VERTEXES_PER_FACE + j] = faces.materials[slot];
+                ao[filled * VERTEXES_PER_FACE + j] = faces.ao[slot * VERTEXES_PER_FACE + j];
+                directions[filled * VERTEXES_PER_FACE + j] = FACE_DIRECTION[i % FACES_PER_VOXEL];
+            }
+            let voxel = (i / FACES_PER_VOXEL) as u32;
+            face_voxels.push(UVec3::new(
+                voxel % size.x,
+                (voxel / size.x) % size.y,
+                voxel / (size.x * size.y),
+            ));
+            filled += 1;
+        }
+    }
+    // println!("   filled: {:?}", filled);
+    // println!("   num_faces: {:?}", num_faces);
+    assert!(filled == num_faces);
+    let mut mesh = MeshData {
+        vertexes,
+        normals,
+        materials,
+        ao,
+        uvs: Vec::new(),
+        directions,
+        face_voxels,
+        indexes: None,
+    };
+    add_uvs(&mut mesh);
+    mesh
+}
+
+/// Corners of each face when the voxels have no offsets, in the same order as
+/// the shader's generate_mesh. Index by `face % FACES_PER_VOXEL`.
+const FLAT_FACES: [[[u8; 3]; VERTEXES_PER_FACE]; FACES_PER_VOXEL] = [
+    [
+        [0, 0, 1],
+        [1, 0, 1],
+        [1, 1, 1],
+        [1, 1, 1],
+        [0, 1, 1],
+        [0, 0, 1],
+    ], // z=1
+    [
+        [1, 0, 1],
+        [1, 0, 0],
+        [1, 1, 0],
+        [1, 1, 0],
+        [1, 1, 1],
+        [1, 0, 1],
+    ], // x=1
+    [
+        [1, 0, 0],
+        [0, 0, 0],
+        [0, 1, 0],
+        [0, 1, 0],
+        [1, 1, 0],
+        [1, 0, 0],
+    ], // z=0
+    [
+        [0, 0, 0],
+        [0, 0, 1],
+        [0, 1, 1],
+        [0, 1, 1],
+        [0, 1, 0],
+        [0, 0, 0],
+    ], // x=0
+    [
+        [0, 1, 1],
+        [1, 1, 1],
+        [1, 1, 0],
+        [1, 1, 0],
+        [0, 1, 0],
+        [0, 1, 1],
+    ], // y=1
+    [
+        [0, 0, 0],
+        [1, 0, 0],
+        [1, 0, 1],
+        [1, 0, 1],
+        [0, 0, 1],
+        [0, 0, 0],
+    ], // y=0
+];
+
+/// Axis each face is perpendicular to. Index by `face % FACES_PER_VOXEL`.
+const FACE_AXIS: [usize; FACES_PER_VOXEL] = [2, 0, 2, 0, 1, 1];
+
+/// Normal of each face when the voxels have no offsets. Index by
+/// `face % FACES_PER_VOXEL`.
+const FLAT_NORMALS: [Vec3; FACES_PER_VOXEL] = [
+    Vec3::Z,
+    Vec3::X,
+    Vec3::NEG_Z,
+    Vec3::NEG_X,
+    Vec3::Y,
+    Vec3::NEG_Y,
+];
+
+/// Direction each face points. Index by `face % FACES_PER_VOXEL`.
+const FACE_DIRECTION: [u32; FACES_PER_VOXEL] = [
+    FACE_DIRECTION_POS_Z,
+    FACE_DIRECTION_POS_X,
+    FACE_DIRECTION_NEG_Z,
+    FACE_DIRECTION_NEG_X,
+    FACE_DIRECTION_POS_Y,
+    FACE_DIRECTION_NEG_Y,
+];
+
+/// Direction of a normal's dominant axis
+fn normal_direction(n: Vec3) -> u32 {
+    let a = n.abs();
+    if a.x >= a.y && a.x >= a.z {
+        if n.x >= 0.0 {
+            FACE_DIRECTION_POS_X
+        } else {
+            FACE_DIRECTION_NEG_X
+        }
+    } else if a.y >= a.z {
+        if n.y >= 0.0 {
+            FACE_DIRECTION_POS_Y
+        } else {
+            FACE_DIRECTION_NEG_Y
+        }
+    } else if n.z >= 0.0 {
+        FACE_DIRECTION_POS_Z
+    } else {
+        FACE_DIRECTION_NEG_Z
+    }
+}
+
+/// Convert generate_mesh's output to a mesh, merging adjacent coplanar
+/// faces which have the same material. Faces whose corners have offsets
+/// larger than merge_epsilon / 64, or ambient occlusion, are copied as-is.
+fn merge_faces(size: UVec3, faces: &FaceArrays, merge_epsilon: f32) -> MeshData {
+    let size = size.to_array().map(|v| v as usize);
+    let face_index = |pos: [usize; 3], dir: usize| {
+        (pos[0] + pos[1] * size[0] + pos[2] * size[0] * size[1]) * FACES_PER_VOXEL + dir
+    };
+    let corner = |pos: [usize; 3], c: &[u8; 3]| {
+        Vec3::new(
+            (pos[0] + c[0] as usize) as f32,
+            (pos[1] + c[1] as usize) as f32,
+            (pos[2] + c[2] as usize) as f32,
+        )
+    };
+    let is_flat = |i: usize, pos: [usize; 3]| {
+        let slot = faces.slot(i);
+        FLAT_FACES[i % FACES_PER_VOXEL]
+            .iter()
+            .enumerate()
+            .all(|(j, c)| {
+                (vec4_to_3(&faces.vertexes[slot * VERTEXES_PER_FACE + j]) - corner(pos, c))
+                    .abs()
+                    .max_element()
+                    <= merge_epsilon / 64.0
+                    && faces.ao[slot * VERTEXES_PER_FACE + j] == 1.0
+            })
+    };
+
+    let mut result = MeshData::default();
+    for dir in 0..FACES_PER_VOXEL {
+        let n = FACE_AXIS[dir];
+        let (u, v) = ((n + 1) % 3, (n + 2) % 3);
+        let mut mask: Vec<Option<u32>> = vec![None; size[u] * size[v]];
+        for slice in 0..size[n] {
+            let to_pos = |i: usize, j: usize| {
+                let mut pos = [0; 3];
+                pos[n] = slice;
+                pos[u] = i;
+                pos[v] = j;
+                pos
+            };
+
+            // Collect flat faces; copy the others as-is
+            for j in 0..size[v] {
+                for i in 0..size[u] {
+                    let pos = to_pos(i, j);
+                    let f = face_index(pos, dir);
+                    mask[i + j * size[u]] = None;
+                    if !faces.is_filled(f) {
+                        continue;
+                    }
+                    let slot = faces.slot(f);
+                    if is_flat(f, pos) {
+                        mask[i + j * size[u]] = Some(faces.materials[slot]);
+                    } else {
+                        for k in 0..VERTEXES_PER_FACE {
+                            result
+                                .vertexes
+                                .push(vec4_to_3(&faces.vertexes[slot * VERTEXES_PER_FACE + k]));
+                            result
+                                .normals
+                                .push(vec4_to_3(&faces.normals[slot * VERTEXES_PER_FACE + k]));
+                            result.materials.push(faces.materials[slot]);
+                            result.ao.push(faces.ao[slot * VERTEXES_PER_FACE + k]);
+                            result.directions.push(FACE_DIRECTION[dir]);
+                        }
+                        result
+                            .face_voxels
+                            .push(UVec3::from_array(pos.map(|p| p as u32)));
+                    }
+                }
+            }
+
+            // Grow each rectangle along u, then along v
+            for j in 0..size[v] {
+                let mut i = 0;
+                while i < size[u] {
+                    let material = match mask[i + j * size[u]] {
+                        Some(material) => material,
+                        None => {
+                            i += 1;
+                            continue;
+                        }
+                    };
+                    let mut w = 1;
+                    while i + w < size[u] && mask[i + w + j * size[u]] == Some(material) {
+                        w += 1;
+                    }
+                    let mut h = 1;
+                    while j + h < size[v]
+                        && (i..i + w).all(|k| mask[k + (j + h) * size[u]] == Some(material))
+                    {
+                        h += 1;
+                    }
+                    for jj in j..j + h {
+                        for k in i..i + w {
+                            mask[k + jj * size[u]] = None;
+                        }
+                    }
+
+                    let pos = to_pos(i, j);
+                    let mut scale = Vec3::ONE;
+                    scale[u] = w as f32;
+                    scale[v] = h as f32;
+                    let base = corner(pos, &[0, 0, 0]);
+                    for c in FLAT_FACES[dir].iter() {
+                        result.vertexes.push(base + (corner([0, 0, 0], c) * scale));
+                        result.normals.push(FLAT_NORMALS[dir]);
+                        result.materials.push(material);
+                        result.ao.push(1.0);
+                        result.directions.push(FACE_DIRECTION[dir]);
+                    }
+                    result
+                        .face_voxels
+                        .push(UVec3::from_array(pos.map(|p| p as u32)));
+                    i += w;
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Replace each vertex's normal with the average of the normals of all
+/// vertexes at the same position, after quantizing to 1/1024. Each triangle
+/// corner contributes equally.
+fn smooth_normals(mesh: &mut MeshData) {
+    let quantize = |v: Vec3| (v * 1024.0).round().as_ivec3();
+    let mut sums: HashMap<IVec3, Vec3> = HashMap::new();
+    for (v, n) in mesh.vertexes.iter().zip(&mesh.normals) {
+        *sums.entry(quantize(*v)).or_default() += *n;
+    }
+    for (v, n) in mesh.vertexes.iter().zip(&mut mesh.normals) {
+        // Opposing faces can cancel out; keep the face normal for those
+        *n = sums[&quantize(*v)].try_normalize().unwrap_or(*n);
+    }
+}
+
+/// Fill uvs by projecting each position onto the plane of its face's
+/// dominant axis. Call this before smooth_normals changes the normals.
+fn add_uvs(mesh: &mut MeshData) {
+    mesh.uvs = mesh
+        .vertexes
+        .iter()
+        .zip(&mesh.normals)
+        .map(|(v, n)| {
+            let n = n.abs();
+            if n.x >= n.y && n.x >= n.z {
+                Vec2::new(v.z, v.y)
+            } else if n.y >= n.z {
+                Vec2::new(v.x, v.z)
+            } else {
+                Vec2::new(v.x, v.y)
+            }
+        })
+        .collect();
+}
+
+/// Merge vertexes which have the same position, normal, material, ambient
+/// occlusion, uv, and direction, after quantizing to 1/1024.
+fn index_mesh(mesh: MeshData) -> MeshData {
+    let quantize = |v: Vec3| (v * 1024.0).round().as_ivec3();
+    let quantize_uv = |v: Vec2| (v * 1024.0).round().as_ivec2();
+    let mut map = HashMap::new();
+    let mut result = MeshData::default();
+    let mut indexes = Vec::with_capacity(mesh.vertexes.len());
+    for (((((v, n), m), a), uv), d) in mesh
+        .vertexes
+        .iter()
+        .zip(&mesh.normals)
+        .zip(&mesh.materials)
+        .zip(&mesh.ao)
+        .zip(&mesh.uvs)
+        .zip(&mesh.directions)
+    {
+        let index = *map
+            .entry((
+                quantize(*v),
+                quantize(*n),
+                *m,
+                (*a * 1024.0).round() as i32,
+                quantize_uv(*uv),
+                *d,
+            ))
+            .or_insert_with(|| {
+                result.vertexes.push(*v);
+                result.normals.push(*n);
+                result.materials.push(*m);
+                result.ao.push(*a);
+                result.uvs.push(*uv);
+                result.directions.push(*d);
+                (result.vertexes.len() - 1) as u32
+            });
+        indexes.push(index);
+    }
+    result.face_voxels = mesh.face_voxels;
+    result.indexes = Some(indexes);
+    result
+}
+
+/// Create BindGroupLayout for the shader's geometry functions.
+pub fn geometry_bind_group_layout(device: &Device) -> BindGroupLayout {
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("geometry_bind_group_layout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: WGSL_ARGS_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: WGSL_VOXEL_GRID_OUT_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+/// Create BindGroupLayout for the shader's paste_grid function. This is
+/// the geometry layout plus the source grid.
+pub fn paste_grid_bind_group_layout(device: &Device) -> BindGroupLayout {
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("paste_grid_bind_group_layout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: WGSL_ARGS_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: WGSL_VOXEL_GRID_A_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: WGSL_VOXEL_GRID_OUT_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+/// Create BindGroupLayout for the shader's paste_heightmap function. This
+/// is the geometry layout plus the heights.
+pub fn paste_heightmap_bind_group_layout(device: &Device) -> BindGroupLayout {
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("paste_heightmap_bind_group_layout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: WGSL_ARGS_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: WGSL_VOXEL_GRID_OUT_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: WGSL_HEIGHTS_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+/// Create BindGroupLayout for the shader's paste_sphere_instanced function.
+/// This is the geometry layout plus the instance offsets.
+pub fn paste_sphere_instanced_bind_group_layout(device: &Device) -> BindGroupLayout {
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("paste_sphere_instanced_bind_group_layout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: WGSL_ARGS_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: WGSL_VOXEL_GRID_OUT_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: WGSL_INSTANCE_OFFSETS_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+/// Create BindGroupLayout for the shader's paste_batch function.
+pub fn paste_batch_bind_group_layout(device: &Device) -> BindGroupLayout {
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("paste_batch_bind_group_layout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: WGSL_ARGS_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: WGSL_VOXEL_GRID_OUT_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: WGSL_BATCH_ARGS_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+/// Use one of the shader's geometry functions.
+///
+/// Call the following in order:
+/// * `[new_*]`
+/// * `[add_pass]`
+#[derive(Debug)]
+pub struct GeometryImpl {
+    bind_group: BindGroup,
+    workgroup_size: UVec3,
+    args: ShaderArgs,
+}
+
+impl GeometryImpl {
+    fn new_impl(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        bind_group_label: &'static str,
+        grid_buffer: &VoxelGrid,
+        extra_entries: &[BindGroupEntry],
+        args: ShaderArgs,
+        workgroup_size: UVec3,
+    ) -> Self {
+        // println!("** GeometryImpl::new_impl");
+        // println!("   {:?}", args);
+        // println!(
+        //     "    grid_buffer {} {:?}",
+        //     grid_buffer.buffer.size(),
+        //     grid_buffer.buffer.usage()
+        // );
+        let args_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: size_of::<ShaderArgs>() as u64,
+            usage: BufferUsages::UNIFORM,
+            mapped_at_creation: true,
+        });
+        *from_bytes_mut::<ShaderArgs>(&mut args_buffer.slice(..).get_mapped_range_mut()) = args;
+        args_buffer.unmap();
+        let mut entries = vec![
+            BindGroupEntry {
+                binding: WGSL_ARGS_BINDING,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: &args_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            BindGroupEntry {
+                binding: WGSL_VOXEL_GRID_OUT_BINDING,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: &grid_buffer.buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+        ];
+        entries.extend_from_slice(extra_entries);
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some(bind_group_label),
+            layout: bind_group_layout,
+            entries: &entries,
+        });
+        Self {
+            bind_group,
+            workgroup_size,
+            args,
+        }
+    }
+
+    /// Args sent to the shader. For paste_batch, each shape's args are in
+    /// the batch buffer instead; this only has out_size.
+    pub fn last_args(&self) -> ShaderArgs {
+        self.args
+    }
+
+    /// Create buffers and bind group for the shader's paste_cube function.
+    ///
+    /// * grid_buffer:  Voxel grid to modify
+    /// * size:         Diameter of cube
+    /// * offset:       Offset cube's coordinates
+    /// * flags:        Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+    ///                 Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+    ///                 PASTE_ERASE empties occupied voxels instead.
+    ///                 PASTE_MASK limits which voxels are overwritten.
+    /// * material:     Material to paste
+    /// * mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+    #[allow(clippy::too_many_arguments)]
+    pub fn paste_cube(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        grid_buffer: &VoxelGrid,
+        size: UVec3,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> Self {
+        let (args, workgroup_size) = Self::paste_cube_args(
+            grid_buffer.size,
+            size,
+            offset,
+            flags,
+            material,
+            mask_material,
+        );
+        Self::new_impl(
+            device,
+            bind_group_layout,
+            "paste_cube_bind_group",
+            grid_buffer,
+            &[],
+            args,
+            workgroup_size,
+        )
+    }
+
+    /// Shader args and workgroup count for [Self::paste_cube], which
+    /// [Self::paste_batch] also accepts. out_size is the size of the grid.
+    pub fn paste_cube_args(
+        out_size: UVec3,
+        size: UVec3,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> (ShaderArgs, UVec3) {
+        let args = ShaderArgs {
+            out_size,
+            size,
+            offset,
+            flags,
+            material,
+            mask_material,
+            rotation: Quat::IDENTITY.into(),
+            shape: SHAPE_CUBE,
+            ..Default::default()
+        };
+        let workgroup_size = paste_workgroups((size.x + 1) * (size.y + 1) * (size.z + 1));
+        (args, split_workgroups(workgroup_size))
+    }
+
+    /// Create buffers and bind group for the shader's paste_cube function,
+    /// rotating the cube around its center.
+    ///
+    /// * grid_buffer:  Voxel grid to modify
+    /// * size:         Diameter of cube
+    /// * offset:       Offset cube's coordinates, before rotating
+    /// * rotation:     Rotation around the cube's center
+    /// * flags:        Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+    ///                 Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+    ///                 PASTE_ERASE empties occupied voxels instead.
+    ///                 PASTE_MASK limits which voxels are overwritten.
+    /// * material:     Material to paste
+    /// * mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+    #[allow(clippy::too_many_arguments)]
+    pub fn paste_rotated_cube(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        grid_buffer: &VoxelGrid,
+        size: UVec3,
+        offset: IVec3,
+        rotation: Quat,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> Self {
+        let (args, workgroup_size) = Self::paste_rotated_cube_args(
+            grid_buffer.size,
+            size,
+            offset,
+            rotation,
+            flags,
+            material,
+            mask_material,
+        );
+        Self::new_impl(
+            device,
+            bind_group_layout,
+            "paste_cube_bind_group",
+            grid_buffer,
+            &[],
+            args,
+            workgroup_size,
+        )
+    }
+
+    /// Shader args and workgroup count for [Self::paste_rotated_cube], which
+    /// [Self::paste_batch] also accepts. out_size is the size of the grid.
+    pub fn paste_rotated_cube_args(
+        out_size: UVec3,
+        size: UVec3,
+        offset: IVec3,
+        rotation: Quat,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> (ShaderArgs, UVec3) {
+        // Scan the rotated cube's bounding box
+        let half = size.as_vec3() / 2.0;
+        let center = offset.as_vec3() + half;
+        let mut extent = Vec3::ZERO;
+        for corner in [
+            Vec3::new(-1.0, -1.0, -1.0),
+            Vec3::new(-1.0, -1.0, 1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(-1.0, 1.0, 1.0),
+        ] {
+            extent = extent.max((rotation * (corner * half)).abs());
+        }
+        let bounds_min = (center - extent).floor();
+        let bounds_size = ((center + extent).ceil() - bounds_min).as_uvec3();
+        let args = ShaderArgs {
+            out_size,
+            size: bounds_size,
+            offset: bounds_min.as_ivec3(),
+            flags,
+            material,
+            mask_material,
+            rotation: rotation.normalize().into(),
+            shape_size: size.as_vec3(),
+            shape_center: center - bounds_min,
+            shape: SHAPE_CUBE,
+            ..Default::default()
+        };
+        let workgroup_size =
+            paste_workgroups((bounds_size.x + 1) * (bounds_size.y + 1) * (bounds_size.z + 1));
+        (args, split_workgroups(workgroup_size))
+    }
+
+    /// Create buffers and bind group for the shader's paste_sphere function.
+    ///
+    /// * grid_buffer:  Voxel grid to modify
+    /// * diameter:     Diameter of sphere
+    /// * offset:       Offset sphere's coordinates
+    /// * flags:        Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+    ///                 Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+    ///                 PASTE_ERASE empties occupied voxels instead.
+    ///                 PASTE_MASK limits which voxels are overwritten.
+    /// * material:     Material to paste
+    /// * mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+    #[allow(clippy::too_many_arguments)]
+    pub fn paste_sphere(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        grid_buffer: &VoxelGrid,
+        diameter: u32,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> Self {
+        let (args, workgroup_size) = Self::paste_sphere_args(
+            grid_buffer.size,
+            diameter,
+            offset,
+            flags,
+            material,
+            mask_material,
+        );
+        Self::new_impl(
+            device,
+            bind_group_layout,
+            "paste_sphere_bind_group",
+            grid_buffer,
+            &[],
+            args,
+            workgroup_size,
+        )
+    }
+
+    /// Shader args and workgroup count for [Self::paste_sphere], which
+    /// [Self::paste_batch] also accepts. out_size is the size of the grid.
+    pub fn paste_sphere_args(
+        out_size: UVec3,
+        diameter: u32,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> (ShaderArgs, UVec3) {
+        let args = ShaderArgs {
+            out_size,
+            offset,
+            flags,
+            material,
+            mask_material,
+            diameter,
+            shape: SHAPE_SPHERE,
+            ..Default::default()
+        };
+        let workgroup_size = paste_workgroups((diameter + 1) * (diameter + 1) * (diameter + 1));
+        (args, split_workgroups(workgroup_size))
+    }
+
+    /// Create buffers and bind group for the shader's paste_sphere_instanced
+    /// function. This pastes a sphere at each offset in 1 dispatch. Each
+    /// voxel applies the instances in order, so overlapping instances give
+    /// the same result as separate pastes in that order. The dispatch covers
+    /// the instances' bounds, clamped to the grid, and each voxel checks
+    /// every instance; scattered instances cost more than clustered ones.
+    ///
+    /// * grid_buffer:  Voxel grid to modify
+    /// * diameter:     Diameter of sphere
+    /// * offsets:      Offset of each sphere's coordinates. Must not be empty.
+    /// * flags:        Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+    ///                 Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+    ///                 PASTE_ERASE empties occupied voxels instead.
+    ///                 PASTE_MASK limits which voxels are overwritten.
+    /// * material:     Material to paste
+    /// * mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+    #[allow(clippy::too_many_arguments)]
+    pub fn paste_sphere_instanced(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        grid_buffer: &VoxelGrid,
+        diameter: u32,
+        offsets: &[IVec3],
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> Self {
+        assert!(
+            !offsets.is_empty(),
+            "paste_sphere_instanced needs at least 1 offset"
+        );
+        // WGSL pads vec3
+        let padded: Vec<IVec4> = offsets.iter().map(|o| o.extend(0)).collect();
+        let offsets_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("instance_offsets_buffer"),
+            size: (padded.len() * size_of::<IVec4>()) as u64,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: true,
+        });
+        offsets_buffer
+            .slice(..)
+            .get_mapped_range_mut()
+            .copy_from_slice(cast_slice::<IVec4, u8>(&padded));
+        offsets_buffer.unmap();
+
+        // Bounds of the instances, including ending padding, clamped to the grid
+        let min = offsets
+            .iter()
+            .copied()
+            .reduce(IVec3::min)
+            .unwrap()
+            .max(IVec3::ZERO);
+        let max = (offsets.iter().copied().reduce(IVec3::max).unwrap() + diameter as i32)
+            .min(grid_buffer.size.as_ivec3());
+        let workgroup_size = if max.cmpge(min).all() {
+            let size = (max - min + 1).as_uvec3();
+            paste_workgroups(size.x * size.y * size.z)
+        } else {
+            0
+        };
+        let args = ShaderArgs {
+            out_size: grid_buffer.size,
+            offset: min,
+            size: (max - min).max(IVec3::ZERO).as_uvec3(),
+            flags,
+            material,
+            mask_material,
+            diameter,
+            ..Default::default()
+        };
+        Self::new_impl(
+            device,
+            bind_group_layout,
+            "paste_sphere_instanced_bind_group",
+            grid_buffer,
+            &[BindGroupEntry {
+                binding: WGSL_INSTANCE_OFFSETS_BINDING,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: &offsets_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            }],
+            args,
+            split_workgroups(workgroup_size),
+        )
+    }
+
+    /// Create buffers and bind group for the shader's paste_ellipsoid function.
+    ///
+    /// * grid_buffer:  Voxel grid to modify
+    /// * radii:        Radius along each axis. Each must be non-0.
+    /// * offset:       Offset ellipsoid's coordinates
+    /// * flags:        Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+    ///                 Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+    ///                 PASTE_ERASE empties occupied voxels instead.
+    ///                 PASTE_MASK limits which voxels are overwritten.
+    /// * material:     Material to paste
+    /// * mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+    #[allow(clippy::too_many_arguments)]
+    pub fn paste_ellipsoid(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        grid_buffer: &VoxelGrid,
+        radii: UVec3,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> Self {
+        let (args, workgroup_size) = Self::paste_ellipsoid_args(
+            grid_buffer.size,
+            radii,
+            offset,
+            flags,
+            material,
+            mask_material,
+        );
+        Self::new_impl(
+            device,
+            bind_group_layout,
+            "paste_ellipsoid_bind_group",
+            grid_buffer,
+            &[],
+            args,
+            workgroup_size,
+        )
+    }
+
+    /// Shader args and workgroup count for [Self::paste_ellipsoid], which
+    /// [Self::paste_batch] also accepts. out_size is the size of the grid.
+    pub fn paste_ellipsoid_args(
+        out_size: UVec3,
+        radii: UVec3,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> (ShaderArgs, UVec3) {
+        assert!(
+            radii.cmpgt(UVec3::ZERO).all(),
+            "Ellipsoid radii must be non-0"
+        );
+        let size = radii * 2;
+        let args = ShaderArgs {
+            out_size,
+            offset,
+            flags,
+            material,
+            mask_material,
+            size,
+            shape_size: radii.as_vec3(),
+            shape: SHAPE_ELLIPSOID,
+            ..Default::default()
+        };
+        let workgroup_size = paste_workgroups((size.x + 1) * (size.y + 1) * (size.z + 1));
+        (args, split_workgroups(workgroup_size))
+    }
+
+    /// Create buffers and bind group for the shader's paste_cylinder function.
+    ///
+    /// * grid_buffer:  Voxel grid to modify
+    /// * radius:       Radius of cylinder
+    /// * height:       Height of cylinder
+    /// * axis:         Axis the cylinder runs along. 0: X, 1: Y, 2: Z
+    /// * offset:       Offset cylinder's coordinates
+    /// * flags:        Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+    ///                 Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+    ///                 PASTE_ERASE empties occupied voxels instead.
+    ///                 PASTE_MASK limits which voxels are overwritten.
+    /// * material:     Material to paste
+    /// * mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+    #[allow(clippy::too_many_arguments)]
+    pub fn paste_cylinder(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        grid_buffer: &VoxelGrid,
+        radius: u32,
+        height: u32,
+        axis: u8,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> Self {
+        let (args, workgroup_size) = Self::paste_cylinder_args(
+            grid_buffer.size,
+            radius,
+            height,
+            axis,
+            offset,
+            flags,
+            material,
+            mask_material,
+        );
+        Self::new_impl(
+            device,
+            bind_group_layout,
+            "paste_cylinder_bind_group",
+            grid_buffer,
+            &[],
+            args,
+            workgroup_size,
+        )
+    }
+
+    /// Shader args and workgroup count for [Self::paste_cylinder], which
+    /// [Self::paste_batch] also accepts. out_size is the size of the grid.
+    #[allow(clippy::too_many_arguments)]
+    pub fn paste_cylinder_args(
+        out_size: UVec3,
+        radius: u32,
+        height: u32,
+        axis: u8,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> (ShaderArgs, UVec3) {
+        let size = match axis {
+            0 => UVec3::new(height, radius * 2, radius * 2),
+            1 => UVec3::new(radius * 2, height, radius * 2),
+            2 => UVec3::new(radius * 2, radius * 2, height),
+            _ => panic!("Invalid cylinder axis: {}", axis),
+        };
+        let args = ShaderArgs {
+            out_size,
+            size,
+            offset,
+            flags,
+            material,
+            mask_material,
+            radius,
+            height,
+            axis: axis as u32,
+            shape: SHAPE_CYLINDER,
+            ..Default::default()
+        };
+        let workgroup_size = paste_workgroups((size.x + 1) * (size.y + 1) * (size.z + 1));
+        (args, split_workgroups(workgroup_size))
+    }
+
+    /// Create buffers and bind group for the shader's paste_cone function.
+    ///
+    /// * grid_buffer:  Voxel grid to modify
+    /// * base_radius:  Radius of the cone's base
+    /// * height:       Height of cone. The base is at the low end of axis.
+    /// * axis:         Axis the cone runs along. 0: X, 1: Y, 2: Z
+    /// * offset:       Offset cone's coordinates
+    /// * flags:        Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+    ///                 Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+    ///                 PASTE_ERASE empties occupied voxels instead.
+    ///                 PASTE_MASK limits which voxels are overwritten.
+    /// * material:     Material to paste
+    /// * mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+    #[allow(clippy::too_many_arguments)]
+    pub fn paste_cone(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        grid_buffer: &VoxelGrid,
+        base_radius: u32,
+        height: u32,
+        axis: u8,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> Self {
+        let (args, workgroup_size) = Self::paste_cone_args(
+            grid_buffer.size,
+            base_radius,
+            height,
+            axis,
+            offset,
+            flags,
+            material,
+            mask_material,
+        );
+        Self::new_impl(
+            device,
+            bind_group_layout,
+            "paste_cone_bind_group",
+            grid_buffer,
+            &[],
+            args,
+            workgroup_size,
+        )
+    }
+
+    /// Shader args and workgroup count for [Self::paste_cone], which
+    /// [Self::paste_batch] also accepts. out_size is the size of the grid.
+    #[allow(clippy::too_many_arguments)]
+    pub fn paste_cone_args(
+        out_size: UVec3,
+        base_radius: u32,
+        height: u32,
+        axis: u8,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> (ShaderArgs, UVec3) {
+        let size = match axis {
+            0 => UVec3::new(height, base_radius * 2, base_radius * 2),
+            1 => UVec3::new(base_radius * 2, height, base_radius * 2),
+            2 => UVec3::new(base_radius * 2, base_radius * 2, height),
+            _ => panic!("Invalid cone axis: {}", axis),
+        };
+        let args = ShaderArgs {
+            out_size,
+            size,
+            offset,
+            flags,
+            material,
+            mask_material,
+            radius: base_radius,
+            height,
+            axis: axis as u32,
+            shape: SHAPE_CONE,
+            ..Default::default()
+        };
+        let workgroup_size = paste_workgroups((size.x + 1) * (size.y + 1) * (size.z + 1));
+        (args, split_workgroups(workgroup_size))
+    }
+
+    /// Create buffers and bind group for the shader's paste_torus function.
+    ///
+    /// * grid_buffer:  Voxel grid to modify
+    /// * major_radius: Distance from the torus's center to the center of its tube
+    /// * minor_radius: Radius of the tube. Must be non-0.
+    /// * axis:         Axis the torus's hole runs along. 0: X, 1: Y, 2: Z
+    /// * offset:       Offset torus's coordinates
+    /// * flags:        Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+    ///                 Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+    ///                 PASTE_ERASE empties occupied voxels instead.
+    ///                 PASTE_MASK limits which voxels are overwritten.
+    /// * material:     Material to paste
+    /// * mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+    #[allow(clippy::too_many_arguments)]
+    pub fn paste_torus(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        grid_buffer: &VoxelGrid,
+        major_radius: u32,
+        minor_radius: u32,
+        axis: u8,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> Self {
+        let (args, workgroup_size) = Self::paste_torus_args(
+            grid_buffer.size,
+            major_radius,
+            minor_radius,
+            axis,
+            offset,
+            flags,
+            material,
+            mask_material,
+        );
+        Self::new_impl(
+            device,
+            bind_group_layout,
+            "paste_torus_bind_group",
+            grid_buffer,
+            &[],
+            args,
+            workgroup_size,
+        )
+    }
+
+    /// Shader args and workgroup count for [Self::paste_torus], which
+    /// [Self::paste_batch] also accepts. out_size is the size of the grid.
+    #[allow(clippy::too_many_arguments)]
+    pub fn paste_torus_args(
+        out_size: UVec3,
+        major_radius: u32,
+        minor_radius: u32,
+        axis: u8,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> (ShaderArgs, UVec3) {
+        assert!(minor_radius > 0, "Torus minor radius must be non-0");
+        let across = (major_radius + minor_radius) * 2;
+        let along = minor_radius * 2;
+        let size = match axis {
+            0 => UVec3::new(along, across, across),
+            1 => UVec3::new(across, along, across),
+            2 => UVec3::new(across, across, along),
+            _ => panic!("Invalid torus axis: {}", axis),
+        };
+        let args = ShaderArgs {
+            out_size,
+            size,
+            offset,
+            flags,
+            material,
+            mask_material,
+            axis: axis as u32,
+            shape_size: Vec3::new(major_radius as f32, minor_radius as f32, 0.0),
+            shape: SHAPE_TORUS,
+            ..Default::default()
+        };
+        let workgroup_size = paste_workgroups((size.x + 1) * (size.y + 1) * (size.z + 1));
+        (args, split_workgroups(workgroup_size))
+    }
+
+    /// Create buffers and bind group for the shader's paste_wedge function.
+    ///
+    /// * grid_buffer:  Voxel grid to modify
+    /// * size:         Bounding box of wedge. Each component must be non-0.
+    /// * axis:         Axis the slope rises along. 0: X, 1: Y, 2: Z. The height
+    ///                 is along the next axis (Y, Z, X) and the wedge extends
+    ///                 straight along the remaining one.
+    /// * offset:       Offset wedge's coordinates
+    /// * flags:        Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+    ///                 Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+    ///                 PASTE_ERASE empties occupied voxels instead.
+    ///                 PASTE_MASK limits which voxels are overwritten.
+    /// * material:     Material to paste
+    /// * mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+    #[allow(clippy::too_many_arguments)]
+    pub fn paste_wedge(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        grid_buffer: &VoxelGrid,
+        size: UVec3,
+        axis: u8,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> Self {
+        let (args, workgroup_size) = Self::paste_wedge_args(
+            grid_buffer.size,
+            size,
+            axis,
+            offset,
+            flags,
+            material,
+            mask_material,
+        );
+        Self::new_impl(
+            device,
+            bind_group_layout,
+            "paste_wedge_bind_group",
+            grid_buffer,
+            &[],
+            args,
+            workgroup_size,
+        )
+    }
+
+    /// Shader args and workgroup count for [Self::paste_wedge], which
+    /// [Self::paste_batch] also accepts. out_size is the size of the grid.
+    pub fn paste_wedge_args(
+        out_size: UVec3,
+        size: UVec3,
+        axis: u8,
+        offset: IVec3,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> (ShaderArgs, UVec3) {
+        assert!(axis < 3, "Invalid wedge axis: {}", axis);
+        assert!(size.cmpgt(UVec3::ZERO).all(), "Wedge size must be non-0");
+        let args = ShaderArgs {
+            out_size,
+            size,
+            offset,
+            flags,
+            material,
+            mask_material,
+            axis: axis as u32,
+            shape: SHAPE_WEDGE,
+            ..Default::default()
+        };
+        let workgroup_size = paste_workgroups((size.x + 1) * (size.y + 1) * (size.z + 1));
+        (args, split_workgroups(workgroup_size))
+    }
+
+    /// Create buffers and bind group for the shader's paste_line function.
+    /// This fills a capsule around the segment between the centers of the
+    /// start and end voxels.
+    ///
+    /// * grid_buffer:  Voxel grid to modify
+    /// * start:        Voxel at one end of the line
+    /// * end:          Voxel at the other end of the line
+    /// * radius:       Radius of the capsule. Must be non-0.
+    /// * flags:        Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+    ///                 Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+    ///                 PASTE_ERASE empties occupied voxels instead.
+    ///                 PASTE_MASK limits which voxels are overwritten.
+    /// * material:     Material to paste
+    /// * mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+    #[allow(clippy::too_many_arguments)]
+    pub fn paste_line(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        grid_buffer: &VoxelGrid,
+        start: IVec3,
+        end: IVec3,
+        radius: u32,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> Self {
+        let (args, workgroup_size) = Self::paste_line_args(
+            grid_buffer.size,
+            start,
+            end,
+            radius,
+            flags,
+            material,
+            mask_material,
+        );
+        Self::new_impl(
+            device,
+            bind_group_layout,
+            "paste_line_bind_group",
+            grid_buffer,
+            &[],
+            args,
+            workgroup_size,
+        )
+    }
+
+    /// Shader args and workgroup count for [Self::paste_line], which
+    /// [Self::paste_batch] also accepts. out_size is the size of the grid.
+    pub fn paste_line_args(
+        out_size: UVec3,
+        start: IVec3,
+        end: IVec3,
+        radius: u32,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
+    ) -> (ShaderArgs, UVec3) {
+        assert!(radius > 0, "Line radius must be non-0");
+        // Bounding box of the segment, inflated by radius
+        let offset = start.min(end) - radius as i32;
+        let size = ((start - end).abs() + 2 * radius as i32 + 1).as_uvec3();
+        let args = ShaderArgs {
+            out_size,
+            size,
+            offset,
+            flags,
+            material,
+            mask_material,
+            radius,
+            shape: SHAPE_LINE,
+            shape_center: (start - offset).as_vec3() + 0.5,
+            shape_size: (end - offset).as_vec3() + 0.5,
+            ..Default::default()
+        };
+        let workgroup_size = paste_workgroups((size.x + 1) * (size.y + 1) * (size.z + 1));
+        (args, split_workgroups(workgroup_size))
+    }
+
+    /// Create buffers and bind group for the shader's paste_noise function.
+    /// This fills voxels where 3D value noise is above threshold. See the
+    /// shader's noise function for the exact noise; it only depends on the
+    /// arguments, so the same arguments always fill the same voxels.
+    ///
+    /// * grid_buffer:  Voxel grid to modify
+    /// * offset:       Lower corner of the region to fill
+    /// * size:         Size of the region to fill
+    /// * frequency:    Noise cells per voxel. e.g. 0.1 makes features about 10 voxels wide.
+    /// * threshold:    Fill voxels where the noise, which is in `[0, 1)`, is greater than this
+    /// * seed:         Selects the noise pattern
+    /// * flags:        Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+    ///                 Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+    ///                 PASTE_ERASE empties occupied voxels instead.
+    ///                 PASTE_MASK limits which voxels are overwritten.
+    /// * material:     Material to paste
+    /// * mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+    #[allow(clippy::too_many_arguments)]
+    pub fn paste_noise(
         device: &Device,
         bind_group_layout: &BindGroupLayout,
-        bind_group_label: &'static str,
         grid_buffer: &VoxelGrid,
-        args: ShaderArgs,
-        workgroup_size: UVec3,
+        offset: IVec3,
+        size: UVec3,
+        frequency: f32,
+        threshold: f32,
+        seed: u32,
+        flags: u32,
+        material: u32,
+        mask_material: u32,
     ) -> Self {
-        // println!("** GeometryImpl::new_impl");
-        // println!("   {:?}", args);
-        // println!(
-        //     "    grid_buffer {} {:?}",
-        //     grid_buffer.buffer.size(),
-        //     grid_buffer.buffer.usage()
-        // );
-        let args_buffer = device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: size_of::<ShaderArgs>() as u64,
-            usage: BufferUsages::UNIFORM,
-            mapped_at_creation: true,
-        });
-        *from_bytes_mut::<ShaderArgs>(&mut args_buffer.slice(..).get_mapped_range_mut()) = args;
-        args_buffer.unmap();
-        let bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some(bind_group_label),
-            layout: bind_group_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: WGSL_ARGS_BINDING,
-                    resource: BindingResource::Buffer(BufferBinding {
-                        buffer: &args_buffer,
-                        offset: 0,
-                        size: None,
-                    }),
-                },
-                BindGroupEntry {
-                    binding: WGSL_VOXEL_GRID_OUT_BINDING,
-                    resource: BindingResource::Buffer(BufferBinding {
-                        buffer: &grid_buffer.buffer,
-                        offset: 0,
-                        size: None,
-                    }),
-                },
-            ],
-        });
-        Self {
-            bind_group,
-            workgroup_size,
-        }
+        let args = ShaderArgs {
+            out_size: grid_buffer.size,
+            size,
+            offset,
+            flags,
+            material,
+            mask_material,
+            seed,
+            frequency,
+            threshold,
+            ..Default::default()
+        };
+        let workgroup_size = paste_workgroups((size.x + 1) * (size.y + 1) * (size.z + 1));
+        Self::new_impl(
+            device,
+            bind_group_layout,
+            "paste_noise_bind_group",
+            grid_buffer,
+            &[],
+            args,
+            split_workgroups(workgroup_size),
+        )
     }
 
-    /// Create buffers and bind group for the shader's paste_cube function.
+    /// Create buffers and bind group for the shader's paste_grid function.
     ///
     /// * grid_buffer:  Voxel grid to modify
-    /// * size:         Diameter of cube
-    /// * offset:       Offset cube's coordinates
-    /// * flags:        Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES.
-    ///                 Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
-    /// * material:     Material to paste
-    pub fn paste_cube(
+    /// * src_buffer:   Voxel grid to copy from. Must be a different grid.
+    /// * src_offset:   Lower corner of the region in src_buffer
+    /// * size:         Size of the region
+    /// * offset:       Lower corner of the region in grid_buffer
+    /// * flags:        Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
+    ///                 PASTE_ERASE empties voxels which are occupied in the source.
+    /// * material:     Material to paste if PASTE_MATERIAL_ARG is set
+    /// * mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+    #[allow(clippy::too_many_arguments)]
+    pub fn paste_grid(
         device: &Device,
         bind_group_layout: &BindGroupLayout,
         grid_buffer: &VoxelGrid,
+        src_buffer: &VoxelGrid,
+        src_offset: IVec3,
         size: UVec3,
         offset: IVec3,
         flags: u32,
         material: u32,
+        mask_material: u32,
     ) -> Self {
         let args = ShaderArgs {
+            a_size: src_buffer.size,
             out_size: grid_buffer.size,
+            src_offset,
             size,
             offset,
             flags,
             material,
+            mask_material,
             ..Default::default()
         };
-        let workgroup_size =
-            ((size.x + 1) * (size.y + 1) * (size.z + 1) + PASTE_CUBE_VOXELS_PER_WORKGROUP - 1)
-                / PASTE_CUBE_VOXELS_PER_WORKGROUP;
+        let workgroup_size = paste_workgroups((size.x + 1) * (size.y + 1) * (size.z + 1));
         Self::new_impl(
             device,
             bind_group_layout,
-            "paste_cube_bind_group",
+            "paste_grid_bind_group",
             grid_buffer,
+            &[BindGroupEntry {
+                binding: WGSL_VOXEL_GRID_A_BINDING,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: &src_buffer.buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            }],
             args,
-            UVec3::new(workgroup_size, 1, 1),
+            split_workgroups(workgroup_size),
         )
     }
 
-    /// Create buffers and bind group for the shader's paste_sphere function.
+    /// Create buffers and bind group for the shader's paste_heightmap function.
     ///
     /// * grid_buffer:  Voxel grid to modify
-    /// * diameter:     Diameter of sphere
-    /// * offset:       Offset sphere's coordinates
-    /// * flags:        Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES.
+    /// * heights:      Height of each column, `width * depth` entries, packed by x then y
+    /// * width:        Size of heightmap along X
+    /// * depth:        Size of heightmap along Y
+    /// * offset:       Offset heightmap's coordinates. Columns run along Z.
+    /// * material:     Material to fill
+    #[allow(clippy::too_many_arguments)]
+    pub fn paste_heightmap(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        grid_buffer: &VoxelGrid,
+        heights: &[u16],
+        width: u32,
+        depth: u32,
+        offset: IVec3,
+        material: u32,
+    ) -> Self {
+        assert_eq!(
+            heights.len(),
+            width as usize * depth as usize,
+            "heights must have width * depth entries"
+        );
+        // WGSL doesn't have u16
+        let heights: Vec<u32> = heights.iter().map(|h| *h as u32).collect();
+        let heights_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("heights_buffer"),
+            size: (heights.len().max(1) * size_of::<u32>()) as u64,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: true,
+        });
+        heights_buffer.slice(..).get_mapped_range_mut()[..heights.len() * size_of::<u32>()]
+            .copy_from_slice(cast_slice::<u32, u8>(&heights));
+        heights_buffer.unmap();
+
+        let args = ShaderArgs {
+            out_size: grid_buffer.size,
+            size: UVec3::new(width, depth, 0),
+            offset,
+            flags: PASTE_MATERIAL_ARG_FLAG | PASTE_VERTEXES_FLAG,
+            material,
+            ..Default::default()
+        };
+        let workgroup_size = paste_workgroups((width + 1) * (depth + 1));
+        Self::new_impl(
+            device,
+            bind_group_layout,
+            "paste_heightmap_bind_group",
+            grid_buffer,
+            &[BindGroupEntry {
+                binding: WGSL_HEIGHTS_BINDING,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: &heights_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            }],
+            args,
+            split_workgroups(workgroup_size),
+        )
+    }
+
+    /// Create buffers and bind group for the shader's paste_slab function.
+    /// This fills the layers `[from, from + thickness)` along the axis,
+    /// across the whole grid; the rest is clipped.
+    ///
+    /// * grid_buffer:  Voxel grid to modify
+    /// * axis:         Axis the slab is perpendicular to. 0: X, 1: Y, 2: Z
+    /// * from:         First layer of the slab
+    /// * thickness:    Number of layers
+    /// * flags:        Any of: PASTE_MATERIAL, PASTE_MATERIAL_ARG, PASTE_VERTEXES, PASTE_ERASE, PASTE_MASK.
     ///                 Note: PASTE_MATERIAL_ARG and PASTE_MATERIAL act the same.
+    ///                 PASTE_ERASE empties occupied voxels instead.
+    ///                 PASTE_MASK limits which voxels are overwritten.
     /// * material:     Material to paste
-    pub fn paste_sphere(
+    /// * mask_material: With PASTE_MASK, only overwrite empty voxels and voxels with this material
+    #[allow(clippy::too_many_arguments)]
+    pub fn paste_slab(
         device: &Device,
         bind_group_layout: &BindGroupLayout,
         grid_buffer: &VoxelGrid,
-        diameter: u32,
-        offset: IVec3,
+        axis: u8,
+        from: i32,
+        thickness: u32,
         flags: u32,
         material: u32,
+        mask_material: u32,
     ) -> Self {
+        assert!(axis < 3, "Invalid slab axis: {}", axis);
+        let mut offset = IVec3::ZERO;
+        offset[axis as usize] = from;
         let args = ShaderArgs {
             out_size: grid_buffer.size,
             offset,
+            axis: axis as u32,
+            height: thickness,
             flags,
             material,
-            diameter,
+            mask_material,
             ..Default::default()
         };
-        let workgroup_size = ((diameter + 1) * (diameter + 1) * (diameter + 1)
-            + PASTE_SPHERE_VOXELS_PER_WORKGROUP
-            - 1)
-            / PASTE_SPHERE_VOXELS_PER_WORKGROUP;
+        // Columns cover the other 2 axes, in the shader's axis frame
+        let size = grid_buffer.size;
+        let (u, v) = match axis {
+            0 => (size.y, size.z),
+            1 => (size.z, size.x),
+            _ => (size.x, size.y),
+        };
+        let workgroup_size = paste_workgroups((u + 1) * (v + 1));
         Self::new_impl(
             device,
             bind_group_layout,
-            "paste_sphere_bind_group",
+            "paste_slab_bind_group",
+            grid_buffer,
+            &[],
+            args,
+            split_workgroups(workgroup_size),
+        )
+    }
+
+    /// Create buffers and bind group for the shader's smooth function. This
+    /// runs 1 pass of a 3x3x3 majority filter over the region's materials.
+    /// Copy grid_buffer into scratch_buffer before each pass.
+    ///
+    /// * grid_buffer:    Voxel grid to modify
+    /// * scratch_buffer: Grid with the same size as grid_buffer
+    /// * offset:         Lower corner of the region. The region must be inside the grid.
+    /// * size:           Size of the region
+    /// * threshold:      A voxel is solid if at least this many of the 27 voxels
+    ///                   around it, including itself, are solid
+    pub fn smooth(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        grid_buffer: &VoxelGrid,
+        scratch_buffer: &VoxelGrid,
+        offset: IVec3,
+        size: UVec3,
+        threshold: u32,
+    ) -> Self {
+        assert_eq!(
+            grid_buffer.size, scratch_buffer.size,
+            "smooth's scratch grid must match the grid's size"
+        );
+        let args = ShaderArgs {
+            a_size: scratch_buffer.size,
+            out_size: grid_buffer.size,
+            offset,
+            size,
+            threshold: threshold as f32,
+            ..Default::default()
+        };
+        let workgroup_size = paste_workgroups(size.x * size.y * size.z);
+        Self::new_impl(
+            device,
+            bind_group_layout,
+            "smooth_bind_group",
+            grid_buffer,
+            &[BindGroupEntry {
+                binding: WGSL_VOXEL_GRID_A_BINDING,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: &scratch_buffer.buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            }],
+            args,
+            split_workgroups(workgroup_size),
+        )
+    }
+
+    /// Create buffers and bind group for the shader's remap_material function.
+    /// Voxels in the region with material from get material to; other voxels
+    /// and all corner offsets are untouched.
+    ///
+    /// * grid_buffer:  Voxel grid to modify
+    /// * offset:       Lower corner of the region. The region must be inside the grid.
+    /// * size:         Size of the region
+    /// * from:         Material to replace
+    /// * to:           Replacement material
+    pub fn remap_material(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        grid_buffer: &VoxelGrid,
+        offset: IVec3,
+        size: UVec3,
+        from: u32,
+        to: u32,
+    ) -> Self {
+        let args = ShaderArgs {
+            out_size: grid_buffer.size,
+            offset,
+            size,
+            mask_material: from,
+            material: to,
+            ..Default::default()
+        };
+        let workgroup_size = paste_workgroups(size.x * size.y * size.z);
+        Self::new_impl(
+            device,
+            bind_group_layout,
+            "remap_material_bind_group",
+            grid_buffer,
+            &[],
+            args,
+            split_workgroups(workgroup_size),
+        )
+    }
+
+    /// Create buffers and bind group for the shader's split_materials function.
+    /// This copies the materials of a [GridLayout::Packed] grid into a
+    /// [GridLayout::Split] grid's buffer. Copy src_buffer's buffer into
+    /// grid_buffer's offsets, if it has them, to copy the offsets.
+    ///
+    /// * grid_buffer:  [GridLayout::Split] grid to write
+    /// * src_buffer:   [GridLayout::Packed] grid with the same size as grid_buffer
+    pub fn split_materials(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        grid_buffer: &VoxelGrid,
+        src_buffer: &VoxelGrid,
+    ) -> Self {
+        assert!(
+            grid_buffer.layout == GridLayout::Split && src_buffer.layout == GridLayout::Packed,
+            "split_materials copies a packed grid into a split grid"
+        );
+        assert_eq!(
+            grid_buffer.size, src_buffer.size,
+            "split_materials' grids must have the same size"
+        );
+        let args = ShaderArgs {
+            a_size: src_buffer.size,
+            out_size: grid_buffer.size,
+            ..Default::default()
+        };
+        let words = (grid_buffer.buffer.size() / size_of::<u32>() as u64) as u32;
+        Self::new_impl(
+            device,
+            bind_group_layout,
+            "split_materials_bind_group",
+            grid_buffer,
+            &[BindGroupEntry {
+                binding: WGSL_VOXEL_GRID_A_BINDING,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: &src_buffer.buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            }],
+            args,
+            split_workgroups(paste_workgroups(words)),
+        )
+    }
+
+    /// Create buffers and bind group for the shader's paste_batch function.
+    /// This pastes several shapes in 1 dispatch. The shapes run concurrently,
+    /// so they must not overlap, including the ending padding each one writes
+    /// vertexes into.
+    ///
+    /// * grid_buffer:  Voxel grid to modify
+    /// * shapes:       Args and workgroup counts from the `paste_*_args` functions.
+    ///                 Each shape's out_size must be grid_buffer's size.
+    ///                 Must not be empty.
+    pub fn paste_batch(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        grid_buffer: &VoxelGrid,
+        shapes: &[(ShaderArgs, UVec3)],
+    ) -> Self {
+        assert!(!shapes.is_empty(), "paste_batch needs at least 1 shape");
+        let batch_args_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("batch_args_buffer"),
+            size: (shapes.len() * size_of::<ShaderArgs>()) as u64,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: true,
+        });
+        {
+            let mut mapped = batch_args_buffer.slice(..).get_mapped_range_mut();
+            for (i, (args, _)) in shapes.iter().enumerate() {
+                *from_bytes_mut::<ShaderArgs>(
+                    &mut mapped[i * size_of::<ShaderArgs>()..(i + 1) * size_of::<ShaderArgs>()],
+                ) = *args;
+            }
+        }
+        batch_args_buffer.unmap();
+
+        // workgroup_id.y selects the shape; x and z cover the largest shape
+        let max_workgroups = shapes.iter().map(|(_, w)| w.x * w.y * w.z).max().unwrap();
+        let x = max_workgroups.min(MAX_WORKGROUPS_PER_DIMENSION);
+        let z = (max_workgroups + x.max(1) - 1) / x.max(1);
+        let args = ShaderArgs {
+            out_size: grid_buffer.size,
+            ..Default::default()
+        };
+        Self::new_impl(
+            device,
+            bind_group_layout,
+            "paste_batch_bind_group",
             grid_buffer,
+            &[BindGroupEntry {
+                binding: WGSL_BATCH_ARGS_BINDING,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: &batch_args_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            }],
             args,
-            UVec3::new(workgroup_size, 1, 1),
+            UVec3::new(x, shapes.len() as u32, z),
         )
     }
 
@@ -684,4 +4735,219 @@ impl GeometryImpl {
             self.workgroup_size.z,
         );
     }
+
+    /// Workgroup counts which [Self::add_pass] dispatches
+    pub fn workgroups(&self) -> UVec3 {
+        self.workgroup_size
+    }
+
+    /// Like [Self::add_pass], but read the workgroup counts from
+    /// indirect_buffer, e.g. written by an earlier compute pass. The buffer
+    /// needs [BufferUsages::INDIRECT] and holds 3 `u32`s at indirect_offset.
+    ///
+    /// The shader's args still come from the CPU, and invocations past the
+    /// shape's bounds do nothing, so dispatching fewer workgroups than
+    /// [Self::workgroups] covers a prefix of the shape in z, y, x order;
+    /// dispatching more does no extra work. paste_batch reads the shape
+    /// from the workgroup's y, so keep y equal to the number of shapes.
+    pub fn add_pass_indirect(
+        &self,
+        pipeline: &ComputePipeline,
+        encoder: &mut CommandEncoder,
+        indirect_buffer: &Buffer,
+        indirect_offset: u64,
+    ) {
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("geometry_pass"),
+        });
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_pipeline(pipeline);
+        pass.dispatch_workgroups_indirect(indirect_buffer, indirect_offset);
+    }
 } // GeometryImpl
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shape;
+
+    // Magic, version, and size
+    const RLE_HEADER_LEN: usize = 4 + 1 + 3 * 4;
+
+    fn write_rle(grid: &VoxelGridVec) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        grid.write_rle(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn rle_round_trip() {
+        let grid = shape::sphere_uniform(9, 3);
+        let read = VoxelGridVec::read_rle(&mut write_rle(&grid).as_slice()).unwrap();
+        assert_eq!(read.size, grid.size);
+        let (mut expected, mut actual) = (Vec::new(), Vec::new());
+        grid.write_to(&mut expected).unwrap();
+        read.write_to(&mut actual).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn rle_truncated() {
+        let bytes = write_rle(&shape::sphere_uniform(9, 3));
+        for len in [
+            0,
+            3,
+            RLE_HEADER_LEN - 1,
+            RLE_HEADER_LEN,
+            RLE_HEADER_LEN + 5,
+            bytes.len() - 1,
+        ] {
+            let err = VoxelGridVec::read_rle(&mut &bytes[..len]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof, "len {}", len);
+        }
+    }
+
+    #[test]
+    fn rle_run_overruns_grid() {
+        let grid = VoxelGridVec::new(UVec3::new(2, 3, 4), 1);
+        let mut header = write_rle(&grid);
+        header.truncate(RLE_HEADER_LEN);
+        let vec_size = grid.data.len() as u32;
+        let cases: [&[u32]; 3] = [&[vec_size + 1], &[vec_size - 1, 2], &[0]];
+        for runs in cases {
+            let mut bytes = header.clone();
+            for count in runs {
+                bytes.extend_from_slice(&count.to_le_bytes());
+                bytes.extend_from_slice(&0u32.to_le_bytes());
+            }
+            let err = VoxelGridVec::read_rle(&mut bytes.as_slice()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "runs {:?}", runs);
+        }
+    }
+
+    #[test]
+    fn mirror_twice_is_original() {
+        for grid in [
+            shape::sphere_uniform(8, 2),
+            shape::ellipsoid(UVec3::new(3, 4, 5), 7),
+        ] {
+            let size = grid.size.as_ivec3();
+            for axis in 0..3 {
+                let twice = grid.mirror(axis).mirror(axis);
+                assert_eq!(twice.size, grid.size);
+                for z in -1..=size.z {
+                    for y in -1..=size.y {
+                        for x in -1..=size.x {
+                            let index = voxel_index_i32(grid.size, x, y, z);
+                            let (expected, actual) = (grid.data[index], twice.data[index]);
+                            let pos = IVec3::new(x, y, z);
+                            if pos[axis as usize] == -1 {
+                                // Offsets in the start padding are cleared
+                                assert_eq!(actual & 0x00ff_ffff, 0, "axis {} pos {}", axis, pos);
+                                assert_eq!(
+                                    actual >> 24,
+                                    expected >> 24,
+                                    "axis {} pos {}",
+                                    axis,
+                                    pos
+                                );
+                            } else {
+                                assert_eq!(actual, expected, "axis {} pos {}", axis, pos);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn thin_grid_indexes() {
+        let size = UVec3::new(4, 3, 1);
+        let mut seen = vec![false; get_vec_size(size)];
+        for z in -1..=1 {
+            for y in -1..=3 {
+                for x in -1..=4 {
+                    let index = voxel_index_checked(size, x, y, z).unwrap();
+                    assert_eq!(index, voxel_index_i32(size, x, y, z));
+                    assert!(!seen[index], "({}, {}, {}) reuses index {}", x, y, z, index);
+                    seen[index] = true;
+                }
+            }
+        }
+        assert!(seen.iter().all(|s| *s));
+        assert_eq!(voxel_index(size, 3, 2, 0), voxel_index_i32(size, 3, 2, 0));
+        assert_eq!(voxel_index_checked(size, 0, 0, 2), None);
+        assert_eq!(voxel_index_checked(size, 0, 0, -2), None);
+
+        let mut grid = VoxelGridVec::new(size, 5);
+        assert_eq!(grid.try_get(3, 2, 0), Some(5 << 24));
+        assert_eq!(grid.try_get(3, 2, 1), Some(0));
+        assert_eq!(grid.try_get(3, 2, 2), None);
+        assert!(grid.try_set(0, 0, -1, 7));
+        assert!(!grid.try_set(0, 0, -2, 7));
+        assert_eq!(grid.material(0, 0, 0), 5);
+    }
+
+    #[test]
+    fn raycast_empty_grid() {
+        for size in [
+            UVec3::new(0, 4, 4),
+            UVec3::new(4, 0, 4),
+            UVec3::new(4, 4, 0),
+        ] {
+            let grid = VoxelGridVec::new(size, 1);
+            assert!(grid.raycast(Vec3::splat(-1.0), Vec3::ONE, 100.0).is_none());
+        }
+        let grid = VoxelGridVec::new(UVec3::splat(4), 1);
+        assert!(grid.raycast(Vec3::splat(-1.0), Vec3::ONE, 100.0).is_some());
+    }
+
+    #[test]
+    fn edges_walk_each_face() {
+        let v = |x: f32, y: f32| Vec3::new(x, y, 0.0);
+        let mesh = MeshData {
+            vertexes: vec![
+                // Face whose second triangle collapsed
+                v(0.0, 0.0),
+                v(1.0, 0.0),
+                v(1.0, 1.0),
+                v(0.0, 0.0),
+                v(0.0, 0.0),
+                v(0.0, 0.0),
+                // Unit quad at x=2
+                v(2.0, 0.0),
+                v(3.0, 0.0),
+                v(3.0, 1.0),
+                v(3.0, 1.0),
+                v(2.0, 1.0),
+                v(2.0, 0.0),
+            ],
+            ..Default::default()
+        };
+        let (positions, lines) = mesh.face_edges();
+        assert_eq!(positions.len(), 7);
+        // 3 edges of the lone triangle, 4 of the quad
+        assert_eq!(lines.len(), 2 * 7);
+        let (_, lines) = mesh.triangle_edges();
+        assert_eq!(lines.len(), 2 * 8);
+    }
+
+    #[test]
+    fn split_round_trip() {
+        let mut grid = shape::sphere_uniform(7, 3);
+        grid.data[voxel_index(grid.size, 3, 3, 3)] |= 0x0012_3456;
+        let materials = VoxelMaterialGrid::from_grid(&grid);
+        assert_eq!(
+            get_split_buf_size_checked(grid.size).unwrap(),
+            (materials.data.len() + 3) / 4 * 4
+        );
+        let mut padded = materials.data.clone();
+        padded.resize(get_split_buf_size_checked(grid.size).unwrap(), 0);
+
+        let read = VoxelGridVec::from_split(grid.size, &padded, Some(&grid.data));
+        assert_eq!(read, grid);
+        let read = VoxelGridVec::from_split(grid.size, &padded, None);
+        assert_eq!(read, materials.to_grid());
+    }
+}